@@ -0,0 +1,45 @@
+//! Copyright (C) 2022 Gaz J.
+//!
+//! This program is free software: you can redistribute it and/or modify
+//! it under the terms of the GNU General Public License as published by
+//! the Free Software Foundation, either version 3 of the License, or
+//! (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//! GNU General Public License for more details.
+//!
+//! You should have received a copy of the GNU General Public License
+//! along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Records the git commit being built, for --version.
+
+use std::path::Path;
+use std::process::Command;
+
+fn main()
+{
+    let commit = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|commit| commit.trim().to_string())
+        .unwrap_or_else(|| String::from("unknown"));
+
+    println!("cargo:rustc-env=SSLCHAINS_GIT_COMMIT={}", commit);
+
+    // Rebuild when the checked out commit changes (outside of git, e.g. in
+    // a source tarball, the build script only runs once).
+    for path in [".git/HEAD", ".git/refs/heads"]
+    {
+        if Path::new(path).exists()
+        {
+            println!("cargo:rerun-if-changed={}", path);
+        }
+    }
+
+    println!("cargo:rerun-if-changed=build.rs");
+}
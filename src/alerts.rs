@@ -0,0 +1,319 @@
+//! Copyright (C) 2022 Gaz J.
+//!
+//! This program is free software: you can redistribute it and/or modify
+//! it under the terms of the GNU General Public License as published by
+//! the Free Software Foundation, either version 3 of the License, or
+//! (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//! GNU General Public License for more details.
+//!
+//! You should have received a copy of the GNU General Public License
+//! along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Alerts raised by comparing successive scans (--daemon, and --watch with
+//! --syslog): certificates approaching (or past) expiry, new chains, chains
+//! which break, files whose contents change, and files which disappear.
+//!
+//! Alerts are raised when a condition starts, rather than on every scan it
+//! persists for, so periodic scans don't repeat them. What files hold is
+//! tracked with an inventory's [`snapshot::Snapshot`], so changed and
+//! removed files are found as with --load-snapshot.
+
+use std::collections::HashMap;
+use serde_json::{json, Value};
+
+use crate::chain::{Chain, Expiry, Inventory};
+use crate::format::{format_time, get_display_name};
+use crate::snapshot;
+
+/// Certificates expiring within this many days raise alerts, unless
+/// --expiry-warn says otherwise.
+pub const DEFAULT_WARN_DAYS: u32 = 30;
+
+/// What an alert is about.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind
+{
+    /// A certificate expires within the warning period.
+    Expiring,
+
+    /// A certificate has expired.
+    Expired,
+
+    /// A chain with a key appeared.
+    Added,
+
+    /// A chain lost its certificate, or signing certificates.
+    Broken,
+
+    /// A file holds other keys, requests, or certificates than before.
+    Changed,
+
+    /// A file which held keys, requests, or certificates before no longer
+    /// holds any (or has disappeared).
+    Removed,
+}
+
+impl Kind
+{
+    pub fn as_str(&self) -> &'static str
+    {
+        match self
+        {
+            Kind::Expiring => "expiring",
+            Kind::Expired => "expired",
+            Kind::Added => "added",
+            Kind::Broken => "broken",
+            Kind::Changed => "changed",
+            Kind::Removed => "removed",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Alert
+{
+    pub kind: Kind,
+
+    /// Name of the chain concerned (empty for changed and removed files).
+    pub name: String,
+
+    pub path: String,
+
+    /// What happened, e.g. "expires in 12 days (2030-01-01T00:00:00Z)".
+    pub message: String,
+}
+
+impl Alert
+{
+    pub fn to_json(&self) -> Value
+    {
+        json!({ "kind": self.kind.as_str(), "name": self.name, "path": self.path, "message": self.message })
+    }
+}
+
+/// A chain, as far as alerts are concerned.
+#[derive(Debug)]
+struct ChainState
+{
+    name: String,
+
+    /// Path of its current certificate, if it has one.
+    certificate: Option<String>,
+
+    /// Number of signing certificates found for its current certificate.
+    signing_certificates: usize,
+}
+
+/// What a scan found.
+#[derive(Debug)]
+pub struct Snapshot
+{
+    /// What the files scanned hold.
+    files: snapshot::Snapshot,
+
+    /// Chains with a key, by their key's path.
+    chains: HashMap<String, ChainState>,
+
+    /// (chain name, expiry, message) of current certificates and their
+    /// signing certificates, by path.
+    expiry: HashMap<String, (String, Expiry, String)>,
+}
+
+impl Snapshot
+{
+    /// Record what an inventory holds and the chains built from it, with
+    /// expiry judged against a warning period.
+    pub fn new(inventory: &Inventory, chains: &[Chain], warn_days: u32) -> Snapshot
+    {
+        let mut snapshot = Snapshot { files: snapshot::Snapshot::new(inventory), chains: HashMap::new(), expiry: HashMap::new() };
+
+        for chain in chains
+        {
+            let name = get_display_name(chain);
+            let certificate = chain.certificates.first();
+
+            if let Some(key) = &chain.key
+            {
+                snapshot.chains.insert(key.path.clone(), ChainState {
+                    name: name.clone(),
+                    certificate: certificate.map(|certificate| certificate.path.clone()),
+                    signing_certificates: certificate.map(|c| c.signing_certificate_chain().len()).unwrap_or(0),
+                });
+            }
+
+            let mut certificate = certificate;
+
+            while let Some(current) = certificate
+            {
+                let expiry = current.expiry(warn_days);
+                let not_after = format_time(current.certificate.not_after());
+
+                let message = match (expiry, current.days_remaining())
+                {
+                    (Expiry::Expired, _) => format!("expired on {}", not_after),
+                    (_, Some(days)) => format!("expires in {} day(s) ({})", days, not_after),
+                    (_, None) => format!("expires on {}", not_after)
+                };
+
+                snapshot.expiry.entry(current.path.clone()).or_insert((name.clone(), expiry, message));
+
+                certificate = current.signing_certificate.as_deref();
+            }
+        }
+
+        snapshot
+    }
+
+    /// Alerts for conditions which have started since the previous scan (or
+    /// which hold, for the first scan).
+    pub fn alerts(&self, previous: Option<&Snapshot>) -> Vec<Alert>
+    {
+        let mut alerts = vec![];
+
+        let mut expiry: Vec<_> = self.expiry.iter().collect();
+
+        expiry.sort_by(|a, b| a.0.cmp(b.0));
+
+        for (path, (name, expiry, message)) in expiry
+        {
+            let before = previous.and_then(|previous| previous.expiry.get(path)).map(|(_, expiry, _)| *expiry);
+
+            let kind = match expiry
+            {
+                Expiry::Expired => Kind::Expired,
+                Expiry::Expiring => Kind::Expiring,
+                Expiry::Valid => continue
+            };
+
+            if before.map(|before| before < *expiry).unwrap_or(true)
+            {
+                alerts.push(Alert { kind, name: name.clone(), path: path.clone(), message: message.clone() });
+            }
+        }
+
+        let previous = match previous
+        {
+            Some(previous) => previous,
+            None => return alerts
+        };
+
+        let mut chains: Vec<_> = previous.chains.iter().collect();
+
+        chains.sort_by(|a, b| a.0.cmp(b.0));
+
+        for (key, before) in chains
+        {
+            let after = self.chains.get(key);
+
+            let message = match (&before.certificate, after)
+            {
+                // Removed keys are reported as removed files.
+                (_, None) => continue,
+                (Some(certificate), Some(after)) if after.certificate.is_none() => {
+                    format!("no longer has a certificate (had {})", certificate)
+                },
+                (Some(_), Some(after)) if after.signing_certificates < before.signing_certificates => {
+                    format!("lost {} signing certificate(s)", before.signing_certificates - after.signing_certificates)
+                },
+                _ => continue
+            };
+
+            alerts.push(Alert { kind: Kind::Broken, name: before.name.clone(), path: key.clone(), message });
+        }
+
+        let mut added: Vec<_> = self.chains.iter().filter(|(key, _)| !previous.chains.contains_key(*key)).collect();
+
+        added.sort_by(|a, b| a.0.cmp(b.0));
+
+        for (key, after) in added
+        {
+            let message = match &after.certificate
+            {
+                Some(certificate) => format!("new chain, with {}", certificate),
+                None => String::from("new chain, without a certificate")
+            };
+
+            alerts.push(Alert { kind: Kind::Added, name: after.name.clone(), path: key.clone(), message });
+        }
+
+        // Files which still hold something have changed; new files are
+        // reported through the chains they add.
+        let before = previous.files.paths();
+        let after = self.files.paths();
+
+        for (path, message) in self.files.changes(&previous.files)
+        {
+            if before.contains(&path) && after.contains(&path)
+            {
+                alerts.push(Alert { kind: Kind::Changed, name: String::new(), path, message });
+            }
+        }
+
+        for path in before.difference(&after)
+        {
+            alerts.push(Alert { kind: Kind::Removed, name: String::new(), path: path.to_string(), message: String::from("disappeared") });
+        }
+
+        alerts
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+    use crate::Inventory;
+
+    #[test]
+    fn raises_alerts_for_changes()
+    {
+        let complete: Vec<String> = ["samples/ca_signed.key", "samples/ca_signed.crt", "samples/intermediate_ca.crt"]
+            .iter().map(|path| path.to_string()).collect();
+
+        let build = |paths: &[String]| {
+            let mut inventory = Inventory::new();
+
+            for path in paths
+            {
+                inventory = inventory.add_path(path);
+            }
+
+            Snapshot::new(&inventory, &inventory.build_chains().unwrap(), 30)
+        };
+
+        // The intermediate has expired, which is only reported once.
+        let first = build(&complete);
+        let alerts = first.alerts(None);
+
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].kind, Kind::Expired);
+        assert_eq!(alerts[0].path, "samples/intermediate_ca.crt");
+        assert_eq!(alerts[0].message, "expired on 2023-04-01T23:52:25Z");
+
+        assert!(build(&complete).alerts(Some(&first)).is_empty());
+
+        let second = build(&complete[..2]);
+        let alerts: Vec<(Kind, String)> = second.alerts(Some(&first)).into_iter().map(|alert| (alert.kind, alert.message)).collect();
+
+        assert_eq!(alerts, vec![
+            (Kind::Broken, String::from("lost 1 signing certificate(s)")),
+            (Kind::Removed, String::from("disappeared")),
+        ]);
+
+        let third = build(&complete[..1]);
+        let alerts = third.alerts(Some(&second));
+
+        assert_eq!(alerts[0].message, "no longer has a certificate (had samples/ca_signed.crt)");
+        assert_eq!(alerts[0].to_json()["kind"], "broken");
+
+        // Chains found after the first scan are new.
+        let alerts = first.alerts(Some(&build(&[])));
+
+        assert_eq!(alerts[1].kind, Kind::Added);
+        assert_eq!(alerts[1].message, "new chain, with samples/ca_signed.crt");
+    }
+}
@@ -0,0 +1,419 @@
+//! Copyright (C) 2022 Gaz J.
+//!
+//! This program is free software: you can redistribute it and/or modify
+//! it under the terms of the GNU General Public License as published by
+//! the Free Software Foundation, either version 3 of the License, or
+//! (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//! GNU General Public License for more details.
+//!
+//! You should have received a copy of the GNU General Public License
+//! along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Apache httpd configurations (--apache): the files each TLS virtual host
+//! names with SSLCertificateFile, SSLCertificateKeyFile, and
+//! SSLCertificateChainFile, so they can be checked against the chains built
+//! from them.
+//!
+//! Only the directives which matter here are read. Conditional sections
+//! (<IfModule>, <IfDefine>, and so on) are assumed to apply.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+use crate::configured::{Configured, Settings};
+
+/// Where distributions keep the main configuration file.
+pub const DEFAULT_CONFIGS: [&str; 2] = ["/etc/apache2/apache2.conf", "/etc/httpd/conf/httpd.conf"];
+
+/// The first of the default configuration files which exists (or the
+/// first, if none do).
+pub fn default_config() -> String
+{
+    DEFAULT_CONFIGS.iter().find(|path| Path::new(path).exists()).unwrap_or(&DEFAULT_CONFIGS[0]).to_string()
+}
+
+/// Includes nested deeper than this are assumed to be loops.
+const MAX_DEPTH: usize = 16;
+
+/// The settings TLS virtual hosts are configured with.
+const SETTINGS: Settings = Settings {
+    certificate: "SSLCertificateFile",
+    key: "SSLCertificateKeyFile",
+    name: "",
+};
+
+/// The TLS sites a configuration defines: a certificate for each (hosts
+/// with several, e.g. RSA and ECDSA, have a site for each), labelled e.g.
+/// "example.com (httpd.conf:12)".
+#[derive(Debug, Default)]
+pub struct Config
+{
+    pub sites: Vec<Configured>,
+
+    /// (path, problem) pairs for includes which couldn't be read.
+    pub problems: Vec<(String, String)>,
+}
+
+/// Directives in effect for the main server, or a virtual host.
+#[derive(Debug, Default, Clone)]
+struct Context
+{
+    location: String,
+    address: String,
+    names: Vec<String>,
+    engine: bool,
+    certificates: Vec<String>,
+    keys: Vec<String>,
+    chain: Option<String>,
+}
+
+impl Config
+{
+    /// Read a main configuration file, and those it includes.
+    pub fn read(path: &Path) -> io::Result<Config>
+    {
+        let contents = fs::read_to_string(path).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+
+        // Relative paths are relative to the ServerRoot, which defaults to
+        // where the configuration is.
+        let mut parser = Parser {
+            root: path.parent().map(Path::to_path_buf).unwrap_or_default(),
+            config: Config::default(),
+            main: Context::default(),
+            host: None,
+        };
+
+        parser.parse(path, &contents, 0);
+
+        Ok(parser.config)
+    }
+}
+
+struct Parser
+{
+    root: PathBuf,
+    config: Config,
+
+    /// The main server's directives, which virtual hosts inherit.
+    main: Context,
+
+    /// The virtual host being read, if any.
+    host: Option<Context>,
+}
+
+impl Parser
+{
+    fn parse(&mut self, path: &Path, contents: &str, depth: usize)
+    {
+        let mut lines = contents.lines().enumerate();
+
+        while let Some((index, line)) = lines.next()
+        {
+            let mut line = line.trim().to_string();
+
+            // Lines ending with a backslash continue on the next.
+            while line.ends_with('\\')
+            {
+                line.pop();
+
+                match lines.next()
+                {
+                    Some((_, next)) => line.push_str(next.trim()),
+                    None => break
+                }
+            }
+
+            if line.is_empty() || line.starts_with('#')
+            {
+                continue;
+            }
+
+            let words = split_words(&line);
+            let location = format!("{}:{}", path.display(), index + 1);
+
+            self.directive(&words, location, depth);
+        }
+    }
+
+    fn directive(&mut self, words: &[String], location: String, depth: usize)
+    {
+        let (name, arguments) = match words.split_first()
+        {
+            Some((name, arguments)) => (name.to_ascii_lowercase(), arguments),
+            None => return
+        };
+
+        let first = arguments.first().map(String::as_str).unwrap_or_default();
+
+        if name == "<virtualhost"
+        {
+            let address = arguments.join(" ").trim_end_matches('>').trim().to_string();
+
+            self.host = Some(Context { location, address, ..Context::default() });
+
+            return;
+        }
+
+        if name == "</virtualhost>"
+        {
+            if let Some(host) = self.host.take()
+            {
+                self.add_sites(host);
+            }
+
+            return;
+        }
+
+        match name.as_str()
+        {
+            "serverroot" => return self.root = PathBuf::from(first),
+            "include" | "includeoptional" => return self.include(first, name == "includeoptional", &location, depth),
+            _ => ()
+        }
+
+        let context = self.host.as_mut().unwrap_or(&mut self.main);
+
+        match name.as_str()
+        {
+            "servername" => context.names.insert(0, server_name(first)),
+            "serveralias" => context.names.extend(arguments.iter().cloned()),
+            "sslengine" => context.engine = first.eq_ignore_ascii_case("on"),
+            "sslcertificatefile" => context.certificates.push(resolve(&self.root, first)),
+            "sslcertificatekeyfile" => context.keys.push(resolve(&self.root, first)),
+            "sslcertificatechainfile" => context.chain = Some(resolve(&self.root, first)),
+            _ => ()
+        }
+    }
+
+    /// Add a site for each certificate a (TLS) virtual host has, or
+    /// inherits, pairing certificates and keys in order, as mod_ssl does.
+    fn add_sites(&mut self, host: Context)
+    {
+        if !host.engine && host.certificates.is_empty()
+        {
+            return;
+        }
+
+        let (certificates, keys) = match host.certificates.is_empty()
+        {
+            true => (&self.main.certificates, &self.main.keys),
+            false => (&host.certificates, &host.keys)
+        };
+
+        for (index, certificate) in certificates.iter().enumerate()
+        {
+            self.config.sites.push(Configured {
+                label: format!("{} ({})", host.names.first().unwrap_or(&host.address), host.location),
+                settings: SETTINGS,
+                certificate: certificate.clone(),
+                key: keys.get(index).cloned(),
+                chain: host.chain.clone().or(self.main.chain.clone()),
+                names: host.names.clone(),
+                ..Configured::default()
+            });
+        }
+    }
+
+    /// Read the files an Include matches: files, every file under
+    /// directories, and wildcards.
+    fn include(&mut self, pattern: &str, optional: bool, location: &str, depth: usize)
+    {
+        if depth >= MAX_DEPTH
+        {
+            self.config.problems.push((location.to_string(), format!("includes {} too deeply", pattern)));
+
+            return;
+        }
+
+        let mut files = vec![];
+
+        for path in expand(&PathBuf::from(resolve(&self.root, pattern)))
+        {
+            let mut walked: Vec<PathBuf> = WalkDir::new(&path).into_iter().flatten()
+                .filter(|entry| entry.file_type().is_file())
+                .map(|entry| entry.into_path())
+                .collect();
+
+            walked.sort();
+            files.extend(walked);
+        }
+
+        if files.is_empty() && !optional
+        {
+            self.config.problems.push((location.to_string(), format!("Include {} matches no files", pattern)));
+        }
+
+        for file in files
+        {
+            match fs::read_to_string(&file)
+            {
+                Ok(contents) => self.parse(&file, &contents, depth + 1),
+                Err(e) => self.config.problems.push((file.display().to_string(), format!("couldn't read: {}", e)))
+            }
+        }
+    }
+}
+
+/// Split a directive into words, keeping quoted words whole.
+fn split_words(line: &str) -> Vec<String>
+{
+    let mut words = vec![];
+    let mut word = String::new();
+    let mut quoted = false;
+
+    for c in line.chars()
+    {
+        match c
+        {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !word.is_empty()
+                {
+                    words.push(std::mem::take(&mut word));
+                }
+            },
+            c => word.push(c)
+        }
+    }
+
+    if !word.is_empty()
+    {
+        words.push(word);
+    }
+
+    words
+}
+
+/// The hostname of a ServerName, which may have a scheme and port.
+fn server_name(value: &str) -> String
+{
+    let host = value.split("://").last().unwrap_or(value);
+
+    host.rsplit_once(':').map(|(host, _)| host).unwrap_or(host).to_string()
+}
+
+fn resolve(root: &Path, path: &str) -> String
+{
+    root.join(path).display().to_string()
+}
+
+/// Expand wildcards (* and ?) in a path's components, returning the paths
+/// which exist, sorted.
+pub fn expand(pattern: &Path) -> Vec<PathBuf>
+{
+    let mut paths = vec![PathBuf::new()];
+
+    for component in pattern.components()
+    {
+        let component = component.as_os_str().to_string_lossy();
+
+        if !component.contains(['*', '?'])
+        {
+            paths.iter_mut().for_each(|path| path.push(component.as_ref()));
+
+            continue;
+        }
+
+        let mut matched = vec![];
+
+        for path in &paths
+        {
+            let directory = if path.as_os_str().is_empty() { Path::new(".") } else { path.as_path() };
+
+            for entry in fs::read_dir(directory).into_iter().flatten().flatten()
+            {
+                if wildcard_match(&component, &entry.file_name().to_string_lossy())
+                {
+                    matched.push(path.join(entry.file_name()));
+                }
+            }
+        }
+
+        matched.sort();
+        paths = matched;
+    }
+
+    paths.retain(|path| path.exists());
+
+    paths
+}
+
+fn wildcard_match(pattern: &str, name: &str) -> bool
+{
+    match pattern.chars().next()
+    {
+        None => name.is_empty(),
+        Some('*') => (0..=name.len()).filter(|i| name.is_char_boundary(*i)).any(|i| wildcard_match(&pattern[1..], &name[i..])),
+        Some(p) => match name.chars().next()
+        {
+            Some(n) if p == '?' || p == n => wildcard_match(&pattern[p.len_utf8()..], &name[n.len_utf8()..]),
+            _ => false
+        }
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+    use crate::configured;
+    use crate::Inventory;
+
+    #[test]
+    fn checks_virtual_hosts()
+    {
+        let mut config = Config::read(Path::new("samples/apache/httpd.conf")).unwrap();
+
+        let labels: Vec<String> = config.sites.iter().map(|site| site.label.clone()).collect();
+
+        assert_eq!(labels, vec![
+            "example.com (samples/apache/sites-enabled/example.com.conf:6)",
+            "san.example.com (samples/apache/sites-enabled/san.example.com.conf:1)",
+            "old.example.com (samples/apache/sites-enabled/san.example.com.conf:9)",
+            "default.example.com (samples/apache/sites-enabled/san.example.com.conf:16)",
+        ]);
+
+        assert_eq!(config.sites[0].names, vec!["example.com", "www.example.com"]);
+        assert_eq!(config.sites[3].certificate, "samples/apache/ssl/example.com.crt");
+
+        let mut inventory = Inventory::new();
+
+        for path in configured::paths(&config.sites)
+        {
+            inventory = inventory.add_path(&path);
+        }
+
+        let chains = inventory.build_chains().unwrap();
+
+        for site in config.sites.iter_mut()
+        {
+            chains.iter().for_each(|chain| site.consider(chain));
+        }
+
+        assert_eq!(config.sites[0].problems(), vec!["www.example.com isn't covered by samples/apache/ssl/example.com.crt"]);
+        assert_eq!(config.sites[1].problems(), vec![
+            "SSLCertificateKeyFile samples/apache/ssl/san.example.com.key doesn't match a certificate in samples/apache/ssl/san.example.com.crt",
+        ]);
+        assert_eq!(config.sites[2].problems(), vec![
+            "samples/apache/ssl/old.example.com.crt doesn't exist",
+            "samples/apache/ssl/old.example.com.key doesn't exist",
+        ]);
+        assert_eq!(config.sites[3].problems(), vec!["default.example.com isn't covered by samples/apache/ssl/example.com.crt"]);
+    }
+
+    #[test]
+    fn matches_wildcards()
+    {
+        assert!(wildcard_match("*.conf", "example.com.conf"));
+        assert!(wildcard_match("site?.conf", "site1.conf"));
+        assert!(!wildcard_match("*.conf", "example.com.conf.disabled"));
+        assert_eq!(expand(Path::new("samples/apache/sites-*/*.conf")).len(), 2);
+        assert_eq!(server_name("https://example.com:443"), "example.com");
+    }
+}
@@ -0,0 +1,225 @@
+//! Copyright (C) 2022 Gaz J.
+//!
+//! This program is free software: you can redistribute it and/or modify
+//! it under the terms of the GNU General Public License as published by
+//! the Free Software Foundation, either version 3 of the License, or
+//! (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//! GNU General Public License for more details.
+//!
+//! You should have received a copy of the GNU General Public License
+//! along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use flate2::read::GzDecoder;
+use log::info;
+
+/// Separates an archive path from the name of one of its members,
+/// e.g. "certs.zip:intermediate.crt".
+pub const SEPARATOR: char = ':';
+
+enum Kind
+{
+    Zip,
+    Tar,
+    TarGz,
+}
+
+fn kind(path: &str) -> Option<Kind>
+{
+    let path = path.to_lowercase();
+
+    if path.ends_with(".zip") { return Some(Kind::Zip); }
+    if path.ends_with(".tar") { return Some(Kind::Tar); }
+    if path.ends_with(".tar.gz") || path.ends_with(".tgz") { return Some(Kind::TarGz); }
+
+    None
+}
+
+/// Determine whether a path looks like a supported archive.
+pub fn is_archive(path: &str) -> bool
+{
+    kind(path).is_some()
+}
+
+/// List an archive's regular file members as "archive:member" paths,
+/// skipping (and logging) those larger than max_size bytes (0 for no limit).
+pub fn members(path: &str, max_size: u64) -> Result<Vec<String>, io::Error>
+{
+    let names = match kind(path)
+    {
+        Some(Kind::Zip) => zip_names(path)?,
+        Some(Kind::Tar) => tar_names(File::open(path)?)?,
+        Some(Kind::TarGz) => tar_names(GzDecoder::new(File::open(path)?))?,
+        None => vec![],
+    };
+
+    Ok(names.into_iter()
+        .filter(|(name, size)| {
+            let fits = max_size == 0 || *size <= max_size;
+
+            if !fits
+            {
+                info!("Skipping {}{}{} ({} bytes exceeds the maximum file size of {} bytes).", path, SEPARATOR, name, size, max_size);
+            }
+
+            fits
+        })
+        .map(|(name, _)| format!("{}{}{}", path, SEPARATOR, name))
+        .collect())
+}
+
+/// Split an "archive:member" path into its parts, provided the archive
+/// portion refers to an existing archive file.
+pub fn split(path: &str) -> Option<(&str, &str)>
+{
+    for (index, _) in path.match_indices(SEPARATOR)
+    {
+        let (archive, member) = (&path[..index], &path[index + 1..]);
+
+        if is_archive(archive) && Path::new(archive).is_file()
+        {
+            return Some((archive, member));
+        }
+    }
+
+    None
+}
+
+/// Read the contents of a single archive member, failing if it holds more
+/// than limit bytes (whatever its header claims), so a decompression bomb
+/// can't exhaust memory.
+pub fn read_member(archive: &str, member: &str, limit: u64) -> Result<Vec<u8>, io::Error>
+{
+    match kind(archive)
+    {
+        Some(Kind::Zip) => zip_read(archive, member, limit),
+        Some(Kind::Tar) => tar_read(File::open(archive)?, member, limit),
+        Some(Kind::TarGz) => tar_read(GzDecoder::new(File::open(archive)?), member, limit),
+        None => Err(io::Error::new(io::ErrorKind::InvalidInput, "Not an archive")),
+    }
+}
+
+/// Read at most limit bytes, failing if there are more.
+fn read_limited<R: Read>(reader: R, limit: u64) -> Result<Vec<u8>, io::Error>
+{
+    let mut contents = vec![];
+
+    reader.take(limit.saturating_add(1)).read_to_end(&mut contents)?;
+
+    if contents.len() as u64 > limit
+    {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("member is larger than {} bytes", limit)));
+    }
+
+    Ok(contents)
+}
+
+/// Names of a zip archive's regular files, with their (uncompressed) sizes.
+fn zip_names(path: &str) -> Result<Vec<(String, u64)>, io::Error>
+{
+    let mut archive = zip::ZipArchive::new(File::open(path)?)?;
+    let mut names = vec![];
+
+    for index in 0..archive.len()
+    {
+        let file = archive.by_index(index)?;
+
+        if file.is_file()
+        {
+            names.push((file.name().to_string(), file.size()));
+        }
+    }
+
+    Ok(names)
+}
+
+fn zip_read(path: &str, member: &str, limit: u64) -> Result<Vec<u8>, io::Error>
+{
+    let mut archive = zip::ZipArchive::new(File::open(path)?)?;
+    let file = archive.by_name(member)?;
+
+    read_limited(file, limit)
+}
+
+/// Names of a tar archive's regular files, with their sizes.
+fn tar_names<R: Read>(reader: R) -> Result<Vec<(String, u64)>, io::Error>
+{
+    let mut archive = tar::Archive::new(reader);
+    let mut names = vec![];
+
+    for entry in archive.entries()?
+    {
+        let entry = entry?;
+
+        if entry.header().entry_type().is_file()
+        {
+            names.push((entry.path()?.to_string_lossy().to_string(), entry.size()));
+        }
+    }
+
+    Ok(names)
+}
+
+fn tar_read<R: Read>(reader: R, member: &str, limit: u64) -> Result<Vec<u8>, io::Error>
+{
+    let mut archive = tar::Archive::new(reader);
+
+    for entry in archive.entries()?
+    {
+        let entry = entry?;
+
+        if entry.path()?.to_string_lossy() == member
+        {
+            return read_limited(entry, limit);
+        }
+    }
+
+    Err(io::Error::new(io::ErrorKind::NotFound, "Archive member not found"))
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    #[test]
+    fn lists_archive_members()
+    {
+        for path in ["samples/archives/ca_signed.zip", "samples/archives/ca_signed.tar", "samples/archives/ca_signed.tar.gz"]
+        {
+            assert_eq!(
+                members(path, 0).unwrap(),
+                vec![
+                    format!("{}:ca_signed.key", path),
+                    format!("{}:ca_signed.crt", path),
+                    format!("{}:intermediate_ca.crt", path),
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn reads_archive_members()
+    {
+        let expected = std::fs::read("samples/intermediate_ca.crt").unwrap();
+
+        for path in ["samples/archives/ca_signed.zip", "samples/archives/ca_signed.tar", "samples/archives/ca_signed.tar.gz"]
+        {
+            let member = format!("{}:intermediate_ca.crt", path);
+            let (archive, name) = split(&member).unwrap();
+
+            assert_eq!(read_member(archive, name, 1 << 20).unwrap(), expected);
+            assert_eq!(read_member(archive, name, 100).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        }
+
+        // Members larger than the maximum size aren't listed.
+        assert!(members("samples/archives/ca_signed.zip", 100).unwrap().is_empty());
+        assert!(members("samples/archives/ca_signed.tar.gz", 100).unwrap().is_empty());
+    }
+}
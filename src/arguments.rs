@@ -13,12 +13,44 @@
 //! You should have received a copy of the GNU General Public License
 //! along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use std::env;
-use std::io;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use log::{debug, info};
 use walkdir::{ DirEntry, WalkDir };
 
+use sslchains::{apache, configured, haproxy, mail};
+use sslchains::archive;
+use sslchains::chain;
+use sslchains::connect::{self, Endpoint};
+use sslchains::error::Error;
+use sslchains::letsencrypt::Layout;
+use sslchains::manifest;
+#[cfg(feature = "nss")]
+use sslchains::nss;
 use crate::options::Options;
 
+/// Sibling file conventions, as (suffix, sibling suffix) pairs. When a path
+/// ends with a suffix, the path with the sibling suffix substituted is
+/// processed too (if it exists), so pairs are matched even when only one
+/// of the files is passed as an argument.
+const SIBLING_HINTS: &[(&str, &str)] = &[
+    // HAProxy 2.x splits certificates and keys into foo.pem and foo.pem.key.
+    (".pem", ".pem.key"),
+    (".pem.key", ".pem"),
+];
+
+/// Extensions of files likely to contain keys, requests, or certificates.
+/// Other files found when walking directories are skipped unless the
+/// --all-files option is used.
+const CANDIDATE_EXTENSIONS: &[&str] = &[
+    "pem", "crt", "cer", "cert", "key", "csr", "req", "der", "p7b", "p7c", "p12", "pfx",
+];
+
+/// Number of bytes read from extensionless files to sniff their contents.
+const SNIFF_LENGTH: u64 = 4096;
+
 fn is_hidden(entry: &DirEntry) -> bool {
     entry.file_name()
          .to_str()
@@ -26,86 +58,954 @@ fn is_hidden(entry: &DirEntry) -> bool {
          .unwrap_or(false)
 }
 
-pub fn process(options: &Options) -> Result<Vec<String>, io::Error>
+/// Determine whether a directory found while walking is on the prune list.
+fn is_pruned(entry: &DirEntry, options: &Options) -> bool
+{
+    let pruned = entry.depth() > 0
+        && entry.file_type().is_dir()
+        && entry.file_name().to_str().map(|name| options.prune.iter().any(|p| p == name)).unwrap_or(false);
+
+    if pruned
+    {
+        debug!("Pruning {}", entry.path().display());
+    }
+
+    pruned
+}
+
+/// Determine whether a directory found while walking has already been
+/// visited (through another symbolic link, or directly), recording it if not.
+fn is_revisited(entry: &DirEntry, directories: &mut HashSet<String>) -> bool
+{
+    if !entry.file_type().is_dir()
+    {
+        return false;
+    }
+
+    let identity = match identify_file(&entry.path().to_string_lossy())
+    {
+        Some(identity) => identity,
+        None => return false
+    };
+
+    // Directories are only skipped when reached through a link, so real
+    // paths are preferred (see deduplicate).
+    if !directories.insert(identity) && entry.path_is_symlink()
+    {
+        debug!("Skipping {} (already visited)", entry.path().display());
+
+        return true;
+    }
+
+    false
+}
+
+/// Determine whether a path is within (or is) another, ignoring a leading
+/// "." on either (walking "." finds "./a.crt", which is within "a.crt").
+fn is_within(path: &Path, other: &Path) -> bool
+{
+    path.strip_prefix(".").unwrap_or(path).starts_with(other.strip_prefix(".").unwrap_or(other))
+}
+
+/// Determine whether a path (or the container holding it) is within any of
+/// the paths changed.
+pub fn is_changed(path: &str, changes: &[&Path]) -> bool
 {
-    let mut args = vec![];
+    let file = Path::new(chain::container_path(path).unwrap_or(path));
+
+    changes.iter().any(|change| is_within(file, change))
+}
+
+/// Determine whether walking a directory would reach a path inside it: the
+/// path is directly inside unless walking recursively, and neither it nor
+/// the directories leading to it are hidden or pruned.
+fn is_reachable(root: &Path, path: &Path, options: &Options) -> bool
+{
+    if !is_within(path, root) || is_within(root, path)
+    {
+        return false;
+    }
+
+    let root = root.strip_prefix(".").unwrap_or(root);
+    let path = path.strip_prefix(".").unwrap_or(path);
+
+    let names: Vec<&str> = match path.strip_prefix(root).map(|relative| relative.iter().map(|name| name.to_str()).collect())
+    {
+        Ok(Some(names)) => names,
+        _ => return false
+    };
+
+    // Only directories are pruned, so the path itself is if it's one.
+    let directories = if path.is_dir() { &names[..] } else { &names[..names.len() - 1] };
+
+    (options.recursive || names.len() == 1)
+        && (options.include_hidden_files || !names.iter().any(|name| name.starts_with('.')))
+        && !directories.iter().any(|name| options.prune.iter().any(|p| p == name))
+}
+
+/// The path arguments, read once (e.g. from --files-from, which may be
+/// standard input), so they can be expanded again as files change.
+pub struct Arguments
+{
+    /// Paths to walk: those given (or "."), and those configurations name.
+    walked: Vec<String>,
+
+    /// Paths listed with --files-from, which aren't walked (though archives,
+    /// databases, and manifests are still replaced with their members).
+    listed: Vec<PathBuf>,
+
+    /// The Let's Encrypt layout (--letsencrypt), whose lineages' archives
+    /// are walked.
+    pub layout: Option<Layout>,
+}
+
+impl Arguments
+{
+    /// Read the path arguments, and the paths which --files-from lists and
+    /// the configurations given name.
+    pub fn read(options: &Options) -> Result<Arguments, io::Error>
+    {
+        let mut args = options.arguments.clone();
+
+        // Paths read with --files-from replace the default argument, and
+        // aren't walked, filtered, or counted towards the file limit.
+        let listed = match &options.files_from
+        {
+            Some(path) => read_file_list(path)?,
+            None => vec![]
+        };
+
+        // Let's Encrypt lineages are read from their archives, which hold every
+        // version (the live links only point to one).
+        let layout = match &options.letsencrypt
+        {
+            Some(directory) => Some(Layout::scan(Path::new(directory))?),
+            None => None
+        };
+
+        args.extend(layout.iter().flat_map(Layout::directories));
+
+        // Files Apache is configured with are read too, where they exist (those
+        // which don't are reported with the virtual hosts naming them).
+        if let Some(config) = &options.apache
+        {
+            args.extend(configured::paths(&apache::Config::read(Path::new(config))?.sites).into_iter().filter(|path| Path::new(path).exists()));
+        }
+
+        // As are the files HAProxy's bind lines load.
+        if let Some(config) = &options.haproxy
+        {
+            args.extend(configured::paths(&haproxy::Config::read(Path::new(config))?.bindings).into_iter().filter(|path| Path::new(path).exists()));
+        }
+
+        // And those mail servers are configured with.
+        if options.postfix.is_some() || options.dovecot.is_some()
+        {
+            let config = mail::Config::read(options.postfix.as_deref(), options.dovecot.as_deref())?;
+
+            args.extend(configured::paths(&config.services).into_iter().filter(|path| Path::new(path).exists()));
+        }
+
+        let configured = options.letsencrypt.is_some() || options.apache.is_some() || options.haproxy.is_some() || options.postfix.is_some() || options.dovecot.is_some() || options.kubeconfig.is_some() || !options.containers.is_empty() || options.acm || !options.aws_secrets.is_empty();
+
+        if args.len() == 0 && options.files_from.is_none() && !configured
+        {
+            args.push(".".to_string());
+        }
+
+        Ok(Arguments { walked: args, listed, layout })
+    }
+
+    /// Expand the arguments into the files to process. Files and directories
+    /// which couldn't be read are added to `warnings`, as (path, reason)
+    /// pairs.
+    pub fn expand(&self, options: &Options, warnings: &mut Vec<(String, String)>) -> Result<Vec<String>, io::Error>
+    {
+        let mut expanded = expand(self.walked.clone(), options, warnings)?;
+
+        expanded.extend(self.listed(options, |_| true, warnings));
+
+        Ok(deduplicate(add_siblings(expanded)))
+    }
+
+    /// Expand the arguments again after changes (e.g. while watching), given
+    /// the paths expanded before and the paths changed, walking only what
+    /// changed: paths within a change are dropped, then what's there now is
+    /// added back, as walking the arguments would find it.
+    pub fn refresh(&self, paths: &[String], changes: &[&Path], options: &Options, warnings: &mut Vec<(String, String)>) -> Result<Vec<String>, io::Error>
+    {
+        let is_changed = |path: &str| is_changed(path, changes);
+
+        let mut refreshed: Vec<String> = paths.iter().filter(|path| !is_changed(path)).cloned().collect();
+
+        // Arguments within a change (e.g. a file argument, or the directory
+        // holding it) are expanded again in full.
+        let walked = self.walked.iter().filter(|arg| is_changed(arg)).cloned().collect();
+
+        refreshed.extend(expand(walked, options, warnings)?);
+        refreshed.extend(self.listed(options, |path| is_changed(path), warnings));
+
+        // Changes inside directory arguments are walked from where they are.
+        for change in changes
+        {
+            let reached = self.walked.iter()
+                .map(Path::new)
+                .any(|root| root.is_dir() && is_reachable(root, change, options));
+
+            if !reached
+            {
+                continue;
+            }
+
+            let path = match change.to_str()
+            {
+                Some(path) => path,
+                None => continue
+            };
+
+            // Directories are only walked into recursively.
+            if change.is_dir()
+            {
+                if options.recursive
+                {
+                    refreshed.extend(expand(vec![path.to_string()], options, warnings)?);
+                }
+            }
+            else if let Some(md) = fs::metadata(path).ok().filter(|md| md.is_file())
+            {
+                if is_candidate(path, options) && !exceeds_max_file_size(path, md.len(), options)
+                {
+                    match fs::File::open(path)
+                    {
+                        Ok(_) => refreshed.extend(expand_file(path, options)),
+                        Err(e) => warnings.push((path.to_string(), e.to_string()))
+                    }
+                }
+            }
+        }
+
+        Ok(deduplicate(add_siblings(refreshed)))
+    }
+
+    /// The paths listed with --files-from which are selected, and within the
+    /// file size limit, expanded as files given as arguments are. Paths which
+    /// aren't valid UTF-8 are added to `warnings`.
+    fn listed(&self, options: &Options, selected: impl Fn(&str) -> bool, warnings: &mut Vec<(String, String)>) -> Vec<String>
+    {
+        let mut listed = vec![];
+
+        for path in &self.listed
+        {
+            let path = match path.to_str()
+            {
+                Some(path) => path,
+                None => {
+                    warnings.push((path.display().to_string(), Error::InvalidPath.to_string()));
+
+                    continue;
+                }
+            };
+
+            if !selected(path)
+            {
+                continue;
+            }
+
+            match fs::metadata(path)
+            {
+                Ok(md) if exceeds_max_file_size(path, md.len(), options) => (),
+                Ok(md) if md.is_file() => listed.extend(expand_file(path, options)),
+
+                // Streams, and paths which don't exist, are reported when
+                // they're read.
+                _ => listed.push(path.to_string())
+            }
+        }
+
+        listed
+    }
+
+    /// List the directories which the path arguments cover (e.g. to watch
+    /// them for changes): directory arguments, their subdirectories when
+    /// walked recursively, and the directories containing the paths given.
+    pub fn directories(&self, paths: &[String], options: &Options) -> Vec<String>
+    {
+        let mut directories: Vec<String> = vec![];
+        let mut visited = HashSet::new();
+
+        for arg in self.walked.iter().filter(|arg| *arg != "-" && !chain::is_stream(arg))
+        {
+            let walker = WalkDir::new(arg)
+                .follow_links(options.follow_symlinks)
+                .max_depth(if options.recursive { 100 } else { 0 })
+                .same_file_system(options.same_file_system)
+                .into_iter()
+                .filter_entry(|e| {
+                    e.file_type().is_dir()
+                        && (options.include_hidden_files || !is_hidden(e))
+                        && !is_pruned(e, options)
+                        && !is_revisited(e, &mut visited)
+                });
+
+            directories.extend(walker.flatten().filter_map(|entry| entry.path().to_str().map(String::from)));
+        }
+
+        // Files processed (including those listed with --files-from) are watched
+        // through their directories, as files are often replaced by renaming.
+        for path in paths.iter().filter(|path| *path != chain::STDIN_PATH && !chain::is_stream(path))
+        {
+            let path = chain::container_path(path).unwrap_or(path);
+
+            if let Some(parent) = Path::new(path).parent().and_then(|parent| parent.to_str())
+            {
+                directories.push(if parent.is_empty() { String::from(".") } else { parent.to_string() });
+            }
+        }
+
+        let mut seen = HashSet::new();
+
+        directories.retain(|directory| seen.insert(directory.clone()));
+
+        directories
+    }
+}
+
+/// List the TLS servers to connect to: those given with --connect, then
+/// those listed with --connect-file (read from standard input for "-").
+pub fn endpoints(options: &Options) -> Result<Vec<Endpoint>, String>
+{
+    let mut endpoints = options.connect.clone();
+
+    if let Some(path) = &options.connect_file
+    {
+        let mut contents = String::new();
+
+        let read = match path.as_str()
+        {
+            "-" => io::stdin().read_to_string(&mut contents).map(|_| ()),
+            _ => fs::read_to_string(path).map(|listed| contents = listed)
+        };
+
+        read.map_err(|e| format!("Couldn't read {}: {}", path, e))?;
+
+        let listed = connect::parse_list(&contents).map_err(|e| format!("{}: {}", path, e))?;
+
+        endpoints.extend(listed.into_iter().map(|endpoint| Endpoint { starttls: options.starttls, ..endpoint }));
+    }
+
+    Ok(endpoints)
+}
+
+/**
+ * Remove paths which refer to the same file as an earlier path (e.g. from
+ * overlapping arguments, like "/etc/ssl" and "/etc/ssl/private" with -r, or
+ * several symbolic links to one file), keeping the first, unless a later
+ * one passes through fewer symbolic links.
+ */
+fn deduplicate(paths: Vec<String>) -> Vec<String>
+{
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    let mut deduplicated: Vec<String> = vec![];
+
+    for path in paths
+    {
+        match seen.get(&identify(&path))
+        {
+            Some(&index) => {
+                if count_links(&path) < count_links(&deduplicated[index])
+                {
+                    deduplicated[index] = path;
+                }
+            },
+            None => {
+                seen.insert(identify(&path), deduplicated.len());
+                deduplicated.push(path);
+            }
+        }
+    }
+
+    deduplicated
+}
+
+/// Count the symbolic links a path (or its container, for member paths)
+/// passes through.
+fn count_links(path: &str) -> usize
+{
+    let file = chain::container_path(path).unwrap_or(path);
+
+    Path::new(file)
+        .ancestors()
+        .filter(|ancestor| fs::symlink_metadata(ancestor).map(|md| md.file_type().is_symlink()).unwrap_or(false))
+        .count()
+}
+
+/// Identify the file (or container member) a path refers to, so that
+/// differently spelled paths to the same file compare equal.
+fn identify(path: &str) -> String
+{
+    let (file, member) = match chain::container_path(path)
+    {
+        Some(container) => (container, &path[container.len()..]),
+        None => (path, "")
+    };
+
+    match identify_file(file)
+    {
+        Some(identity) => format!("{}{}", identity, member),
+        None => path.to_string()
+    }
+}
+
+/// Identify a file by its device and inode, which also catches hard links.
+#[cfg(unix)]
+fn identify_file(path: &str) -> Option<String>
+{
+    use std::os::unix::fs::MetadataExt;
+
+    fs::metadata(path).ok().map(|md| format!("{}:{}", md.dev(), md.ino()))
+}
+
+/// Identify a file by its canonical path.
+#[cfg(not(unix))]
+fn identify_file(path: &str) -> Option<String>
+{
+    fs::canonicalize(path).ok().map(|path| path.display().to_string())
+}
+
+/**
+ * Add existing sibling files (see SIBLING_HINTS) which are missing from
+ * the expanded paths.
+ */
+fn add_siblings(paths: Vec<String>) -> Vec<String>
+{
+    let mut known: HashSet<String> = paths.iter().cloned().collect();
+    let mut with_siblings = vec![];
+
+    for path in paths
+    {
+        let siblings: Vec<String> = SIBLING_HINTS.iter()
+            .filter(|(suffix, _)| path.ends_with(suffix))
+            .map(|(suffix, sibling)| format!("{}{}", &path[..path.len() - suffix.len()], sibling))
+            .collect();
+
+        with_siblings.push(path);
+
+        for sibling in siblings
+        {
+            if !known.contains(&sibling) && Path::new(&sibling).is_file()
+            {
+                known.insert(sibling.clone());
+                with_siblings.push(sibling);
+            }
+        }
+    }
+
+    with_siblings
+}
+
+/**
+ * Determine whether a file exceeds the maximum file size (if any), logging
+ * skipped files.
+ */
+fn exceeds_max_file_size(path: &str, size: u64, options: &Options) -> bool
+{
+    if options.max_file_size == 0 || size <= options.max_file_size
+    {
+        return false;
+    }
+
+    info!("Skipping {} ({} bytes exceeds the maximum file size of {} bytes).", path, size, options.max_file_size);
+
+    true
+}
+
+/**
+ * Determine whether a file found when walking a directory should be
+ * processed, based on its extension.
+ */
+fn is_candidate(path: &str, options: &Options) -> bool
+{
+    if options.all_files
+    {
+        return true;
+    }
+
+    let extension = match Path::new(path).extension()
+    {
+        Some(extension) => extension.to_string_lossy().to_lowercase(),
+
+        // Keys often live in files without an extension (e.g. "server"),
+        // so check their contents instead.
+        None => return looks_like_candidate(path)
+    };
+
+    if CANDIDATE_EXTENSIONS.contains(&extension.as_str())
+        || (options.archives && archive::is_archive(path))
+        || manifest::is_manifest(path)
+    {
+        return true;
+    }
+
+    #[cfg(feature = "nss")]
+    if nss::is_database(path)
+    {
+        return true;
+    }
+
+    false
+}
+
+/**
+ * Sniff the start of a file for a PEM header or a DER structure.
+ */
+fn looks_like_candidate(path: &str) -> bool
+{
+    let mut head = vec![];
+
+    if fs::File::open(path).and_then(|file| file.take(SNIFF_LENGTH).read_to_end(&mut head)).is_err()
+    {
+        return false;
+    }
+
+    // PEM (including OpenSSH) files contain an armor header, possibly
+    // after some explanatory text.
+    if String::from_utf8_lossy(&head).contains("-----BEGIN ")
+    {
+        return true;
+    }
+
+    // DER files start with a SEQUENCE using a long form length.
+    head.len() >= 2 && head[0] == 0x30 && (0x81..=0x84).contains(&head[1])
+}
+
+/**
+ * Expand a single file path, replacing containers (archives, certificate
+ * databases) with the paths of their members.
+ */
+fn expand_file(path: &str, options: &Options) -> Vec<String>
+{
+    // Optionally replace archives with their members.
+    if options.archives && archive::is_archive(path)
+    {
+        return archive::members(path, options.max_file_size).unwrap_or_default();
+    }
+
+    #[cfg(feature = "nss")]
+    if nss::is_database(path)
+    {
+        return nss::members(path).unwrap_or_default();
+    }
+
+    // Replace Kubernetes Secret manifests with their key and certificate
+    // fields. Other YAML and JSON files (and those which can't be parsed)
+    // are kept, so they're reported as skipped with the reason.
+    if manifest::is_manifest(path)
+    {
+        return manifest::members(path).unwrap_or_else(|_| vec![path.to_string()]);
+    }
+
+    vec![path.to_string()]
+}
+
+/**
+ * Read a list of paths from a file, or standard input ("-").
+ *
+ * Lists are NUL-delimited when they contain any NUL characters (e.g. from
+ * `find -print0`), and newline-delimited otherwise. Paths needn't be valid
+ * UTF-8.
+ */
+fn read_file_list(path: &str) -> Result<Vec<PathBuf>, io::Error>
+{
+    let mut contents = vec![];
+
+    if path == "-"
+    {
+        io::stdin().read_to_end(&mut contents)?;
+    }
+    else
+    {
+        contents = fs::read(path).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path, e)))?;
+    }
+
+    Ok(split_file_list(&contents))
+}
+
+/// Split a list of paths on NUL characters or newlines.
+fn split_file_list(contents: &[u8]) -> Vec<PathBuf>
+{
+    let delimiter = if contents.contains(&b'\0') { b'\0' } else { b'\n' };
+
+    contents.split(|&byte| byte == delimiter)
+        .map(|line| if delimiter == b'\n' { line.strip_suffix(b"\r").unwrap_or(line) } else { line })
+        .filter(|line| !line.is_empty())
+        .map(to_path)
+        .collect()
+}
+
+/// Convert bytes read from a file list to a path.
+#[cfg(unix)]
+fn to_path(bytes: &[u8]) -> PathBuf
+{
+    use std::os::unix::ffi::OsStrExt;
+
+    PathBuf::from(std::ffi::OsStr::from_bytes(bytes))
+}
+
+/// Convert bytes read from a file list to a path (elsewhere, paths must be
+/// valid UTF-8).
+#[cfg(not(unix))]
+fn to_path(bytes: &[u8]) -> PathBuf
+{
+    PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+}
+
+/**
+ * Expand command line arguments.
+ *
+ * This method is separated for testability.
+ */
+fn expand(args: Vec<String>, options: &Options, warnings: &mut Vec<(String, String)>) -> Result<Vec<String>, io::Error>
+{
+    let mut expanded: Vec<String> = vec![];
+
+    // Directories visited so far, so those reached again through symbolic
+    // links aren't walked twice.
+    let mut directories = HashSet::new();
+
+    // If the recursive option is used, expand arguments
+    // by walking the filesystem hierarchy from each argument.
+    for arg in args
+    {
+        // A lone dash refers to content piped in on standard input.
+        if arg == "-"
+        {
+            expanded.push(chain::STDIN_PATH.to_string());
+
+            continue;
+        }
+
+        // Explicitly passed streams (FIFOs, e.g. from process substitution,
+        // and character devices) aren't regular files, so aren't walked.
+        if chain::is_stream(&arg)
+        {
+            expanded.push(arg);
+
+            continue;
+        }
+
+        // println!("{}", arg.to_string());
+        for entry in WalkDir::new(arg.to_string())
+
+            // Optionally follow symbolic links.
+            .follow_links(options.follow_symlinks)
+
+            // Optionally recurse up to 100 directories deep.
+            .max_depth(if options.recursive { 100 } else { 1 })
+
+            // Optionally cross filesystem boundaries.
+            .same_file_system(options.same_file_system)
+
+            // Convert to an Iterator.
+            .into_iter()
+
+            // Optionally include hidden files, and skip pruned directories
+            // and those already visited.
+            .filter_entry(|e| {
+                (options.include_hidden_files || !is_hidden(e))
+                    && !is_pruned(e, options)
+                    && !is_revisited(e, &mut directories)
+            })
+        {
+            // Record inaccessible files and directories, and move on.
+            let entry = match entry
+            {
+                Ok(entry) => entry,
+                Err(e) if e.loop_ancestor().is_some() => {
+                    debug!("Skipping {} (symbolic link loop)", e.path().unwrap_or(Path::new(&arg)).display());
+
+                    continue;
+                },
+                Err(e) => {
+                    let path = e.path().map(|p| p.display().to_string()).unwrap_or_else(|| arg.clone());
+                    let reason = e.io_error().map(|io| io.to_string()).unwrap_or_else(|| e.to_string());
+
+                    warnings.push((path, reason));
+
+                    continue;
+                }
+            };
+
+            // Add only files to the expanded items list.
+            if let Ok(md) = entry.metadata()
+            {
+                let path = match entry.path().to_str()
+                {
+                    Some(path) => path,
+                    None => {
+                        warnings.push((entry.path().display().to_string(), Error::InvalidPath.to_string()));
+
+                        continue;
+                    }
+                };
+
+                // Files passed as arguments are always processed, while
+                // files found inside directories must look like candidates.
+                if md.is_file() && entry.depth() > 0 && !is_candidate(path, options)
+                {
+                    debug!("Skipping {} (not a likely key, request, or certificate file).", path);
+                }
+                else if md.is_file() && !exceeds_max_file_size(path, md.len(), options)
+                {
+                    match fs::File::open(path)
+                    {
+                        Ok(_) => expanded.extend(expand_file(path, options)),
+                        Err(e) => warnings.push((path.to_string(), e.to_string()))
+                    }
+                }
+            }
+
+            // Stop walking once the file limit is exceeded, and continue
+            // with the paths found so far.
+            if options.max_files > 0 && expanded.len() > options.max_files
+            {
+                expanded.truncate(options.max_files);
+
+                eprintln!(
+                    "File limit ({} paths) reached; results are incomplete. Use --max-files N or -U to raise the limit.",
+                    options.max_files
+                );
+
+                return Ok(expanded);
+            }
+        }
+    }
+
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+    use crate::options::Options;
+
+    #[test]
+    fn expands_arguments()
+    {
+        let args = vec![
+            String::from("test"),
+            String::from("Cargo.toml"),
+        ];
+
+        let opts = Options {
+            all_files: true,
+            ..Options::default()
+        };
+
+        match expand(args, &opts, &mut vec![])
+        {
+            Ok(x) => {
+                assert_eq!(
+                    x,
+                    vec![
+                        String::from("test/file2"),
+                        String::from("test/file1"),
+                        String::from("Cargo.toml"),
+                    ]
+                );
+            },
+            Err(x) => assert!(false, "{}", x)
+        }
+    }
+
+    #[test]
+    fn expands_arguments_recursively()
+    {
+        let args = vec![
+            String::from("test"),
+            String::from("Cargo.toml"),
+        ];
+
+        let opts = Options {
+            all_files: true,
+            recursive: true,
+            ..Options::default()
+        };
+
+        match expand(args, &opts, &mut vec![])
+        {
+            Ok(x) => {
+                assert_eq!(
+                    x,
+                    vec![
+                        String::from("test/file2"),
+                        String::from("test/dir/file3"),
+                        String::from("test/dir/file4"),
+                        String::from("test/file1"),
+                        String::from("Cargo.toml"),
+                    ]
+                );
+            },
+            Err(x) => assert!(false, "{}", x)
+        }
+    }
 
-    for arg in env::args().skip(options.index) // Skip executable name and options.
+    #[test]
+    fn prunes_directories()
     {
-        args.push(arg);
+        let args = vec![
+            String::from("test"),
+        ];
+
+        let opts = Options {
+            all_files: true,
+            recursive: true,
+            prune: vec![String::from("dir")],
+            ..Options::default()
+        };
+
+        match expand(args, &opts, &mut vec![])
+        {
+            Ok(mut x) => {
+                x.sort();
+
+                assert_eq!(
+                    x,
+                    vec![
+                        String::from("test/file1"),
+                        String::from("test/file2"),
+                    ]
+                );
+            },
+            Err(x) => assert!(false, "{}", x)
+        }
+
+        // Explicitly passed directories are still processed.
+        match expand(vec![String::from("test/dir")], &opts, &mut vec![])
+        {
+            Ok(x) => assert_eq!(x.len(), 2),
+            Err(x) => assert!(false, "{}", x)
+        }
     }
 
-    if args.len() == 0
+    #[test]
+    fn refreshes_changed_paths()
     {
-        args.push(".".to_string());
-    }
+        let directory = std::env::temp_dir().join(format!("sslchains-test-{}.refresh", std::process::id()));
+        let path = |name: &str| directory.join(name).to_str().unwrap().to_string();
 
-    expand(args, options)
-}
+        fs::create_dir_all(directory.join("sub")).unwrap();
 
-/**
- * Expand command line arguments.
- *
- * This method is separated for testability.
- */
-fn expand(args: Vec<String>, options: &Options) -> Result<Vec<String>, io::Error>
-{
-    let mut expanded: Vec<String> = vec![];
+        for name in ["a.crt", "b.crt", "sub/c.crt"]
+        {
+            fs::write(path(name), "").unwrap();
+        }
 
-    // If the recursive option is used, expand arguments
-    // by walking the filesystem hierarchy from each argument.
-    for arg in args
-    {
-        // println!("{}", arg.to_string());
-        for entry in WalkDir::new(arg.to_string())
+        let opts = Options {
+            arguments: vec![path("")],
+            recursive: true,
+            ..Options::default()
+        };
 
-            // Optionally follow symbolic links.
-            .follow_links(options.follow_symlinks)
+        let arguments = Arguments::read(&opts).unwrap();
+        let paths = arguments.expand(&opts, &mut vec![]).unwrap();
 
-            // Optionally recurse up to 100 directories deep.
-            .max_depth(if options.recursive { 100 } else { 1 })
+        // Only the changes are walked: b.crt is kept even though it's gone.
+        fs::remove_file(path("a.crt")).unwrap();
+        fs::remove_file(path("b.crt")).unwrap();
+        fs::create_dir_all(directory.join("new")).unwrap();
 
-            // Optionally cross filesystem boundaries.
-            .same_file_system(options.same_file_system)
+        for name in ["d.crt", "d.txt", ".e.crt", "new/f.crt"]
+        {
+            fs::write(path(name), "").unwrap();
+        }
 
-            // Convert to an Iterator.
-            .into_iter()
+        let changes = ["a.crt", "d.crt", "d.txt", ".e.crt", "new"].map(|name| directory.join(name));
+        let changes: Vec<&Path> = changes.iter().map(|change| change.as_path()).collect();
+
+        let mut refreshed = arguments.refresh(&paths, &changes, &opts, &mut vec![]).unwrap();
+
+        fs::remove_dir_all(&directory).unwrap();
+
+        refreshed.sort();
+
+        assert_eq!(refreshed, vec![path("b.crt"), path("d.crt"), path("new/f.crt"), path("sub/c.crt")]);
+    }
+
+    #[test]
+    fn stops_at_max_files()
+    {
+        let args = vec![
+            String::from("test"),
+            String::from("Cargo.toml"),
+        ];
 
-            // Optionally include hidden files.
-            .filter_entry(|e| options.include_hidden_files || !is_hidden(e))
+        let opts = Options {
+            all_files: true,
+            recursive: true,
+            max_files: 2,
+            ..Options::default()
+        };
 
-            // Skip inaccessible files.
-            .filter_map(|e| e.ok())
+        match expand(args, &opts, &mut vec![])
         {
-            // Add only files to the expanded items list.
-            if let Ok(md) = entry.metadata()
-            {
-                if md.is_file()
-                {
-                    expanded.push(entry.path().to_str().unwrap().to_string());
-                }
-            }
+            Ok(x) => assert_eq!(x.len(), 2),
+            Err(x) => assert!(false, "{}", x)
+        }
+    }
 
-            // Do not continue if expanded file listing exceeds limit.
-            if expanded.len() > 10_000 && !options.disable_file_limit
-            {
-                eprintln!("File count (10,000 paths) exceeded. Try using path arguments which contain fewer files.");
-                std::process::exit(5);
-            }
+    #[test]
+    fn records_inaccessible_paths()
+    {
+        let args = vec![
+            String::from("test/missing"),
+            String::from("Cargo.toml"),
+        ];
+
+        let mut warnings = vec![];
+
+        match expand(args, &Options::default(), &mut warnings)
+        {
+            Ok(x) => assert_eq!(x, vec![String::from("Cargo.toml")]),
+            Err(x) => assert!(false, "{}", x)
         }
+
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].0, "test/missing");
+        assert!(warnings[0].1.contains("No such file or directory"));
     }
 
-    Ok(expanded)
-}
+    #[cfg(unix)]
+    #[test]
+    fn records_non_utf8_paths()
+    {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
 
-#[cfg(test)]
-mod test
-{
-    use super::*;
-    use crate::options::{ Options, OptionsDisplayMode };
+        let directory = std::env::temp_dir().join(format!("sslchains-test-{}.utf8", std::process::id()));
+
+        fs::create_dir_all(&directory).unwrap();
+        fs::copy("samples/ca_signed.crt", directory.join(OsStr::from_bytes(b"invalid-\xff.crt"))).unwrap();
+        fs::copy("samples/ca_signed.crt", directory.join("valid.crt")).unwrap();
+
+        let mut warnings = vec![];
+
+        let expanded = expand(vec![directory.to_str().unwrap().to_string()], &Options::default(), &mut warnings);
+
+        fs::remove_dir_all(&directory).unwrap();
+
+        match expanded
+        {
+            Ok(x) => assert_eq!(x, vec![directory.join("valid.crt").to_str().unwrap().to_string()]),
+            Err(x) => assert!(false, "{}", x)
+        }
+
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].1, "path isn't valid UTF-8");
+    }
 
     #[test]
-    fn expands_arguments()
+    fn expands_arguments_with_hidden()
     {
         let args = vec![
             String::from("test"),
@@ -113,17 +1013,12 @@ mod test
         ];
 
         let opts = Options {
-            print_help: false,
-            display_mode: OptionsDisplayMode::Default,
-            follow_symlinks: false,
-            include_hidden_files: false,
-            recursive: false,
-            same_file_system: true,
-            suppress_oneline_header: false,
-            index: 0
+            all_files: true,
+            include_hidden_files: true,
+            ..Options::default()
         };
 
-        match expand(args, &opts)
+        match expand(args, &opts, &mut vec![])
         {
             Ok(x) => {
                 assert_eq!(
@@ -131,6 +1026,7 @@ mod test
                     vec![
                         String::from("test/file2"),
                         String::from("test/file1"),
+                        String::from("test/.hidden_file"),
                         String::from("Cargo.toml"),
                     ]
                 );
@@ -140,35 +1036,216 @@ mod test
     }
 
     #[test]
-    fn expands_arguments_recursively()
+    fn expands_stdin_argument()
     {
         let args = vec![
-            String::from("test"),
+            String::from("-"),
             String::from("Cargo.toml"),
         ];
 
+        match expand(args, &Options::default(), &mut vec![])
+        {
+            Ok(x) => {
+                assert_eq!(
+                    x,
+                    vec![
+                        String::from("(stdin)"),
+                        String::from("Cargo.toml"),
+                    ]
+                );
+            },
+            Err(x) => assert!(false, "{}", x)
+        }
+    }
+
+    #[test]
+    fn splits_file_lists()
+    {
+        assert_eq!(
+            split_file_list(b"samples/ca_signed.key\nsamples/ca signed.crt\r\n\n"),
+            vec![
+                PathBuf::from("samples/ca_signed.key"),
+                PathBuf::from("samples/ca signed.crt"),
+            ]
+        );
+
+        assert_eq!(
+            split_file_list(b"samples/ca_signed.key\0samples/ca\nsigned.crt\0"),
+            vec![
+                PathBuf::from("samples/ca_signed.key"),
+                PathBuf::from("samples/ca\nsigned.crt"),
+            ]
+        );
+    }
+
+    #[test]
+    fn expands_listed_files()
+    {
+        let list = std::env::temp_dir().join(format!("sslchains-test-{}.list", std::process::id()));
+
+        fs::write(&list, b"samples/kubernetes/secret.yaml\nsamples/archives/ca_signed.zip\nsamples/ca_signed.key\n\xff.crt\n").unwrap();
+
+        let opts = Options {
+            files_from: Some(list.to_str().unwrap().to_string()),
+            archives: true,
+            ..Options::default()
+        };
+
+        let arguments = Arguments::read(&opts);
+
+        fs::remove_file(&list).unwrap();
+
+        let mut warnings = vec![];
+
+        match arguments.and_then(|arguments| arguments.expand(&opts, &mut warnings))
+        {
+            Ok(x) => assert_eq!(
+                x,
+                vec![
+                    String::from("samples/kubernetes/secret.yaml:tls.crt"),
+                    String::from("samples/kubernetes/secret.yaml:tls.key"),
+                    String::from("samples/archives/ca_signed.zip:ca_signed.key"),
+                    String::from("samples/archives/ca_signed.zip:ca_signed.crt"),
+                    String::from("samples/archives/ca_signed.zip:intermediate_ca.crt"),
+                    String::from("samples/ca_signed.key"),
+                ]
+            ),
+            Err(x) => assert!(false, "{}", x)
+        }
+
+        // Paths which aren't UTF-8 are recorded, rather than dropped.
+        if cfg!(unix)
+        {
+            assert_eq!(warnings.len(), 1);
+            assert_eq!(warnings[0].1, Error::InvalidPath.to_string());
+        }
+    }
+
+    #[test]
+    fn adds_sibling_files()
+    {
+        assert_eq!(
+            add_siblings(vec![String::from("samples/haproxy/example.com.pem")]),
+            vec![
+                String::from("samples/haproxy/example.com.pem"),
+                String::from("samples/haproxy/example.com.pem.key"),
+            ]
+        );
+
+        assert_eq!(
+            add_siblings(vec![String::from("samples/haproxy/example.com.pem.key")]),
+            vec![
+                String::from("samples/haproxy/example.com.pem.key"),
+                String::from("samples/haproxy/example.com.pem"),
+            ]
+        );
+
+        // Siblings which are already present, or don't exist, aren't added.
+        assert_eq!(
+            add_siblings(vec![
+                String::from("samples/haproxy/example.com.pem.key"),
+                String::from("samples/haproxy/example.com.pem"),
+                String::from("samples/self_signed.pem"),
+            ]),
+            vec![
+                String::from("samples/haproxy/example.com.pem.key"),
+                String::from("samples/haproxy/example.com.pem"),
+                String::from("samples/self_signed.pem"),
+            ]
+        );
+    }
+
+    #[test]
+    fn skips_files_without_candidate_extensions()
+    {
+        let args = vec![
+            String::from("samples"),
+            String::from("samples/self_signed.crt-old"),
+        ];
+
+        match expand(args, &Options::default(), &mut vec![])
+        {
+            Ok(x) => {
+                assert!(x.contains(&String::from("samples/ca_signed.key")));
+                assert!(x.contains(&String::from("samples/self_signed.crt-old")));
+                assert!(!x.contains(&String::from("samples/intermediate_ca.srl")));
+                assert_eq!(x.iter().filter(|path| path.ends_with(".crt-old")).count(), 1);
+            },
+            Err(x) => assert!(false, "{}", x)
+        }
+    }
+
+    #[test]
+    fn deduplicates_paths()
+    {
+        let paths = vec![
+            String::from("samples/ca_signed.key"),
+            String::from("samples/../samples/ca_signed.key"),
+            String::from("./samples/ca_signed.crt"),
+            String::from("samples/ca_signed.crt"),
+            String::from("samples/archives/ca_signed.zip:ca_signed.key"),
+            String::from("./samples/archives/ca_signed.zip:ca_signed.key"),
+            String::from("samples/archives/ca_signed.zip:ca_signed.crt"),
+            String::from("-"),
+            String::from("-"),
+        ];
+
+        assert_eq!(
+            deduplicate(paths),
+            vec![
+                String::from("samples/ca_signed.key"),
+                String::from("./samples/ca_signed.crt"),
+                String::from("samples/archives/ca_signed.zip:ca_signed.key"),
+                String::from("samples/archives/ca_signed.zip:ca_signed.crt"),
+                String::from("-"),
+            ]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn prefers_real_paths_to_symbolic_links()
+    {
+        let args = vec![
+            String::from("samples/symlinks"),
+        ];
+
         let opts = Options {
-            print_help: false,
-            display_mode: OptionsDisplayMode::Default,
-            follow_symlinks: false,
-            include_hidden_files: false,
             recursive: true,
-            same_file_system: true,
-            suppress_oneline_header: false,
-            index: 0
+            follow_symlinks: true,
+            ..Options::default()
         };
 
-        match expand(args, &opts)
+        let mut warnings = vec![];
+
+        match expand(args, &opts, &mut warnings)
+        {
+            Ok(x) => assert_eq!(deduplicate(x), vec![String::from("samples/symlinks/certs/self_signed.pem")]),
+            Err(x) => assert!(false, "{}", x)
+        }
+
+        // Loops are skipped quietly.
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn expands_dash_prefixed_paths()
+    {
+        let args = vec![
+            String::from("samples/dashes"),
+            String::from("-"),
+            String::from("samples/dashes/-self_signed.pem"),
+        ];
+
+        match expand(args, &Options::default(), &mut vec![])
         {
             Ok(x) => {
                 assert_eq!(
                     x,
                     vec![
-                        String::from("test/file2"),
-                        String::from("test/dir/file3"),
-                        String::from("test/dir/file4"),
-                        String::from("test/file1"),
-                        String::from("Cargo.toml"),
+                        String::from("samples/dashes/-self_signed.pem"),
+                        String::from(chain::STDIN_PATH),
+                        String::from("samples/dashes/-self_signed.pem"),
                     ]
                 );
             },
@@ -177,34 +1254,101 @@ mod test
     }
 
     #[test]
-    fn expands_arguments_with_hidden()
+    fn sniffs_files_without_extensions()
+    {
+        let args = vec![String::from("samples/extensionless")];
+
+        match expand(args, &Options::default(), &mut vec![])
+        {
+            Ok(mut x) => {
+                x.sort();
+
+                assert_eq!(
+                    x,
+                    vec![
+                        String::from("samples/extensionless/server"),
+                        String::from("samples/extensionless/tls"),
+                    ]
+                );
+            },
+            Err(x) => assert!(false, "{}", x)
+        }
+    }
+
+    #[test]
+    fn expands_manifest_members()
+    {
+        let args = vec![String::from("samples/kubernetes/secret.yaml")];
+
+        match expand(args, &Options::default(), &mut vec![])
+        {
+            Ok(x) => assert_eq!(
+                x,
+                vec![
+                    String::from("samples/kubernetes/secret.yaml:tls.crt"),
+                    String::from("samples/kubernetes/secret.yaml:tls.key"),
+                ]
+            ),
+            Err(x) => assert!(false, "{}", x)
+        }
+    }
+
+    #[test]
+    fn skips_files_exceeding_max_file_size()
     {
         let args = vec![
-            String::from("test"),
-            String::from("Cargo.toml"),
+            String::from("samples/ca_signed.crt"),
+            String::from("samples/ca_signed.key"),
         ];
 
         let opts = Options {
-            print_help: false,
-            display_mode: OptionsDisplayMode::Default,
-            follow_symlinks: false,
-            include_hidden_files: true,
-            recursive: false,
-            same_file_system: true,
-            suppress_oneline_header: false,
-            index: 0
+            max_file_size: 1100,
+            ..Options::default()
+        };
+
+        match expand(args, &opts, &mut vec![])
+        {
+            Ok(x) => assert_eq!(x, vec![String::from("samples/ca_signed.crt")]),
+            Err(x) => assert!(false, "{}", x)
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn expands_stream_arguments()
+    {
+        let args = vec![
+            String::from("/dev/null"),
+        ];
+
+        match expand(args, &Options::default(), &mut vec![])
+        {
+            Ok(x) => assert_eq!(x, vec![String::from("/dev/null")]),
+            Err(x) => assert!(false, "{}", x)
+        }
+    }
+
+    #[test]
+    fn expands_archive_members()
+    {
+        let args = vec![
+            String::from("samples/archives/ca_signed.zip"),
+        ];
+
+        let opts = Options {
+            archives: true,
+            ..Options::default()
         };
 
-        match expand(args, &opts)
+        match expand(args, &opts, &mut vec![])
         {
             Ok(x) => {
                 assert_eq!(
                     x,
                     vec![
-                        String::from("test/file2"),
-                        String::from("test/file1"),
-                        String::from("test/.hidden_file"),
-                        String::from("Cargo.toml"),
+                        String::from("samples/archives/ca_signed.zip:ca_signed.key"),
+                        String::from("samples/archives/ca_signed.zip:ca_signed.crt"),
+                        String::from("samples/archives/ca_signed.zip:intermediate_ca.crt"),
                     ]
                 );
             },
@@ -0,0 +1,448 @@
+//! Copyright (C) 2022 Gaz J.
+//!
+//! This program is free software: you can redistribute it and/or modify
+//! it under the terms of the GNU General Public License as published by
+//! the Free Software Foundation, either version 3 of the License, or
+//! (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//! GNU General Public License for more details.
+//!
+//! You should have received a copy of the GNU General Public License
+//! along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! AWS Certificate Manager (--acm) and Secrets Manager (--aws-secret): the
+//! certificates and chains uploaded to (or issued by) ACM, and keys and
+//! certificates kept as secrets, read so they can be matched with local
+//! keys, e.g. to check that a load balancer's certificate corresponds to a
+//! key on hand.
+//!
+//! Requests are signed (Signature Version 4) with credentials from the
+//! environment (AWS_ACCESS_KEY_ID, AWS_SECRET_ACCESS_KEY, and
+//! AWS_SESSION_TOKEN), or the shared credentials file (~/.aws/credentials,
+//! or AWS_SHARED_CREDENTIALS_FILE, for AWS_PROFILE). Instance roles and SSO
+//! aren't supported.
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use openssl::asn1::Asn1Time;
+use openssl::base64;
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::sha::sha256;
+use openssl::sign::Signer;
+use serde_json::{json, Value};
+
+use crate::format;
+use crate::http::{self, Credentials, Url};
+
+/// Prefixes the names of ACM certificates, e.g. "acm:us-east-1/ID/certificate.pem".
+pub const ACM_PREFIX: &str = "acm:";
+
+/// Prefixes the names of secrets, e.g. "secretsmanager:us-east-1/NAME".
+pub const SECRETS_MANAGER_PREFIX: &str = "secretsmanager:";
+
+/// Key types ACM lists (it lists only RSA 2048 certificates otherwise).
+const KEY_TYPES: [&str; 7] = ["RSA_1024", "RSA_2048", "RSA_3072", "RSA_4096", "EC_prime256v1", "EC_secp384r1", "EC_secp521r1"];
+
+const CONTENT_TYPE: &str = "application/x-amz-json-1.1";
+
+/// (name, contents) pairs, as read from ACM and Secrets Manager.
+pub type Contents = Vec<(String, Vec<u8>)>;
+
+/// An access key, as requests are signed with.
+#[derive(Debug, PartialEq)]
+pub struct AccessKey
+{
+    pub id: String,
+    pub secret: String,
+    pub session_token: Option<String>,
+}
+
+impl AccessKey
+{
+    /// Find an access key in the environment, or the shared credentials
+    /// file.
+    pub fn find() -> io::Result<AccessKey>
+    {
+        if let (Ok(id), Ok(secret)) = (env::var("AWS_ACCESS_KEY_ID"), env::var("AWS_SECRET_ACCESS_KEY"))
+        {
+            return Ok(AccessKey { id, secret, session_token: env::var("AWS_SESSION_TOKEN").ok() });
+        }
+
+        let path = match (env::var_os("AWS_SHARED_CREDENTIALS_FILE"), env::var_os("HOME"))
+        {
+            (Some(path), _) => PathBuf::from(path),
+            (None, Some(home)) => PathBuf::from(home).join(".aws/credentials"),
+            (None, None) => return Err(io::Error::new(io::ErrorKind::NotFound, "no AWS credentials found"))
+        };
+
+        let profile = env::var("AWS_PROFILE").unwrap_or_else(|_| String::from("default"));
+        let contents = fs::read_to_string(&path).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+
+        parse_credentials(&contents, &profile)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{}: no access key for profile {}", path.display(), profile)))
+    }
+}
+
+/// Signs requests to AWS services in a region.
+pub struct Client
+{
+    pub region: String,
+    access_key: AccessKey,
+
+    /// Where requests are sent in place of the services' regional
+    /// endpoints, e.g. "http://127.0.0.1:4566" (for tests, or LocalStack).
+    pub endpoint: Option<String>,
+}
+
+impl Client
+{
+    /// A client for a region (by default, AWS_REGION or AWS_DEFAULT_REGION).
+    pub fn new(region: Option<&str>) -> io::Result<Client>
+    {
+        let region = region.map(String::from)
+            .or_else(|| env::var("AWS_REGION").ok())
+            .or_else(|| env::var("AWS_DEFAULT_REGION").ok())
+            .filter(|region| !region.is_empty())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no AWS region given (use --aws-region, or set AWS_REGION)"))?;
+
+        Ok(Client { region, access_key: AccessKey::find()?, endpoint: None })
+    }
+
+    /// Every certificate in ACM, with its chain, as (name, PEM) pairs, and
+    /// (ARN, reason) pairs for certificates which couldn't be read (e.g.
+    /// those pending validation).
+    pub fn acm_certificates(&self, timeout: Duration) -> io::Result<(Contents, Vec<(String, String)>)>
+    {
+        let mut arns = vec![];
+        let mut next: Option<String> = None;
+
+        loop
+        {
+            let mut request = json!({ "Includes": { "keyTypes": KEY_TYPES } });
+
+            if let Some(token) = &next
+            {
+                request["NextToken"] = json!(token);
+            }
+
+            let response = self.call("acm", "CertificateManager.ListCertificates", &request, timeout)?;
+
+            for summary in response["CertificateSummaryList"].as_array().into_iter().flatten()
+            {
+                arns.extend(summary["CertificateArn"].as_str().map(String::from));
+            }
+
+            match response["NextToken"].as_str()
+            {
+                Some(token) if !token.is_empty() => next = Some(token.to_string()),
+                _ => break
+            }
+        }
+
+        let mut certificates = vec![];
+        let mut failures = vec![];
+
+        for arn in arns
+        {
+            // Certificates are named by their IDs, the last part of their ARNs.
+            let id = arn.rsplit('/').next().unwrap_or(&arn);
+            let name = format!("{}{}/{}", ACM_PREFIX, self.region, id);
+
+            match self.call("acm", "CertificateManager.GetCertificate", &json!({ "CertificateArn": arn }), timeout)
+            {
+                Ok(response) => {
+                    for (field, file) in [("Certificate", "certificate.pem"), ("CertificateChain", "chain.pem")]
+                    {
+                        if let Some(pem) = response[field].as_str()
+                        {
+                            certificates.push((format!("{}/{}", name, file), pem.as_bytes().to_vec()));
+                        }
+                    }
+                },
+                Err(e) => failures.push((arn.clone(), format!("couldn't get certificate: {}", e)))
+            }
+        }
+
+        Ok((certificates, failures))
+    }
+
+    /// A secret's contents, as (name, contents) pairs: the secret itself,
+    /// or each field of a secret holding a JSON object (e.g. {"tls.crt":
+    /// "...", "tls.key": "..."}).
+    pub fn secret(&self, id: &str, timeout: Duration) -> io::Result<Contents>
+    {
+        let response = self.call("secretsmanager", "secretsmanager.GetSecretValue", &json!({ "SecretId": id }), timeout)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", id, e)))?;
+
+        let secret_name = response["Name"].as_str().unwrap_or(id);
+        let name = format!("{}{}/{}", SECRETS_MANAGER_PREFIX, self.region, secret_name);
+
+        if let Some(binary) = response["SecretBinary"].as_str()
+        {
+            let contents = base64::decode_block(binary).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", id, e)))?;
+
+            return Ok(vec![(name, contents)]);
+        }
+
+        let string = response["SecretString"].as_str().unwrap_or_default();
+
+        match serde_json::from_str::<Value>(string)
+        {
+            Ok(Value::Object(fields)) => Ok(fields.iter()
+                .filter_map(|(field, value)| value.as_str().map(|value| (format!("{}/{}", name, field), value.as_bytes().to_vec())))
+                .collect()),
+            _ => Ok(vec![(name, string.as_bytes().to_vec())])
+        }
+    }
+
+    /// Make a (JSON protocol) request of a service, returning its response.
+    fn call(&self, service: &str, target: &str, request: &Value, timeout: Duration) -> io::Result<Value>
+    {
+        let endpoint = match &self.endpoint
+        {
+            Some(endpoint) => format!("{}/", endpoint.trim_end_matches('/')),
+            None => format!("https://{}.{}.amazonaws.com/", service, self.region)
+        };
+
+        let url = Url::parse(&endpoint).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("unsupported URL {}", endpoint)))?;
+        let body = request.to_string().into_bytes();
+
+        let headers = vec![
+            (String::from("Content-Type"), String::from(CONTENT_TYPE)),
+            (String::from("X-Amz-Target"), target.to_string()),
+        ];
+
+        let credentials = Credentials {
+            connector: None,
+            headers: sign(&self.access_key, "POST", &url, &self.region, service, headers, &body, &timestamp()),
+        };
+
+        let response = http::post_with(&endpoint, CONTENT_TYPE, &body, &credentials, timeout)?;
+
+        serde_json::from_slice(&response).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+/// Read an access key from a shared credentials file's profile.
+fn parse_credentials(contents: &str, profile: &str) -> Option<AccessKey>
+{
+    let mut section = String::new();
+    let (mut id, mut secret, mut session_token) = (None, None, None);
+
+    for line in contents.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with(['#', ';']))
+    {
+        if let Some(name) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']'))
+        {
+            section = name.trim().to_string();
+
+            continue;
+        }
+
+        if section != profile
+        {
+            continue;
+        }
+
+        match line.split_once('=').map(|(name, value)| (name.trim(), value.trim().to_string()))
+        {
+            Some(("aws_access_key_id", value)) => id = Some(value),
+            Some(("aws_secret_access_key", value)) => secret = Some(value),
+            Some(("aws_session_token", value)) => session_token = Some(value),
+            _ => ()
+        }
+    }
+
+    Some(AccessKey { id: id?, secret: secret?, session_token })
+}
+
+/// The time now, as requests are signed with (e.g. "20150830T123600Z").
+fn timestamp() -> String
+{
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|now| now.as_secs() as i64).unwrap_or(0);
+
+    Asn1Time::from_unix(now).map(|now| format::format_time(&now).replace(['-', ':'], "")).unwrap_or_default()
+}
+
+/// Sign a request (Signature Version 4), returning its headers with those
+/// the signature adds: Host, X-Amz-Date, X-Amz-Security-Token (for
+/// temporary credentials), and Authorization. Host is sent anyway, so is
+/// left out.
+#[allow(clippy::too_many_arguments)]
+fn sign(key: &AccessKey, method: &str, url: &Url, region: &str, service: &str, mut headers: Vec<(String, String)>, body: &[u8], time: &str) -> Vec<(String, String)>
+{
+    headers.push((String::from("X-Amz-Date"), time.to_string()));
+    headers.extend(key.session_token.iter().map(|token| (String::from("X-Amz-Security-Token"), token.clone())));
+
+    let mut canonical_headers: Vec<(String, String)> = headers.iter()
+        .map(|(name, value)| (name.to_ascii_lowercase(), value.trim().to_string()))
+        .chain([(String::from("host"), url.authority())])
+        .collect();
+
+    canonical_headers.sort();
+
+    let signed_headers = canonical_headers.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>().join(";");
+    let (path, query) = url.path.split_once('?').unwrap_or((&url.path, ""));
+
+    let mut parameters: Vec<&str> = query.split('&').filter(|parameter| !parameter.is_empty()).collect();
+
+    parameters.sort();
+
+    let canonical_request = format!(
+        "{}\n{}\n{}\n{}\n{}\n{}",
+        method,
+        path,
+        parameters.join("&"),
+        canonical_headers.iter().map(|(name, value)| format!("{}:{}\n", name, value)).collect::<String>(),
+        signed_headers,
+        hex(&sha256(body))
+    );
+
+    let date = &time[..8];
+    let scope = format!("{}/{}/{}/aws4_request", date, region, service);
+    let string_to_sign = format!("AWS4-HMAC-SHA256\n{}\n{}\n{}", time, scope, hex(&sha256(canonical_request.as_bytes())));
+
+    let signing_key = [date, region, service, "aws4_request"].iter()
+        .fold(format!("AWS4{}", key.secret).into_bytes(), |key, part| hmac(&key, part.as_bytes()));
+
+    let signature = hex(&hmac(&signing_key, string_to_sign.as_bytes()));
+
+    headers.push((
+        String::from("Authorization"),
+        format!("AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}", key.id, scope, signed_headers, signature)
+    ));
+
+    headers
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8>
+{
+    PKey::hmac(key)
+        .and_then(|key| Signer::new(MessageDigest::sha256(), &key)?.sign_oneshot_to_vec(data))
+        .unwrap_or_default()
+}
+
+fn hex(bytes: &[u8]) -> String
+{
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+    use std::io::{BufRead, Read, Write};
+    use std::net::TcpListener;
+
+    fn example_key() -> AccessKey
+    {
+        AccessKey { id: String::from("AKIDEXAMPLE"), secret: String::from("wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY"), session_token: None }
+    }
+
+    #[test]
+    fn signs_requests()
+    {
+        // The example from AWS's Signature Version 4 documentation.
+        let url = Url::parse("https://iam.amazonaws.com/?Action=ListUsers&Version=2010-05-08").unwrap();
+        let headers = vec![(String::from("Content-Type"), String::from("application/x-www-form-urlencoded; charset=utf-8"))];
+
+        let signed = sign(&example_key(), "GET", &url, "us-east-1", "iam", headers, b"", "20150830T123600Z");
+
+        assert_eq!(signed[1], (String::from("X-Amz-Date"), String::from("20150830T123600Z")));
+        assert_eq!(signed[2].1, concat!(
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/iam/aws4_request, ",
+            "SignedHeaders=content-type;host;x-amz-date, ",
+            "Signature=5d672d79c15b13162d9279b0855cfba6789a8edb4c82c400e06b5924a6f2b5d7"
+        ));
+    }
+
+    #[test]
+    fn reads_shared_credentials()
+    {
+        let contents = "[default]\naws_access_key_id = AKIDEXAMPLE\naws_secret_access_key = secret\n\n[temporary]\n# Expires hourly.\naws_access_key_id=ASIAEXAMPLE\naws_secret_access_key=other\naws_session_token=token\n";
+
+        assert_eq!(parse_credentials(contents, "default").unwrap().secret, "secret");
+        assert_eq!(parse_credentials(contents, "temporary").unwrap().session_token.as_deref(), Some("token"));
+        assert!(parse_credentials(contents, "missing").is_none());
+    }
+
+    #[test]
+    fn reads_acm_certificates_and_secrets()
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let certificate = fs::read_to_string("samples/cross_signed/example.com.crt").unwrap();
+        let chain = fs::read_to_string("samples/cross_signed/intermediate_by_a.crt").unwrap();
+        let key = fs::read_to_string("samples/cross_signed/example.com.key").unwrap();
+
+        let responses = vec![
+            json!({ "CertificateSummaryList": [{ "CertificateArn": "arn:aws:acm:eu-west-1:123456789012:certificate/1111" }], "NextToken": "2" }),
+            json!({ "CertificateSummaryList": [{ "CertificateArn": "arn:aws:acm:eu-west-1:123456789012:certificate/2222" }] }),
+            json!({ "Certificate": certificate, "CertificateChain": chain }),
+            json!({ "__type": "RequestInProgressException" }),
+            json!({ "Name": "prod/tls", "SecretString": json!({ "tls.key": key }).to_string() }),
+        ];
+
+        let server = std::thread::spawn(move || {
+            let mut targets = vec![];
+
+            for (index, response) in responses.into_iter().enumerate()
+            {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = io::BufReader::new(&stream);
+                let mut line = String::new();
+                let mut length = 0;
+
+                // Read the headers, noting the target and body's length.
+                while reader.read_line(&mut line).unwrap() > 2
+                {
+                    if let Some((name, value)) = line.trim().split_once(": ")
+                    {
+                        match name
+                        {
+                            "X-Amz-Target" => targets.push(value.to_string()),
+                            "Content-Length" => length = value.parse().unwrap(),
+                            _ => ()
+                        }
+                    }
+
+                    line.clear();
+                }
+
+                reader.read_exact(&mut vec![0; length]).unwrap();
+
+                let status = if index == 3 { "400 Bad Request" } else { "200 OK" };
+                let body = response.to_string();
+
+                write!(stream, "HTTP/1.1 {}\r\nContent-Length: {}\r\n\r\n{}", status, body.len(), body).unwrap();
+            }
+
+            targets
+        });
+
+        let client = Client { region: String::from("eu-west-1"), access_key: example_key(), endpoint: Some(format!("http://{}", address)) };
+        let (certificates, failures) = client.acm_certificates(Duration::from_secs(5)).unwrap();
+
+        let names: Vec<&str> = certificates.iter().map(|(name, _)| name.as_str()).collect();
+
+        assert_eq!(names, vec!["acm:eu-west-1/1111/certificate.pem", "acm:eu-west-1/1111/chain.pem"]);
+        assert_eq!(failures, vec![(String::from("arn:aws:acm:eu-west-1:123456789012:certificate/2222"), String::from("couldn't get certificate: HTTP 400"))]);
+
+        let secret = client.secret("prod/tls", Duration::from_secs(5)).unwrap();
+
+        assert_eq!(secret, vec![(String::from("secretsmanager:eu-west-1/prod/tls/tls.key"), key.into_bytes())]);
+        assert_eq!(server.join().unwrap(), vec![
+            "CertificateManager.ListCertificates",
+            "CertificateManager.ListCertificates",
+            "CertificateManager.GetCertificate",
+            "CertificateManager.GetCertificate",
+            "secretsmanager.GetSecretValue",
+        ]);
+    }
+}
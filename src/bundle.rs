@@ -0,0 +1,409 @@
+//! Copyright (C) 2022 Gaz J.
+//!
+//! This program is free software: you can redistribute it and/or modify
+//! it under the terms of the GNU General Public License as published by
+//! the Free Software Foundation, either version 3 of the License, or
+//! (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//! GNU General Public License for more details.
+//!
+//! You should have received a copy of the GNU General Public License
+//! along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Repairing PEM bundles (--fix-bundle): a server's certificate followed by
+//! its intermediates, in order, once each. Intermediates the bundle lacks
+//! are taken from the files read; certificates which aren't part of the
+//! chain are left out. Keys (as in HAProxy's combined files) and other
+//! blocks (e.g. DH parameters) are kept, and text between blocks dropped.
+//!
+//! Splitting bundles (--split): each block in a file of its own, with
+//! certificates named for their subject, expiry, and serial number.
+
+use openssl::pkey::PKey;
+use openssl::x509::X509;
+
+use crate::chain::{self, Inventory};
+use crate::export;
+use crate::format;
+use crate::trust::{self, Status};
+
+/// Stop following issuers after this many (in case of a loop).
+const MAX_DEPTH: usize = 10;
+
+/// A repaired bundle.
+#[derive(Debug)]
+pub struct Repair
+{
+    pub contents: Vec<u8>,
+
+    /// What was wrong with the bundle (nothing, if it needn't change).
+    pub problems: Vec<String>,
+}
+
+/// A split bundle.
+#[derive(Debug)]
+pub struct Split
+{
+    pub parts: Vec<Part>,
+
+    /// What was unusual about the bundle, e.g. different blocks which would
+    /// have had the same name.
+    pub problems: Vec<String>,
+}
+
+/// A block of a split bundle.
+#[derive(Debug, PartialEq)]
+pub struct Part
+{
+    /// Its file name, e.g. "example.com_20300101_1000.pem".
+    pub name: String,
+
+    pub contents: Vec<u8>,
+
+    /// Whether it's a private key (to be readable only by its owner).
+    pub is_key: bool,
+}
+
+/// A PEM block, e.g. ("CERTIFICATE", "-----BEGIN CERTIFICATE-----\n...").
+struct Block
+{
+    label: String,
+    text: String,
+}
+
+/// Repair a bundle, with issuers from an inventory.
+pub fn repair(contents: &[u8], inventory: &Inventory) -> Result<Repair, String>
+{
+    let text = std::str::from_utf8(contents).map_err(|_| String::from("isn't a PEM file"))?;
+    let blocks = blocks(text);
+
+    let mut problems = vec![];
+    let mut certificates: Vec<X509> = vec![];
+    let mut keys = vec![];
+    let mut others = vec![];
+
+    for block in &blocks
+    {
+        match block.label.as_str()
+        {
+            "CERTIFICATE" => {
+                let certificate = X509::from_pem(block.text.as_bytes()).map_err(|e| format!("couldn't parse a certificate: {}", e))?;
+
+                if certificates.iter().any(|c| same(c, &certificate))
+                {
+                    problems.push(format!("held {} more than once", describe(&certificate)));
+                    continue;
+                }
+
+                certificates.push(certificate);
+            },
+            label if label.ends_with("PRIVATE KEY") => keys.push(block),
+            _ => others.push(block)
+        }
+    }
+
+    if certificates.is_empty()
+    {
+        return Err(String::from("holds no certificates"));
+    }
+
+    // The server's certificate is the one for the bundle's key, if it has
+    // one, otherwise the first which didn't sign another.
+    let key = keys.iter().find_map(|block| PKey::private_key_from_pem(block.text.as_bytes()).ok());
+    let key_digest = key.as_ref().and_then(|key| chain::key_digest(key));
+
+    let leaf = certificates.iter()
+        .position(|c| key_digest.is_some() && c.public_key().ok().and_then(|public_key| chain::key_digest(&public_key)) == key_digest)
+        .or_else(|| certificates.iter().position(|c| !certificates.iter().any(|other| !same(c, other) && chain::signs(c, other))))
+        .unwrap_or(0);
+
+    let mut used = vec![leaf];
+    let mut ordered = vec![certificates[leaf].clone()];
+
+    while ordered.len() < MAX_DEPTH
+    {
+        let current = ordered.last().unwrap().clone();
+
+        if trust::is_self_signed(&current)
+        {
+            break;
+        }
+
+        if let Some(index) = (0..certificates.len()).find(|index| !used.contains(index) && chain::signs(&certificates[*index], &current))
+        {
+            used.push(index);
+            ordered.push(certificates[index].clone());
+            continue;
+        }
+
+        let issuers = inventory.issuers_of(&current);
+
+        // Roots needn't be sent, as clients have them already.
+        if let Some(issuer) = issuers.iter().find(|issuer| !issuer.self_signed && !ordered.iter().any(|c| same(c, &issuer.certificate)))
+        {
+            problems.push(format!("lacked its intermediate {} (from {})", describe(&issuer.certificate), issuer.path));
+            ordered.push(issuer.certificate.clone());
+            continue;
+        }
+
+        let trusted = matches!(trust::system_status(&current), Some(Status::TrustedIssuer(_)));
+
+        if issuers.is_empty() && !trusted
+        {
+            problems.push(format!("is incomplete (no issuer found for {})", describe(&current)));
+        }
+
+        break;
+    }
+
+    if used.windows(2).any(|pair| pair[0] > pair[1])
+    {
+        problems.push(String::from("had its certificates out of order"));
+    }
+
+    for (_, certificate) in certificates.iter().enumerate().filter(|(index, _)| !used.contains(index))
+    {
+        problems.push(format!("held {}, which isn't part of the chain of {}", describe(certificate), describe(&certificates[leaf])));
+    }
+
+    // Keys stay before the certificates if they were, and after otherwise.
+    let keys_first = blocks.first().map(|block| block.label.ends_with("PRIVATE KEY")).unwrap_or(false);
+    let mut repaired = String::new();
+
+    if keys_first
+    {
+        keys.iter().for_each(|block| repaired.push_str(&block.text));
+    }
+
+    for certificate in &ordered
+    {
+        repaired.push_str(&String::from_utf8_lossy(&certificate.to_pem().map_err(|e| e.to_string())?));
+    }
+
+    if !keys_first
+    {
+        keys.iter().for_each(|block| repaired.push_str(&block.text));
+    }
+
+    others.iter().for_each(|block| repaired.push_str(&block.text));
+
+    Ok(Repair { contents: repaired.into_bytes(), problems })
+}
+
+/// Split a bundle into its blocks, once each: certificates named
+/// "CN_NOTAFTER_SERIAL.pem", keys named as their certificate's ".key" (or
+/// "key_N.key"), and other blocks by their label, e.g. "dh_parameters_1.pem".
+/// Different blocks which would have the same name (e.g. a key in two
+/// formats) are numbered, e.g. "example.com_20300101_1000_2.key".
+pub fn split(contents: &[u8]) -> Result<Split, String>
+{
+    let text = std::str::from_utf8(contents).map_err(|_| String::from("isn't a PEM file"))?;
+    let blocks = blocks(text);
+
+    if blocks.is_empty()
+    {
+        return Err(String::from("holds no PEM blocks"));
+    }
+
+    // The certificates' names, also naming their keys, wherever those are.
+    let mut certificates = vec![];
+
+    for block in blocks.iter().filter(|block| block.label == "CERTIFICATE")
+    {
+        let certificate = X509::from_pem(block.text.as_bytes()).map_err(|e| format!("couldn't parse a certificate: {}", e))?;
+
+        certificates.push((block.text.as_str(), part_name(&certificate), certificate));
+    }
+
+    let mut parts: Vec<Part> = vec![];
+    let mut problems = vec![];
+    let mut counts: Vec<(String, usize)> = vec![];
+
+    for block in &blocks
+    {
+        // The same block again adds nothing.
+        if parts.iter().any(|part| part.contents == block.text.as_bytes())
+        {
+            continue;
+        }
+
+        let is_key = block.label.ends_with("PRIVATE KEY");
+        let key_digest = if is_key { PKey::private_key_from_pem(block.text.as_bytes()).ok().and_then(|key| chain::key_digest(&key)) } else { None };
+
+        let certificate = certificates.iter().find(|(text, _, certificate)| match &key_digest
+        {
+            Some(digest) => certificate.public_key().ok().and_then(|public_key| chain::key_digest(&public_key)).as_ref() == Some(digest),
+            None => *text == block.text
+        });
+
+        let extension = if is_key { "key" } else { "pem" };
+
+        let mut name = match certificate
+        {
+            Some((_, name, _)) => format!("{}.{}", name, extension),
+            None => {
+                // Number other blocks by label, e.g. "key_1.key", "key_2.key".
+                let label = if is_key { String::from("key") } else { export::file_name(&block.label.to_lowercase().replace(' ', "_")) };
+                let count = match counts.iter_mut().find(|(counted, _)| *counted == label)
+                {
+                    Some((_, count)) => {
+                        *count += 1;
+                        *count
+                    },
+                    None => {
+                        counts.push((label.clone(), 1));
+                        1
+                    }
+                };
+
+                format!("{}_{}.{}", label, count, extension)
+            }
+        };
+
+        if parts.iter().any(|part| part.name == name)
+        {
+            let stem = name.trim_end_matches(&format!(".{}", extension)).to_string();
+            let numbered = (2..).map(|number| format!("{}_{}.{}", stem, number, extension)).find(|numbered| !parts.iter().any(|part| part.name == *numbered)).unwrap_or_default();
+
+            problems.push(format!("holds different blocks named {}, so one is {}", name, numbered));
+            name = numbered;
+        }
+
+        parts.push(Part { name, contents: block.text.clone().into_bytes(), is_key });
+    }
+
+    Ok(Split { parts, problems })
+}
+
+/// The name of a certificate's file (without its extension), e.g.
+/// "wildcard.example.com_20300101_1000" for "*.example.com", expiring on
+/// 1 January 2030, with serial number 0x1000.
+fn part_name(certificate: &X509) -> String
+{
+    let common_name = certificate.subject_name().entries_by_nid(openssl::nid::Nid::COMMONNAME).next()
+        .and_then(|entry| entry.data().as_utf8().ok().map(|name| name.to_string()))
+        .unwrap_or_default();
+
+    let not_after: String = format::format_time(certificate.not_after()).chars().take(10).filter(char::is_ascii_digit).collect();
+    let serial = certificate.serial_number().to_bn().ok().and_then(|serial| serial.to_hex_str().ok().map(|hex| hex.to_lowercase())).unwrap_or_default();
+
+    format!("{}_{}_{}", export::file_name(&common_name), not_after, serial)
+}
+
+/// Split PEM text into its blocks, each ending with a newline.
+fn blocks(text: &str) -> Vec<Block>
+{
+    let mut blocks = vec![];
+    let mut current: Option<Block> = None;
+
+    for line in text.lines().map(str::trim_end)
+    {
+        if let Some(label) = line.strip_prefix("-----BEGIN ").and_then(|rest| rest.strip_suffix("-----"))
+        {
+            current = Some(Block { label: label.to_string(), text: String::new() });
+        }
+
+        if let Some(block) = current.as_mut()
+        {
+            block.text.push_str(line);
+            block.text.push('\n');
+
+            if line.starts_with("-----END ")
+            {
+                blocks.extend(current.take());
+            }
+        }
+    }
+
+    blocks
+}
+
+fn same(a: &X509, b: &X509) -> bool
+{
+    a.to_der().ok() == b.to_der().ok()
+}
+
+fn describe(certificate: &X509) -> String
+{
+    format::format_name(certificate.subject_name())
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+    use std::fs;
+
+    fn read(path: &str) -> String
+    {
+        fs::read_to_string(path).unwrap()
+    }
+
+    #[test]
+    fn repairs_bundles()
+    {
+        let leaf = read("samples/cross_signed/example.com.crt");
+        let intermediate = read("samples/cross_signed/intermediate_by_a.crt");
+        let key = read("samples/cross_signed/example.com.key");
+        let unrelated = read("samples/self_signed_dsa.crt");
+
+        let inventory = Inventory::new().add_path("samples/cross_signed/intermediate_by_a.crt").add_path("samples/cross_signed/root_a.crt");
+        let expected = format!("{}{}", leaf, intermediate);
+
+        // A correct bundle is left as it is.
+        let repair = repair(expected.as_bytes(), &inventory).unwrap();
+
+        assert_eq!(String::from_utf8(repair.contents).unwrap(), expected);
+        assert!(repair.problems.is_empty());
+
+        // Out of order, with a duplicate and an unrelated certificate.
+        let bundle = format!("{}{}{}subject=ignored\n{}", intermediate, leaf, unrelated, intermediate);
+        let repaired = super::repair(bundle.as_bytes(), &Inventory::new()).unwrap();
+
+        assert_eq!(String::from_utf8(repaired.contents).unwrap(), expected);
+        assert_eq!(repaired.problems, vec![
+            "held C=US, O=Sample Org, CN=Intermediate CA more than once",
+            "is incomplete (no issuer found for C=US, O=Sample Org, CN=Intermediate CA)",
+            "had its certificates out of order",
+            "held C=US, O=Sample Org, CN=dsa.example.com, which isn't part of the chain of C=US, O=Sample Org, CN=example.com",
+        ]);
+
+        // Missing its intermediate, with the key first.
+        let repaired = super::repair(format!("{}{}", key, leaf).as_bytes(), &inventory).unwrap();
+
+        assert_eq!(String::from_utf8(repaired.contents).unwrap(), format!("{}{}{}", key, leaf, intermediate));
+        assert_eq!(repaired.problems, vec!["lacked its intermediate C=US, O=Sample Org, CN=Intermediate CA (from samples/cross_signed/intermediate_by_a.crt)"]);
+
+        assert!(super::repair(key.as_bytes(), &inventory).is_err());
+    }
+
+    #[test]
+    fn splits_bundles()
+    {
+        let leaf = read("samples/cross_signed/example.com.crt");
+        let intermediate = read("samples/cross_signed/intermediate_by_a.crt");
+        let key = read("samples/cross_signed/example.com.key");
+        let parameters = "-----BEGIN DH PARAMETERS-----\nMAYCAQACAQI=\n-----END DH PARAMETERS-----\n";
+
+        let bundle = format!("{}{}text\n{}{}{}", intermediate, key, leaf, intermediate, parameters);
+        let parts = split(bundle.as_bytes()).unwrap().parts;
+        let names: Vec<&str> = parts.iter().map(|part| part.name.as_str()).collect();
+
+        assert_eq!(names, ["Intermediate_CA_20540302_01.pem", "example.com_20540302_04.key", "example.com_20540302_04.pem", "dh_parameters_1.pem"]);
+        assert_eq!(String::from_utf8(parts[2].contents.clone()).unwrap(), leaf);
+        assert!(parts[1].is_key && !parts[2].is_key);
+
+        // The same key in another format.
+        let pkcs1 = String::from_utf8(PKey::private_key_from_pem(key.as_bytes()).unwrap().rsa().unwrap().private_key_to_pem().unwrap()).unwrap();
+        let numbered = split(format!("{}{}{}", leaf, key, pkcs1).as_bytes()).unwrap();
+        let names: Vec<&str> = numbered.parts.iter().map(|part| part.name.as_str()).collect();
+
+        assert_eq!(names, ["example.com_20540302_04.pem", "example.com_20540302_04.key", "example.com_20540302_04_2.key"]);
+        assert_eq!(numbered.problems, ["holds different blocks named example.com_20540302_04.key, so one is example.com_20540302_04_2.key"]);
+
+        assert_eq!(split(b"subject=nothing\n").err().unwrap(), "holds no PEM blocks");
+    }
+}
@@ -0,0 +1,282 @@
+//! Copyright (C) 2022 Gaz J.
+//!
+//! This program is free software: you can redistribute it and/or modify
+//! it under the terms of the GNU General Public License as published by
+//! the Free Software Foundation, either version 3 of the License, or
+//! (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//! GNU General Public License for more details.
+//!
+//! You should have received a copy of the GNU General Public License
+//! along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! On-disk cache of what each scanned file holds, kept between runs.
+//!
+//! Entries are keyed by canonical path, and are only used while the file's
+//! modification time and size are unchanged. Files known to hold nothing
+//! aren't read again, and neither are files holding only requests and
+//! certificates, which are rebuilt from the cache. Only public items are
+//! stored (requests and certificates, with their public key digest, names,
+//! and validity); never private key material, so files holding a key are
+//! read again, and only parsed as a key.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use log::debug;
+use openssl::pkey::{PKeyRef, Private};
+use openssl::x509::{X509, X509Ref, X509Req, X509ReqRef};
+use serde_json::{json, Map, Value};
+
+use crate::chain;
+use crate::format;
+
+/// Name of the cache file within the cache directory.
+const FILE_NAME: &str = "scan.json";
+
+/// Items an unchanged file holds, rebuilt from the cache.
+#[derive(Default)]
+pub struct Items
+{
+    /// Whether the file holds a private key (which isn't cached).
+    pub key: bool,
+
+    pub request: Option<X509Req>,
+    pub certificates: Vec<X509>,
+}
+
+/// What the cache knows about an unchanged file.
+pub enum Entry
+{
+    /// The file holds these items.
+    Holds(Items),
+
+    /// The file couldn't be parsed, for this reason.
+    Skipped(chain::Skipped),
+}
+
+pub struct Cache
+{
+    path: PathBuf,
+    files: Map<String, Value>,
+}
+
+impl Cache
+{
+    /// Open the cache in a directory. Caches which can't be read, or were
+    /// written by another version, start empty.
+    pub fn open(directory: &str) -> Cache
+    {
+        let path = Path::new(directory).join(FILE_NAME);
+
+        let files = fs::read(&path).ok()
+            .and_then(|contents| serde_json::from_slice::<Value>(&contents).ok())
+            .filter(|cache| cache["version"] == version())
+            .and_then(|mut cache| match cache["files"].take()
+            {
+                Value::Object(files) => Some(files),
+                _ => None
+            });
+
+        if files.is_none()
+        {
+            debug!("Starting a new cache in {}", path.display());
+        }
+
+        Cache { path, files: files.unwrap_or_default() }
+    }
+
+    /// Look up a file, provided it hasn't changed since it was cached (and
+    /// its items can be rebuilt).
+    pub fn lookup(&self, path: &str) -> Option<Entry>
+    {
+        let (name, modified, size) = stamp(path)?;
+        let entry = self.files.get(&name)?;
+
+        if entry["modified"] != modified || entry["size"] != size
+        {
+            return None;
+        }
+
+        // Files are only cached once they're read, so were skipped as
+        // unrecognized, or invalid.
+        if let Some(reason) = entry["skipped"].as_str()
+        {
+            return Some(Entry::Skipped(match reason == chain::Skipped::Unrecognized.to_string()
+            {
+                true => chain::Skipped::Unrecognized,
+                false => chain::Skipped::Invalid(reason.to_string())
+            }));
+        }
+
+        let mut items = Items::default();
+
+        for item in entry["items"].as_array()?
+        {
+            match item["type"].as_str()?
+            {
+                "key" => items.key = true,
+                "request" => items.request = Some(X509Req::from_der(&der(item)?).ok()?),
+                "certificate" => items.certificates.push(X509::from_der(&der(item)?).ok()?),
+                _ => return None
+            }
+        }
+
+        Some(Entry::Holds(items))
+    }
+
+    /// Record what a file holds (items described by the key, request, and
+    /// certificate functions), or why it was skipped.
+    pub fn store(&mut self, path: &str, items: Vec<Value>, skipped: Option<&str>)
+    {
+        if let Some((name, modified, size)) = stamp(path)
+        {
+            self.files.insert(name, json!({
+                "modified": modified,
+                "size": size,
+                "items": items,
+                "skipped": skipped,
+            }));
+        }
+    }
+
+    /// Write the cache (creating its directory if needed), leaving out files
+    /// which no longer exist.
+    pub fn save(&mut self) -> Result<(), io::Error>
+    {
+        self.files.retain(|name, _| Path::new(name).exists());
+
+        if let Some(directory) = self.path.parent()
+        {
+            fs::create_dir_all(directory)?;
+        }
+
+        let cache = json!({ "version": version(), "files": self.files });
+
+        // Write a temporary file first, so an interrupted run can't leave a
+        // truncated cache.
+        let temporary = self.path.with_extension("tmp");
+
+        fs::write(&temporary, cache.to_string())?;
+        fs::rename(&temporary, &self.path)
+    }
+}
+
+/// Describe a private key by its public portion.
+pub fn key(key: &PKeyRef<Private>) -> Value
+{
+    json!({ "type": "key", "public_key_sha256": chain::key_digest(key).map(|d| hex(&d)) })
+}
+
+/// Describe a certificate signing request.
+pub fn request(request: &X509ReqRef) -> Value
+{
+    json!({
+        "type": "request",
+        "public_key_sha256": request.public_key().ok().and_then(|k| chain::key_digest(&k)).map(|d| hex(&d)),
+        "subject": format::format_name(request.subject_name()),
+        "der": request.to_der().ok().map(|der| hex(&der)),
+    })
+}
+
+/// Describe a certificate.
+pub fn certificate(certificate: &X509Ref) -> Value
+{
+    json!({
+        "type": "certificate",
+        "public_key_sha256": certificate.public_key().ok().and_then(|k| chain::key_digest(&k)).map(|d| hex(&d)),
+        "subject": format::format_name(certificate.subject_name()),
+        "issuer": format::format_name(certificate.issuer_name()),
+        "not_before": format::format_time(certificate.not_before()),
+        "not_after": format::format_time(certificate.not_after()),
+        "der": certificate.to_der().ok().map(|der| hex(&der)),
+    })
+}
+
+/// Entries are only reused by the same build, as parsing may differ
+/// between versions.
+fn version() -> String
+{
+    format!("{} ({})", env!("CARGO_PKG_VERSION"), env!("SSLCHAINS_GIT_COMMIT"))
+}
+
+/// Canonical path, modification time (in nanoseconds), and size of a
+/// regular file. Streams and archive members aren't cached.
+fn stamp(path: &str) -> Option<(String, u64, u64)>
+{
+    let metadata = fs::metadata(path).ok().filter(|md| md.is_file())?;
+    let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    let name = fs::canonicalize(path).ok()?.to_str()?.to_string();
+
+    Some((name, modified.as_nanos() as u64, metadata.len()))
+}
+
+pub fn hex(bytes: &[u8]) -> String
+{
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Decode an item's DER encoding (see hex).
+fn der(item: &Value) -> Option<Vec<u8>>
+{
+    let text = item["der"].as_str()?;
+
+    (0..text.len()).step_by(2).map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok()).collect()
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    #[test]
+    fn reuses_entries_for_unchanged_files()
+    {
+        let directory = std::env::temp_dir().join(format!("sslchains-test-{}.cache", std::process::id()));
+        let directory = directory.to_str().unwrap();
+        let path = format!("{}.crt", directory);
+
+        fs::copy("samples/ca_signed.crt", &path).unwrap();
+
+        let mut cache = Cache::open(directory);
+
+        assert!(cache.lookup(&path).is_none());
+
+        let contents = fs::read(&path).unwrap();
+        let items = chain::bytes_to_x509_stack(&contents).unwrap().iter().map(|c| certificate(c)).collect();
+
+        cache.store(&path, items, None);
+        cache.store("samples/intermediate_ca.srl", vec![], Some("not a recognized key, request, or certificate"));
+        cache.save().unwrap();
+
+        let cache = Cache::open(directory);
+
+        match cache.lookup(&path)
+        {
+            Some(Entry::Holds(items)) => {
+                assert!(!items.key);
+                assert!(items.request.is_none());
+                assert_eq!(items.certificates.iter().map(|c| c.to_der().unwrap()).collect::<Vec<_>>(), vec![chain::bytes_to_x509(&contents).unwrap().to_der().unwrap()]);
+            },
+            _ => panic!("{} wasn't rebuilt from the cache", path)
+        }
+
+        match cache.lookup("samples/intermediate_ca.srl")
+        {
+            Some(Entry::Skipped(reason)) => assert_eq!(reason, chain::Skipped::Unrecognized),
+            _ => panic!("samples/intermediate_ca.srl wasn't cached as skipped")
+        }
+
+        // Changed files are looked at afresh.
+        fs::write(&path, [contents.as_slice(), b"\n"].concat()).unwrap();
+
+        assert!(cache.lookup(&path).is_none());
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -13,45 +13,345 @@
 //! You should have received a copy of the GNU General Public License
 //! along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 use std::fs;
-use std::io;
-use openssl::rsa::Rsa;
-use openssl::x509::{X509, X509NameEntryRef, X509Req};
-use openssl::pkey::{Public, Private};
+use std::io::{self, Read};
+use std::net::IpAddr;
+use std::path::Path;
+use std::time::Duration;
+use openssl::asn1::Asn1Time;
+use openssl::ecdsa::EcdsaSig;
+use openssl::stack::Stack;
+use openssl::x509::{GeneralName, X509, X509NameEntryRef, X509Ref, X509Req};
+use openssl::pkey::{HasPublic, Id, PKey, PKeyRef, Public, Private};
 use openssl::error::ErrorStack;
+use openssl::hash::{self, MessageDigest};
 use openssl::nid::Nid;
+use log::{debug, info, warn};
+use rayon::prelude::*;
+
+use crate::archive;
+use crate::error::{self, Error};
+use crate::cache::{self, Cache};
+use crate::format;
+use crate::manifest;
+#[cfg(feature = "nss")]
+use crate::nss;
+use crate::ocsp;
+use crate::openssh;
+use crate::trust;
+use crate::usage;
 
 #[path = "compare.rs"] mod compare;
 
 /// Chain struct contains instances of related items.
+#[derive(Default)]
 pub struct Chain
 {
     pub name: Option<String>,
     pub key: Option<PrivateKeyFile>,
     pub request: Option<CertificateRequestFile>,
     pub certificates: Vec<CertificateFile>,
+
+    /// TLS server which presented the certificates (see connect), for
+    /// chains of served certificates, which have no key.
+    pub endpoint: Option<String>,
+
+    /// Why the endpoint couldn't be reached, for chains of endpoints which
+    /// presented no certificates.
+    pub unreachable: Option<String>,
 }
 
 impl Chain
 {
     pub fn new() -> Self
     {
-        Chain { name: None, key: None, request: None, certificates: vec![] }
+        Chain { name: None, key: None, request: None, certificates: vec![], endpoint: None, unreachable: None }
+    }
+
+    /// Where the chain is anchored: its key's path, or the endpoint which
+    /// served its certificates. Chains without either aren't displayed.
+    pub fn origin(&self) -> Option<&str>
+    {
+        match (&self.key, &self.endpoint)
+        {
+            (Some(key), _) => Some(&key.path),
+            (None, Some(endpoint)) => Some(endpoint),
+            (None, None) => None
+        }
+    }
+
+    /// Describe a certificate's place in the chain's renewal lineage: the
+    /// newest of several certificates is "current", and older ones are
+    /// "superseded".
+    pub fn lineage(&self, index: usize) -> Option<&'static str>
+    {
+        match (self.certificates.len(), index)
+        {
+            (0..=1, _) => None,
+            (_, 0) => Some("current"),
+            _ => Some("superseded")
+        }
+    }
+
+    /// Paths of every file in the chain: its key, request, certificates (and
+    /// their duplicates), and signing certificates.
+    pub fn paths(&self) -> Vec<&str>
+    {
+        let mut paths = vec![];
+
+        paths.extend(self.key.as_ref().map(|key| key.path.as_str()));
+        paths.extend(self.request.as_ref().map(|request| request.path.as_str()));
+
+        for certificate in &self.certificates
+        {
+            let mut certificate = Some(certificate);
+
+            while let Some(c) = certificate
+            {
+                paths.push(c.path.as_str());
+                paths.extend(c.duplicates.iter().map(String::as_str));
+
+                certificate = c.signing_certificate.as_deref();
+            }
+        }
+
+        paths
+    }
+
+    /// Most severe expiry among the chain's certificates and their signing
+    /// certificates, or None if it has no certificates.
+    pub fn expiry(&self, warn_days: u32) -> Option<Expiry>
+    {
+        self.certificates.iter()
+            .flat_map(|certificate| {
+                let signing_certificates = certificate.signing_certificate_chain();
+
+                std::iter::once(certificate.expiry(warn_days))
+                    .chain(signing_certificates.into_iter().map(move |c| c.expiry(warn_days)))
+            })
+            .max()
+    }
+
+    /// Find the name by which the chain's current (first) certificate covers
+    /// a hostname (see CertificateFile::host_match).
+    pub fn host_match(&self, host: &str) -> Option<String>
+    {
+        self.certificates.first().and_then(|certificate| certificate.host_match(host))
+    }
+
+    /// Verify the chain's current (first) certificate, with its signing
+    /// certificates, against the system trust store.
+    pub fn verify(&self) -> trust::Verification
+    {
+        let certificate = match self.certificates.first()
+        {
+            Some(certificate) => certificate,
+            None => return trust::Verification::Incomplete(String::from("no certificate"))
+        };
+
+        let intermediates: Vec<X509> = certificate.signing_certificate_chain().into_iter()
+            .map(|signing_certificate| signing_certificate.certificate)
+            .collect();
+
+        trust::system_verify(&certificate.certificate, &intermediates)
+            .unwrap_or_else(|| trust::Verification::Untrusted(String::from("couldn't verify")))
+    }
+
+    /// Check the chain's key for consistency, and the signatures of its
+    /// certificates (and their signing certificates) for structure, listing
+    /// problems by path.
+    pub fn check_keys(&self) -> Vec<(String, Error)>
+    {
+        let mut problems = vec![];
+
+        if let Some(key) = &self.key
+        {
+            if let Err(e) = key.check()
+            {
+                problems.push((key.path.clone(), e));
+            }
+        }
+
+        let mut checked = vec![];
+
+        for certificate in &self.certificates
+        {
+            let signing_certificates = certificate.signing_certificate_chain();
+
+            for certificate in std::iter::once(certificate).chain(signing_certificates.iter().map(|c| c.as_ref()))
+            {
+                // Signing certificates are often shared between certificates.
+                let fingerprint = fingerprint(&certificate.certificate);
+
+                if checked.contains(&fingerprint)
+                {
+                    continue;
+                }
+
+                checked.push(fingerprint);
+
+                if let Err(e) = certificate.check_signature()
+                {
+                    problems.push((certificate.path.clone(), e));
+                }
+            }
+        }
+
+        problems
+    }
+
+    /// Check the usage constraints of the chain's current (first) certificate
+    /// and its signing certificates (see usage::check), listing problems by
+    /// path.
+    pub fn usage_problems(&self) -> Vec<(String, String)>
+    {
+        let certificate = match self.certificates.first()
+        {
+            Some(certificate) => certificate,
+            None => return vec![]
+        };
+
+        let signing_certificates = certificate.signing_certificate_chain();
+
+        let certificates: Vec<&CertificateFile> = std::iter::once(certificate)
+            .chain(signing_certificates.iter().map(|c| c.as_ref()))
+            .collect();
+
+        let x509s: Vec<&X509Ref> = certificates.iter().map(|c| c.certificate.as_ref()).collect();
+
+        usage::check(&x509s).into_iter()
+            .map(|(index, problem)| (certificates[index].path.clone(), problem))
+            .collect()
+    }
+
+    /// Find what the chain's request asked for, but its current (first)
+    /// certificate doesn't cover, e.g. because the CA stripped names: subject
+    /// attributes (e.g. "O=Example") and Subject Alternative Names (e.g.
+    /// "DNS:www.example.com").
+    pub fn request_mismatches(&self) -> Vec<String>
+    {
+        let (request, certificate) = match (&self.request, self.certificates.first())
+        {
+            (Some(request), Some(certificate)) => (request, certificate),
+            _ => return vec![]
+        };
+
+        let issued = certificate.certificate.subject_name();
+
+        let mut mismatches: Vec<String> = request.request.subject_name().entries()
+            .filter(|entry| {
+                !issued.entries_by_nid(entry.object().nid()).any(|e| e.data().as_slice() == entry.data().as_slice())
+            })
+            .map(|entry| {
+                let key = entry.object().nid().short_name().unwrap_or("?");
+
+                format!("{}={}", key, entry.data().as_utf8().map(|v| v.to_string()).unwrap_or_default())
+            })
+            .collect();
+
+        let names = certificate.subject_alt_names();
+
+        for name in request.subject_alt_names()
+        {
+            // DNS names may be covered by a wildcard.
+            let covered = match name.strip_prefix("DNS:")
+            {
+                Some(dnsname) => certificate.host_match(dnsname).is_some(),
+                None => names.contains(&name)
+            };
+
+            if !covered
+            {
+                mismatches.push(name);
+            }
+        }
+
+        mismatches
+    }
+
+    /// Query the OCSP responder named by the chain's current (first)
+    /// certificate for its revocation status, or None if it names none.
+    pub fn revocation(&self, timeout: Duration) -> Option<ocsp::Revocation>
+    {
+        let certificate = self.certificates.first()?;
+        let url = ocsp::responder(&certificate.certificate)?;
+
+        Some(match &certificate.signing_certificate
+        {
+            Some(issuer) => ocsp::check(&certificate.certificate, &issuer.certificate, &url, timeout),
+            None => ocsp::Revocation::Unknown(String::from("the issuer is needed to query the responder"))
+        })
+    }
+
+    /// Determine whether the key shares its file with one or more of the
+    /// chain's certificates (e.g. HAProxy-style combined PEM files).
+    pub fn is_combined(&self) -> bool
+    {
+        match &self.key
+        {
+            Some(key) => self.certificates.iter().any(|c| c.path == key.path),
+            None => false
+        }
     }
 }
 
 /// Represents private key files as the foundation of each Chain.
+#[derive(Clone)]
 pub struct PrivateKeyFile
 {
     pub path: String,
-    pub rsa: Rsa<Private>,
+    pub key: PKey<Private>,
 }
 
 impl PrivateKeyFile
 {
-    pub fn new(path: &str, rsa: Rsa<Private>) -> Self
+    pub fn new(path: &str, key: PKey<Private>) -> Self
+    {
+        PrivateKeyFile { path: path.to_string(), key }
+    }
+
+    /// Parse a key from contents in any supported format, naming it (in
+    /// place of a path) with name.
+    pub fn from_bytes(name: &str, contents: &[u8]) -> error::Result<Self>
+    {
+        Ok(PrivateKeyFile::new(name, bytes_to_private_key(contents)?))
+    }
+
+    /// Name of the key's public key algorithm.
+    pub fn algorithm(&self) -> &'static str
+    {
+        key_algorithm(self.key.id())
+    }
+
+    /// Determine whether the key uses a deprecated algorithm (DSA).
+    pub fn is_deprecated(&self) -> bool
+    {
+        self.key.id() == Id::DSA
+    }
+
+    /// Check the key's internal consistency (RSA and EC keys only), so
+    /// corrupted or tampered keys are reported, rather than just failing to
+    /// match anything.
+    pub fn check(&self) -> error::Result<()>
     {
-        PrivateKeyFile { path: path.to_string(), rsa }
+        let consistent = match self.key.id()
+        {
+            Id::RSA => {
+                let consistent = self.key.rsa()?.check_key().unwrap_or(false);
+
+                // Failed checks leave their reasons on OpenSSL's error queue.
+                ErrorStack::get();
+
+                consistent
+            },
+            Id::EC => self.key.ec_key()?.check_key().is_ok(),
+            _ => true
+        };
+
+        if consistent { Ok(()) } else { Err(Error::InconsistentKey) }
     }
 }
 
@@ -69,15 +369,59 @@ impl CertificateRequestFile
         CertificateRequestFile { path: path.to_string(), request }
     }
 
-    pub fn to_rsa(&self) -> Result<Rsa<Public>, ErrorStack>
+    /// Parse a request from contents in any supported format, naming it (in
+    /// place of a path) with name.
+    pub fn from_bytes(name: &str, contents: &[u8]) -> error::Result<Self>
+    {
+        Ok(CertificateRequestFile::new(name, bytes_to_x509req(contents)?))
+    }
+
+    pub fn public_key(&self) -> Result<PKey<Public>, ErrorStack>
+    {
+        self.request.public_key()
+    }
+
+    /// Copy the request. X509Req can't be cloned directly, so it's copied
+    /// via its encoding.
+    pub fn try_clone(&self) -> error::Result<Self>
     {
-        self.request.public_key().unwrap().rsa()
+        let request = X509Req::from_der(&self.request.to_der()?)?;
+
+        Ok(CertificateRequestFile::new(&self.path, request))
     }
 
     pub fn common_name(&self) -> Option<&X509NameEntryRef>
     {
         self.request.subject_name().entries_by_nid(Nid::COMMONNAME).last()
     }
+
+    /// Requested Subject Alternative Name values, formatted as for
+    /// certificates (see CertificateFile::subject_alt_names).
+    pub fn subject_alt_names(&self) -> Vec<String>
+    {
+        // The openssl crate doesn't decode request extensions, so they're
+        // copied into an (unsigned) certificate which it does decode.
+        let certificate = X509::builder().and_then(|mut builder| {
+            for extension in self.request.extensions().into_iter().flatten()
+            {
+                builder.append_extension(extension)?;
+            }
+
+            Ok(builder.build())
+        });
+
+        match certificate
+        {
+            Ok(certificate) => format_general_names(certificate.subject_alt_names()),
+            Err(_) => vec![]
+        }
+    }
+
+    /// Digest of the DER-encoded request.
+    pub fn fingerprint(&self, digest: MessageDigest) -> Result<Vec<u8>, ErrorStack>
+    {
+        Ok(hash::hash(digest, &self.request.to_der()?)?.to_vec())
+    }
 }
 
 /// Represents all X509 certificates found, including intermediate and
@@ -88,7 +432,40 @@ pub struct CertificateFile
     pub path: String,
     pub certificate: X509,
     pub signing_certificate: Option<Box<CertificateFile>>,
-    pub self_signed: bool
+
+    /// Issued by itself (issuer and subject match), and signed by its own key.
+    pub self_signed: bool,
+
+    /// Other paths containing the same certificate.
+    pub duplicates: Vec<String>,
+
+    /// Why its signing certificates weren't followed any further, when it
+    /// tops a chain without being self-signed, though its issuer was found.
+    pub stopped: Option<Stopped>,
+}
+
+/// Why a chain's signing certificates weren't followed any further.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Stopped
+{
+    /// Each issuer (named by subject) is already in the chain, e.g. for
+    /// mutually signed CAs.
+    Loop(String),
+
+    /// The chain is as deep as signing certificates are followed.
+    Depth(usize),
+}
+
+impl fmt::Display for Stopped
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        match self
+        {
+            Stopped::Loop(issuer) => write!(f, "loop at {}", issuer),
+            Stopped::Depth(levels) => write!(f, "stopped after {} levels", levels),
+        }
+    }
 }
 
 impl CertificateFile
@@ -97,15 +474,52 @@ impl CertificateFile
     {
         CertificateFile {
             path: path.to_string(),
+            self_signed: trust::is_self_signed(&certificate),
             certificate,
             signing_certificate: None,
-            self_signed: false
+            duplicates: vec![],
+            stopped: None,
+        }
+    }
+
+    /// Parse every certificate in contents (in any supported format),
+    /// naming them (in place of a path) with name.
+    pub fn from_bytes(name: &str, contents: &[u8]) -> error::Result<Vec<Self>>
+    {
+        let certificates = bytes_to_x509_stack(contents)?;
+
+        if certificates.is_empty()
+        {
+            return Err(Error::Unrecognized);
         }
+
+        Ok(certificates.into_iter().map(|certificate| CertificateFile::new(name, certificate)).collect())
+    }
+
+    pub fn public_key(&self) -> Result<PKey<Public>, ErrorStack>
+    {
+        self.certificate.public_key()
+    }
+
+    /// Subject Alternative Name values, formatted as openssl does (e.g.
+    /// "DNS:example.com", "IP:192.0.2.1").
+    pub fn subject_alt_names(&self) -> Vec<String>
+    {
+        format_general_names(self.certificate.subject_alt_names())
     }
 
-    pub fn to_rsa(&self) -> Result<Rsa<Public>, ErrorStack>
+    /// Digest of the DER-encoded certificate.
+    pub fn fingerprint(&self, digest: MessageDigest) -> Result<Vec<u8>, ErrorStack>
     {
-        self.certificate.public_key().unwrap().rsa()
+        Ok(self.certificate.digest(digest)?.to_vec())
+    }
+
+    /// Serial number, in hexadecimal.
+    pub fn serial(&self) -> Option<String>
+    {
+        let serial = self.certificate.serial_number().to_bn().ok()?;
+
+        serial.to_hex_str().ok().map(|hex| hex.to_string())
     }
 
     pub fn signing_certificate_chain(&self) -> Vec<Box<CertificateFile>>
@@ -128,247 +542,2353 @@ impl CertificateFile
         certificate_chain
     }
 
-    pub fn common_name(&self) -> Option<&X509NameEntryRef>
+    /// Whether the certificate was read from a file (or a duplicate of it
+    /// was).
+    pub fn is_in(&self, path: &str) -> bool
     {
-        self.certificate.subject_name().entries_by_nid(Nid::COMMONNAME).last()
+        same_file(&self.path, path) || self.duplicates.iter().any(|duplicate| same_file(duplicate, path))
     }
-}
 
-/// Begin building each Chain instance.
-pub fn build(paths: Vec<String>) -> Result<Vec<Chain>, String>
-{
-    let mut chains = vec![];
+    /// Ways the files a server sends the certificate from (usually just its
+    /// own) don't complete its chain: intermediates which aren't in them
+    /// (roots needn't be sent, as clients have them already), and an issuer
+    /// which wasn't found, unless the system trusts it.
+    pub fn bundle_problems(&self, paths: &[&str]) -> Vec<String>
+    {
+        let mut problems = vec![];
+        let signing_certificates = self.signing_certificate_chain();
+
+        for signing_certificate in signing_certificates.iter().filter(|c| !c.self_signed && !paths.iter().any(|path| c.is_in(path)))
+        {
+            problems.push(format!("doesn't hold its intermediate {}", signing_certificate.path));
+        }
 
-    initialize(&mut chains, &paths);
+        if let Some(top) = self.missing_issuer()
+        {
+            problems.push(format!("its chain is incomplete (no issuer found for {})", top));
+        }
 
-    attach_certificate_signing_requests(&mut chains, &paths);
+        problems
+    }
 
-    attach_certificates(&mut chains, &paths);
+    /// The path of the last certificate in the chain, if its issuer wasn't
+    /// found (so the chain doesn't reach a root), unless the system trusts
+    /// its issuer.
+    pub fn missing_issuer(&self) -> Option<String>
+    {
+        let top = self.signing_certificate_chain().pop().map(|top| *top);
+        let top = top.as_ref().unwrap_or(self);
 
-    attach_signing_certificates(&mut chains, &paths);
+        let trusted = matches!(trust::system_status(&top.certificate), Some(trust::Status::TrustedIssuer(_)));
 
-    Ok(chains)
-}
+        (!top.self_signed && !trusted).then(|| top.path.clone())
+    }
 
-/// Initialize Chains, creating one for each private key.
-fn initialize(chains: &mut Vec<Chain>, paths: &Vec<String>)
-{
-    for path in paths
+    pub fn common_name(&self) -> Option<&X509NameEntryRef>
+    {
+        self.certificate.subject_name().entries_by_nid(Nid::COMMONNAME).last()
+    }
+
+    /// DNS names the certificate covers: its DNS Subject Alternative Names
+    /// (lowercased), or its common name if it has none, as TLS clients use.
+    pub fn dns_names(&self) -> Vec<String>
     {
-        let contents = get_file_contents(&path);
+        let mut names: Vec<String> = self.subject_alt_names().iter()
+            .filter_map(|name| name.strip_prefix("DNS:"))
+            .map(|name| name.to_ascii_lowercase())
+            .collect();
+
+        if names.is_empty()
+        {
+            let common_name = self.common_name().and_then(|name| name.data().as_utf8().ok());
+
+            names.extend(common_name.map(|name| name.to_ascii_lowercase()));
+        }
+
+        let mut seen = HashSet::new();
 
-        if contents.is_err() { continue; }
+        names.retain(|name| seen.insert(name.clone()));
+
+        names
+    }
 
-        let contents = contents.unwrap();
+    /// Find the name (e.g. "DNS:*.example.com") by which the certificate
+    /// covers a hostname or IP address, as TLS clients do (RFC 6125): Subject
+    /// Alternative Names are used if there are any, otherwise the common
+    /// name, and wildcards match exactly one leftmost label.
+    pub fn host_match(&self, host: &str) -> Option<String>
+    {
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        let names = self.subject_alt_names();
 
-        if let Ok(rsa) = str_to_private_key(&contents)
+        if let Ok(address) = host.parse::<IpAddr>()
         {
-            let key = PrivateKeyFile::new(&path, rsa);
+            let name = format!("IP:{}", address);
 
-            let mut chain = Chain::new();
+            return names.into_iter().find(|n| *n == name);
+        }
 
-            chain.key = Some(key);
+        let dnsnames: Vec<&str> = names.iter().filter_map(|name| name.strip_prefix("DNS:")).collect();
 
-            chains.push(chain);
+        if !dnsnames.is_empty()
+        {
+            return dnsnames.into_iter()
+                .find(|name| is_host_match(name, &host))
+                .map(|name| format!("DNS:{}", name));
         }
+
+        let common_name = self.common_name()?.data().as_utf8().ok()?.to_string();
+
+        is_host_match(&common_name, &host).then(|| format!("CN={}", common_name))
     }
-}
 
-/// Locate certificate signing requests for all existing chains.
-fn attach_certificate_signing_requests(chains: &mut Vec<Chain>, paths: &Vec<String>)
-{
-    for chain in chains
+    /// Check the structure of the certificate's signature: a known
+    /// algorithm, a well-formed value (for ECDSA), and the length expected of
+    /// the signing key (for RSA, when the signing certificate is known).
+    /// Certificates issued by themselves but signed by another key (e.g. by
+    /// the old key in a CA key rollover) are checked as intermediates.
+    pub fn check_signature(&self) -> error::Result<()>
     {
-        for path in paths
+        let malformed = |reason: &str| Err(Error::MalformedSignature(reason.to_string()));
+
+        let algorithms = match self.certificate.signature_algorithm().object().nid().signature_algorithms()
         {
-            let contents = get_file_contents(&path);
+            Some(algorithms) => algorithms,
+            None => return malformed("unknown signature algorithm")
+        };
 
-            if contents.is_err() { continue; }
+        let signature = self.certificate.signature().as_slice();
 
-            let contents = contents.unwrap();
+        if signature.is_empty()
+        {
+            return malformed("empty signature");
+        }
 
-            let request = str_to_x509req(&contents);
+        if algorithms.pkey == Nid::X9_62_ID_ECPUBLICKEY && EcdsaSig::from_der(signature).is_err()
+        {
+            return malformed("invalid ECDSA signature value");
+        }
 
-            if request.is_err() { continue; }
+        let signing_key = match (&self.signing_certificate, self.self_signed)
+        {
+            (_, true) => Some(self.public_key()?),
+            (Some(signing_certificate), false) => Some(signing_certificate.public_key()?),
+            (None, false) => None
+        };
 
-            let request = CertificateRequestFile::new(&path, request.unwrap());
+        match signing_key
+        {
+            Some(key) if key.id() == Id::RSA && signature.len() != key.size() => {
+                malformed(&format!("{} byte signature for a {} byte RSA key", signature.len(), key.size()))
+            },
+            _ => Ok(())
+        }
+    }
 
-            if let Some(key) = &chain.key
-            {
-                let rsa = request.to_rsa().unwrap();
+    /// Whole days until the certificate expires (negative once it has).
+    pub fn days_remaining(&self) -> Option<i32>
+    {
+        let now = Asn1Time::days_from_now(0).ok()?;
+        let diff = now.diff(self.certificate.not_after()).ok()?;
 
-                if compare::private_to_public(&key.rsa, &rsa).is_ok()
-                {
-                    chain.request = Some(request);
+        // Round partial days down, so certificates which expired less than
+        // a day ago have -1 days remaining, not 0.
+        Some(if diff.secs < 0 { diff.days - 1 } else { diff.days })
+    }
 
-                    break;
-                }
-            }
+    /// Whether the certificate has expired, or expires within warn_days.
+    pub fn expiry(&self, warn_days: u32) -> Expiry
+    {
+        match self.days_remaining()
+        {
+            Some(days) if days < 0 => Expiry::Expired,
+            Some(days) if (days as i64) < warn_days as i64 => Expiry::Expiring,
+            _ => Expiry::Valid
         }
     }
 }
 
-/// Locate certificates for all existing chains.
-fn attach_certificates(chains: &mut Vec<Chain>, paths: &Vec<String>)
+/// Format Subject Alternative Name values as openssl does (e.g.
+/// "DNS:example.com", "IP:192.0.2.1").
+fn format_general_names(general_names: Option<Stack<GeneralName>>) -> Vec<String>
 {
-    for chain in chains
+    let mut names = vec![];
+
+    for name in general_names.iter().flatten()
     {
-        for certificate in find_certificates(paths)
+        if let Some(dnsname) = name.dnsname()
         {
-            if let Some(key) = &chain.key
+            names.push(format!("DNS:{}", dnsname));
+        }
+        else if let Some(ipaddress) = name.ipaddress()
+        {
+            let address = match ipaddress.len()
             {
-                let rsa = certificate.to_rsa().unwrap();
+                4 => IpAddr::from(<[u8; 4]>::try_from(ipaddress).unwrap()).to_string(),
+                16 => IpAddr::from(<[u8; 16]>::try_from(ipaddress).unwrap()).to_string(),
+                _ => continue
+            };
 
-                if compare::private_to_public(&key.rsa, &rsa).is_ok()
-                {
-                    chain.certificates.push(certificate);
-                }
-            }
+            names.push(format!("IP:{}", address));
+        }
+        else if let Some(email) = name.email()
+        {
+            names.push(format!("email:{}", email));
+        }
+        else if let Some(uri) = name.uri()
+        {
+            names.push(format!("URI:{}", uri));
         }
     }
+
+    names
 }
 
-/// Locate signing certificates for all existing chains.
-fn attach_signing_certificates(chains: &mut Vec<Chain>, paths: &Vec<String>)
+/// Name of a public key algorithm.
+pub fn key_algorithm(id: Id) -> &'static str
 {
-    for chain in chains
+    match id
     {
-        // Iterate (mutably) over known certificates, looking for
-        // signing certificates for each.
-        for certificate in chain.certificates.iter_mut()
-        {
-            attach_signing_certificate_chain(certificate, &paths);
-        }
+        Id::RSA => "RSA",
+        Id::DSA => "DSA",
+        Id::EC => "EC",
+        Id::ED25519 => "Ed25519",
+        Id::ED448 => "Ed448",
+        _ => "unknown",
     }
 }
 
-/// Recursively apply signing certificates.
-fn attach_signing_certificate_chain(certificate: &mut CertificateFile, paths: &Vec<String>)
+/// Match a certificate name against a lowercase hostname. A wildcard may
+/// only be the whole leftmost label, of a name with at least two more labels
+/// (so "*.com" matches nothing), and matches exactly one label.
+fn is_host_match(name: &str, host: &str) -> bool
 {
-    let mut certificates = find_certificates(&paths);
+    let name = name.trim_end_matches('.').to_ascii_lowercase();
 
-    for signing_certificate in certificates.iter_mut()
+    match name.strip_prefix("*.")
     {
-        if let Ok(()) = compare::certificate_to_signing_certificate(
-            &certificate.certificate,
-            &signing_certificate.certificate
-        )
+        Some(suffix) if suffix.contains('.') => match host.split_once('.')
         {
-            // Check if certificate is self-signed.
-            if certificate.certificate.signature().as_slice()
-                == signing_certificate.certificate.signature().as_slice()
-            {
-                certificate.self_signed = true;
-
-                return;
-            }
+            Some((label, rest)) => !label.is_empty() && rest == suffix,
+            None => false
+        },
+        Some(_) => false,
+        None => name == host
+    }
+}
 
-            // Copy signing certificate into certificate.signing_certificate.
-            certificate.signing_certificate = Some(Box::new(signing_certificate.clone()));
+/// How soon a certificate expires, relative to a warning period, ordered by
+/// severity.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Expiry
+{
+    Valid,
+    Expiring,
+    Expired,
+}
 
-            // Call this function recursively, but with signing_certificate
-            // as the first argument.
-            attach_signing_certificate_chain(signing_certificate, &paths)
+impl Expiry
+{
+    pub fn as_str(&self) -> &'static str
+    {
+        match self
+        {
+            Expiry::Valid => "ok",
+            Expiry::Expiring => "expiring",
+            Expiry::Expired => "expired",
         }
     }
 }
 
-/// Create vector containing all certificate files, including
-/// those which aren't associated with any known chain.
-fn find_certificates(paths: &Vec<String>) -> Vec<CertificateFile>
+/// Lightweight record of a certificate found in a file, kept in place of the
+/// parsed certificate so huge inventories needn't hold every certificate in
+/// memory. Only the DER encoding is kept besides what chains are matched up
+/// by; certificates are parsed again from it when needed (to verify
+/// signatures, or to show or export chains), never read from their files.
+#[derive(Clone)]
+pub struct CertificateRecord
 {
-    let mut certificates: Vec<CertificateFile> = vec![];
+    pub path: String,
 
-    for path in paths
-    {
-        let contents = get_file_contents(&path);
+    /// Position of the certificate among those in its file.
+    pub index: usize,
 
-        if contents.is_err() { continue; }
+    /// SHA-256 digest of the DER-encoded certificate.
+    pub fingerprint: Vec<u8>,
 
-        let contents = contents.unwrap();
+    /// DER-encoded subject name.
+    pub subject: Vec<u8>,
 
-        let certificate = str_to_x509(&contents);
+    /// DER-encoded issuer name.
+    pub issuer: Vec<u8>,
 
-        if certificate.is_err() { continue; }
+    /// Subject Key Identifier, if present.
+    pub subject_key_id: Option<Vec<u8>>,
 
-        let certificate = CertificateFile::new(&path, certificate.unwrap());
+    /// Digest of the public key (see key_digest).
+    pub public_key: Option<Vec<u8>>,
 
-        certificates.push(certificate);
-    }
+    /// Validity period, in seconds since the Unix epoch.
+    pub not_before: Option<i64>,
+    pub not_after: Option<i64>,
 
-    certificates
-}
+    pub self_signed: bool,
 
-/// Converts string slices to private keys.
-pub fn str_to_private_key(contents: &str) -> Result<Rsa<Private>, ErrorStack>
-{
-    Rsa::private_key_from_pem(contents.as_bytes())
-}
+    /// Other paths containing the same certificate.
+    pub duplicates: Vec<String>,
 
-/// Converts string slices to X509 certificate requests.
-pub fn str_to_x509req(contents: &str) -> Result<X509Req, ErrorStack>
-{
-    X509Req::from_pem(contents.as_bytes())
+    /// The DER-encoded certificate.
+    der: Vec<u8>,
 }
 
-/// Converts string slices to X509 certificates.
-pub fn str_to_x509(contents: &str) -> Result<X509, ErrorStack>
+impl CertificateRecord
 {
-    X509::from_pem(contents.as_bytes())
-}
+    fn new(path: &str, index: usize, certificate: &X509) -> Self
+    {
+        CertificateRecord {
+            path: path.to_string(),
+            index,
+            fingerprint: fingerprint(certificate),
+            subject: certificate.subject_name().to_der().unwrap_or_default(),
+            issuer: certificate.issuer_name().to_der().unwrap_or_default(),
+            subject_key_id: certificate.subject_key_id().map(|id| id.as_slice().to_vec()),
+            public_key: certificate.public_key().ok().and_then(|key| key_digest(&key)),
+            not_before: format::unix_time(certificate.not_before()),
+            not_after: format::unix_time(certificate.not_after()),
+            self_signed: trust::is_self_signed(certificate),
+            duplicates: vec![],
+            der: certificate.to_der().unwrap_or_default(),
+        }
+    }
 
-/// Wrapper for file reading operation, in case this is handled
+    /// Parse the certificate again.
+    pub fn certificate(&self) -> Option<X509>
+    {
+        X509::from_der(&self.der)
+            .map_err(|e| debug!("Couldn't parse certificate {} again: {}", self.path, e))
+            .ok()
+    }
+
+    /// Parse the certificate again, as found at its path (and its
+    /// duplicates).
+    pub fn load(&self) -> Option<CertificateFile>
+    {
+        Some(CertificateFile {
+            path: self.path.clone(),
+            certificate: self.certificate()?,
+            signing_certificate: None,
+            self_signed: self.self_signed,
+            duplicates: self.duplicates.clone(),
+            stopped: None,
+        })
+    }
+}
+
+/// SHA-256 digest of a DER-encoded certificate.
+fn fingerprint(certificate: &X509) -> Vec<u8>
+{
+    certificate.digest(MessageDigest::sha256()).map(|d| d.to_vec()).unwrap_or_default()
+}
+
+/// Why a file was skipped.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Skipped
+{
+    /// It couldn't be read (e.g. permission was denied).
+    Unreadable(String),
+
+    /// It isn't a key, request, or certificate.
+    Unrecognized,
+
+    /// It looked like a key, request, or certificate, but couldn't be
+    /// parsed, e.g. "invalid certificate".
+    Invalid(String),
+}
+
+impl fmt::Display for Skipped
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        match self
+        {
+            Skipped::Unreadable(reason) | Skipped::Invalid(reason) => write!(f, "{}", reason),
+            Skipped::Unrecognized => write!(f, "{}", Error::Unrecognized),
+        }
+    }
+}
+
+/// Keys, requests, and certificates found in a set of paths. Each file is
+/// read and parsed once, and chains are then matched up in memory using
+/// certificate records; only the certificates which chains need are parsed
+/// again.
+#[derive(Default)]
+pub struct Inventory
+{
+    /// Private keys, in path order.
+    pub keys: Vec<PrivateKeyFile>,
+
+    /// Certificate signing requests, in path order.
+    pub requests: Vec<CertificateRequestFile>,
+
+    /// Certificates, in path order. Files containing several certificates
+    /// (bundles, combined PEM files) contribute one record per certificate.
+    /// Certificates found at several paths are only included once, with the
+    /// other paths recorded as duplicates.
+    pub certificates: Vec<CertificateRecord>,
+
+    /// Files which couldn't be read or parsed, as (path, reason) pairs.
+    pub skipped: Vec<(String, Skipped)>,
+
+    /// Indexes of requests, by public key digest (see key_digest).
+    requests_by_key: HashMap<Vec<u8>, Vec<usize>>,
+
+    /// Indexes of certificates, by public key digest (see key_digest).
+    certificates_by_key: HashMap<Vec<u8>, Vec<usize>>,
+
+    /// Indexes of certificates, by DER-encoded subject name.
+    certificates_by_subject: HashMap<Vec<u8>, Vec<usize>>,
+
+    /// Indexes of certificates, by fingerprint.
+    certificates_by_fingerprint: HashMap<Vec<u8>, usize>,
+
+    /// What files held when they were last read (see --cache).
+    cache: Option<Cache>,
+
+    /// Contents of streams (standard input, FIFOs, character devices),
+    /// keyed by path, as they can only be consumed once.
+    streams: HashMap<String, Vec<u8>>,
+}
+
+impl Inventory
+{
+    /// Start an empty inventory, to which items are added with add_path and
+    /// add_pem_bytes before chains are built with build_chains, e.g.
+    /// `Inventory::new().add_pem_bytes(name, bytes).add_path(path).build_chains()`.
+    pub fn new() -> Inventory
+    {
+        Inventory::default()
+    }
+
+    /// Read and parse a file, as with ingest.
+    pub fn add_path(mut self, path: &str) -> Inventory
+    {
+        self.read(&[path.to_string()]);
+
+        self
+    }
+
+    /// Alias of add_bytes which takes and returns the inventory, so calls
+    /// can be chained. Despite the name, contents needn't be PEM: they're
+    /// parsed as files are (PEM, DER, unarmored base64 DER, or an OpenSSH
+    /// private key).
+    pub fn add_pem_bytes(mut self, name: &str, contents: &[u8]) -> Inventory
+    {
+        self.add_bytes(name, contents);
+
+        self
+    }
+
+    /// Build each Chain instance (see build_each).
+    pub fn build_chains(&self) -> error::Result<Vec<Chain>>
+    {
+        let mut chains = vec![];
+
+        build_each(self, |chain| chains.push(chain))?;
+
+        Ok(chains)
+    }
+
+    /// Read and parse each path once.
+    pub fn ingest(paths: &[String]) -> Inventory
+    {
+        let mut inventory = Inventory::default();
+
+        inventory.read(paths);
+
+        inventory
+    }
+
+    /// Read and parse each path once, using (and then updating) a cache of
+    /// what files held when they were last read.
+    pub fn ingest_cached(paths: &[String], cache: Cache) -> Inventory
+    {
+        let mut inventory = Inventory { cache: Some(cache), ..Default::default() };
+
+        inventory.read(paths);
+
+        if let Some(Err(e)) = inventory.cache.as_mut().map(Cache::save)
+        {
+            warn!("Couldn't save cache: {}", e);
+        }
+
+        inventory
+    }
+
+    /// Read files again after they've changed (e.g. with --watch), replacing
+    /// what they held before, and forget removed files. Items read again are
+    /// added after the others.
+    pub fn rescan(&mut self, changed: &[String], removed: &[String])
+    {
+        let mut stale: HashSet<String> = changed.iter().chain(removed).cloned().collect();
+        let mut reread: Vec<String> = changed.to_vec();
+
+        // A certificate found at several paths is recorded against the first
+        // only, so when that path is stale, the others are read again too
+        // (as are their other certificates' other paths, and so on).
+        loop
+        {
+            let others: Vec<String> = self.certificates.iter()
+                .filter(|record| stale.contains(&record.path))
+                .flat_map(|record| record.duplicates.iter())
+                .filter(|path| !stale.contains(*path))
+                .cloned()
+                .collect();
+
+            if others.is_empty()
+            {
+                break;
+            }
+
+            for path in others
+            {
+                reread.push(path.clone());
+                stale.insert(path);
+            }
+        }
+
+        self.keys.retain(|key| !stale.contains(&key.path));
+        self.skipped.retain(|(path, _)| !stale.contains(path));
+
+        // Indexes of the items kept, by their old index.
+        let requests = std::mem::take(&mut self.requests);
+        let mut request_indexes = vec![None; requests.len()];
+
+        for (index, request) in requests.into_iter().enumerate()
+        {
+            if !stale.contains(&request.path)
+            {
+                request_indexes[index] = Some(self.requests.len());
+                self.requests.push(request);
+            }
+        }
+
+        let certificates = std::mem::take(&mut self.certificates);
+        let mut certificate_indexes = vec![None; certificates.len()];
+
+        for (index, mut record) in certificates.into_iter().enumerate()
+        {
+            if !stale.contains(&record.path)
+            {
+                record.duplicates.retain(|path| !stale.contains(path));
+                certificate_indexes[index] = Some(self.certificates.len());
+                self.certificates.push(record);
+            }
+        }
+
+        let reindex = |index: &mut HashMap<Vec<u8>, Vec<usize>>, indexes: &[Option<usize>]| {
+            for entries in index.values_mut()
+            {
+                *entries = entries.iter().filter_map(|&entry| indexes[entry]).collect();
+            }
+
+            index.retain(|_, entries| !entries.is_empty());
+        };
+
+        reindex(&mut self.requests_by_key, &request_indexes);
+        reindex(&mut self.certificates_by_key, &certificate_indexes);
+        reindex(&mut self.certificates_by_subject, &certificate_indexes);
+
+        self.certificates_by_fingerprint = self.certificates_by_fingerprint.drain()
+            .filter_map(|(fingerprint, index)| Some((fingerprint, certificate_indexes[index]?)))
+            .collect();
+
+        // Removed files (which may have been found again at other paths)
+        // aren't read.
+        reread.retain(|path| !removed.contains(path));
+
+        self.read(&reread);
+    }
+
+    /// Add contents held in memory rather than read from a file (e.g. from
+    /// a network or database), naming them (in place of a path) with name.
+    /// Contents which aren't a key, request, or certificate are skipped.
+    pub fn add_bytes(&mut self, name: &str, contents: &[u8])
+    {
+        self.add(name, contents);
+    }
+
+    fn read(&mut self, paths: &[String])
+    {
+        for path in paths
+        {
+            match self.cache.as_ref().and_then(|cache| cache.lookup(path))
+            {
+                Some(cache::Entry::Skipped(reason)) => {
+                    info!("Read {} (cached): {}", path, reason);
+
+                    self.skipped.push((path.clone(), reason));
+                },
+                Some(cache::Entry::Holds(items)) => self.add_cached(path, items),
+                None => {
+                    let read = self.read_contents(path).map(|contents| Parsed::new(&contents));
+
+                    self.add_read(path, read)
+                }
+            }
+        }
+    }
+
+    /// Add the items the cache holds for an unchanged file. Private keys
+    /// aren't cached, so files holding one are read again (and parsed as a
+    /// key alone, or afresh if that fails).
+    fn add_cached(&mut self, path: &str, items: cache::Items)
+    {
+        let cache::Items { key, request, certificates } = items;
+
+        let key = match key
+        {
+            true => match self.read_contents(path)
+            {
+                Ok(contents) => match bytes_to_private_key(&contents)
+                {
+                    Ok(key) => Ok(key),
+                    Err(_) => return self.add(path, &contents)
+                },
+                Err(e) => return self.add_read(path, Err(e))
+            },
+            false => Err(ErrorStack::get())
+        };
+
+        self.insert(path, Parsed::from_items(key, request.ok_or_else(ErrorStack::get), Ok(certificates), &[]), false);
+    }
+
+    /// What a file held, as get_file_contents reads it, except that streams
+    /// already read into the inventory (e.g. standard input) give the
+    /// contents kept then.
+    pub fn contents(&self, path: &str) -> Result<Vec<u8>, io::Error>
+    {
+        match self.streams.get(path)
+        {
+            Some(contents) => Ok(contents.clone()),
+            None => get_file_contents(path)
+        }
+    }
+
+    /// Read a file, keeping the contents of streams (see contents).
+    fn read_contents(&mut self, path: &str) -> Result<Vec<u8>, io::Error>
+    {
+        let contents = self.contents(path)?;
+
+        if reads_once(path) && !self.streams.contains_key(path)
+        {
+            self.streams.insert(path.to_string(), contents.clone());
+        }
+
+        Ok(contents)
+    }
+
+    /// Add the items parsed from a file, or note that it couldn't be read.
+    fn add_read(&mut self, path: &str, read: Result<Parsed, io::Error>)
+    {
+        match read
+        {
+            Ok(parsed) => self.insert(path, parsed, true),
+            Err(e) => {
+                info!("Couldn't read {}: {}", path, e);
+
+                self.skipped.push((path.to_string(), Skipped::Unreadable(e.to_string())));
+            }
+        }
+    }
+
+    /// Add the items parsed on a blocking thread (see ingest_async), keeping
+    /// the contents of streams.
+    #[cfg(feature = "async")]
+    fn add_joined(&mut self, path: &str, joined: Result<(Parsed, Option<Vec<u8>>), io::Error>)
+    {
+        let read = joined.map(|(parsed, contents)| {
+            if let Some(contents) = contents
+            {
+                self.streams.insert(path.to_string(), contents);
+            }
+
+            parsed
+        });
+
+        self.add_read(path, read);
+    }
+
+    /// Parse a file's contents, adding what it holds.
+    fn add(&mut self, path: &str, contents: &[u8])
+    {
+        self.insert(path, Parsed::new(contents), true);
+    }
+
+    /// Add the items parsed from a file, recording them in the cache (if
+    /// any) unless they came from it.
+    fn insert(&mut self, path: &str, parsed: Parsed, store: bool)
+    {
+        let Parsed { key, request, certificates, reason } = parsed;
+
+        // Record what files not seen before hold.
+        if let Some(cache) = self.cache.as_mut().filter(|_| store)
+        {
+            let mut items = vec![];
+
+            items.extend(key.as_ref().ok().map(|k| cache::key(k)));
+            items.extend(request.as_ref().ok().map(|r| cache::request(r)));
+            items.extend(certificates.iter().flatten().map(|c| cache::certificate(c)));
+
+            cache.store(path, items, reason.as_ref().err().map(Skipped::to_string).as_deref());
+        }
+
+        match reason
+        {
+            Ok(parsed) => info!("Read {}: {}", path, parsed),
+            Err(reason) => {
+                info!("Read {}: {}", path, reason);
+
+                self.skipped.push((path.to_string(), reason));
+
+                return;
+            }
+        }
+
+        if let Ok(key) = key
+        {
+            self.keys.push(PrivateKeyFile::new(path, key));
+        }
+
+        if let Ok(request) = request
+        {
+            match request.public_key().ok().and_then(|k| key_digest(&k))
+            {
+                Some(digest) => self.requests_by_key.entry(digest).or_default().push(self.requests.len()),
+                None => debug!("Request {} can't match a key, as its public key couldn't be read", path)
+            }
+
+            self.requests.push(CertificateRequestFile::new(path, request));
+        }
+
+        for (index, certificate) in certificates.into_iter().flatten().enumerate()
+        {
+            let record = CertificateRecord::new(path, index, &certificate);
+
+            match self.certificates_by_fingerprint.get(&record.fingerprint)
+            {
+                Some(&existing) => {
+                    let existing = &mut self.certificates[existing];
+
+                    if existing.path != path && !existing.duplicates.iter().any(|d| d == path)
+                    {
+                        existing.duplicates.push(path.to_string());
+                    }
+                },
+                None => {
+                    let index = self.certificates.len();
+
+                    match &record.public_key
+                    {
+                        Some(digest) => self.certificates_by_key.entry(digest.clone()).or_default().push(index),
+                        None => debug!("Certificate {} can't match a key, as its public key couldn't be read", path)
+                    }
+
+                    self.certificates_by_subject.entry(record.subject.clone()).or_default().push(index);
+                    self.certificates_by_fingerprint.insert(record.fingerprint.clone(), index);
+                    self.certificates.push(record);
+                }
+            }
+        }
+    }
+
+    /// Read and parse each path once, as with ingest, without blocking an
+    /// async runtime. Files are read and parsed on tokio's blocking thread
+    /// pool (as tokio::fs does), with at most concurrency files in flight at
+    /// a time; items are still added in path order. Must be called within a
+    /// tokio runtime.
+    #[cfg(feature = "async")]
+    pub async fn ingest_async(paths: &[String], concurrency: usize) -> Inventory
+    {
+        let mut inventory = Inventory::default();
+        let mut pending: std::collections::VecDeque<(String, _)> = Default::default();
+
+        for path in paths
+        {
+            // Wait for the oldest file before starting another, so files
+            // read out of order don't pile up in memory.
+            if pending.len() >= concurrency.max(1)
+            {
+                if let Some((path, task)) = pending.pop_front()
+                {
+                    inventory.add_joined(&path, join(task).await);
+                }
+            }
+
+            let owned = path.clone();
+
+            pending.push_back((path.clone(), tokio::task::spawn_blocking(move || {
+                get_file_contents(&owned).map(|contents| {
+                    let parsed = Parsed::new(&contents);
+
+                    (parsed, reads_once(&owned).then_some(contents))
+                })
+            })));
+        }
+
+        while let Some((path, task)) = pending.pop_front()
+        {
+            inventory.add_joined(&path, join(task).await);
+        }
+
+        inventory
+    }
+
+    /// Find requests for a private key.
+    fn requests_for<'a>(&'a self, key: &'a PrivateKeyFile) -> impl Iterator<Item = &'a CertificateRequestFile>
+    {
+        self.lookup(&self.requests_by_key, key)
+            .map(|&index| &self.requests[index])
+            .filter(|request| is_match(key, "Request", &request.path, request.public_key()))
+    }
+
+    /// Find (and load) certificates for a private key.
+    fn certificates_for<'a>(&'a self, key: &'a PrivateKeyFile) -> impl Iterator<Item = CertificateFile> + 'a
+    {
+        self.lookup(&self.certificates_by_key, key)
+            .filter_map(|&index| {
+                let record = &self.certificates[index];
+                let certificate = record.load();
+
+                if certificate.is_none()
+                {
+                    debug!("Certificate {} has key {}'s public key, but couldn't be parsed again", record.path, key.path);
+                }
+
+                certificate
+            })
+            .filter(|certificate| is_match(key, "Certificate", &certificate.path, certificate.public_key()))
+    }
+
+    /// How many keys, requests, and certificates were read from a file.
+    pub fn items_in(&self, path: &str) -> usize
+    {
+        self.keys.iter().filter(|key| key.path == path).count()
+            + self.requests.iter().filter(|request| request.path == path).count()
+            + self.certificates.iter().filter(|record| record.path == path || record.duplicates.iter().any(|duplicate| duplicate == path)).count()
+    }
+
+    /// Find (and load) certificates which have signed a certificate.
+    ///
+    /// Only certificates whose subject is the certificate's issuer, and whose
+    /// Subject Key Identifier matches its Authority Key Identifier (when both
+    /// are present), are candidates; signatures are then verified on those
+    /// alone. Cross-signed CAs (sharing a subject and key) may yield several.
+    pub fn issuers_of(&self, certificate: &X509) -> Vec<CertificateFile>
+    {
+        let issuer = certificate.issuer_name().to_der().unwrap_or_default();
+        let authority_key_id = certificate.authority_key_id().map(|id| id.as_slice().to_vec());
+
+        self.certificates_by_subject.get(&issuer)
+            .map(|indexes| indexes.iter())
+            .unwrap_or_default()
+            .filter(|&&index| {
+                match (&authority_key_id, &self.certificates[index].subject_key_id)
+                {
+                    (Some(authority), Some(subject)) => authority == subject,
+                    _ => true
+                }
+            })
+            .filter_map(|&index| self.certificates[index].load())
+            .filter(|candidate| {
+                compare::certificate_to_signing_certificate(certificate, &candidate.certificate).is_ok()
+            })
+            .collect()
+    }
+
+    fn lookup<'a>(&self, index: &'a HashMap<Vec<u8>, Vec<usize>>, key: &PrivateKeyFile) -> std::slice::Iter<'a, usize>
+    {
+        key_digest(&key.key)
+            .and_then(|digest| index.get(&digest))
+            .map(|indexes| indexes.iter())
+            .unwrap_or_default()
+    }
+}
+
+/// Items parsed from a file's contents, before they're added to an
+/// inventory (so files can be parsed on other threads).
+struct Parsed
+{
+    key: Result<PKey<Private>, ErrorStack>,
+    request: Result<X509Req, ErrorStack>,
+    certificates: Result<Vec<X509>, ErrorStack>,
+
+    /// What the file holds (e.g. "private key, certificate"), or why it was
+    /// skipped.
+    reason: Result<String, Skipped>,
+}
+
+impl Parsed
+{
+    /// Parse contents as each kind of item.
+    fn new(contents: &[u8]) -> Parsed
+    {
+        let key = bytes_to_private_key(contents);
+        let request = bytes_to_x509req(contents);
+        let certificates = bytes_to_x509_stack(contents);
+
+        Parsed::from_items(key, request, certificates, contents)
+    }
+
+    /// Describe the items parsed from contents (or rebuilt from the cache).
+    fn from_items(
+        key: Result<PKey<Private>, ErrorStack>,
+        request: Result<X509Req, ErrorStack>,
+        certificates: Result<Vec<X509>, ErrorStack>,
+        contents: &[u8]
+    ) -> Parsed
+    {
+        let mut parsed = vec![];
+
+        if key.is_ok()
+        {
+            parsed.push(String::from("private key"));
+        }
+
+        if request.is_ok()
+        {
+            parsed.push(String::from("certificate signing request"));
+        }
+
+        match certificates.as_ref().map(|c| c.len())
+        {
+            Ok(1) => parsed.push(String::from("certificate")),
+            Ok(count) if count > 1 => parsed.push(format!("{} certificates", count)),
+            _ => {}
+        }
+
+        let reason = match parsed.is_empty()
+        {
+            true => Err(skipped_reason(contents, key.as_ref().err(), request.as_ref().err(), certificates.as_ref().err())),
+            false => Ok(parsed.join(", "))
+        };
+
+        Parsed { key, request, certificates, reason }
+    }
+}
+
+/// Wait for a file to be read and parsed on the blocking thread pool.
+#[cfg(feature = "async")]
+async fn join<T>(task: tokio::task::JoinHandle<Result<T, io::Error>>) -> Result<T, io::Error>
+{
+    task.await.unwrap_or_else(|e| Err(io::Error::other(e)))
+}
+
+/// Confirm that a public key (found by its digest) belongs to a private key,
+/// logging why not (e.g. it couldn't be read) for the kind of item at path.
+fn is_match(key: &PrivateKeyFile, kind: &str, path: &str, public_key: Result<PKey<Public>, ErrorStack>) -> bool
+{
+    let matched = public_key.map_err(Error::from).and_then(|public_key| compare::private_to_public(&key.key, &public_key));
+
+    if let Err(e) = &matched
+    {
+        debug!("{} {} doesn't match key {}: {}", kind, path, key.path, e);
+    }
+
+    matched.is_ok()
+}
+
+/// Determine whether a certificate was signed by another's key (and names
+/// it as its issuer).
+pub fn signs(issuer: &X509, certificate: &X509) -> bool
+{
+    issuer.subject_name().to_der().ok() == certificate.issuer_name().to_der().ok()
+        && compare::certificate_to_signing_certificate(certificate, issuer).is_ok()
+}
+
+/// Digest of a key's public portion (its DER SubjectPublicKeyInfo), so keys,
+/// requests, and certificates can be matched with a lookup rather than by
+/// comparing each pair.
+pub fn key_digest<T: HasPublic>(key: &PKeyRef<T>) -> Option<Vec<u8>>
+{
+    let der = key.public_key_to_der().ok()?;
+
+    hash::hash(MessageDigest::sha256(), &der).ok().map(|digest| digest.to_vec())
+}
+
+/// Explain why a file couldn't be parsed, distinguishing files which don't
+/// look like any supported format from corrupted ones.
+fn skipped_reason(
+    contents: &[u8],
+    key: Option<&ErrorStack>,
+    request: Option<&ErrorStack>,
+    certificates: Option<&ErrorStack>
+) -> Skipped
+{
+    // Report the error of the parser matching the PEM label, if any.
+    let text = String::from_utf8_lossy(contents);
+
+    let error = if text.contains("PRIVATE KEY-----")
+    {
+        Some(("private key", key))
+    }
+    else if text.contains("CERTIFICATE REQUEST-----")
+    {
+        Some(("certificate signing request", request))
+    }
+    else if text.contains("CERTIFICATE-----")
+    {
+        Some(("certificate", certificates))
+    }
+    else
+    {
+        None
+    };
+
+    match error
+    {
+        Some((kind, Some(e))) if !e.errors().is_empty() => Skipped::Invalid(format!("invalid {}: {}", kind, e)),
+        Some((kind, _)) => Skipped::Invalid(format!("invalid {}", kind)),
+        None => Skipped::Unrecognized
+    }
+}
+
+/// Begin building each Chain instance.
+pub fn build(paths: Vec<String>) -> error::Result<Vec<Chain>>
+{
+    Inventory::ingest(&paths).build_chains()
+}
+
+/// Chains are assembled in parallel in batches of this many keys, so they
+/// can still be streamed as each batch completes.
+const BATCH_SIZE: usize = 256;
+
+/// Build each Chain instance, passing each to a callback as soon as it's
+/// complete, so results can be streamed.
+///
+/// Chains are independent of each other, so they're assembled in parallel,
+/// but passed to the callback in key order.
+pub fn build_each<F: FnMut(Chain)>(inventory: &Inventory, mut callback: F) -> error::Result<()>
+{
+    for keys in inventory.keys.chunks(BATCH_SIZE)
+    {
+        let chains: Vec<Chain> = keys.par_iter().map(|key| build_chain(key, inventory)).collect();
+
+        chains.into_iter().for_each(&mut callback);
+    }
+
+    Ok(())
+}
+
+/// Create the chain for a private key.
+fn build_chain(key: &PrivateKeyFile, inventory: &Inventory) -> Chain
+{
+    let mut chain = Chain::new();
+
+    chain.key = Some(key.clone());
+
+    attach_certificate_signing_request(&mut chain, inventory);
+
+    attach_certificates(&mut chain, inventory);
+
+    attach_signing_certificates(&mut chain, inventory);
+
+    chain
+}
+
+/// Locate the certificate signing request for a chain.
+fn attach_certificate_signing_request(chain: &mut Chain, inventory: &Inventory)
+{
+    if let Some(key) = &chain.key
+    {
+        match inventory.requests_for(key).next().map(|request| (request, request.try_clone()))
+        {
+            Some((request, Ok(copy))) => {
+                debug!("Request {} matches key {}", request.path, key.path);
+
+                chain.request = Some(copy);
+            },
+            Some((request, Err(e))) => debug!("Request {} matches key {}, but couldn't be copied: {}", request.path, key.path, e),
+            None => debug!("No request matches key {} (none of the {} read has its public key)", key.path, inventory.requests.len())
+        }
+    }
+}
+
+/// Locate certificates for a chain, newest (by notBefore) first, as keys
+/// are often reused when certificates are renewed.
+fn attach_certificates(chain: &mut Chain, inventory: &Inventory)
+{
+    if let Some(key) = &chain.key
+    {
+        for certificate in inventory.certificates_for(key)
+        {
+            debug!("Certificate {} matches key {}", certificate.path, key.path);
+
+            chain.certificates.push(certificate);
+        }
+
+        if chain.certificates.is_empty()
+        {
+            debug!("No certificate matches key {} (none of the {} read has its public key)", key.path, inventory.certificates.len());
+        }
+    }
+
+    chain.certificates.sort_by(|a, b| {
+        b.certificate.not_before().partial_cmp(a.certificate.not_before()).unwrap_or(Ordering::Equal)
+    });
+}
+
+/// Locate signing certificates for a chain.
+fn attach_signing_certificates(chain: &mut Chain, inventory: &Inventory)
+{
+    // Iterate (mutably) over known certificates, looking for
+    // signing certificates for each.
+    for certificate in chain.certificates.iter_mut()
+    {
+        attach_signing_certificate_chain(certificate, inventory, &mut vec![]);
+    }
+}
+
+/// Files which were parsed, but aren't part of any chain.
+#[derive(Default)]
+pub struct Orphans
+{
+    /// Keys without any certificates.
+    pub keys: Vec<String>,
+
+    /// Certificate signing requests without keys.
+    pub requests: Vec<String>,
+
+    /// Certificates without keys, which haven't signed other certificates.
+    pub certificates: Vec<String>,
+
+    /// Certificates which have signed other certificates, but aren't part
+    /// of any chain.
+    pub intermediates: Vec<String>,
+}
+
+/// Find parsed files which don't belong to any of the given chains.
+pub fn find_orphans(inventory: &Inventory, chains: &[Chain]) -> Orphans
+{
+    let mut orphans = Orphans::default();
+
+    let mut used_requests = vec![];
+    let mut used_certificates = vec![];
+
+    for chain in chains
+    {
+        if let Some(key) = &chain.key
+        {
+            if chain.certificates.is_empty()
+            {
+                orphans.keys.push(key.path.clone());
+            }
+        }
+
+        if let Some(request) = &chain.request
+        {
+            used_requests.push((request.path.clone(), request.request.to_der().unwrap_or_default()));
+        }
+
+        for certificate in &chain.certificates
+        {
+            used_certificates.push((certificate.path.clone(), fingerprint(&certificate.certificate)));
+
+            for signing_certificate in certificate.signing_certificate_chain()
+            {
+                used_certificates.push((signing_certificate.path.clone(), fingerprint(&signing_certificate.certificate)));
+            }
+        }
+    }
+
+    for request in &inventory.requests
+    {
+        let der = request.request.to_der().unwrap_or_default();
+
+        if !used_requests.iter().any(|(p, d)| *p == request.path && *d == der)
+        {
+            orphans.requests.push(request.path.clone());
+        }
+    }
+
+    // Certificates which have signed others (besides themselves).
+    let mut signers = vec![];
+
+    for other in inventory.certificates.iter().filter_map(CertificateRecord::load)
+    {
+        for issuer in inventory.issuers_of(&other.certificate)
+        {
+            if issuer.certificate != other.certificate
+            {
+                signers.push(fingerprint(&issuer.certificate));
+            }
+        }
+    }
+
+    for certificate in &inventory.certificates
+    {
+        if used_certificates.iter().any(|(p, f)| *p == certificate.path && *f == certificate.fingerprint)
+        {
+            continue;
+        }
+
+        if signers.contains(&certificate.fingerprint)
+        {
+            orphans.intermediates.push(certificate.path.clone());
+        }
+        else
+        {
+            orphans.certificates.push(certificate.path.clone());
+        }
+    }
+
+    orphans
+}
+
+/// Signing certificate chains are followed at most this many levels, so
+/// pathological inputs terminate.
+const MAX_CHAIN_DEPTH: usize = 16;
+
+/// Recursively apply signing certificates.
+///
+/// Certificates already in the chain (by SHA-256 fingerprint) aren't used
+/// again, so mutually-signed or cross-signed CAs can't cause a loop. Where
+/// that (or the depth limit) stops the chain, the certificate it stops at
+/// records why.
+fn attach_signing_certificate_chain(certificate: &mut CertificateFile, inventory: &Inventory, visited: &mut Vec<Vec<u8>>)
+{
+    visited.push(fingerprint(&certificate.certificate));
+
+    // Self-signed certificates (detected as they're read) end the chain.
+    if certificate.self_signed
+    {
+        return;
+    }
+
+    if visited.len() > MAX_CHAIN_DEPTH
+    {
+        debug!("Stopped following the signing chain of {} at {} levels", certificate.path, MAX_CHAIN_DEPTH);
+
+        certificate.stopped = Some(Stopped::Depth(MAX_CHAIN_DEPTH));
+
+        return;
+    }
+
+    let mut issuers = inventory.issuers_of(&certificate.certificate);
+
+    let mut signing_certificate = match issuers.iter().position(|issuer| !visited.contains(&fingerprint(&issuer.certificate)))
+    {
+        Some(index) => issuers.swap_remove(index),
+        None => {
+            if let Some(issuer) = issuers.first()
+            {
+                debug!("Certificate {} is signed by {}, which is already in its chain", certificate.path, issuer.path);
+
+                certificate.stopped = Some(Stopped::Loop(format::format_name(issuer.certificate.subject_name())));
+            }
+
+            return;
+        }
+    };
+
+    debug!("Certificate {} is signed by {}", certificate.path, signing_certificate.path);
+
+    // Complete the signing certificate's own chain before moving it into
+    // certificate.signing_certificate.
+    attach_signing_certificate_chain(&mut signing_certificate, inventory, visited);
+
+    certificate.signing_certificate = Some(Box::new(signing_certificate));
+}
+
+/// Converts file contents to private keys.
+///
+/// PEM, DER, and OpenSSH-format keys are accepted.
+pub fn bytes_to_private_key(contents: &[u8]) -> Result<PKey<Private>, ErrorStack>
+{
+    let text = std::str::from_utf8(contents).ok();
+
+    PKey::private_key_from_pem(contents)
+        .or_else(|e| PKey::private_key_from_der(contents).map_err(|_| e))
+        .or_else(|e| match text.map(openssh::str_to_private_key)
+        {
+            Some(Ok(rsa)) => PKey::from_rsa(rsa),
+            _ => Err(e)
+        })
+        .or_else(|e| match text.and_then(decode_unarmored)
+        {
+            Some(der) => PKey::private_key_from_der(&der),
+            None => Err(e)
+        })
+}
+
+/// Converts file contents to X509 certificate requests.
+///
+/// DER-encoded requests (e.g. from Windows CertSrv) are accepted when the
+/// contents aren't PEM.
+pub fn bytes_to_x509req(contents: &[u8]) -> Result<X509Req, ErrorStack>
+{
+    X509Req::from_pem(contents)
+        .or_else(|e| X509Req::from_der(contents).map_err(|_| e))
+        .or_else(|e| match std::str::from_utf8(contents).ok().and_then(decode_unarmored)
+        {
+            Some(der) => X509Req::from_der(&der),
+            None => Err(e)
+        })
+}
+
+/// Converts file contents to X509 certificates.
+pub fn bytes_to_x509(contents: &[u8]) -> Result<X509, ErrorStack>
+{
+    X509::from_pem(contents)
+        .or_else(|e| bytes_to_x509_stack(contents)?.into_iter().next().ok_or(e))
+}
+
+/// Converts file contents to all X509 certificates they contain.
+///
+/// PEM (including "trusted certificate" blocks) and DER certificates are
+/// accepted.
+pub fn bytes_to_x509_stack(contents: &[u8]) -> Result<Vec<X509>, ErrorStack>
+{
+    let mut certificates = X509::stack_from_pem(contents)?;
+
+    let text = std::str::from_utf8(contents).ok();
+
+    if let Some(text) = text
+    {
+        certificates.extend(trusted_pem_to_x509_stack(text));
+    }
+
+    if certificates.is_empty()
+    {
+        match text.and_then(decode_unarmored)
+        {
+            Some(der) => certificates.extend(X509::from_der(&der)),
+            None => certificates.extend(X509::from_der(contents))
+        }
+    }
+
+    Ok(certificates)
+}
+
+/// Decode base64 DER content delivered without PEM armor (as offered by
+/// some vendor portals).
+///
+/// Content must consist solely of base64 characters, and decode to an
+/// ASN.1 SEQUENCE, which all supported DER structures begin with.
+fn decode_unarmored(contents: &str) -> Option<Vec<u8>>
+{
+    let encoded: String = contents.chars().filter(|c| !c.is_whitespace()).collect();
+
+    let is_base64 = |c: char| c.is_ascii_alphanumeric() || c == '+' || c == '/' || c == '=';
+
+    if encoded.len() < 4 || !encoded.len().is_multiple_of(4) || !encoded.chars().all(is_base64)
+    {
+        return None;
+    }
+
+    let der = openssl::base64::decode_block(&encoded).ok()?;
+
+    if der.first() != Some(&0x30)
+    {
+        return None;
+    }
+
+    Some(der)
+}
+
+/// Converts OpenSSL "trusted certificate" PEM blocks (as found in distro CA
+/// files) to X509 certificates.
+///
+/// These blocks hold a DER certificate followed by trust settings, which
+/// are ignored; only the leading certificate is decoded.
+fn trusted_pem_to_x509_stack(contents: &str) -> Vec<X509>
+{
+    const BEGIN: &str = "-----BEGIN TRUSTED CERTIFICATE-----";
+    const END: &str = "-----END TRUSTED CERTIFICATE-----";
+
+    let mut certificates = vec![];
+    let mut remaining = contents;
+
+    while let Some(start) = remaining.find(BEGIN)
+    {
+        let block = &remaining[start + BEGIN.len()..];
+
+        let end = match block.find(END)
+        {
+            Some(end) => end,
+            None => break
+        };
+
+        let encoded: String = block[..end].chars().filter(|c| !c.is_whitespace()).collect();
+
+        if let Ok(der) = openssl::base64::decode_block(&encoded)
+        {
+            if let Ok(certificate) = X509::from_der(&der)
+            {
+                certificates.push(certificate);
+            }
+        }
+
+        remaining = &block[end + END.len()..];
+    }
+
+    certificates
+}
+
+/// Path used for content piped in on standard input.
+pub const STDIN_PATH: &str = "(stdin)";
+
+/// Streams are read up to this many bytes, as some (e.g. /dev/zero) never end.
+const STREAM_SIZE_LIMIT: u64 = 16 * 1024 * 1024;
+
+/// Wrapper for file reading operation, in case this is handled
 /// differently later.
-pub fn get_file_contents(path: &str) -> Result<String, io::Error>
+///
+/// Contents are read as bytes, so binary (DER) formats can be parsed.
+/// Paths referring to archive members ("archive:member") are read from
+/// within the archive.
+pub fn get_file_contents(path: &str) -> Result<Vec<u8>, io::Error>
+{
+    if reads_once(path)
+    {
+        return get_stream_contents(path);
+    }
+
+    // Manifests are read through their members (see manifest::members), so
+    // explain why one read as a whole has none.
+    if manifest::is_manifest(path) && Path::new(path).is_file()
+    {
+        return Err(manifest::members(path).err().unwrap_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "Manifest is read through its members (e.g. manifest.yaml:tls.crt)")
+        }));
+    }
+
+    match fs::read(path)
+    {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => get_member_contents(path).unwrap_or(Err(e)),
+        result => result
+    }
+}
+
+/// Whether two paths name the same file (judging by their canonical paths,
+/// where they exist).
+pub fn same_file(a: &str, b: &str) -> bool
+{
+    a == b || match (fs::canonicalize(a), fs::canonicalize(b))
+    {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false
+    }
+}
+
+/// Whether a path names a regular file (and not a symbolic link), as only
+/// those are renamed or removed (see rename and cleanup).
+pub fn is_regular_file(path: &str) -> bool
+{
+    fs::symlink_metadata(path).map(|metadata| metadata.file_type().is_file()).unwrap_or(false)
+}
+
+/// Determine whether a path refers to a stream which can only be read once,
+/// such as a FIFO (e.g. from process substitution) or character device.
+#[cfg(unix)]
+pub fn is_stream(path: &str) -> bool
+{
+    use std::os::unix::fs::FileTypeExt;
+
+    fs::metadata(path)
+        .map(|md| md.file_type().is_fifo() || md.file_type().is_char_device())
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+pub fn is_stream(_path: &str) -> bool
+{
+    false
+}
+
+/// Determine whether a path is standard input or a stream, which can only
+/// be consumed once (so an Inventory keeps what it held).
+fn reads_once(path: &str) -> bool
+{
+    path == STDIN_PATH || is_stream(path)
+}
+
+/// Read a stream (or standard input), up to STREAM_SIZE_LIMIT bytes.
+fn get_stream_contents(path: &str) -> Result<Vec<u8>, io::Error>
+{
+    let mut contents = vec![];
+
+    if path == STDIN_PATH
+    {
+        io::stdin().take(STREAM_SIZE_LIMIT).read_to_end(&mut contents)?;
+    }
+    else
+    {
+        fs::File::open(path)?.take(STREAM_SIZE_LIMIT).read_to_end(&mut contents)?;
+    }
+
+    Ok(contents)
+}
+/// Read members of archives ("archive:member"), Kubernetes Secret manifests
+/// ("manifest.yaml:tls.crt"), and certificate databases
+/// ("cert9.db:nickname#id"), if the path refers to one.
+fn get_member_contents(path: &str) -> Option<Result<Vec<u8>, io::Error>>
+{
+    if let Some((archive, member)) = archive::split(path)
+    {
+        return Some(archive::read_member(archive, member, STREAM_SIZE_LIMIT));
+    }
+
+    if let Some((manifest, member)) = manifest::split(path)
+    {
+        return Some(manifest::read_member(manifest, member));
+    }
+
+    #[cfg(feature = "nss")]
+    if let Some((database, id)) = nss::split(path)
+    {
+        return Some(nss::read_member(database, id).map(String::into_bytes));
+    }
+
+    None
+}
+
+/// Get the container (archive, manifest, or certificate database) portion
+/// of a member path, if the path refers to a member.
+pub fn container_path(path: &str) -> Option<&str>
 {
-    fs::read_to_string(path)
+    if let Some((archive, _)) = archive::split(path)
+    {
+        return Some(archive);
+    }
+
+    if let Some((manifest, _)) = manifest::split(path)
+    {
+        return Some(manifest);
+    }
+
+    #[cfg(feature = "nss")]
+    if let Some((database, _)) = nss::split(path)
+    {
+        return Some(database);
+    }
+
+    None
 }
 
-#[cfg(test)]
-mod test
-{
-    use super::*;
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    #[test]
+    fn reads_private_keys()
+    {
+        let paths = vec![
+            "samples/ca_signed.key",
+            "samples/self_signed.key",
+            "samples/self_signed_san_no_csr_openssh.key",
+            "samples/self_signed_dsa.key",
+            "samples/gnutls/self_signed_ec.key",
+            "samples/gnutls/pkcs8_attributes.key",
+        ];
+
+        for path in paths
+        {
+            let contents = get_file_contents(path).unwrap();
+
+            assert!(bytes_to_private_key(&contents).is_ok());
+        }
+    }
+
+    #[test]
+    fn reads_certificate_requests()
+    {
+        let paths = vec!["samples/ca_signed.csr", "samples/self_signed.csr", "samples/self_signed_dsa.csr"];
+
+        for path in paths
+        {
+            let contents = get_file_contents(path).unwrap();
+
+            let request = CertificateRequestFile::new(&path, bytes_to_x509req(&contents).unwrap());
+
+            assert!(request.public_key().is_ok());
+        }
+    }
 
     #[test]
-    fn reads_private_keys()
+    fn reads_der_certificate_requests()
+    {
+        let contents = get_file_contents("samples/der/ca_signed.csr").unwrap();
+
+        let request = CertificateRequestFile::new("samples/der/ca_signed.csr", bytes_to_x509req(&contents).unwrap());
+
+        let pem = get_file_contents("samples/ca_signed.csr").unwrap();
+
+        assert_eq!(request.request.to_der().unwrap(), bytes_to_x509req(&pem).unwrap().to_der().unwrap());
+    }
+
+    #[test]
+    fn reads_der_certificates_and_keys()
+    {
+        let contents = get_file_contents("samples/der/ca_signed.crt").unwrap();
+        let certificate = bytes_to_x509_stack(&contents).unwrap();
+
+        let contents = get_file_contents("samples/ca_signed.crt").unwrap();
+        let pem = bytes_to_x509(&contents).unwrap();
+
+        assert_eq!(certificate.len(), 1);
+        assert_eq!(certificate[0].to_der().unwrap(), pem.to_der().unwrap());
+
+        let contents = get_file_contents("samples/der/ca_signed.key").unwrap();
+        let key = bytes_to_private_key(&contents).unwrap();
+
+        assert!(key.public_eq(&pem.public_key().unwrap()));
+    }
+
+    #[test]
+    fn reads_certificates()
     {
-        let paths = vec!["samples/ca_signed.key", "samples/self_signed.key"];
+        let paths = vec!["samples/ca_signed.crt", "samples/self_signed.crt", "samples/self_signed_dsa.crt"];
 
         for path in paths
         {
             let contents = get_file_contents(path).unwrap();
 
-            assert!(str_to_private_key(&contents).is_ok());
+            let certificate = CertificateFile::new(&path, bytes_to_x509(&contents).unwrap());
+
+            assert!(certificate.public_key().is_ok());
         }
     }
 
     #[test]
-    fn reads_certificate_requests()
+    fn builds_chains_from_combined_files()
     {
-        let paths = vec!["samples/ca_signed.csr", "samples/self_signed.csr"];
+        let paths = vec![String::from("samples/combined/ca_signed.pem")];
 
-        for path in paths
+        let chains = build(paths).unwrap();
+
+        assert_eq!(chains.len(), 1);
+
+        let chain = &chains[0];
+
+        assert!(chain.is_combined());
+        assert_eq!(chain.certificates.len(), 1);
+        assert_eq!(chain.certificates[0].signing_certificate_chain().len(), 1);
+    }
+
+    #[test]
+    fn reads_unarmored_certificates_and_requests()
+    {
+        let contents = get_file_contents("samples/unarmored/ca_signed.crt").unwrap();
+        let unarmored = bytes_to_x509_stack(&contents).unwrap();
+
+        let contents = get_file_contents("samples/ca_signed.crt").unwrap();
+        let certificate = bytes_to_x509(&contents).unwrap();
+
+        assert_eq!(unarmored.len(), 1);
+        assert_eq!(unarmored[0].to_der().unwrap(), certificate.to_der().unwrap());
+
+        let contents = get_file_contents("samples/unarmored/ca_signed.csr").unwrap();
+
+        assert!(bytes_to_x509req(&contents).is_ok());
+    }
+
+    #[test]
+    fn ignores_unarmored_non_der_content()
+    {
+        assert!(decode_unarmored("").is_none());
+        assert!(decode_unarmored("abcd").is_none());
+        assert!(decode_unarmored("not base64!").is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn reads_fifos_once()
+    {
+        use std::process::Command;
+        use std::thread;
+
+        let path = std::env::temp_dir().join(format!("sslchains-test-{}.fifo", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+
+        assert!(Command::new("mkfifo").arg(&path).status().unwrap().success());
+        assert!(is_stream(&path));
+
+        let writer = {
+            let path = path.clone();
+
+            thread::spawn(move || fs::write(&path, fs::read("samples/ca_signed.crt").unwrap()).unwrap())
+        };
+
+        let inventory = Inventory::ingest(std::slice::from_ref(&path));
+
+        writer.join().unwrap();
+
+        let contents = inventory.contents(&path).unwrap();
+
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(contents, fs::read("samples/ca_signed.crt").unwrap());
+        assert_eq!(inventory.certificates.len(), 1);
+    }
+
+    #[test]
+    fn reads_trusted_certificates()
+    {
+        let contents = get_file_contents("samples/trusted/intermediate_ca.crt").unwrap();
+        let trusted = bytes_to_x509(&contents).unwrap();
+
+        let contents = get_file_contents("samples/intermediate_ca.crt").unwrap();
+        let certificate = bytes_to_x509(&contents).unwrap();
+
+        assert_eq!(trusted.to_der().unwrap(), certificate.to_der().unwrap());
+    }
+
+    #[test]
+    fn builds_chains_from_archive_members()
+    {
+        let paths = vec![
+            String::from("samples/archives/ca_signed.tar.gz:ca_signed.key"),
+            String::from("samples/archives/ca_signed.tar.gz:ca_signed.crt"),
+            String::from("samples/archives/ca_signed.tar.gz:intermediate_ca.crt"),
+        ];
+
+        let chains = build(paths).unwrap();
+
+        assert_eq!(chains.len(), 1);
+        assert_eq!(chains[0].certificates.len(), 1);
+        assert_eq!(chains[0].certificates[0].signing_certificate_chain().len(), 1);
+    }
+
+    #[test]
+    fn orders_renewed_certificates()
+    {
+        let paths = vec![
+            String::from("samples/renewed/example.com.key"),
+            String::from("samples/renewed/example.com-2023.crt"),
+            String::from("samples/renewed/example.com-2025.crt"),
+            String::from("samples/renewed/example.com-2024.crt"),
+        ];
+
+        let chains = build(paths).unwrap();
+        let paths: Vec<&str> = chains[0].certificates.iter().map(|c| c.path.as_str()).collect();
+
+        assert_eq!(
+            paths,
+            vec![
+                "samples/renewed/example.com-2025.crt",
+                "samples/renewed/example.com-2024.crt",
+                "samples/renewed/example.com-2023.crt",
+            ]
+        );
+
+        assert_eq!(chains[0].lineage(0), Some("current"));
+        assert_eq!(chains[0].lineage(2), Some("superseded"));
+    }
+
+    #[test]
+    fn records_duplicate_certificates()
+    {
+        let paths = vec![
+            String::from("samples/ca_signed.key"),
+            String::from("samples/ca_signed.crt"),
+            String::from("samples/der/ca_signed.crt"),
+            String::from("samples/intermediate_ca.crt"),
+            String::from("samples/trusted/intermediate_ca.crt"),
+        ];
+
+        let chains = build(paths).unwrap();
+
+        assert_eq!(chains[0].certificates.len(), 1);
+        assert_eq!(chains[0].certificates[0].path, "samples/ca_signed.crt");
+        assert_eq!(chains[0].certificates[0].duplicates, vec![String::from("samples/der/ca_signed.crt")]);
+
+        let signing_certificates = chains[0].certificates[0].signing_certificate_chain();
+
+        assert_eq!(signing_certificates[0].path, "samples/intermediate_ca.crt");
+        assert_eq!(signing_certificates[0].duplicates, vec![String::from("samples/trusted/intermediate_ca.crt")]);
+    }
+
+    #[test]
+    fn finds_orphans()
+    {
+        let paths = vec![
+            String::from("samples/self_signed_san_no_cert.key"),
+            String::from("samples/self_signed_san_no_cert.csr"),
+            String::from("samples/self_signed.csr"),
+            String::from("samples/ca_signed.crt"),
+            String::from("samples/intermediate_ca.crt"),
+            String::from("samples/self_signed.crt"),
+        ];
+
+        let inventory = Inventory::ingest(&paths);
+        let chains = build(paths).unwrap();
+        let orphans = find_orphans(&inventory, &chains);
+
+        assert_eq!(orphans.keys, vec![String::from("samples/self_signed_san_no_cert.key")]);
+        assert_eq!(orphans.requests, vec![String::from("samples/self_signed.csr")]);
+        assert_eq!(
+            orphans.certificates,
+            vec![String::from("samples/ca_signed.crt"), String::from("samples/self_signed.crt")]
+        );
+        assert_eq!(orphans.intermediates, vec![String::from("samples/intermediate_ca.crt")]);
+    }
+
+    #[test]
+    fn indexes_public_keys()
+    {
+        let paths = vec![
+            String::from("samples/ca_signed.key"),
+            String::from("samples/self_signed.pem"),
+            String::from("samples/ca_signed.csr"),
+            String::from("samples/self_signed.crt"),
+            String::from("samples/ca_signed.crt"),
+        ];
+
+        let inventory = Inventory::ingest(&paths);
+
+        let paths_for = |key: &PrivateKeyFile| (
+            inventory.requests_for(key).map(|r| r.path.clone()).collect::<Vec<_>>(),
+            inventory.certificates_for(key).map(|c| c.path.clone()).collect::<Vec<_>>()
+        );
+
+        assert_eq!(
+            paths_for(&inventory.keys[0]),
+            (vec![String::from("samples/ca_signed.csr")], vec![String::from("samples/ca_signed.crt")])
+        );
+
+        assert_eq!(
+            paths_for(&inventory.keys[1]),
+            (vec![String::from("samples/self_signed.pem")], vec![String::from("samples/self_signed.pem")])
+        );
+    }
+
+    #[test]
+    fn builds_signing_chains_by_key_identifier()
+    {
+        let paths = vec![
+            String::from("samples/cross_signed/example.com.key"),
+            String::from("samples/cross_signed/example.com.crt"),
+            String::from("samples/cross_signed/old_intermediate.crt"),
+            String::from("samples/cross_signed/intermediate_by_b.crt"),
+            String::from("samples/cross_signed/intermediate_by_a.crt"),
+            String::from("samples/cross_signed/root_a.crt"),
+            String::from("samples/cross_signed/root_b.crt"),
+        ];
+
+        let inventory = Inventory::ingest(&paths);
+
+        // The old intermediate shares the issuer's subject, but not its key.
+        let issuers: Vec<String> = inventory.issuers_of(&inventory.certificates[0].certificate().unwrap())
+            .into_iter()
+            .map(|c| c.path)
+            .collect();
+
+        assert_eq!(
+            issuers,
+            vec![
+                String::from("samples/cross_signed/intermediate_by_b.crt"),
+                String::from("samples/cross_signed/intermediate_by_a.crt"),
+            ]
+        );
+
+        let chains = build(paths).unwrap();
+        let signing_certificates = chains[0].certificates[0].signing_certificate_chain();
+        let paths: Vec<&str> = signing_certificates.iter().map(|c| c.path.as_str()).collect();
+
+        assert_eq!(paths, vec!["samples/cross_signed/intermediate_by_b.crt", "samples/cross_signed/root_b.crt"]);
+        assert!(signing_certificates[1].self_signed);
+    }
+
+    #[test]
+    fn stops_at_mutually_signed_certificates()
+    {
+        let paths = vec![
+            String::from("samples/mutually_signed/example.com.key"),
+            String::from("samples/mutually_signed/example.com.crt"),
+            String::from("samples/mutually_signed/ca_x.crt"),
+            String::from("samples/mutually_signed/ca_y.crt"),
+        ];
+
+        let chains = build(paths).unwrap();
+        let paths: Vec<String> = chains[0].certificates[0].signing_certificate_chain().iter()
+            .map(|c| c.path.clone())
+            .collect();
+
+        assert_eq!(
+            paths,
+            vec![String::from("samples/mutually_signed/ca_x.crt"), String::from("samples/mutually_signed/ca_y.crt")]
+        );
+    }
+
+    #[test]
+    fn detects_self_signed_certificates()
+    {
+        let paths = vec![
+            String::from("samples/self_signed.crt"),
+            String::from("samples/ca_signed.crt"),
+            String::from("samples/cross_signed/intermediate_by_a.crt"),
+            String::from("samples/mutually_signed/ca_x.crt"),
+            String::from("samples/gnutls/self_signed_ec.crt"),
+        ];
+
+        let inventory = Inventory::ingest(&paths);
+        let self_signed: Vec<bool> = inventory.certificates.iter().map(|c| c.self_signed).collect();
+
+        assert_eq!(self_signed, vec![true, false, false, false, true]);
+    }
+
+    #[test]
+    fn loads_certificate_records()
+    {
+        let paths = vec![String::from("samples/combined/ca_signed.pem")];
+
+        let inventory = Inventory::ingest(&paths);
+
+        assert_eq!(inventory.certificates.len(), 2);
+
+        for (index, record) in inventory.certificates.iter().enumerate()
         {
-            let contents = get_file_contents(path).unwrap();
+            let certificate = record.load().unwrap();
+
+            assert_eq!(record.index, index);
+            assert_eq!(fingerprint(&certificate.certificate), record.fingerprint);
+            assert_eq!(certificate.certificate.issuer_name().to_der().unwrap(), record.issuer);
+            assert_eq!(format::unix_time(certificate.certificate.not_after()), record.not_after);
+            assert_eq!(certificate.self_signed, index == 1);
+        }
+    }
+
+    #[test]
+    fn matches_hostnames()
+    {
+        assert!(is_host_match("example.com", "example.com"));
+        assert!(is_host_match("Example.COM.", "example.com"));
+        assert!(is_host_match("*.example.com", "www.example.com"));
+        assert!(!is_host_match("*.example.com", "example.com"));
+        assert!(!is_host_match("*.example.com", "a.b.example.com"));
+        assert!(!is_host_match("*.com", "example.com"));
+        assert!(!is_host_match("w*.example.com", "www.example.com"));
+
+        let contents = get_file_contents("samples/wildcard/example.com.crt").unwrap();
+        let certificate = CertificateFile::new("samples/wildcard/example.com.crt", bytes_to_x509(&contents).unwrap());
+
+        assert_eq!(certificate.host_match("WWW.example.com."), Some(String::from("DNS:*.example.com")));
+        assert_eq!(certificate.host_match("example.com"), Some(String::from("DNS:example.com")));
+        assert_eq!(certificate.host_match("192.0.2.1"), Some(String::from("IP:192.0.2.1")));
+        assert_eq!(certificate.host_match("example.org"), None);
+
+        // Without SANs, the common name is used.
+        let contents = get_file_contents("samples/ca_signed.crt").unwrap();
+        let certificate = CertificateFile::new("samples/ca_signed.crt", bytes_to_x509(&contents).unwrap());
+
+        assert_eq!(certificate.host_match("example.com"), Some(String::from("CN=example.com")));
+    }
 
-            let request = CertificateRequestFile::new(&path, str_to_x509req(&contents).unwrap());
+    #[test]
+    fn checks_keys()
+    {
+        let paths = vec![
+            String::from("samples/ca_signed.key"),
+            String::from("samples/ca_signed.crt"),
+            String::from("samples/intermediate_ca.crt"),
+            String::from("samples/gnutls/self_signed_ec.key"),
+            String::from("samples/gnutls/self_signed_ec.crt"),
+            String::from("samples/inconsistent/rsa.key"),
+        ];
+
+        let chains = build(paths).unwrap();
+
+        for chain in &chains
+        {
+            let problems = chain.check_keys();
 
-            assert!(request.to_rsa().is_ok());
+            match chain.key.as_ref().unwrap().path.as_str()
+            {
+                "samples/inconsistent/rsa.key" => {
+                    assert_eq!(problems.len(), 1);
+                    assert!(matches!(problems[0].1, Error::InconsistentKey));
+                },
+                _ => assert!(problems.is_empty())
+            }
         }
+
+        // Certificates issued by themselves, but signed by another key (a
+        // CA key rollover), are intermediates.
+        let paths = vec![String::from("samples/rollover/new_by_old.crt"), String::from("samples/rollover/old_ca.crt")];
+        let inventory = Inventory::ingest(&paths);
+        let mut certificate = inventory.certificates[0].load().unwrap();
+
+        assert!(!certificate.self_signed);
+        assert!(certificate.check_signature().is_ok());
+
+        let issuers = inventory.issuers_of(&certificate.certificate);
+
+        assert_eq!(issuers.iter().map(|issuer| issuer.path.as_str()).collect::<Vec<_>>(), vec!["samples/rollover/old_ca.crt"]);
+
+        certificate.signing_certificate = issuers.into_iter().next().map(Box::new);
+
+        assert!(certificate.check_signature().is_ok());
     }
 
     #[test]
-    fn reads_certificates()
+    fn finds_request_mismatches()
     {
-        let paths = vec!["samples/ca_signed.crt", "samples/self_signed.crt"];
+        let contents = get_file_contents("samples/mismatched/example.com.csr").unwrap();
+        let request = CertificateRequestFile::new("samples/mismatched/example.com.csr", bytes_to_x509req(&contents).unwrap());
 
-        for path in paths
+        assert_eq!(request.subject_alt_names(), vec!["DNS:example.com", "DNS:www.example.com"]);
+
+        let paths = vec![
+            String::from("samples/self_signed_san.key"),
+            String::from("samples/self_signed_san.csr"),
+            String::from("samples/self_signed_san.crt"),
+            String::from("samples/mismatched/example.com.key"),
+            String::from("samples/mismatched/example.com.csr"),
+            String::from("samples/mismatched/example.com.crt"),
+        ];
+
+        for chain in build(paths).unwrap()
         {
-            let contents = get_file_contents(path).unwrap();
+            let expected: Vec<String> = match chain.key.as_ref().unwrap().path.as_str()
+            {
+                "samples/mismatched/example.com.key" => vec![String::from("O=Example"), String::from("DNS:www.example.com")],
+                _ => vec![]
+            };
+
+            assert_eq!(chain.request_mismatches(), expected);
+        }
+    }
+
+    #[test]
+    fn flags_expiring_certificates()
+    {
+        let paths = vec![String::from("samples/ca_signed.key"), String::from("samples/ca_signed.crt"), String::from("samples/intermediate_ca.crt")];
+
+        let chains = build(paths).unwrap();
+        let certificate = &chains[0].certificates[0];
+
+        // samples/ca_signed.crt expires in 2049, and its signer expired in 2023.
+        assert_eq!(certificate.expiry(30), Expiry::Valid);
+        assert_eq!(certificate.expiry(100_000), Expiry::Expiring);
+        assert_eq!(certificate.signing_certificate_chain()[0].expiry(30), Expiry::Expired);
+        assert!(certificate.signing_certificate_chain()[0].days_remaining().unwrap() < 0);
+        assert_eq!(chains[0].expiry(30), Some(Expiry::Expired));
+    }
+
+    #[test]
+    fn adds_contents_from_memory()
+    {
+        let mut inventory = Inventory::default();
+
+        inventory.add_bytes("key", &fs::read("samples/ca_signed.key").unwrap());
+        inventory.add_bytes("bundle", &fs::read("samples/combined/ca_signed.pem").unwrap());
+        inventory.add_bytes("other", b"not a certificate");
+
+        assert_eq!(inventory.skipped, vec![(String::from("other"), Skipped::Unrecognized)]);
+
+        let chains = inventory.build_chains().unwrap();
+
+        assert_eq!(chains[0].certificates[0].path, "bundle");
+        assert_eq!(chains[0].certificates[0].signing_certificate_chain()[0].path, "bundle");
+
+        let certificates = CertificateFile::from_bytes("bundle", &fs::read("samples/combined/ca_signed.pem").unwrap());
+
+        assert_eq!(certificates.map(|c| c.len()).ok(), Some(2));
+        assert!(CertificateFile::from_bytes("other", b"not a certificate").is_err());
+    }
+
+    #[test]
+    fn keeps_certificates_read_once()
+    {
+        let path = std::env::temp_dir().join(format!("sslchains-test-{}-kept.crt", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        fs::copy("samples/ca_signed.crt", path).unwrap();
+
+        let mut inventory = Inventory::new().add_path("samples/ca_signed.key").add_path(path);
+
+        // Contents added under a name already used don't replace the first.
+        inventory.add_bytes("issuer", &fs::read("samples/intermediate_ca.crt").unwrap());
+        inventory.add_bytes("issuer", &fs::read("samples/self_signed.crt").unwrap());
+
+        // Files changed (or removed) after they're read don't affect chains.
+        fs::remove_file(path).unwrap();
+
+        let chains = inventory.build_chains().unwrap();
+
+        assert_eq!(chains[0].certificates[0].path, path);
+        assert_eq!(chains[0].certificates[0].signing_certificate_chain()[0].path, "issuer");
+    }
+
+    #[test]
+    fn builds_chains_from_paths_and_memory()
+    {
+        let chains = Inventory::new()
+            .add_pem_bytes("example.com.crt", &fs::read("samples/ca_signed.crt").unwrap())
+            .add_path("samples/ca_signed.key")
+            .add_path("samples/intermediate_ca.crt")
+            .build_chains()
+            .unwrap();
+
+        assert_eq!(chains.len(), 1);
+        assert_eq!(chains[0].key.as_ref().map(|k| k.path.as_str()), Some("samples/ca_signed.key"));
+        assert_eq!(chains[0].certificates[0].path, "example.com.crt");
+        assert_eq!(chains[0].certificates[0].signing_certificate_chain()[0].path, "samples/intermediate_ca.crt");
+    }
+
+    /// Benchmark chain assembly on one thread and on all threads, with the
+    /// samples directory's files replicated 1000 times. Run with:
+    ///
+    ///     cargo test --release benchmarks_chain_assembly -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn benchmarks_chain_assembly()
+    {
+        use std::time::Instant;
+
+        let directory = std::env::temp_dir().join(format!("sslchains-bench-{}", std::process::id()));
+        let mut paths = vec![];
+
+        for copy in 0..1000
+        {
+            let copy = directory.join(copy.to_string());
+
+            fs::create_dir_all(&copy).unwrap();
+
+            for entry in fs::read_dir("samples").unwrap().flatten().filter(|e| e.path().is_file())
+            {
+                let path = copy.join(entry.file_name());
+
+                fs::copy(entry.path(), &path).unwrap();
+                paths.push(path.to_str().unwrap().to_string());
+            }
+        }
+
+        let inventory = Inventory::ingest(&paths);
+
+        let assemble = |threads: usize| {
+            let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+            let start = Instant::now();
+            let mut count = 0;
+
+            pool.install(|| build_each(&inventory, |_| count += 1)).unwrap();
+
+            (count, start.elapsed(), pool.current_num_threads())
+        };
+
+        let (sequential, sequential_time, _) = assemble(1);
+        let (parallel, parallel_time, threads) = assemble(0);
+
+        fs::remove_dir_all(&directory).unwrap();
+
+        println!(
+            "{} chains from {} files: {:?} on 1 thread, {:?} on {} threads ({:.1}x)",
+            parallel,
+            paths.len(),
+            sequential_time,
+            parallel_time,
+            threads,
+            sequential_time.as_secs_f64() / parallel_time.as_secs_f64()
+        );
+
+        assert_eq!(sequential, parallel);
+    }
+
+    #[test]
+    fn survives_malformed_public_keys()
+    {
+        let paths = vec![
+            String::from("samples/ca_signed.key"),
+            String::from("samples/ca_signed.crt"),
+            String::from("samples/intermediate_ca.crt"),
+            String::from("samples/malformed/badkey.pem"),
+        ];
+
+        let inventory = Inventory::ingest(&paths);
+        let chains = build(paths).unwrap();
+
+        assert_eq!(chains[0].certificates[0].signing_certificate_chain().len(), 1);
+
+        // The malformed certificate is issued by itself, so it's checked for
+        // having signed itself, which fails rather than aborting.
+        let orphans = find_orphans(&inventory, &chains);
+
+        assert_eq!(orphans.certificates, vec![String::from("samples/malformed/badkey.pem")]);
+    }
+
+    #[test]
+    fn finds_skipped_files()
+    {
+        let paths = vec![
+            String::from("samples/ca_signed.crt"),
+            String::from("samples/intermediate_ca.srl"),
+            String::from("samples/corrupted/ca_signed.crt"),
+            String::from("samples/missing.crt"),
+        ];
+
+        let skipped = Inventory::ingest(&paths).skipped;
+
+        assert_eq!(skipped.len(), 3);
+        assert_eq!(skipped[0], (String::from("samples/intermediate_ca.srl"), Skipped::Unrecognized));
+        assert_eq!(skipped[0].1.to_string(), "not a recognized key, request, or certificate");
+        assert_eq!(skipped[1].0, "samples/corrupted/ca_signed.crt");
+        assert!(matches!(&skipped[1].1, Skipped::Invalid(reason) if reason.starts_with("invalid certificate")));
+        assert_eq!(skipped[2].0, "samples/missing.crt");
+        assert!(matches!(skipped[2].1, Skipped::Unreadable(_)));
+    }
+
+    #[test]
+    fn lists_subject_alternative_names()
+    {
+        let contents = get_file_contents("samples/self_signed_san.crt").unwrap();
+        let certificate = CertificateFile::new("samples/self_signed_san.crt", bytes_to_x509(&contents).unwrap());
+
+        assert_eq!(
+            certificate.subject_alt_names(),
+            vec![String::from("DNS:www.san.example.com"), String::from("DNS:san.example.com")]
+        );
+
+        let contents = get_file_contents("samples/ca_signed.crt").unwrap();
+        let certificate = CertificateFile::new("samples/ca_signed.crt", bytes_to_x509(&contents).unwrap());
+
+        assert!(certificate.subject_alt_names().is_empty());
+    }
+
+    #[test]
+    fn builds_chains_from_kubernetes_secrets()
+    {
+        let paths = vec![
+            String::from("samples/kubernetes/secrets.json:example-tls/tls.crt"),
+            String::from("samples/kubernetes/secrets.json:example-tls/tls.key"),
+            String::from("samples/kubernetes/secrets.json:intermediate-ca/ca.crt"),
+        ];
+
+        let chains = build(paths).unwrap();
+
+        assert_eq!(chains.len(), 1);
+        assert_eq!(chains[0].certificates.len(), 1);
+        assert_eq!(chains[0].certificates[0].signing_certificate_chain().len(), 1);
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn ingests_asynchronously()
+    {
+        let paths: Vec<String> = fs::read_dir("samples").unwrap()
+            .map(|entry| entry.unwrap().path().to_str().unwrap().to_string())
+            .filter(|path| !fs::metadata(path).unwrap().is_dir())
+            .collect();
+
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+
+        for concurrency in [1, 4]
+        {
+            let inventory = runtime.block_on(Inventory::ingest_async(&paths, concurrency));
+            let expected = Inventory::ingest(&paths);
+
+            let keys = |inventory: &Inventory| inventory.keys.iter().map(|k| k.path.clone()).collect::<Vec<String>>();
+            let certificates = |inventory: &Inventory| inventory.certificates.iter().map(|c| c.path.clone()).collect::<Vec<String>>();
+
+            assert_eq!(keys(&inventory), keys(&expected));
+            assert_eq!(certificates(&inventory), certificates(&expected));
+            assert_eq!(inventory.skipped, expected.skipped);
+        }
+    }
+
+    #[cfg(feature = "nss")]
+    #[test]
+    fn builds_chains_from_nss_databases()
+    {
+        let paths = vec![
+            String::from("samples/ca_signed.key"),
+            String::from("samples/nss/cert9.db:example.com#1"),
+            String::from("samples/nss/cert9.db:Sample Org CA#2"),
+        ];
+
+        let chains = build(paths).unwrap();
 
-            let certificate = CertificateFile::new(&path, str_to_x509(&contents).unwrap());
+        assert_eq!(chains.len(), 1);
+        assert_eq!(chains[0].certificates[0].path, "samples/nss/cert9.db:example.com#1");
+        assert_eq!(chains[0].certificates[0].signing_certificate_chain().len(), 1);
+    }
+
+    #[test]
+    fn builds_chains_from_gnutls_keys()
+    {
+        let paths = vec![
+            String::from("samples/gnutls/pkcs8_attributes.crt"),
+            String::from("samples/gnutls/pkcs8_attributes.key"),
+            String::from("samples/gnutls/self_signed_ec.crt"),
+            String::from("samples/gnutls/self_signed_ec.key"),
+        ];
+
+        let chains = build(paths).unwrap();
+
+        assert_eq!(chains.len(), 2);
+
+        for chain in chains
+        {
+            assert_eq!(chain.certificates.len(), 1);
+            assert!(chain.certificates[0].self_signed);
+        }
+    }
+
+    #[test]
+    fn rescans_changed_files()
+    {
+        let directory = std::env::temp_dir().join(format!("sslchains-test-{}.rescan", std::process::id()));
+        let path = |name: &str| directory.join(name).to_str().unwrap().to_string();
+
+        fs::create_dir_all(&directory).unwrap();
 
-            assert!(certificate.to_rsa().is_ok());
+        for (sample, name) in [("ca_signed.key", "a.key"), ("ca_signed.crt", "a.crt"), ("intermediate_ca.crt", "b.crt"), ("intermediate_ca.crt", "c.crt")]
+        {
+            fs::copy(format!("samples/{}", sample), path(name)).unwrap();
         }
+
+        let paths: Vec<String> = ["a.key", "a.crt", "b.crt", "c.crt"].iter().map(|name| path(name)).collect();
+        let mut inventory = Inventory::ingest(&paths);
+
+        assert_eq!(inventory.certificates[1].duplicates, vec![path("c.crt")]);
+
+        // The signing certificate is still found at its other path.
+        fs::remove_file(path("b.crt")).unwrap();
+        inventory.rescan(&[], &[path("b.crt")]);
+
+        let chains = inventory.build_chains().unwrap();
+
+        assert_eq!(chains[0].certificates[0].signing_certificate.as_ref().unwrap().path, path("c.crt"));
+
+        // The certificate no longer matches the key.
+        fs::copy("samples/self_signed.crt", path("a.crt")).unwrap();
+        inventory.rescan(&[path("a.crt")], &[]);
+
+        let chains = inventory.build_chains().unwrap();
+
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert!(chains[0].certificates.is_empty());
+        assert_eq!(inventory.certificates.len(), 2);
+    }
+
+    #[test]
+    fn builds_chains_from_dsa_keys()
+    {
+        let paths = vec![
+            String::from("samples/self_signed_dsa.key"),
+            String::from("samples/self_signed_dsa.csr"),
+            String::from("samples/self_signed_dsa.crt"),
+        ];
+
+        let chains = build(paths).unwrap();
+
+        assert_eq!(chains.len(), 1);
+
+        let chain = &chains[0];
+
+        assert!(chain.key.as_ref().unwrap().is_deprecated());
+        assert!(chain.request.is_some());
+        assert_eq!(chain.certificates.len(), 1);
     }
 }
@@ -0,0 +1,257 @@
+//! Copyright (C) 2022 Gaz J.
+//!
+//! This program is free software: you can redistribute it and/or modify
+//! it under the terms of the GNU General Public License as published by
+//! the Free Software Foundation, either version 3 of the License, or
+//! (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//! GNU General Public License for more details.
+//!
+//! You should have received a copy of the GNU General Public License
+//! along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Cleanup plans (--cleanup-plan): files which can probably go, as a shell
+//! script to review and run, as nothing is removed here. They're expired
+//! certificates superseded by newer ones (for the same key, or covering the
+//! same names), exact copies of other files, and requests whose keys
+//! weren't found.
+//!
+//! Only regular files holding nothing else are listed, so bundles, combined
+//! files, and symbolic links are left, as are files which symbolic links
+//! beside them (or, in certbot's archive, in its live directory) point to.
+//! When only some chains are selected (e.g. with --host), only their files
+//! are listed.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+use openssl::sha::sha256;
+
+use crate::chain::{self, Chain, CertificateFile, Expiry, Inventory};
+use crate::format;
+
+/// A file to remove, and why.
+#[derive(Debug, PartialEq)]
+pub struct Removal
+{
+    pub path: String,
+    pub reason: String,
+}
+
+/// Plan which files to remove, of the chains selected, if only some are
+/// (requests without keys belong to no chain, so they're left then).
+pub fn plan(inventory: &Inventory, chains: &[Chain], selects: Option<&dyn Fn(&Chain) -> bool>) -> Vec<Removal>
+{
+    let selected: Option<HashSet<&str>> = selects.map(|selects| chains.iter().filter(|chain| selects(chain)).flat_map(files).collect());
+
+    let mut paths: Vec<&str> = inventory.keys.iter().map(|key| key.path.as_str())
+        .chain(inventory.requests.iter().map(|request| request.path.as_str()))
+        .chain(inventory.certificates.iter().flat_map(|record| std::iter::once(&record.path).chain(&record.duplicates).map(String::as_str)))
+        .collect();
+
+    let mut removals: Vec<Removal> = vec![];
+
+    let mut remove = |path: &str, reason: String| {
+        let is_selected = selected.as_ref().is_none_or(|selected| selected.contains(path));
+
+        if is_selected && inventory.items_in(path) == 1 && chain::is_regular_file(path) && !is_linked(path) && !removals.iter().any(|removal| removal.path == path)
+        {
+            removals.push(Removal { path: path.to_string(), reason });
+        }
+    };
+
+    // Expired certificates superseded by their chain's current certificate,
+    // or another chain's covering the same names.
+    for chain in chains
+    {
+        let Some(current) = chain.certificates.first() else { continue };
+
+        for superseded in chain.certificates.iter().skip(1).filter(|c| supersedes(current, c))
+        {
+            let reason = format!("expired {}, superseded by {} (expires {})", expires(superseded), current.path, expires(current));
+
+            std::iter::once(&superseded.path).chain(&superseded.duplicates).for_each(|path| remove(path, reason.clone()));
+        }
+
+        let names = current.dns_names();
+        let newer = chains.iter()
+            .filter_map(|other| other.certificates.first())
+            .find(|other| other.expiry(0) != Expiry::Expired && !names.is_empty() && names.iter().all(|name| other.host_match(name).is_some()) && supersedes(other, current));
+
+        if let Some(newer) = newer
+        {
+            let reason = format!("expired {}, superseded by {} (expires {}) for the same names", expires(current), newer.path, expires(newer));
+
+            std::iter::once(&current.path).chain(&current.duplicates).for_each(|path| remove(path, reason.clone()));
+        }
+    }
+
+    // Exact copies, keeping the first of each in path order, or one which
+    // a symbolic link points to.
+    paths.sort_unstable_by_key(|path| (!is_linked(path), *path));
+    paths.dedup();
+
+    let mut originals: HashMap<[u8; 32], &str> = HashMap::new();
+
+    for path in paths.into_iter().filter(|path| chain::is_regular_file(path))
+    {
+        let Ok(contents) = fs::read(path) else { continue };
+
+        match originals.get(&sha256(&contents))
+        {
+            Some(original) if !chain::same_file(original, path) => remove(path, format!("identical to {}", original)),
+            Some(_) => {},
+            None => {
+                originals.insert(sha256(&contents), path);
+            }
+        }
+    }
+
+    // Requests whose keys weren't found.
+    for path in chain::find_orphans(inventory, chains).requests.iter().filter(|_| selects.is_none())
+    {
+        remove(path, String::from("request whose key wasn't found"));
+    }
+
+    removals
+}
+
+/// A chain's files: its key's, request's, and certificates' (and their
+/// copies').
+fn files(chain: &Chain) -> Vec<&str>
+{
+    chain.key.iter().map(|key| key.path.as_str())
+        .chain(chain.request.iter().map(|request| request.path.as_str()))
+        .chain(chain.certificates.iter().flat_map(|certificate| std::iter::once(&certificate.path).chain(&certificate.duplicates).map(String::as_str)))
+        .collect()
+}
+
+/// A shell script which removes the files, with the reasons as comments.
+pub fn script(removals: &[Removal]) -> String
+{
+    let mut script = String::from("#!/bin/sh\n# Files sslchains --cleanup-plan suggests removing. Review before running.\nset -e\n");
+
+    if removals.is_empty()
+    {
+        script.push_str("\n# Nothing to remove.\n");
+    }
+
+    for removal in removals
+    {
+        script.push_str(&format!("\n# {}\nrm -- {}\n", removal.reason.replace('\n', " "), quote(&removal.path)));
+    }
+
+    script
+}
+
+/// Whether a symbolic link in the file's directory, or for certbot's
+/// archive/NAME directories, in live/NAME, points to a file.
+fn is_linked(path: &str) -> bool
+{
+    let Ok(target) = fs::canonicalize(path) else { return false };
+    let Some(directory) = Path::new(path).parent() else { return false };
+
+    let mut directories = vec![directory.to_path_buf()];
+
+    if let (Some(archive), Some(name)) = (directory.parent(), directory.file_name())
+    {
+        if archive.file_name().is_some_and(|archive| archive == "archive")
+        {
+            directories.extend(archive.parent().map(|parent| parent.join("live").join(name)));
+        }
+    }
+
+    directories.iter()
+        .filter_map(|directory| fs::read_dir(if directory.as_os_str().is_empty() { Path::new(".") } else { directory }).ok())
+        .flat_map(|entries| entries.flatten())
+        .any(|entry| entry.file_type().map(|file_type| file_type.is_symlink()).unwrap_or(false) && fs::canonicalize(entry.path()).ok().as_ref() == Some(&target))
+}
+
+/// Whether a certificate has expired and a newer one has replaced it.
+fn supersedes(newer: &CertificateFile, older: &CertificateFile) -> bool
+{
+    older.expiry(0) == Expiry::Expired && older.certificate.not_after() < newer.certificate.not_after()
+}
+
+/// When a certificate expires, e.g. "2030-01-01".
+fn expires(certificate: &CertificateFile) -> String
+{
+    format::format_time(certificate.certificate.not_after()).chars().take(10).collect()
+}
+
+/// Quote a path for the shell.
+fn quote(path: &str) -> String
+{
+    format!("'{}'", path.replace('\'', "'\\''"))
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    #[test]
+    fn plans_cleanups()
+    {
+        let directory = std::env::temp_dir().join(format!("sslchains-test-cleanup-{}", std::process::id()));
+        let path = |name: &str| directory.join(name).to_string_lossy().to_string();
+
+        fs::create_dir_all(&directory).unwrap();
+
+        for name in ["example.com.key", "example.com-2023.crt", "example.com-2024.crt", "example.com-2025.crt"]
+        {
+            fs::copy(format!("samples/renewed/{}", name), path(name)).unwrap();
+        }
+
+        fs::copy("samples/renewed/example.com-2025.crt", path("example.com-2025.crt.orig")).unwrap();
+        fs::copy("samples/self_signed_san_no_cert.csr", path("orphan.csr")).unwrap();
+
+        let mut paths: Vec<String> = fs::read_dir(&directory).unwrap().map(|entry| entry.unwrap().path().to_string_lossy().to_string()).collect();
+
+        paths.sort();
+
+        let inventory = paths.iter().fold(Inventory::new(), |inventory, path| inventory.add_path(path));
+        let chains = inventory.build_chains().unwrap();
+        let removals = plan(&inventory, &chains, None);
+
+        assert_eq!(removals, vec![
+            Removal { path: path("example.com-2024.crt"), reason: format!("expired 2025-01-01, superseded by {} (expires 2026-01-01)", path("example.com-2025.crt")) },
+            Removal { path: path("example.com-2023.crt"), reason: format!("expired 2024-01-01, superseded by {} (expires 2026-01-01)", path("example.com-2025.crt")) },
+            Removal { path: path("example.com-2025.crt.orig"), reason: format!("identical to {}", path("example.com-2025.crt")) },
+            Removal { path: path("orphan.csr"), reason: String::from("request whose key wasn't found") },
+        ]);
+
+        let script = script(&removals);
+
+        assert!(script.starts_with("#!/bin/sh\n"));
+        assert!(script.contains(&format!("\n# request whose key wasn't found\nrm -- '{}'\n", path("orphan.csr"))));
+        assert_eq!(quote("copy of example.com's.crt"), "'copy of example.com'\\''s.crt'");
+
+        // Only the selected chains' files are listed.
+        let selects = |chain: &Chain| chain.host_match("example.org").is_some();
+
+        assert!(plan(&inventory, &chains, Some(&selects)).is_empty());
+
+        let selects = |chain: &Chain| chain.host_match("example.com").is_some();
+
+        assert_eq!(plan(&inventory, &chains, Some(&selects)), removals[..3]);
+
+        // Nothing's removed.
+        assert_eq!(fs::read_dir(&directory).unwrap().count(), 6);
+
+        // Files symbolic links point to are kept.
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink("example.com-2023.crt", path("example.com.crt")).unwrap();
+
+            assert!(is_linked(&path("example.com-2023.crt")) && !is_linked(&path("example.com-2024.crt")));
+            assert!(!plan(&inventory, &chains, None).iter().any(|removal| removal.path == path("example.com-2023.crt")));
+        }
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -13,34 +13,39 @@
 //! You should have received a copy of the GNU General Public License
 //! along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use openssl::rsa::Rsa;
 use openssl::x509::X509;
-use openssl::pkey::{Public, Private};
+use openssl::pkey::{PKey, Public, Private};
+
+use crate::error::{Error, Result};
 
 /// Compares private and public key files.
-pub fn private_to_public<'a>(
-    rsa_private: &Rsa<Private>,
-    rsa_public: &Rsa<Public>
-) -> Result<(), &'a str>
+pub fn private_to_public(
+    private_key: &PKey<Private>,
+    public_key: &PKey<Public>
+) -> Result<()>
 {
-    if rsa_private.n() != rsa_public.n()
+    if !private_key.public_eq(public_key)
     {
-        return Err("Key file mismatch");
+        return Err(Error::KeyMismatch);
     }
     Ok(())
 }
 
 /// Compares X509 certificates to see if one is signed by the other.
-pub fn certificate_to_signing_certificate<'a>(
+///
+/// Signing certificates whose public key can't be decoded (e.g. malformed
+/// or unsupported algorithms) are reported as errors.
+pub fn certificate_to_signing_certificate(
     certificate: &X509,
     signing_certificate: &X509
-) -> Result<(), &'a str>
+) -> Result<()>
 {
-    let signing_key = signing_certificate.public_key().unwrap();
+    let signing_key = signing_certificate.public_key()?;
 
-    if !certificate.verify(&signing_key).unwrap()
+    // Verification errors (e.g. mismatched key types) count as mismatches.
+    if !certificate.verify(&signing_key).unwrap_or(false)
     {
-        return Err("Certificate not signed by given signing certificate");
+        return Err(Error::SignatureMismatch);
     }
     Ok(())
 }
@@ -58,8 +63,8 @@ mod test
 
         let (key, req, cert) = keys::generate();
 
-        let req: Rsa<Public> = req.public_key().unwrap().rsa().unwrap();
-        let cert: Rsa<Public> = cert.public_key().unwrap().rsa().unwrap();
+        let req: PKey<Public> = req.public_key().unwrap();
+        let cert: PKey<Public> = cert.public_key().unwrap();
 
         assert!(private_to_public(&key, &req).is_ok());
         assert!(private_to_public(&key, &cert).is_ok());
@@ -73,8 +78,8 @@ mod test
         let (key, _, _) = keys::generate();
         let (_, req, cert) = keys::generate();
 
-        let req: Rsa<Public> = req.public_key().unwrap().rsa().unwrap();
-        let cert: Rsa<Public> = cert.public_key().unwrap().rsa().unwrap();
+        let req: PKey<Public> = req.public_key().unwrap();
+        let cert: PKey<Public> = cert.public_key().unwrap();
 
         assert!(private_to_public(&key, &req).is_err());
         assert!(private_to_public(&key, &cert).is_err());
@@ -86,7 +91,7 @@ mod test
         use crate::chain;
 
         let cert_path = chain::get_file_contents("samples/self_signed.crt").unwrap();
-        let cert = chain::str_to_x509(&cert_path).unwrap();
+        let cert = chain::bytes_to_x509(&cert_path).unwrap();
 
         assert!(certificate_to_signing_certificate(&cert, &cert).is_ok());
     }
@@ -97,10 +102,10 @@ mod test
         use crate::chain;
 
         let cert_path = chain::get_file_contents("samples/ca_signed.crt").unwrap();
-        let cert = chain::str_to_x509(&cert_path).unwrap();
+        let cert = chain::bytes_to_x509(&cert_path).unwrap();
 
         let ca_cert_path = chain::get_file_contents("samples/intermediate_ca.crt").unwrap();
-        let ca_cert = chain::str_to_x509(&ca_cert_path).unwrap();
+        let ca_cert = chain::bytes_to_x509(&ca_cert_path).unwrap();
 
         assert!(certificate_to_signing_certificate(&cert, &ca_cert).is_ok());
     }
@@ -111,10 +116,10 @@ mod test
         use crate::chain;
 
         let cert_path = chain::get_file_contents("samples/self_signed.crt").unwrap();
-        let cert = chain::str_to_x509(&cert_path).unwrap();
+        let cert = chain::bytes_to_x509(&cert_path).unwrap();
 
         let ca_cert_path = chain::get_file_contents("samples/intermediate_ca.crt").unwrap();
-        let ca_cert = chain::str_to_x509(&ca_cert_path).unwrap();
+        let ca_cert = chain::bytes_to_x509(&ca_cert_path).unwrap();
 
         assert!(certificate_to_signing_certificate(&cert, &ca_cert).is_err());
     }
@@ -125,11 +130,26 @@ mod test
         use crate::chain;
 
         let cert_path = chain::get_file_contents("samples/ca_signed.crt").unwrap();
-        let cert = chain::str_to_x509(&cert_path).unwrap();
+        let cert = chain::bytes_to_x509(&cert_path).unwrap();
+
+        let ca_cert_path = chain::get_file_contents("samples/intermediate_ca.crt").unwrap();
+        let ca_cert = chain::bytes_to_x509(&ca_cert_path).unwrap();
+
+        assert!(certificate_to_signing_certificate(&ca_cert, &cert).is_err());
+    }
+
+    #[test]
+    fn identifies_mixed_key_type_certificate_to_signing_certificate_mismatches()
+    {
+        use crate::chain;
+
+        let cert_path = chain::get_file_contents("samples/gnutls/self_signed_ec.crt").unwrap();
+        let cert = chain::bytes_to_x509(&cert_path).unwrap();
 
         let ca_cert_path = chain::get_file_contents("samples/intermediate_ca.crt").unwrap();
-        let ca_cert = chain::str_to_x509(&ca_cert_path).unwrap();
+        let ca_cert = chain::bytes_to_x509(&ca_cert_path).unwrap();
 
+        assert!(certificate_to_signing_certificate(&cert, &ca_cert).is_err());
         assert!(certificate_to_signing_certificate(&ca_cert, &cert).is_err());
     }
 }
@@ -0,0 +1,202 @@
+//! Copyright (C) 2022 Gaz J.
+//!
+//! This program is free software: you can redistribute it and/or modify
+//! it under the terms of the GNU General Public License as published by
+//! the Free Software Foundation, either version 3 of the License, or
+//! (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//! GNU General Public License for more details.
+//!
+//! You should have received a copy of the GNU General Public License
+//! along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Certificates servers are configured with: Apache's virtual hosts
+//! (--apache), HAProxy's bind lines (--haproxy), mail servers (--postfix and
+//! --dovecot), and what refers to Kubernetes Secrets (--kubeconfig). Each
+//! module reads its configuration into a [`Configured`] per certificate,
+//! which is then checked against the chain the certificate belongs to.
+
+use std::fs;
+use std::path::Path;
+
+use crate::chain::{same_file, Chain};
+
+/// Names of the settings a certificate is configured with, for messages
+/// (empty where there's no setting).
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Settings
+{
+    pub certificate: &'static str,
+    pub key: &'static str,
+
+    /// Prefixes names which the certificate doesn't cover, e.g. "SNI filter".
+    pub name: &'static str,
+}
+
+/// A certificate something is configured with, and where its key and
+/// intermediates are read from.
+#[derive(Debug, Clone, Default)]
+pub struct Configured
+{
+    /// A name for what's configured, e.g. "example.com (httpd.conf:12)".
+    pub label: String,
+
+    pub settings: Settings,
+
+    /// The certificate's file (or the name it's read as).
+    pub certificate: String,
+
+    /// The key's file, if configured (it's read from the certificate's file
+    /// otherwise).
+    pub key: Option<String>,
+
+    /// Another file the key is read from when it isn't configured, and the
+    /// certificate's file doesn't hold it (as HAProxy reads "example.pem.key").
+    pub fallback_key: Option<String>,
+
+    /// A file intermediates are sent from, besides the certificate's.
+    pub chain: Option<String>,
+
+    /// Names the certificate should cover. Wildcards (with * or ?) and
+    /// exclusions (beginning with !) aren't checked.
+    pub names: Vec<String>,
+
+    /// Whether the certificate exists, where that isn't a file's (e.g. a
+    /// Kubernetes Secret's).
+    pub exists: Option<bool>,
+
+    pub matched: Option<Matched>,
+}
+
+/// The chain which a configured certificate belongs to.
+#[derive(Debug, Clone)]
+pub struct Matched
+{
+    /// Path of the chain's key, if any.
+    pub key: Option<String>,
+
+    /// Ways the configuration doesn't fit the chain.
+    pub problems: Vec<String>,
+}
+
+impl Configured
+{
+    /// Compare a chain with the configuration, if its certificate is among
+    /// the chain's (the first chain found is kept).
+    pub fn consider(&mut self, chain: &Chain)
+    {
+        if self.matched.is_some() || chain.endpoint.is_some()
+        {
+            return;
+        }
+
+        let (index, certificate) = match chain.certificates.iter().enumerate().find(|(_, c)| c.is_in(&self.certificate))
+        {
+            Some(found) => found,
+            None => return
+        };
+
+        let mut problems = vec![];
+        let key = chain.key.as_ref().map(|key| key.path.clone());
+
+        let read = match &self.key
+        {
+            Some(configured) => vec![configured],
+            None => [Some(&self.certificate), self.fallback_key.as_ref()].into_iter().flatten().collect()
+        };
+
+        match (&self.key, &key)
+        {
+            (_, Some(key)) if read.iter().any(|path| same_file(path, key)) => (),
+            (Some(configured), Some(key)) => problems.push(format!("{} doesn't match it (its key is {})", setting(self.settings.key, configured), key)),
+            (Some(configured), None) => problems.push(format!("{} doesn't match it", setting(self.settings.key, configured))),
+            (None, _) if !self.settings.key.is_empty() => problems.push(format!("has no {}, and its certificate's file doesn't hold its key", self.settings.key)),
+            (None, Some(key)) => problems.push(format!("its key is in {}, which isn't read", key)),
+            (None, None) => problems.push(String::from("has no key"))
+        }
+
+        if index > 0
+        {
+            problems.push(format!("{} is superseded by {}", certificate.path, chain.certificates[0].path));
+        }
+
+        for name in self.names.iter().filter(|name| !name.starts_with('!') && !name.contains(['*', '?']))
+        {
+            if certificate.host_match(name).is_none()
+            {
+                problems.push(format!("{} isn't covered by {}", setting(self.settings.name, name), certificate.path));
+            }
+        }
+
+        let sent: Vec<&str> = [Some(&self.certificate), self.chain.as_ref()].into_iter().flatten().map(String::as_str).collect();
+
+        problems.extend(certificate.bundle_problems(&sent));
+
+        self.matched = Some(Matched { key, problems });
+    }
+
+    /// The files configured, which should exist (unless the certificate
+    /// isn't a file's), and the fallback key's, if it does.
+    pub fn paths(&self) -> Vec<&String>
+    {
+        let fallback_key = self.fallback_key.as_ref().filter(|path| Path::new(path).exists());
+
+        [Some(&self.certificate), self.key.as_ref(), fallback_key, self.chain.as_ref()].into_iter().flatten().collect()
+    }
+
+    /// Ways the configuration is broken: files which don't exist,
+    /// certificates without their keys (as chains are only built for keys),
+    /// and problems with the chain found.
+    pub fn problems(&self) -> Vec<String>
+    {
+        let missing: Vec<String> = match self.exists
+        {
+            Some(exists) => (!exists).then(|| format!("{} doesn't exist", self.certificate)).into_iter().collect(),
+            None => [Some(&self.certificate), self.key.as_ref(), self.chain.as_ref()].into_iter().flatten()
+                .filter(|path| fs::metadata(path).is_err())
+                .map(|path| format!("{} doesn't exist", path))
+                .collect()
+        };
+
+        if !missing.is_empty()
+        {
+            return missing;
+        }
+
+        match (&self.matched, &self.key)
+        {
+            (Some(matched), _) => matched.problems.clone(),
+            (None, Some(key)) if !self.settings.key.is_empty() => vec![format!("{} doesn't match a certificate in {}", setting(self.settings.key, key), self.certificate)],
+            _ => vec![format!("{} doesn't hold a certificate with its key", setting(self.settings.certificate, &self.certificate))]
+        }
+    }
+}
+
+/// The files configured for certificates, without duplicates.
+pub fn paths(configured: &[Configured]) -> Vec<String>
+{
+    let mut paths: Vec<String> = vec![];
+
+    for path in configured.iter().flat_map(Configured::paths)
+    {
+        if !paths.contains(path)
+        {
+            paths.push(path.clone());
+        }
+    }
+
+    paths
+}
+
+/// A value, after the name of the setting it's from (if any).
+fn setting(name: &str, value: &str) -> String
+{
+    match name.is_empty()
+    {
+        true => value.to_string(),
+        false => format!("{} {}", name, value)
+    }
+}
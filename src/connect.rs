@@ -0,0 +1,505 @@
+//! Copyright (C) 2022 Gaz J.
+//!
+//! This program is free software: you can redistribute it and/or modify
+//! it under the terms of the GNU General Public License as published by
+//! the Free Software Foundation, either version 3 of the License, or
+//! (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//! GNU General Public License for more details.
+//!
+//! You should have received a copy of the GNU General Public License
+//! along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Fetches the certificates which TLS servers present (--connect), and
+//! matches them against local chains, answering whether a server actually
+//! serves the certificate (and key) on disk, e.g. after a renewal.
+//!
+//! Servers aren't verified, as what they serve is what's being checked.
+//! When a server serves a local certificate, the signing certificates it
+//! serves are compared with the local chain's too, as missing, misordered,
+//! or extra ones cause failures in some clients but not others.
+
+use std::fmt;
+use std::io;
+use std::net::IpAddr;
+use std::time::Duration;
+use log::debug;
+use openssl::hash::MessageDigest;
+use openssl::ssl::{SslConnector, SslMethod, SslVerifyMode};
+use openssl::x509::{X509, X509Ref, X509VerifyResult};
+use rayon::prelude::*;
+
+use crate::chain::{key_digest, Chain, CertificateFile};
+use crate::format::format_name;
+use crate::http;
+use crate::trust;
+use crate::starttls::Protocol;
+
+/// Port connected to when an endpoint doesn't give one.
+pub const DEFAULT_PORT: u16 = 443;
+
+/// A host (name or address) and port to connect to.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Endpoint
+{
+    pub host: String,
+
+    /// Port, if given (see Endpoint::port).
+    pub port: Option<u16>,
+
+    /// Name to indicate (SNI) in the handshake, in place of the host.
+    pub sni: Option<String>,
+
+    /// Protocol to upgrade to TLS with (--starttls), if any.
+    pub starttls: Option<Protocol>,
+}
+
+impl Endpoint
+{
+    /// Parse "host", "host:port", or "host:port:sni" (with IPv6 addresses in
+    /// brackets, e.g. "[2001:db8::1]:443").
+    pub fn parse(endpoint: &str) -> Option<Endpoint>
+    {
+        let (host, rest) = match endpoint.strip_prefix('[')
+        {
+            Some(rest) => {
+                let (host, rest) = rest.split_once(']')?;
+
+                match rest
+                {
+                    "" => (host, None),
+                    _ => (host, Some(rest.strip_prefix(':')?))
+                }
+            },
+            None => match endpoint.split_once(':')
+            {
+                Some((host, rest)) => (host, Some(rest)),
+                None => (endpoint, None)
+            }
+        };
+
+        let (port, sni) = match rest.map(|rest| rest.split_once(':').map_or((rest, None), |(port, sni)| (port, Some(sni))))
+        {
+            Some((port, sni)) => (Some(port.parse().ok().filter(|&port| port != 0)?), sni),
+            None => (None, None)
+        };
+
+        let invalid = |name: &str| name.is_empty() || name.contains(char::is_whitespace);
+
+        match invalid(host) || sni.is_some_and(invalid)
+        {
+            true => None,
+            false => Some(Endpoint { host: host.to_string(), port, sni: sni.map(String::from), starttls: None })
+        }
+    }
+
+    /// Port to connect to: the one given, or the default for the protocol.
+    pub fn port(&self) -> u16
+    {
+        self.port.unwrap_or_else(|| self.starttls.map(|protocol| protocol.default_port()).unwrap_or(DEFAULT_PORT))
+    }
+
+    /// Name to indicate (SNI) in the handshake: the one given, or the host.
+    /// Addresses aren't allowed.
+    fn server_name(&self) -> Option<&str>
+    {
+        match (&self.sni, self.host.parse::<IpAddr>())
+        {
+            (Some(sni), _) => Some(sni),
+            (None, Ok(_)) => None,
+            (None, Err(_)) => Some(&self.host)
+        }
+    }
+}
+
+impl fmt::Display for Endpoint
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        match self.host.contains(':')
+        {
+            true => write!(f, "[{}]:{}", self.host, self.port())?,
+            false => write!(f, "{}:{}", self.host, self.port())?
+        }
+
+        match &self.sni
+        {
+            Some(sni) => write!(f, ":{}", sni),
+            None => Ok(())
+        }
+    }
+}
+
+/// Parse a list of endpoints (--connect-file), one per line, ignoring blank
+/// lines and comments (from "#").
+pub fn parse_list(contents: &str) -> Result<Vec<Endpoint>, String>
+{
+    let mut endpoints = vec![];
+
+    for (number, line) in contents.lines().enumerate()
+    {
+        let line = line.split('#').next().unwrap_or_default().trim();
+
+        if line.is_empty()
+        {
+            continue;
+        }
+
+        endpoints.push(Endpoint::parse(line).ok_or_else(|| format!("line {}: invalid endpoint -- '{}'", number + 1, line))?);
+    }
+
+    Ok(endpoints)
+}
+
+/// A chain for an endpoint which couldn't be reached, holding the reason in
+/// place of certificates, so it's still displayed.
+pub fn unreachable(endpoint: &Endpoint, error: &io::Error) -> Chain
+{
+    Chain { endpoint: Some(endpoint.to_string()), unreachable: Some(format!("couldn't connect: {}", error)), ..Chain::new() }
+}
+
+/// Fetch what endpoints serve, connecting to at most concurrency at once,
+/// returning the results in the same order.
+pub fn fetch_all(endpoints: &[Endpoint], concurrency: usize, timeout: Duration) -> Vec<io::Result<Served>>
+{
+    let fetch = || endpoints.par_iter().map(|endpoint| Served::fetch(endpoint, timeout)).collect();
+
+    match rayon::ThreadPoolBuilder::new().num_threads(concurrency).build()
+    {
+        Ok(pool) => pool.install(fetch),
+        Err(_) => endpoints.iter().map(|endpoint| Served::fetch(endpoint, timeout)).collect()
+    }
+}
+
+/// Handshake with an endpoint, returning the certificates it presents, in
+/// the order it presents them (its own first).
+pub fn fetch(endpoint: &Endpoint, timeout: Duration) -> io::Result<Vec<X509>>
+{
+    let (mut stream, address) = http::connect(&endpoint.host, endpoint.port(), timeout)?;
+
+    debug!("Connected to {} ({})", endpoint, address);
+
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    if let Some(protocol) = endpoint.starttls
+    {
+        protocol.negotiate(&mut stream)?;
+    }
+
+    let mut builder = SslConnector::builder(SslMethod::tls()).map_err(io::Error::other)?;
+
+    builder.set_verify(SslVerifyMode::NONE);
+
+    let mut configuration = builder.build().configure().map_err(io::Error::other)?;
+
+    configuration.set_verify_hostname(false);
+    configuration.set_use_server_name_indication(endpoint.server_name().is_some());
+
+    let stream = configuration.connect(endpoint.server_name().unwrap_or(&endpoint.host), stream)
+        .map_err(|e| io::Error::other(e.to_string()))?;
+
+    let certificates: Vec<X509> = stream.ssl().peer_cert_chain()
+        .map(|chain| chain.iter().map(|certificate| certificate.to_owned()).collect())
+        .unwrap_or_default();
+
+    match certificates.is_empty()
+    {
+        true => Err(io::Error::other("no certificate presented")),
+        false => Ok(certificates)
+    }
+}
+
+/// What an endpoint served, and the local chain corresponding best to it.
+pub struct Served
+{
+    pub endpoint: Endpoint,
+
+    /// Certificates presented, its own first.
+    pub certificates: Vec<X509>,
+
+    pub matched: Option<Match>,
+}
+
+impl Served
+{
+    pub fn fetch(endpoint: &Endpoint, timeout: Duration) -> io::Result<Served>
+    {
+        Ok(Served { endpoint: endpoint.clone(), certificates: fetch(endpoint, timeout)?, matched: None })
+    }
+
+    /// The certificates served, as a chain in the order served, with paths
+    /// naming the endpoint (and each signing certificate's position, e.g.
+    /// "example.com:443#1").
+    pub fn chain(&self) -> Chain
+    {
+        let endpoint = self.endpoint.to_string();
+        let mut signing_certificate = None;
+
+        for (index, certificate) in self.certificates.iter().enumerate().skip(1).rev()
+        {
+            let mut file = CertificateFile::new(&format!("{}#{}", endpoint, index), certificate.clone());
+
+            file.signing_certificate = signing_certificate;
+            signing_certificate = Some(Box::new(file));
+        }
+
+        let mut certificate = CertificateFile::new(&endpoint, self.certificates[0].clone());
+
+        certificate.signing_certificate = signing_certificate;
+
+        Chain { certificates: vec![certificate], endpoint: Some(endpoint), ..Chain::new() }
+    }
+
+    /// Compare a local chain with the served certificate, keeping the match
+    /// if it's closer than any before.
+    pub fn consider(&mut self, chain: &Chain)
+    {
+        // Served chains (including this one) aren't local.
+        if chain.endpoint.is_some()
+        {
+            return;
+        }
+
+        if let Some(matched) = compare(&self.certificates, chain)
+        {
+            if self.matched.as_ref().is_none_or(|best| matched.kind > best.kind)
+            {
+                self.matched = Some(matched);
+            }
+        }
+    }
+}
+
+/// How closely a served certificate corresponds to a local chain, weakest
+/// first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Kind
+{
+    /// A certificate for the chain's key, but none of its certificates.
+    Key,
+
+    /// One of the chain's older certificates.
+    Superseded,
+
+    /// The chain's current certificate.
+    Current,
+}
+
+/// A local chain corresponding to a served certificate.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Match
+{
+    pub kind: Kind,
+
+    /// Path of the local copy of the served certificate, if there is one.
+    pub certificate: Option<String>,
+
+    /// Path of the chain's current certificate.
+    pub current: Option<String>,
+
+    /// Path of the chain's key.
+    pub key: Option<String>,
+
+    /// How the served signing certificates differ from the local chain's
+    /// (when the certificate is found locally), e.g. "doesn't serve
+    /// intermediate.crt (...)".
+    pub discrepancies: Vec<String>,
+}
+
+/// Match a served chain against a local chain: its certificate by
+/// fingerprint against the local certificates (and their duplicates), then
+/// by public key against the local key.
+pub fn compare(served: &[X509], chain: &Chain) -> Option<Match>
+{
+    let fingerprint = served.first()?.digest(MessageDigest::sha256()).ok()?;
+    let current = chain.certificates.first().map(|certificate| certificate.path.clone());
+    let key = chain.key.as_ref().map(|key| key.path.clone());
+
+    let same = |certificate: &CertificateFile| {
+        certificate.fingerprint(MessageDigest::sha256()).is_ok_and(|local| local == fingerprint.as_ref())
+    };
+
+    if let Some(index) = chain.certificates.iter().position(same)
+    {
+        return Some(Match {
+            kind: if index == 0 { Kind::Current } else { Kind::Superseded },
+            certificate: Some(chain.certificates[index].path.clone()),
+            current,
+            key,
+            discrepancies: discrepancies(served, &chain.certificates[index])
+        });
+    }
+
+    let served_key = served[0].public_key().ok().and_then(|key| key_digest(&key));
+
+    match (&chain.key, served_key)
+    {
+        (Some(local), Some(served_key)) if key_digest(&local.key).as_ref() == Some(&served_key) => {
+            Some(Match { kind: Kind::Key, certificate: None, current, key, discrepancies: vec![] })
+        },
+        _ => None
+    }
+}
+
+/// Differences between the signing certificates served after a certificate
+/// and those found locally for it: intermediates missing, served out of
+/// order (each should issue the one before it), roots served (which clients
+/// must already have), and certificates unrelated to the local chain.
+/// Cross-signed intermediates (with the same subject and key) are
+/// interchangeable.
+fn discrepancies(served: &[X509], certificate: &CertificateFile) -> Vec<String>
+{
+    let mut discrepancies = vec![];
+    let local = certificate.signing_certificate_chain();
+    let name = |certificate: &X509Ref| format_name(certificate.subject_name());
+
+    let equivalent = |a: &X509Ref, b: &X509Ref| {
+        a.subject_name().to_der().ok() == b.subject_name().to_der().ok()
+            && a.public_key().ok().and_then(|key| key_digest(&key)) == b.public_key().ok().and_then(|key| key_digest(&key))
+    };
+
+    for pair in served.windows(2)
+    {
+        if pair[1].issued(&pair[0]) != X509VerifyResult::OK
+        {
+            discrepancies.push(format!("serves {} after {}, which it didn't issue", name(&pair[1]), name(&pair[0])));
+        }
+    }
+
+    for signing in &served[1..]
+    {
+        if trust::is_self_signed(signing)
+        {
+            discrepancies.push(format!("serves a root certificate ({}), which clients don't need", name(signing)));
+        }
+        else if !local.iter().any(|local| equivalent(&local.certificate, signing))
+        {
+            discrepancies.push(format!("serves a certificate which isn't in the local chain ({})", name(signing)));
+        }
+    }
+
+    for intermediate in local.iter().filter(|local| !local.self_signed)
+    {
+        if !served[1..].iter().any(|signing| equivalent(&intermediate.certificate, signing))
+        {
+            discrepancies.push(format!("doesn't serve intermediate {} ({})", intermediate.path, name(&intermediate.certificate)));
+        }
+    }
+
+    discrepancies
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+    use std::fs;
+    use crate::chain::bytes_to_x509;
+    use crate::Inventory;
+
+    #[test]
+    fn parses_endpoints()
+    {
+        assert_eq!(
+            Endpoint::parse("example.com"),
+            Some(Endpoint { host: String::from("example.com"), port: None, sni: None, starttls: None })
+        );
+        assert_eq!(Endpoint::parse("example.com").unwrap().port(), 443);
+        assert_eq!(Endpoint::parse("example.com:8443").unwrap().port(), 8443);
+        assert_eq!(Endpoint::parse("[2001:db8::1]:993").unwrap().to_string(), "[2001:db8::1]:993");
+        assert_eq!(Endpoint::parse("192.0.2.1:443").unwrap().server_name(), None);
+        assert_eq!(Endpoint::parse("example.com:https"), None);
+        assert_eq!(Endpoint::parse(":443"), None);
+        assert_eq!(Endpoint::parse("192.0.2.1:443:www.example.com").unwrap().server_name(), Some("www.example.com"));
+        assert_eq!(Endpoint::parse("[2001:db8::1]:443:www.example.com").unwrap().to_string(), "[2001:db8::1]:443:www.example.com");
+        assert_eq!(Endpoint::parse("example.com:443:"), None);
+
+        let endpoint = Endpoint { starttls: Some(Protocol::Smtp), ..Endpoint::parse("mail.example.com").unwrap() };
+
+        assert_eq!(endpoint.to_string(), "mail.example.com:25");
+
+        let endpoints = parse_list("# Web servers\nexample.com\n\n192.0.2.1:8443:example.org # staging\n").unwrap();
+
+        assert_eq!(endpoints.iter().map(Endpoint::to_string).collect::<Vec<_>>(), vec!["example.com:443", "192.0.2.1:8443:example.org"]);
+        assert_eq!(parse_list("example.com\nexample.com:x\n").unwrap_err(), "line 2: invalid endpoint -- 'example.com:x'");
+    }
+
+    #[test]
+    fn matches_served_certificates()
+    {
+        let chains = Inventory::new()
+            .add_path("samples/ca_signed.key")
+            .add_path("samples/ca_signed.crt")
+            .build_chains()
+            .unwrap();
+
+        let served = [bytes_to_x509(&fs::read("samples/ca_signed.crt").unwrap()).unwrap()];
+        let matched = compare(&served, &chains[0]).unwrap();
+
+        assert_eq!(matched.kind, Kind::Current);
+        assert_eq!(matched.certificate.as_deref(), Some("samples/ca_signed.crt"));
+        assert_eq!(matched.key.as_deref(), Some("samples/ca_signed.key"));
+
+        let other = [bytes_to_x509(&fs::read("samples/intermediate_ca.crt").unwrap()).unwrap()];
+
+        assert_eq!(compare(&other, &chains[0]), None);
+
+        // Without the certificate, it's still served for the key.
+        let chains = Inventory::new().add_path("samples/ca_signed.key").build_chains().unwrap();
+
+        assert_eq!(compare(&served, &chains[0]).unwrap().kind, Kind::Key);
+    }
+
+    #[test]
+    fn compares_served_chains()
+    {
+        let directory = "samples/cross_signed";
+        let read = |name: &str| bytes_to_x509(&fs::read(format!("{}/{}", directory, name)).unwrap()).unwrap();
+
+        let chains = Inventory::new()
+            .add_path(&format!("{}/example.com.key", directory))
+            .add_path(&format!("{}/example.com.crt", directory))
+            .add_path(&format!("{}/intermediate_by_a.crt", directory))
+            .add_path(&format!("{}/root_a.crt", directory))
+            .build_chains()
+            .unwrap();
+
+        let discrepancies = |names: &[&str]| {
+            let served: Vec<X509> = names.iter().map(|name| read(name)).collect();
+
+            compare(&served, &chains[0]).unwrap().discrepancies
+        };
+
+        let served = Served {
+            endpoint: Endpoint::parse("example.com").unwrap(),
+            certificates: vec![read("example.com.crt"), read("intermediate_by_b.crt")],
+            matched: None
+        };
+
+        let chain = served.chain();
+
+        assert_eq!(chain.origin(), Some("example.com:443"));
+        assert_eq!(chain.paths(), vec!["example.com:443", "example.com:443#1"]);
+
+        // Either cross-signed intermediate completes the chain.
+        assert!(discrepancies(&["example.com.crt", "intermediate_by_a.crt"]).is_empty());
+        assert!(discrepancies(&["example.com.crt", "intermediate_by_b.crt"]).is_empty());
+
+        assert_eq!(discrepancies(&["example.com.crt"]), vec![
+            "doesn't serve intermediate samples/cross_signed/intermediate_by_a.crt (C=US, O=Sample Org, CN=Intermediate CA)"
+        ]);
+        assert_eq!(discrepancies(&["example.com.crt", "root_a.crt", "intermediate_by_a.crt"]), vec![
+            "serves C=US, O=Sample Org, CN=Root CA A after C=US, O=Sample Org, CN=example.com, which it didn't issue",
+            "serves C=US, O=Sample Org, CN=Intermediate CA after C=US, O=Sample Org, CN=Root CA A, which it didn't issue",
+            "serves a root certificate (C=US, O=Sample Org, CN=Root CA A), which clients don't need",
+        ]);
+        assert_eq!(discrepancies(&["example.com.crt", "intermediate_by_a.crt", "root_b.crt"]), vec![
+            "serves C=US, O=Sample Org, CN=Root CA B after C=US, O=Sample Org, CN=Intermediate CA, which it didn't issue",
+            "serves a root certificate (C=US, O=Sample Org, CN=Root CA B), which clients don't need",
+        ]);
+    }
+}
@@ -0,0 +1,277 @@
+//! Copyright (C) 2022 Gaz J.
+//!
+//! This program is free software: you can redistribute it and/or modify
+//! it under the terms of the GNU General Public License as published by
+//! the Free Software Foundation, either version 3 of the License, or
+//! (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//! GNU General Public License for more details.
+//!
+//! You should have received a copy of the GNU General Public License
+//! along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Docker and Podman containers (--container): the keys and certificates in
+//! a container's filesystem, named "container:NAME/etc/ssl/server.pem".
+//!
+//! The filesystem is read where it's mounted (the overlay's merged
+//! directory, or /proc/PID/root), which usually needs root, or otherwise
+//! streamed with `docker export` (which works for stopped containers).
+//! Either way, volumes and bind mounts are read from their sources on the
+//! host, where they can be.
+
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use serde_json::Value;
+use walkdir::WalkDir;
+
+/// Prefixes the names of files in containers.
+pub const PREFIX: &str = "container:";
+
+/// Engines tried, in order, to find a container.
+const ENGINES: [&str; 2] = ["docker", "podman"];
+
+/// Directories of kernel interfaces, rather than files, in a running
+/// container's root.
+const SKIPPED: [&str; 3] = ["proc", "sys", "dev"];
+
+/// A container, as its engine describes it.
+#[derive(Debug)]
+pub struct Container
+{
+    /// The engine managing it, "docker" or "podman".
+    pub engine: String,
+
+    pub id: String,
+
+    /// Its name (without Docker's leading "/").
+    pub name: String,
+
+    /// Where its filesystem is mounted on the host, if readable.
+    pub root: Option<PathBuf>,
+
+    /// Its volumes and bind mounts, as (source on the host, destination)
+    /// pairs, which aren't part of its filesystem.
+    pub mounts: Vec<(PathBuf, String)>,
+}
+
+impl Container
+{
+    /// Find a container by name or ID with the first engine which knows it.
+    pub fn inspect(name: &str) -> io::Result<Container>
+    {
+        let mut errors = vec![];
+
+        for engine in ENGINES
+        {
+            let output = match Command::new(engine).args(["inspect", "--type", "container", name]).stdin(Stdio::null()).output()
+            {
+                Ok(output) => output,
+                Err(e) => {
+                    errors.push(format!("{}: {}", engine, e));
+                    continue;
+                }
+            };
+
+            if output.status.success()
+            {
+                return parse_inspect(engine, &output.stdout)
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("{} inspect {}: unexpected output", engine, name)));
+            }
+
+            errors.push(format!("{}: {}", engine, String::from_utf8_lossy(&output.stderr).trim()));
+        }
+
+        Err(io::Error::new(io::ErrorKind::NotFound, format!("{}: no such container ({})", name, errors.join("; "))))
+    }
+
+    /// The name a file in the container is given, e.g.
+    /// "container:web/etc/ssl/server.pem".
+    pub fn path(&self, file: &str) -> String
+    {
+        format!("{}{}/{}", PREFIX, self.name, file.trim_start_matches("./").trim_start_matches('/'))
+    }
+
+    /// The keys and certificates (files which look like PEM or DER, and no
+    /// larger than max_size bytes, unless it's 0) in the container, and its
+    /// mounts, as (name, contents) pairs.
+    pub fn files(&self, max_size: u64) -> io::Result<Vec<(String, Vec<u8>)>>
+    {
+        let mut files = match &self.root
+        {
+            Some(root) => self.read_directory(root, "", max_size),
+            None => self.export(max_size)?
+        };
+
+        for (source, destination) in &self.mounts
+        {
+            files.extend(self.read_directory(source, destination, max_size));
+        }
+
+        Ok(files)
+    }
+
+    /// Stream the container's filesystem with `docker export` (or podman's).
+    fn export(&self, max_size: u64) -> io::Result<Vec<(String, Vec<u8>)>>
+    {
+        let mut child = Command::new(&self.engine)
+            .args(["export", &self.id])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        let files = self.read_tar(child.stdout.take().expect("piped stdout"), max_size);
+        let output = child.wait_with_output()?;
+
+        if !output.status.success()
+        {
+            return Err(io::Error::other(format!("{} export failed: {}", self.engine, String::from_utf8_lossy(&output.stderr).trim())));
+        }
+
+        files
+    }
+
+    /// Walk a directory (or read a file) on the host, which is mounted at
+    /// destination in the container, staying on its filesystem (so the
+    /// container's root doesn't lead into its mounts, or the host's).
+    fn read_directory(&self, directory: &Path, destination: &str, max_size: u64) -> Vec<(String, Vec<u8>)>
+    {
+        let entries = WalkDir::new(directory)
+            .same_file_system(true)
+            .sort_by_file_name()
+            .into_iter()
+            .filter_entry(|entry| !destination.is_empty() || entry.depth() != 1 || !SKIPPED.iter().any(|skipped| entry.file_name() == *skipped))
+            .flatten()
+            .filter(|entry| entry.file_type().is_file() && entry.metadata().map(|m| max_size == 0 || m.len() <= max_size).unwrap_or(false));
+
+        entries
+            .filter_map(|entry| {
+                let contents = fs::read(entry.path()).ok().filter(|contents| looks_like_candidate(contents))?;
+                let file = Path::new(destination).join(entry.path().strip_prefix(directory).ok()?);
+
+                Some((self.path(&file.to_string_lossy()), contents))
+            })
+            .collect()
+    }
+
+    /// Read an exported filesystem.
+    fn read_tar<R: Read>(&self, reader: R, max_size: u64) -> io::Result<Vec<(String, Vec<u8>)>>
+    {
+        let mut archive = tar::Archive::new(reader);
+        let mut files = vec![];
+
+        for entry in archive.entries()?
+        {
+            let mut entry = entry?;
+
+            if !entry.header().entry_type().is_file() || (max_size > 0 && entry.size() > max_size)
+            {
+                continue;
+            }
+
+            let file = entry.path()?.to_string_lossy().to_string();
+            let mut contents = vec![];
+
+            entry.read_to_end(&mut contents)?;
+
+            if looks_like_candidate(&contents)
+            {
+                files.push((self.path(&file), contents));
+            }
+        }
+
+        Ok(files)
+    }
+}
+
+/// Describe a container from `inspect` output, preferring its overlay's
+/// merged directory, then its init process's root, if they can be read.
+/// Mounts without a source on the host (e.g. tmpfs) are left out.
+fn parse_inspect(engine: &str, output: &[u8]) -> Option<Container>
+{
+    let inspected: Value = serde_json::from_slice(output).ok()?;
+    let container = inspected.get(0)?;
+
+    let readable = |path: PathBuf| fs::read_dir(&path).is_ok().then_some(path);
+
+    let merged = container["GraphDriver"]["Data"]["MergedDir"].as_str().map(PathBuf::from);
+
+    let mounts = container["Mounts"].as_array().into_iter().flatten()
+        .filter_map(|mount| Some((PathBuf::from(mount["Source"].as_str().filter(|source| !source.is_empty())?), mount["Destination"].as_str()?.to_string())))
+        .collect();
+    let process = container["State"]["Pid"].as_u64().filter(|pid| *pid > 0).map(|pid| PathBuf::from(format!("/proc/{}/root", pid)));
+
+    Some(Container {
+        engine: engine.to_string(),
+        id: container["Id"].as_str()?.to_string(),
+        name: container["Name"].as_str()?.trim_start_matches('/').to_string(),
+        root: merged.and_then(readable).or_else(|| process.and_then(readable)),
+        mounts,
+    })
+}
+
+/// Whether contents have a PEM header, or start like a DER structure.
+fn looks_like_candidate(contents: &[u8]) -> bool
+{
+    let head = &contents[..contents.len().min(4096)];
+
+    String::from_utf8_lossy(head).contains("-----BEGIN ") || (head.len() >= 2 && head[0] == 0x30 && (0x81..=0x84).contains(&head[1]))
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    fn container(root: Option<&str>) -> Container
+    {
+        Container { engine: String::from("docker"), id: String::from("0123abcd"), name: String::from("web"), root: root.map(PathBuf::from), mounts: vec![] }
+    }
+
+    #[test]
+    fn parses_inspect_output()
+    {
+        let output = br#"[{"Id": "0123abcd", "Name": "/web", "State": {"Pid": 0}, "GraphDriver": {"Data": {"MergedDir": "samples/cross_signed"}},
+            "Mounts": [{"Type": "bind", "Source": "/srv/certs", "Destination": "/etc/ssl/certs"}, {"Type": "tmpfs", "Source": "", "Destination": "/tmp"}]}]"#;
+        let container = parse_inspect("docker", output).unwrap();
+
+        assert_eq!((container.id.as_str(), container.name.as_str()), ("0123abcd", "web"));
+        assert_eq!(container.root, Some(PathBuf::from("samples/cross_signed")));
+        assert_eq!(container.mounts, vec![(PathBuf::from("/srv/certs"), String::from("/etc/ssl/certs"))]);
+
+        let output = br#"[{"Id": "4567ef", "Name": "db", "State": {"Pid": 0}, "GraphDriver": {"Data": {"MergedDir": "samples/missing"}}}]"#;
+
+        assert_eq!(parse_inspect("podman", output).unwrap().root, None);
+        assert!(parse_inspect("docker", b"[]").is_none());
+    }
+
+    #[test]
+    fn reads_container_files()
+    {
+        let mut web = container(Some("samples/cross_signed"));
+
+        web.mounts.push((PathBuf::from("samples/haproxy"), String::from("/etc/haproxy/certs")));
+
+        let files = web.files(0).unwrap();
+        let names: Vec<&str> = files.iter().map(|(name, _)| name.as_str()).collect();
+
+        assert_eq!(names[..2], ["container:web/example.com.crt", "container:web/example.com.key"]);
+        assert_eq!(names[7..], ["container:web/etc/haproxy/certs/example.com.pem", "container:web/etc/haproxy/certs/example.com.pem.key"]);
+        assert_eq!(web.files(1300).unwrap().len(), 6);
+
+        let mut builder = tar::Builder::new(vec![]);
+
+        builder.append_path_with_name("samples/cross_signed/example.com.crt", "etc/ssl/example.com.crt").unwrap();
+        builder.append_path_with_name("Cargo.toml", "etc/Cargo.toml").unwrap();
+
+        let exported = builder.into_inner().unwrap();
+        let files = container(None).read_tar(exported.as_slice(), 0).unwrap();
+
+        assert_eq!(files, vec![(String::from("container:web/etc/ssl/example.com.crt"), fs::read("samples/cross_signed/example.com.crt").unwrap())]);
+    }
+}
@@ -0,0 +1,264 @@
+//! Copyright (C) 2022 Gaz J.
+//!
+//! This program is free software: you can redistribute it and/or modify
+//! it under the terms of the GNU General Public License as published by
+//! the Free Software Foundation, either version 3 of the License, or
+//! (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//! GNU General Public License for more details.
+//!
+//! You should have received a copy of the GNU General Public License
+//! along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Looks certificates up in Certificate Transparency logs (--ct), via a
+//! search frontend with crt.sh's JSON interface, to find whether they're
+//! logged, and whether other unexpired certificates have been logged for
+//! their names (e.g. issued by another team, or misissued).
+
+use std::collections::HashSet;
+use std::time::Duration;
+use openssl::bn::BigNum;
+use openssl::hash::{self, MessageDigest};
+use serde_json::Value;
+
+use crate::chain::CertificateFile;
+use crate::format;
+use crate::http;
+
+/// The default search frontend.
+pub const DEFAULT_FRONTEND: &str = "https://crt.sh/";
+
+/// A logged certificate, as described by the frontend.
+#[derive(Debug, PartialEq)]
+pub struct Sibling
+{
+    /// Serial number, in hexadecimal.
+    pub serial: String,
+
+    pub issuer: String,
+
+    /// Start of its validity period.
+    pub not_before: String,
+}
+
+/// What the logs say about a certificate.
+#[derive(Debug, PartialEq)]
+pub struct Transparency
+{
+    /// Whether the certificate itself (or its precertificate) is logged.
+    pub logged: bool,
+
+    /// Unexpired certificates logged for the same names, which aren't among
+    /// the known certificates.
+    pub unknown: Vec<Sibling>,
+}
+
+/// Identify a certificate as logs describe it: by its serial number (as
+/// normalize_serial gives it) and issuer (as normalize_issuer gives it), which
+/// it shares with its precertificate.
+pub fn identify(certificate: &CertificateFile) -> Option<(String, String)>
+{
+    let serial = certificate.serial()?;
+    let issuer = normalize_issuer(&format::format_name(certificate.certificate.issuer_name()));
+
+    Some((serial, issuer))
+}
+
+/// Look a certificate up with a search frontend (e.g. DEFAULT_FRONTEND).
+/// Known certificates (e.g. every certificate scanned, as identify gives
+/// them) aren't reported as unknown siblings.
+pub fn check(certificate: &CertificateFile, frontend: &str, known: &HashSet<(String, String)>, timeout: Duration) -> Result<Transparency, String>
+{
+    let own = identify(certificate);
+    let hex = |bytes: &[u8]| bytes.iter().map(|byte| format!("{:02X}", byte)).collect::<String>();
+
+    let fingerprint = certificate.fingerprint(MessageDigest::sha256()).map_err(|e| e.to_string())?;
+    let mut logged = !search(frontend, &format!("sha256={}", hex(&fingerprint)), timeout)?.is_empty();
+
+    // Often only the precertificate is logged, which shares the certificate's
+    // key, serial number, and issuer (but not its fingerprint).
+    if !logged
+    {
+        let key = certificate.certificate.public_key().and_then(|key| key.public_key_to_der()).map_err(|e| e.to_string())?;
+        let key = hash::hash(MessageDigest::sha256(), &key).map_err(|e| e.to_string())?;
+
+        logged = search(frontend, &format!("spkisha256={}", hex(&key)), timeout)?
+            .iter()
+            .any(|entry| own.is_some() && identify_entry(entry) == own);
+    }
+
+    let mut seen = HashSet::new();
+    let mut unknown = vec![];
+
+    for name in certificate.dns_names()
+    {
+        for entry in search(frontend, &format!("q={}&exclude=expired", http::encode(&name)), timeout)?
+        {
+            // Searches match names loosely, so only entries naming this one
+            // exactly (ignoring case) count.
+            let names = entry["name_value"].as_str().unwrap_or_default();
+
+            if !names.lines().any(|n| n.trim().eq_ignore_ascii_case(&name))
+            {
+                continue;
+            }
+
+            let identity = match identify_entry(&entry)
+            {
+                Some(identity) => identity,
+                None => continue
+            };
+
+            // Certificates and their precertificates share a serial number
+            // and issuer.
+            if Some(&identity) == own.as_ref() || known.contains(&identity) || !seen.insert(identity.clone())
+            {
+                continue;
+            }
+
+            unknown.push(Sibling {
+                serial: identity.0,
+                issuer: entry["issuer_name"].as_str().unwrap_or_default().to_string(),
+                not_before: entry["not_before"].as_str().unwrap_or_default().to_string(),
+            });
+        }
+    }
+
+    Ok(Transparency { logged, unknown })
+}
+
+/// Normalize a hexadecimal serial number (e.g. "00a1b2") to the form
+/// CertificateFile::serial uses ("A1B2"), for comparison.
+pub fn normalize_serial(serial: &str) -> Option<String>
+{
+    let serial = BigNum::from_hex_str(serial.trim()).ok()?;
+
+    serial.to_hex_str().ok().map(|hex| hex.to_string())
+}
+
+/// Normalize an issuer's name (e.g. "C=US, O=Let's Encrypt, CN=R11") for
+/// comparison, as frontends space and case names differently.
+pub fn normalize_issuer(issuer: &str) -> String
+{
+    issuer.chars().filter(|c| !c.is_whitespace()).flat_map(char::to_lowercase).collect()
+}
+
+/// Identify a logged certificate by its serial number and issuer (see
+/// identify).
+fn identify_entry(entry: &Value) -> Option<(String, String)>
+{
+    let serial = entry["serial_number"].as_str().and_then(normalize_serial)?;
+    let issuer = normalize_issuer(entry["issuer_name"].as_str()?);
+
+    Some((serial, issuer))
+}
+
+/// Run a search, returning the entries found.
+fn search(frontend: &str, query: &str, timeout: Duration) -> Result<Vec<Value>, String>
+{
+    let separator = if frontend.contains('?') { '&' } else { '?' };
+    let url = format!("{}{}{}&output=json", frontend, separator, query);
+
+    let body = http::get(&url, timeout).map_err(|e| format!("couldn't query {}: {}", frontend, e))?;
+
+    // crt.sh returns an empty body, rather than [], for some empty results.
+    if body.iter().all(u8::is_ascii_whitespace)
+    {
+        return Ok(vec![]);
+    }
+
+    match serde_json::from_slice(&body)
+    {
+        Ok(Value::Array(entries)) => Ok(entries),
+        _ => Err(format!("{} didn't return a list of certificates", frontend))
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+    use std::io::{self, BufRead, Write};
+    use std::net::TcpListener;
+    use openssl::x509::X509;
+
+    #[test]
+    fn finds_unknown_siblings()
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let frontend = format!("http://{}/", listener.local_addr().unwrap());
+
+        // Answer the certificate's lookup (which only finds its
+        // precertificate, by its key), then its name's.
+        let server = std::thread::spawn(move || {
+            let mut paths = vec![];
+            let responses = [
+                "[]",
+                r#"[{"serial_number": "2001", "issuer_name": "CN = AIA Sample CA"}]"#,
+                r#"[
+                    {"name_value": "example.com\nwww.example.com", "serial_number": "2001", "issuer_name": "CN=AIA Sample CA"},
+                    {"name_value": "EXAMPLE.COM", "serial_number": "00beef", "issuer_name": "CN=Other CA", "not_before": "2030-01-01T00:00:00"},
+                    {"name_value": "example.com", "serial_number": "BEEF", "issuer_name": "CN=Other CA", "not_before": "2030-01-01T00:00:00"},
+                    {"name_value": "example.com", "serial_number": "1234", "issuer_name": "CN=Known CA"},
+                    {"name_value": "example.com", "serial_number": "1234", "issuer_name": "CN=Another CA", "not_before": "2030-01-01T00:00:00"},
+                    {"name_value": "mail.example.com", "serial_number": "5678", "issuer_name": "CN=Other CA"}
+                ]"#,
+            ];
+
+            for response in responses
+            {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = io::BufReader::new(&stream);
+                let mut line = String::new();
+
+                reader.read_line(&mut line).unwrap();
+                paths.push(line.split_whitespace().nth(1).unwrap().to_string());
+
+                while reader.read_line(&mut line).unwrap() > 2
+                {
+                    line.clear();
+                }
+
+                write!(stream, "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}", response.len(), response).unwrap();
+            }
+
+            paths
+        });
+
+        let contents = std::fs::read("samples/aia/example.com.crt").unwrap();
+        let certificate = CertificateFile::new("samples/aia/example.com.crt", X509::from_pem(&contents).unwrap());
+        let known = HashSet::from([(String::from("1234"), normalize_issuer("CN=Known CA"))]);
+
+        let transparency = check(&certificate, &frontend, &known, Duration::from_secs(5)).unwrap();
+        let paths = server.join().unwrap();
+
+        assert!(paths[0].starts_with("/?sha256="));
+        assert!(paths[1].starts_with("/?spkisha256="));
+        assert_eq!(paths[2], "/?q=example.com&exclude=expired&output=json");
+
+        // Serial numbers are only known with their issuer.
+        assert!(transparency.logged);
+        assert_eq!(transparency.unknown, vec![
+            Sibling {
+                serial: String::from("BEEF"),
+                issuer: String::from("CN=Other CA"),
+                not_before: String::from("2030-01-01T00:00:00"),
+            },
+            Sibling {
+                serial: String::from("1234"),
+                issuer: String::from("CN=Another CA"),
+                not_before: String::from("2030-01-01T00:00:00"),
+            },
+        ]);
+    }
+
+    #[test]
+    fn encodes_names()
+    {
+        assert_eq!(http::encode("*.example.com"), "%2A.example.com");
+        assert_eq!(normalize_serial("00a1b2").as_deref(), Some("A1B2"));
+    }
+}
@@ -0,0 +1,224 @@
+//! Copyright (C) 2022 Gaz J.
+//!
+//! This program is free software: you can redistribute it and/or modify
+//! it under the terms of the GNU General Public License as published by
+//! the Free Software Foundation, either version 3 of the License, or
+//! (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//! GNU General Public License for more details.
+//!
+//! You should have received a copy of the GNU General Public License
+//! along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Daemon mode (--daemon): the path arguments (and --kubeconfig,
+//! --container, --acm, and --aws-secret) are scanned again every --interval,
+//! and alerts for what changed (see sslchains::alerts) are printed, posted
+//! to the --webhook URL, and logged to syslog (--syslog).
+//! The chains found, and their metrics, can be served over HTTP (--listen).
+
+use std::io::{self, Write};
+use std::process;
+use std::thread;
+use std::time::Duration;
+use log::info;
+use serde_json::{json, Value};
+
+use sslchains::alerts::{self, Alert, Kind, Snapshot};
+use sslchains::{cache, chain, http, metrics};
+use sslchains::server::{Page, Server};
+#[cfg(unix)]
+use sslchains::syslog::{self, Priority};
+use crate::arguments::Arguments;
+use crate::display;
+use crate::options::Options;
+use crate::status;
+
+/// Content type of Prometheus's text format.
+const PROMETHEUS_TEXT: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Most alerts kept to post again while the webhook fails (the oldest are
+/// dropped first).
+const MAX_UNSENT_ALERTS: usize = 10_000;
+
+/// Scan, and report alerts, until interrupted.
+pub fn run(options: &Options) -> !
+{
+    let server = options.listen.as_ref().map(|address| {
+        match Server::bind(address).and_then(|server| server.spawn().map(|_| server))
+        {
+            Ok(server) => server,
+            Err(e) => {
+                eprintln!("Couldn't listen on {}: {}", address, e);
+                process::exit(status::ERROR);
+            }
+        }
+    });
+
+    // The arguments are read once (--files-from may be standard input), and
+    // walked again by each scan.
+    let arguments = match Arguments::read(options)
+    {
+        Ok(arguments) => arguments,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(status::ERROR);
+        }
+    };
+
+    let mut previous: Option<Snapshot> = None;
+    let mut unsent = vec![];
+
+    loop
+    {
+        match scan(&arguments, options, server.as_ref())
+        {
+            Ok(snapshot) => {
+                report(snapshot.alerts(previous.as_ref()), &mut unsent, options);
+
+                previous = Some(snapshot);
+            },
+            Err(e) => eprintln!("Warning: couldn't scan: {}", e)
+        }
+
+        thread::sleep(Duration::from_secs(options.interval));
+    }
+}
+
+/// Read the files which the path arguments cover now, build chains, and
+/// publish them (with their metrics) to the server, if any.
+fn scan(arguments: &Arguments, options: &Options, server: Option<&Server>) -> Result<Snapshot, String>
+{
+    let mut warnings = vec![];
+
+    let paths = arguments.expand(options, &mut warnings).map_err(|e| e.to_string())?;
+
+    let mut inventory = match &options.cache
+    {
+        Some(directory) => chain::Inventory::ingest_cached(&paths, cache::Cache::open(directory)),
+        None => chain::Inventory::ingest(&paths)
+    };
+
+    // Kubernetes Secrets, containers, and AWS are listed again too.
+    crate::add_sources(&mut inventory, options)?;
+
+    let mut chains = vec![];
+
+    chain::build_each(&inventory, |chain| if options.selects(&chain) { chains.push(chain) }).map_err(|e| e.to_string())?;
+
+    if options.warnings || options.verbose
+    {
+        display::warnings(&warnings, options);
+    }
+
+    info!("Scanned {} file(s), finding {} chain(s)", paths.len(), chains.len());
+
+    let snapshot = Snapshot::new(&inventory, &chains, options.expiry_warn.unwrap_or(alerts::DEFAULT_WARN_DAYS));
+
+    if let Some(server) = server
+    {
+        publish(server, chains, options);
+    }
+
+    Ok(snapshot)
+}
+
+/// Serve chains with keys at /chains (as JSON, as with --ndjson, but in an
+/// array), and metrics for them at /metrics.
+fn publish(server: &Server, chains: Vec<chain::Chain>, options: &Options)
+{
+    let mut values = vec![];
+    let mut metrics = metrics::Metrics::new();
+
+    for mut chain in chains
+    {
+        display::rewrite_paths(&mut chain, options);
+        metrics.add(&chain);
+
+        if chain.key.is_some()
+        {
+            values.push(display::to_json(&chain, options));
+        }
+    }
+
+    server.publish("/chains", Page { content_type: "application/json", body: Value::Array(values).to_string().into_bytes() });
+    server.publish("/metrics", Page { content_type: PROMETHEUS_TEXT, body: metrics.render().into_bytes() });
+}
+
+/// Print alerts, and post them to the webhook (if any), as one request, with
+/// those which couldn't be posted before. Alerts are kept in `unsent` until
+/// they're posted.
+fn report(alerts: Vec<Alert>, unsent: &mut Vec<Alert>, options: &Options)
+{
+    for alert in &alerts
+    {
+        println!("{}", describe(alert, options));
+    }
+
+    #[cfg(unix)]
+    if options.syslog
+    {
+        log(&alerts, options);
+    }
+
+    // Output is often redirected to a log, so it shouldn't wait for more.
+    let _ = io::stdout().flush();
+
+    let url = match &options.webhook
+    {
+        Some(url) => url,
+        None => return
+    };
+
+    unsent.extend(alerts);
+
+    if unsent.is_empty()
+    {
+        return;
+    }
+
+    if unsent.len() > MAX_UNSENT_ALERTS
+    {
+        unsent.drain(..unsent.len() - MAX_UNSENT_ALERTS);
+    }
+
+    let body = json!({ "alerts": unsent.iter().map(Alert::to_json).collect::<Vec<_>>() });
+
+    match http::post(url, "application/json", body.to_string().as_bytes(), Duration::from_secs(options.timeout))
+    {
+        Ok(_) => unsent.clear(),
+        Err(e) => eprintln!("Warning: couldn't post {} alert(s) to {} (trying again after the next scan): {}", unsent.len(), url, e)
+    }
+}
+
+/// Log alerts to syslog, as they're printed.
+#[cfg(unix)]
+pub fn log(alerts: &[Alert], options: &Options)
+{
+    for alert in alerts
+    {
+        let priority = match alert.kind
+        {
+            Kind::Expired | Kind::Broken => Priority::Error,
+            Kind::Expiring | Kind::Removed => Priority::Warning,
+            Kind::Added | Kind::Changed => Priority::Info
+        };
+
+        syslog::log(priority, &describe(alert, options));
+    }
+}
+
+/// An alert as a line, e.g. "expiring: example.com.crt (example.com):
+/// expires in 12 day(s) (...)".
+fn describe(alert: &Alert, options: &Options) -> String
+{
+    let path = display::rewrite_path(&alert.path, options);
+
+    match alert.name.is_empty()
+    {
+        true => format!("{}: {}: {}", alert.kind.as_str(), path, alert.message),
+        false => format!("{}: {} ({}): {}", alert.kind.as_str(), path, alert.name, alert.message)
+    }
+}
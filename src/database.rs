@@ -0,0 +1,290 @@
+//! Copyright (C) 2022 Gaz J.
+//!
+//! This program is free software: you can redistribute it and/or modify
+//! it under the terms of the GNU General Public License as published by
+//! the Free Software Foundation, either version 3 of the License, or
+//! (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//! GNU General Public License for more details.
+//!
+//! You should have received a copy of the GNU General Public License
+//! along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Inventory databases (--db): each scan recorded in SQLite, with the host
+//! it ran on and when, and the files, keys, requests, certificates, and
+//! chains it found, so a database on shared storage can gather a fleet's
+//! scans, and be queried (`sslchains db expiring`).
+//!
+//! Only public metadata is stored (as in the cache), never private key
+//! material. Times are seconds since the epoch.
+
+use std::collections::BTreeSet;
+use std::io;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use openssl::sha::sha256;
+use openssl::x509::X509Name;
+use rusqlite::{params, Connection, OpenFlags};
+
+use crate::cache;
+use crate::chain::{self, Chain, Inventory};
+use crate::format;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS scans (id INTEGER PRIMARY KEY, host TEXT NOT NULL, time INTEGER NOT NULL);
+    CREATE TABLE IF NOT EXISTS files (scan INTEGER NOT NULL REFERENCES scans(id), path TEXT NOT NULL);
+    CREATE TABLE IF NOT EXISTS keys (scan INTEGER NOT NULL REFERENCES scans(id), path TEXT NOT NULL, public_key_sha256 TEXT);
+    CREATE TABLE IF NOT EXISTS requests (scan INTEGER NOT NULL REFERENCES scans(id), path TEXT NOT NULL, sha256 TEXT, subject TEXT,
+        public_key_sha256 TEXT);
+    CREATE TABLE IF NOT EXISTS certificates (scan INTEGER NOT NULL REFERENCES scans(id), path TEXT NOT NULL, fingerprint TEXT NOT NULL,
+        subject TEXT, issuer TEXT, not_before INTEGER, not_after INTEGER, public_key_sha256 TEXT);
+    CREATE TABLE IF NOT EXISTS chains (scan INTEGER NOT NULL REFERENCES scans(id), name TEXT NOT NULL, key_path TEXT,
+        certificate_path TEXT, complete INTEGER NOT NULL);
+    CREATE INDEX IF NOT EXISTS certificates_by_scan ON certificates (scan, not_after);
+";
+
+/// A certificate which expires soon, as the latest scan of a host found it.
+#[derive(Debug, PartialEq)]
+pub struct Expiring
+{
+    pub host: String,
+    pub path: String,
+    pub subject: String,
+
+    /// When it expires (seconds since the epoch).
+    pub not_after: i64,
+}
+
+/// What's recorded of a chain with a key.
+#[derive(Debug)]
+pub struct ChainRow
+{
+    name: String,
+    key: String,
+    certificate: Option<String>,
+    complete: bool,
+}
+
+impl ChainRow
+{
+    /// A chain's row, if it has a key (certificates without keys are
+    /// recorded as certificates only).
+    pub fn new(chain: &Chain) -> Option<ChainRow>
+    {
+        let key = chain.key.as_ref()?;
+        let certificate = chain.certificates.first();
+
+        Some(ChainRow {
+            name: format::get_display_name(chain),
+            key: key.path.clone(),
+            certificate: certificate.map(|certificate| certificate.path.clone()),
+            complete: certificate.map(|certificate| certificate.missing_issuer().is_none()).unwrap_or(false),
+        })
+    }
+}
+
+pub struct Database
+{
+    connection: Connection,
+}
+
+impl Database
+{
+    /// Open a database, creating it (and its tables) if needed.
+    pub fn open(path: &str) -> io::Result<Database>
+    {
+        let connection = Connection::open(path).map_err(to_io_error)?;
+
+        // Several hosts may write to a database on shared storage at once.
+        connection.busy_timeout(std::time::Duration::from_secs(30)).map_err(to_io_error)?;
+        connection.execute_batch(SCHEMA).map_err(to_io_error)?;
+
+        Ok(Database { connection })
+    }
+
+    /// Open an existing database to query it, without creating it, or
+    /// writing to it.
+    pub fn open_existing(path: &str) -> io::Result<Database>
+    {
+        let connection = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX)
+            .map_err(to_io_error)?;
+
+        connection.busy_timeout(std::time::Duration::from_secs(30)).map_err(to_io_error)?;
+
+        Ok(Database { connection })
+    }
+
+    /// Record a scan of this host, returning its ID.
+    pub fn record(&mut self, inventory: &Inventory, chains: &[ChainRow]) -> io::Result<i64>
+    {
+        self.record_as(&hostname(), now(), inventory, chains)
+    }
+
+    /// Record a scan of a host at a time.
+    fn record_as(&mut self, host: &str, time: i64, inventory: &Inventory, chains: &[ChainRow]) -> io::Result<i64>
+    {
+        let transaction = self.connection.transaction().map_err(to_io_error)?;
+
+        transaction.execute("INSERT INTO scans (host, time) VALUES (?1, ?2)", params![host, time]).map_err(to_io_error)?;
+
+        let scan = transaction.last_insert_rowid();
+        let mut files = BTreeSet::new();
+
+        for key in &inventory.keys
+        {
+            let digest = chain::key_digest(&key.key).map(|digest| cache::hex(&digest));
+
+            transaction.execute("INSERT INTO keys VALUES (?1, ?2, ?3)", params![scan, key.path, digest]).map_err(to_io_error)?;
+            files.insert(key.path.as_str());
+        }
+
+        for request in &inventory.requests
+        {
+            let digest = request.request.to_der().ok().map(|der| cache::hex(&sha256(&der)));
+            let public_key = request.public_key().ok().and_then(|key| chain::key_digest(&key)).map(|digest| cache::hex(&digest));
+            let subject = format::format_name(request.request.subject_name());
+
+            transaction.execute("INSERT INTO requests VALUES (?1, ?2, ?3, ?4, ?5)", params![scan, request.path, digest, subject, public_key])
+                .map_err(to_io_error)?;
+            files.insert(request.path.as_str());
+        }
+
+        for record in &inventory.certificates
+        {
+            let public_key = record.public_key.as_ref().map(|digest| cache::hex(digest));
+            let name = |der: &[u8]| X509Name::from_der(der).map(|name| format::format_name(&name)).unwrap_or_default();
+
+            for path in std::iter::once(&record.path).chain(&record.duplicates)
+            {
+                transaction.execute(
+                    "INSERT INTO certificates VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                    params![
+                        scan, path, cache::hex(&record.fingerprint),
+                        name(&record.subject), name(&record.issuer),
+                        record.not_before, record.not_after, public_key
+                    ]
+                ).map_err(to_io_error)?;
+                files.insert(path.as_str());
+            }
+        }
+
+        for path in files
+        {
+            transaction.execute("INSERT INTO files VALUES (?1, ?2)", params![scan, path]).map_err(to_io_error)?;
+        }
+
+        for chain in chains
+        {
+            transaction.execute(
+                "INSERT INTO chains VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![scan, chain.name, chain.key, chain.certificate, chain.complete]
+            ).map_err(to_io_error)?;
+        }
+
+        transaction.commit().map_err(to_io_error)?;
+
+        Ok(scan)
+    }
+
+    /// Certificates expiring within a number of days (or already expired),
+    /// as the latest scan of each host found them, soonest first.
+    pub fn expiring(&self, days: u32) -> io::Result<Vec<Expiring>>
+    {
+        self.expiring_at(now(), days)
+    }
+
+    fn expiring_at(&self, time: i64, days: u32) -> io::Result<Vec<Expiring>>
+    {
+        let mut statement = self.connection.prepare("
+            SELECT scans.host, certificates.path, certificates.subject, certificates.not_after
+            FROM certificates JOIN scans ON scans.id = certificates.scan
+            WHERE certificates.scan IN (SELECT MAX(id) FROM scans GROUP BY host) AND certificates.not_after < ?1
+            ORDER BY certificates.not_after, scans.host, certificates.path
+        ").map_err(to_io_error)?;
+
+        let rows = statement.query_map([time + days as i64 * 86_400], |row| {
+            Ok(Expiring { host: row.get(0)?, path: row.get(1)?, subject: row.get::<_, Option<String>>(2)?.unwrap_or_default(), not_after: row.get(3)? })
+        }).map_err(to_io_error)?;
+
+        rows.collect::<Result<_, _>>().map_err(to_io_error)
+    }
+}
+
+/// The host's name, which scans are recorded under.
+pub fn hostname() -> String
+{
+    #[cfg(unix)]
+    {
+        let mut buffer = [0u8; 256];
+
+        // SAFETY: the buffer is valid for its length, and NUL-terminated on
+        // success unless the name was truncated (which the search handles).
+        if unsafe { libc::gethostname(buffer.as_mut_ptr() as *mut libc::c_char, buffer.len()) } == 0
+        {
+            let length = buffer.iter().position(|byte| *byte == 0).unwrap_or(buffer.len());
+
+            return String::from_utf8_lossy(&buffer[..length]).to_string();
+        }
+    }
+
+    std::env::var("COMPUTERNAME").or_else(|_| std::env::var("HOSTNAME")).unwrap_or_else(|_| String::from("localhost"))
+}
+
+fn now() -> i64
+{
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|now| now.as_secs() as i64).unwrap_or(0)
+}
+
+fn to_io_error(error: rusqlite::Error) -> io::Error
+{
+    io::Error::other(error)
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    #[test]
+    fn records_scans_and_finds_expiring_certificates()
+    {
+        let mut database = Database::open(":memory:").unwrap();
+
+        let inventory = Inventory::new()
+            .add_path("samples/renewed/example.com.key")
+            .add_path("samples/renewed/example.com-2024.crt")
+            .add_path("samples/renewed/example.com-2025.crt")
+            .add_path("samples/self_signed.csr");
+        let chains: Vec<ChainRow> = inventory.build_chains().unwrap().iter().filter_map(ChainRow::new).collect();
+
+        // 2024-12-01, when web1 had both certificates, and web2 the older.
+        let time = 1_733_011_200;
+
+        database.record_as("web1", time - 86_400, &Inventory::new(), &[]).unwrap();
+        database.record_as("web1", time, &inventory, &chains).unwrap();
+        database.record_as("web2", time, &Inventory::new().add_path("samples/renewed/example.com-2024.crt"), &[]).unwrap();
+
+        let count = |table: &str| database.connection.query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |row| row.get::<_, i64>(0)).unwrap();
+
+        assert_eq!((count("scans"), count("files"), count("keys"), count("requests"), count("certificates"), count("chains")), (3, 5, 1, 1, 3, 1));
+
+        let expiring = database.expiring_at(time, 32).unwrap();
+
+        assert_eq!(expiring, vec![
+            Expiring { host: String::from("web1"), path: String::from("samples/renewed/example.com-2024.crt"), subject: String::from("CN=example.com"), not_after: 1_735_689_600 },
+            Expiring { host: String::from("web2"), path: String::from("samples/renewed/example.com-2024.crt"), subject: String::from("CN=example.com"), not_after: 1_735_689_600 },
+        ]);
+
+        assert!(database.expiring_at(time, 31).unwrap().is_empty());
+        assert!(!hostname().is_empty());
+
+        // Queries don't create databases.
+        let path = std::env::temp_dir().join(format!("sslchains-test-{}.db", std::process::id())).to_string_lossy().to_string();
+
+        assert!(Database::open_existing(&path).is_err());
+        assert!(!std::path::Path::new(&path).exists());
+    }
+}
@@ -13,28 +13,84 @@
 //! You should have received a copy of the GNU General Public License
 //! along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use crate::chain::{Chain, CertificateFile, CertificateRequestFile};
-use crate::options::Options;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+use openssl::asn1::Asn1Time;
+use openssl::hash::MessageDigest;
+use serde_json::Value;
+use sslchains::chain::{self, Chain, CertificateFile, Expiry, Orphans, Skipped};
+pub use sslchains::format::{format_name, format_time, get_display_name};
+use crate::options::{Options, OptionsDisplayMode};
+use sslchains::openssh::PublicKey;
+use sslchains::configured::Configured;
+use sslchains::{connect, ct, export, fetch, letsencrypt, ocsp, ssh, trust};
 
 /// Default display mode handler.
-pub fn default(chains: Vec<Chain>)
+pub fn default(chains: Vec<Chain>, options: &Options)
+{
+    annotated(chains, options, &|_| None)
+}
+
+/// Default display mode, with a note (if any) after each chain's name.
+fn annotated(chains: Vec<Chain>, options: &Options, note: &dyn Fn(&Chain) -> Option<String>)
 {
     for chain in chains
     {
-        println!("{}", get_display_name(&chain));
+        match note(&chain)
+        {
+            Some(note) => println!("{} ({})", get_display_name(&chain), note),
+            None => println!("{}", get_display_name(&chain))
+        }
 
-        match chain.key {
-            Some(key) => println!("  * Key: {}", key.path),
-            _ => continue
+        let combined = chain.is_combined();
+
+        match &chain.key {
+            Some(key) => {
+                print!("  * Key: {}", key.path);
+
+                if combined { print!(" (combined)"); }
+
+                if key.is_deprecated() { print!(" ({}, deprecated)", key.algorithm()); }
+
+                println!();
+            },
+            None => match &chain.endpoint {
+                Some(endpoint) => match &chain.unreachable {
+                    Some(reason) => println!("  * Endpoint: {} ({})", endpoint, reason),
+                    None => println!("  * Endpoint: {}", endpoint)
+                },
+                None => continue
+            }
         }
 
         print!("  * CSR: ");
 
-        match chain.request {
-            Some(request) => println!("{}", request.path),
+        match &chain.request {
+            Some(request) => {
+                println!("{}", request.path);
+
+                for name in &options.fingerprints
+                {
+                    if let Ok(fingerprint) = request.fingerprint(digest(name))
+                    {
+                        print_indentation(4);
+                        println!("{}: {}", digest_label(name), format_fingerprint(&fingerprint));
+                    }
+                }
+            },
             _ => println!("n/a")
         }
 
+        if options.sans
+        {
+            let sans = chain.certificates.first().map(|c| c.subject_alt_names()).unwrap_or_default();
+
+            println!("  * SANs: {}", if sans.is_empty() { String::from("n/a") } else { sans.join(", ") });
+        }
+
         print!("  * Certificates: ");
 
         if chain.certificates.len() == 0
@@ -45,7 +101,7 @@ pub fn default(chains: Vec<Chain>)
         {
             println!();
 
-            for certificate in chain.certificates
+            for (index, certificate) in chain.certificates.iter().enumerate()
             {
                 let mut indentation = 4;
 
@@ -53,62 +109,284 @@ pub fn default(chains: Vec<Chain>)
 
                 print!("- {}", certificate.path);
 
+                if let Some(lineage) = chain.lineage(index) { print!(" ({})", lineage); }
+
+                // Note which name covers the requested hostname (--host).
+                if let Some(name) = options.host.as_ref().filter(|_| index == 0).and_then(|host| certificate.host_match(host))
+                {
+                    print!(" (matches {})", name);
+                }
+
+                if options.dates { print!(" ({})", format_validity(certificate)); }
+
+                print_expiry(certificate, options);
+
+                let signing_certificates = certificate.signing_certificate_chain();
+
                 if certificate.self_signed
                 {
-                    println!(" (self-signed)");
+                    print!(" (self-signed)");
+
+                    print_trust_status(certificate);
+
+                    println!();
+
+                    print_details(certificate, indentation + 2, options);
+
+                    print_duplicates(certificate, indentation + 2);
 
-                    break;
+                    // Other (e.g. renewed) certificates may follow.
+                    continue;
                 }
 
+                if signing_certificates.is_empty() { print_trust_status(certificate); }
+
                 println!();
 
+                print_details(certificate, indentation + 2, options);
+
+                print_duplicates(certificate, indentation + 2);
+
                 // Print chain of signing certificates recursively.
-                for signing_certificate in certificate.signing_certificate_chain()
+                for (index, signing_certificate) in signing_certificates.iter().enumerate()
                 {
                     indentation += 2;
 
                     print_indentation(indentation);
 
-                    println!("> {}", signing_certificate.path);
+                    print!("> {}", signing_certificate.path);
+
+                    if options.dates { print!(" ({})", format_validity(signing_certificate)); }
+
+                    print_expiry(signing_certificate, options);
+
+                    // Annotate the top of the chain.
+                    if index == signing_certificates.len() - 1 { print_trust_status(signing_certificate); }
+
+                    println!();
+
+                    print_details(signing_certificate, indentation + 2, options);
+
+                    print_duplicates(signing_certificate, indentation + 2);
                 }
             }
         }
+
+        if options.verify
+        {
+            let verification = chain.verify();
+
+            match verification.reason()
+            {
+                Some(reason) => println!("  * Verification: {} ({})", verification.as_str(), reason),
+                None => println!("  * Verification: {}", verification.as_str())
+            }
+        }
+
+        if options.ocsp && !chain.certificates.is_empty()
+        {
+            match revocation(&chain, options)
+            {
+                Some(revocation) => match revocation.reason()
+                {
+                    Some(reason) => println!("  * OCSP: {} ({})", revocation.as_str(), reason),
+                    None => println!("  * OCSP: {}", revocation.as_str())
+                },
+                None => println!("  * OCSP: n/a (no responder)")
+            }
+        }
+    }
+}
+
+/// Query the chain's OCSP responder (--ocsp), within the network timeout.
+fn revocation(chain: &Chain, options: &Options) -> Option<ocsp::Revocation>
+{
+    chain.revocation(Duration::from_secs(options.timeout))
+}
+
+/// Print other paths containing the same certificate.
+fn print_duplicates(certificate: &CertificateFile, indentation: i32)
+{
+    for path in &certificate.duplicates
+    {
+        print_indentation(indentation);
+
+        println!("= {} (duplicate)", path);
+    }
+}
+
+/// Print whether a certificate has expired or expires soon (--expiry-warn).
+/// Expired certificates are already noted alongside their dates (-d).
+fn print_expiry(certificate: &CertificateFile, options: &Options)
+{
+    let days = match options.expiry_warn
+    {
+        Some(days) => days,
+        None => return
+    };
+
+    match certificate.expiry(days)
+    {
+        Expiry::Expired if !options.dates => print!(" (expired)"),
+        Expiry::Expiring => match certificate.days_remaining()
+        {
+            Some(1) => print!(" (expires in 1 day)"),
+            Some(remaining) => print!(" (expires in {} days)", remaining),
+            None => {}
+        },
+        _ => {}
+    }
+}
+
+/// Print whether the top certificate of a chain is (or is issued by) a
+/// root in the system trust store.
+fn print_trust_status(certificate: &CertificateFile)
+{
+    if let Some(status) = trust_status(certificate)
+    {
+        print!(" ({})", status);
+    }
+}
+
+/// Describe the top certificate of a chain: why its chain stopped there,
+/// if its issuer was found but not followed (e.g. in a loop), or else its
+/// trust status.
+fn trust_status(certificate: &CertificateFile) -> Option<String>
+{
+    match &certificate.stopped
+    {
+        Some(stopped) => Some(stopped.to_string()),
+        None => trust::system_status(&certificate.certificate).map(|status| status.to_string())
+    }
+}
+
+/// Default display mode handler, with chains grouped under a heading for
+/// the directory containing their files, and Let's Encrypt versions marked
+/// as the layout (if any) has them.
+pub fn grouped(chains: Vec<Chain>, options: &Options, layout: Option<&letsencrypt::Layout>)
+{
+    let mut groups: BTreeMap<String, Vec<Chain>> = BTreeMap::new();
+
+    for chain in chains
+    {
+        let directory = match (&chain.key, &chain.endpoint)
+        {
+            (Some(_), _) => get_directory(&chain),
+            (None, Some(_)) => String::from("(endpoints)"),
+            (None, None) => continue
+        };
+
+        groups.entry(directory).or_default().push(chain);
+    }
+
+    // Let's Encrypt lineages (--letsencrypt) are grouped by their archive
+    // directories, so their versions are listed newest first, noting which
+    // is live.
+    let version_of = |chain: &Chain| {
+        let path = chain.key.as_ref().map(|key| &key.path).or(chain.certificates.first().map(|certificate| &certificate.path));
+
+        layout.zip(path).and_then(|(layout, path)| layout.version_of(path))
+    };
+
+    let note = |chain: &Chain| version_of(chain).map(|(lineage, version)| format!("version {}, {}", version, lineage.status(version).as_str()));
+
+    for (index, (directory, mut chains)) in groups.into_iter().enumerate()
+    {
+        if index > 0 { println!(); }
+
+        println!("{}:", directory);
+
+        chains.sort_by_key(|chain| std::cmp::Reverse(version_of(chain).map(|(_, version)| version)));
+
+        annotated(chains, options, &note);
+    }
+}
+
+/// Get the deepest directory containing a chain's key, request, and
+/// certificates. Signing certificates are excluded, as they're often kept
+/// in shared bundles elsewhere.
+fn get_directory(chain: &Chain) -> String
+{
+    let mut paths: Vec<&str> = vec![];
+
+    if let Some(key) = &chain.key { paths.push(&key.path); }
+    if let Some(request) = &chain.request { paths.push(&request.path); }
+
+    for certificate in &chain.certificates
+    {
+        paths.push(&certificate.path);
+    }
+
+    let directories: Vec<Vec<Component>> = paths.iter()
+        .map(|path| chain::container_path(path).unwrap_or(path))
+        .map(|path| Path::new(path).parent().map(|p| p.components().collect()).unwrap_or_default())
+        .collect();
+
+    let mut common: Vec<Component> = directories.first().cloned().unwrap_or_default();
+
+    for directory in &directories[1..]
+    {
+        let length = common.iter().zip(directory.iter()).take_while(|(a, b)| a == b).count();
+
+        common.truncate(length);
+    }
+
+    if common.is_empty()
+    {
+        return String::from(".");
     }
+
+    common.iter().collect::<PathBuf>().display().to_string()
 }
 
 /// OneLine display mode handler.
-pub fn oneline(chains: Vec<Chain>)
+pub fn oneline(chains: Vec<Chain>, options: &Options)
 {
-    if !Options::new().suppress_oneline_header
+    if !options.suppress_oneline_header
     {
-        println!("name key request certificate_chain");
+        print!("name key request certificate_chain");
+
+        if options.verify { print!(" verification"); }
+
+        if options.ocsp { print!(" ocsp"); }
+
+        println!();
     }
 
     for chain in chains
     {
         print!("{}", get_display_name(&chain));
 
-        match chain.key {
-            Some(key) => print!(" {}", key.path),
+        match chain.origin() {
+            Some(origin) => print!(" {}", origin),
             _ => continue
         }
 
-        match chain.request {
+        if chain.is_combined() { print!("|(combined)"); }
+
+        if chain.unreachable.is_some() { print!("|(unreachable)"); }
+
+        match &chain.request {
             Some(request) => print!(" {}", request.path),
             _ => print!(" -")
         }
 
+        let verification = options.verify.then(|| chain.verify());
+        let revocation = options.ocsp.then(|| revocation(&chain, options));
+
         if chain.certificates.len() == 0
         {
-            println!(" -");
-
-            continue;
+            print!(" -");
         }
 
         for certificate in chain.certificates
         {
             print!(" {}", certificate.path);
 
+            if options.dates { print!("@{}", format_validity_interval(&certificate)); }
+
+            print_expiry_suffix(&certificate, options);
+
             if certificate.self_signed
             {
                 print!("|(self-signed)");
@@ -120,199 +398,1007 @@ pub fn oneline(chains: Vec<Chain>)
             for signing_certificate in certificate.signing_certificate_chain()
             {
                 print!("|{}", signing_certificate.path);
+
+                if options.dates { print!("@{}", format_validity_interval(&signing_certificate)); }
+
+                print_expiry_suffix(&signing_certificate, options);
             }
         }
 
+        if let Some(verification) = verification { print!(" {}", verification.as_str()); }
+
+        if let Some(revocation) = revocation { print!(" {}", revocation.as_ref().map_or("-", |r| r.as_str())); }
+
         println!();
     }
 }
 
-/// Get display name for chain by checking multiple sources.
-fn get_display_name(chain: &Chain) -> String
+/// Suffix a certificate's path with "!expired" or "!expiring" in single
+/// line display mode (--expiry-warn).
+fn print_expiry_suffix(certificate: &CertificateFile, options: &Options)
 {
-
-    if let Some(certificate) = &chain.certificates.get(0)
+    if let Some(days) = options.expiry_warn
     {
-        if let Some(name) = get_display_name_from_certificate(&certificate)
+        match certificate.expiry(days)
         {
-            return name;
+            Expiry::Valid => {},
+            expiry => print!("!{}", expiry.as_str())
         }
     }
+}
 
-    if let Some(request) = &chain.request
+/// Print files which aren't part of any chain, after the chains.
+pub fn orphans(orphans: &Orphans, options: &Options)
+{
+    println!("Orphans");
+
+    let sections = [
+        ("Keys without certificates", &orphans.keys),
+        ("CSRs without keys", &orphans.requests),
+        ("Certificates without keys", &orphans.certificates),
+        ("Unused intermediates", &orphans.intermediates),
+    ];
+
+    for (title, paths) in sections
     {
-        if let Some(name) = get_display_name_from_request(&request)
+        print!("  * {}: ", title);
+
+        if paths.is_empty()
         {
-            return name;
+            println!("n/a");
+
+            continue;
         }
-    }
 
-    "(unknown)".to_string()
-}
+        println!();
 
-/// Get display name from a certificate signing request.
-fn get_display_name_from_request(request: &CertificateRequestFile) -> Option<String>
-{
-    if let Some(common_name) = request.common_name()
-    {
-        if let Some(line) = common_name.data().as_utf8().unwrap().lines().last()
+        for path in paths
         {
-            return Some(line.to_string());
+            println!("    - {}", rewrite_path(path, options));
         }
     }
-
-    None
 }
 
-/// Get display name from a certificate signing request.
-fn get_display_name_from_certificate(certificate: &CertificateFile) -> Option<String>
+/// Print OpenSSH host keys, with their certificates and the CA keys which
+/// signed those, then other (CA) keys, after the chains.
+pub fn ssh(inventory: &ssh::Inventory, options: &Options)
 {
-    // Prefer subject alternative name values.
-    let x509 = &certificate.certificate;
+    let describe = |key: &PublicKey| format!("({} {})", key.kind(), key.fingerprint());
+
+    println!("SSH host keys");
+
+    let mut host_keys: Vec<_> = inventory.host_keys().into_iter()
+        .map(|key| (Some(key), inventory.certificates_of(key)))
+        .collect();
+
+    for certificate in inventory.unmatched_certificates()
+    {
+        host_keys.push((None, vec![certificate]));
+    }
+
+    if host_keys.is_empty()
+    {
+        println!("  * n/a");
+    }
 
-    if let Some(general_name_stack) = x509.subject_alt_names()
+    for (key, certificates) in host_keys
     {
-        if ! general_name_stack.is_empty()
+        match key
+        {
+            Some(key) => print!("  * {} {}", rewrite_path(&key.paths[0], options), describe(&key.key)),
+            None => print!("  * n/a {}", describe(&certificates[0].1.key))
+        }
+
+        if certificates.is_empty()
+        {
+            print!(" (no certificate)");
+        }
+
+        println!();
+
+        for (path, certificate) in certificates
         {
-            // Prefer non-www values.
-            for general_name in &general_name_stack
+            let principals = match certificate.principals.is_empty()
             {
-                if let Some(dnsname) = general_name.dnsname()
-                {
-                    if ! dnsname.starts_with("www.")
-                    {
-                        return Some(dnsname.to_string());
-                    }
-                }
+                true => String::from("any principal"),
+                false => certificate.principals.join(", ")
+            };
+
+            print!("    - {} ({})", rewrite_path(path, options), principals);
+
+            if !certificate.host
+            {
+                print!(" (user certificate)");
             }
 
-            // Fall back to first entry.
-            if let Some(general_name) = general_name_stack.get(0)
+            match ssh::validity(certificate)
             {
-                if let Some(dnsname) = general_name.dnsname()
-                {
-                    return Some(dnsname.to_string());
-                }
+                ssh::Validity::NotYetValid => print!(" (not valid until {})", format_ssh_time(certificate.valid_after)),
+                ssh::Validity::Expired => print!(" (expired {})", format_ssh_time(certificate.valid_before)),
+                ssh::Validity::Valid => print!(" (expires {})", format_ssh_time(certificate.valid_before))
+            }
+
+            println!();
+
+            match inventory.signer_of(certificate)
+            {
+                Some(signer) => println!("      > {} {}", rewrite_path(&signer.paths[0], options), describe(&signer.key)),
+                None => println!("      > n/a {}", describe(&certificate.signature_key))
             }
         }
     }
 
-    // Fall back to common name value.
-    if let Some(common_name) = certificate.common_name()
+    println!("SSH CA keys");
+
+    let ca_keys = inventory.ca_keys();
+
+    if ca_keys.is_empty()
+    {
+        println!("  * n/a");
+    }
+
+    for key in ca_keys
     {
-        if let Some(line) = common_name.data().as_utf8().unwrap().lines().last()
+        print!("  * {} {}", rewrite_path(&key.paths[0], options), describe(&key.key));
+
+        if !key.key.comment.is_empty()
         {
-            return Some(line.to_string());
+            print!(" ({})", key.key.comment);
         }
-    }
 
-    None
+        println!();
+    }
 }
 
-/// Print a given number spaces for intentation.
-fn print_indentation(spaces: i32)
+/// Format OpenSSH certificate times, which count seconds since the epoch
+/// (with the largest value meaning forever).
+fn format_ssh_time(seconds: u64) -> String
 {
-    for _ in 1..=spaces { print!(" "); }
+    match i64::try_from(seconds).ok().and_then(|seconds| Asn1Time::from_unix(seconds).ok())
+    {
+        Some(time) if seconds != u64::MAX => format_time(&time),
+        _ => String::from("never")
+    }
 }
 
-#[cfg(test)]
-mod test
+/// Report files which couldn't be parsed on stderr, so they don't mix
+/// with (possibly machine-readable) output.
+pub fn skipped(skipped: &[(String, Skipped)], options: &Options)
 {
-    use super::*;
-
-    #[test]
-    fn gets_display_name_prefers_certificate()
+    for (path, reason) in skipped
     {
-        use crate::chain;
-
-        let mut chain = chain::Chain::new();
-
-        let path = "samples/self_signed_san.key";
-        let contents = chain::get_file_contents(&path).unwrap();
-        let key = chain::str_to_private_key(&contents).unwrap();
-        chain.key = Some(chain::PrivateKeyFile::new(path, key));
+        eprintln!("Skipped {}: {}", rewrite_path(path, options), reason);
+    }
+}
 
-        let path = "samples/self_signed_san.csr";
-        let contents = chain::get_file_contents(&path).unwrap();
-        let x509req = chain::str_to_x509req(&contents).unwrap();
-        chain.request = Some(chain::CertificateRequestFile::new(path, x509req));
+/// List the files which would be processed, one per line (or
+/// NUL-terminated with --print0).
+pub fn files(paths: &[String], options: &Options)
+{
+    let terminator = match options.display_mode
+    {
+        OptionsDisplayMode::Print0 => '\0',
+        _ => '\n'
+    };
 
-        let path = "samples/self_signed_san.crt";
-        let contents = chain::get_file_contents(&path).unwrap();
-        let x509 = chain::str_to_x509(&contents).unwrap();
-        chain.certificates = vec![chain::CertificateFile::new(path, x509)];
+    let mut stdout = io::stdout().lock();
 
-        assert_eq!(get_display_name(&chain), "san.example.com".to_string());
+    for path in paths
+    {
+        // Ignore write errors (e.g. a closed pipe) as the default mode does.
+        let _ = write!(stdout, "{}{}", rewrite_path(path, options), terminator);
     }
+}
 
-    #[test]
-    fn gets_display_name_falls_back_to_request()
+/// Warn about private keys with lax permissions or unexpected owners on
+/// stderr, as (path, issues) pairs.
+pub fn key_permissions(keys: &[(String, Vec<String>)], options: &Options)
+{
+    for (path, issues) in keys
     {
-        use crate::chain;
+        eprintln!("Warning: private key {} is {}", rewrite_path(path, options), issues.join(", "));
+    }
+}
 
-        let mut chain = chain::Chain::new();
+/// Report signing certificates fetched from caIssuers URLs (--fetch-missing)
+/// on stderr, with where they were saved (--save-fetched).
+pub fn fetched(fetched: &[fetch::Fetched], options: &Options)
+{
+    for fetched in fetched
+    {
+        match &fetched.saved
+        {
+            Some(path) => eprintln!(
+                "Fetched the signing certificate of {} from {}, saved as {}",
+                rewrite_path(&fetched.certificate, options), fetched.url, rewrite_path(path, options)
+            ),
+            None => eprintln!(
+                "Fetched the signing certificate of {} from {}",
+                rewrite_path(&fetched.certificate, options), fetched.url
+            )
+        }
+    }
+}
 
-        let path = "samples/self_signed_san_no_cert.key";
-        let contents = chain::get_file_contents(&path).unwrap();
-        let key = chain::str_to_private_key(&contents).unwrap();
-        chain.key = Some(chain::PrivateKeyFile::new(path, key));
+/// Report what changed since a snapshot (--load-snapshot) on stderr, as
+/// (path, change) pairs.
+pub fn drift(drift: &[(String, String)], options: &Options)
+{
+    for (path, change) in drift
+    {
+        eprintln!("Changed since the snapshot: {}: {}", rewrite_path(path, options), change);
+    }
+}
 
-        let path = "samples/self_signed_san_no_cert.csr";
-        let contents = chain::get_file_contents(&path).unwrap();
-        let x509req = chain::str_to_x509req(&contents).unwrap();
-        chain.request = Some(chain::CertificateRequestFile::new(path, x509req));
+/// Report files written for chains (e.g. --export-fullchain) on stderr.
+pub fn exported(exporters: &[export::Exporter], options: &Options)
+{
+    for exported in exporters.iter().flat_map(|exporter| &exporter.exported)
+    {
+        eprintln!("Exported the chain of {} to {}", rewrite_path(&exported.key, options), exported.path);
+    }
+}
 
-        assert_eq!(get_display_name(&chain), "example.com".to_string());
+/// Warn about certificates which don't cover everything their request asked
+/// for on stderr, as (certificate path, request path, mismatches) tuples.
+pub fn request_mismatches(mismatches: &[(String, String, Vec<String>)], options: &Options)
+{
+    for (certificate, request, names) in mismatches
+    {
+        eprintln!(
+            "Warning: {} doesn't cover {} from {}",
+            rewrite_path(certificate, options), names.join(", "), rewrite_path(request, options)
+        );
     }
+}
 
-    #[test]
-    fn reads_common_name_from_certificate_request()
+/// Report what Certificate Transparency logs say about certificates (--ct)
+/// on stderr, as (path, transparency) pairs, warning about those which aren't
+/// logged, and about unknown certificates logged for their names.
+pub fn transparency(transparency: &[(String, ct::Transparency)], options: &Options)
+{
+    for (path, transparency) in transparency
     {
-        use crate::chain;
+        let path = rewrite_path(path, options);
 
-        let path = "samples/self_signed.csr";
-        let contents = chain::get_file_contents(&path).unwrap();
-        let x509req = chain::str_to_x509req(&contents).unwrap();
-        let request = chain::CertificateRequestFile::new(path, x509req);
+        match transparency.logged
+        {
+            true => eprintln!("{} is in Certificate Transparency logs", path),
+            false => eprintln!("Warning: {} isn't in Certificate Transparency logs", path)
+        }
 
-        match get_display_name_from_request(&request)
+        for sibling in &transparency.unknown
         {
-            Some(display_name) => assert_eq!(display_name, "example.com"),
-            _ => assert!(false)
+            eprintln!(
+                "Warning: {}: unknown certificate logged for its names: serial {}, issued by {}, valid from {}",
+                path, sibling.serial, sibling.issuer, sibling.not_before
+            );
         }
     }
+}
 
-    #[test]
-    fn reads_subject_alternative_name_from_certificate_request()
+/// Report which local chain each TLS server's certificate belongs to
+/// (--connect) on stderr, warning when it's out of date or unknown.
+pub fn served(served: &[connect::Served], options: &Options)
+{
+    for served in served
     {
-        use crate::chain;
+        let endpoint = &served.endpoint;
+        let path = |path: &Option<String>| path.as_deref().map(|path| rewrite_path(path, options)).unwrap_or_default();
 
-        let path = "samples/self_signed_san.crt";
-        let contents = chain::get_file_contents(&path).unwrap();
-        let x509 = chain::str_to_x509(&contents).unwrap();
-        let certificate = chain::CertificateFile::new(path, x509);
+        match &served.matched
+        {
+            Some(matched) => match matched.kind
+            {
+                connect::Kind::Current if matched.key.is_some() => {
+                    eprintln!("{} serves {} (key {})", endpoint, path(&matched.certificate), path(&matched.key));
+                },
+                connect::Kind::Current => eprintln!("{} serves {}", endpoint, path(&matched.certificate)),
+                connect::Kind::Superseded => eprintln!(
+                    "Warning: {} serves {}, which is superseded by {}", endpoint, path(&matched.certificate), path(&matched.current)
+                ),
+                connect::Kind::Key => eprintln!(
+                    "Warning: {} serves a certificate for {} which isn't found locally ({}){}",
+                    endpoint, path(&matched.key), describe_served(served),
+                    matched.current.as_ref().map(|_| format!(", rather than {}", path(&matched.current))).unwrap_or_default()
+                )
+            },
+            None => eprintln!("Warning: {} serves a certificate which isn't found locally ({})", endpoint, describe_served(served))
+        }
 
-        match get_display_name_from_certificate(&certificate)
+        for discrepancy in served.matched.iter().flat_map(|matched| &matched.discrepancies)
         {
-            Some(display_name) => assert_eq!(display_name, "san.example.com"),
-            _ => assert!(false)
+            eprintln!("Warning: {} {}", endpoint, discrepancy);
         }
     }
+}
 
-    #[test]
-    fn reads_subject_alternative_name_from_certificate_request_with_www_fallback()
+/// Report which chain each certificate servers are configured with (see
+/// configured) belongs to, and problems with their configuration, on
+/// stderr.
+pub fn configured(configured: &[Configured], options: &Options)
+{
+    for configured in configured
     {
-        use crate::chain;
-
-        let path = "samples/self_signed_san_www_only.crt";
-        let contents = chain::get_file_contents(&path).unwrap();
-        let x509 = chain::str_to_x509(&contents).unwrap();
-        let certificate = chain::CertificateFile::new(path, x509);
+        if let Some(matched) = &configured.matched
+        {
+            match &matched.key
+            {
+                Some(key) => eprintln!("{} uses {} (key {})", configured.label, rewrite_path(&configured.certificate, options), rewrite_path(key, options)),
+                None => eprintln!("{} uses {}", configured.label, rewrite_path(&configured.certificate, options))
+            }
+        }
 
-        match get_display_name_from_certificate(&certificate)
+        for problem in configured.problems()
         {
-            Some(display_name) => assert_eq!(display_name, "www.san.example.com"),
-            _ => assert!(false)
+            eprintln!("Warning: {}: {}", configured.label, problem);
         }
     }
 }
+
+/// Describe a served certificate by its subject, serial, and expiry.
+fn describe_served(served: &connect::Served) -> String
+{
+    let certificate = CertificateFile::new(&served.endpoint.to_string(), served.certificates[0].clone());
+
+    format!(
+        "{}, serial {}, expires {}",
+        format_name(certificate.certificate.subject_name()),
+        certificate.serial().unwrap_or_default(),
+        format_time(certificate.certificate.not_after())
+    )
+}
+
+/// Report policy violations (--policy) on stderr, as (path, violation)
+/// pairs.
+pub fn policy_violations(violations: &[(String, String)], options: &Options)
+{
+    for (path, violation) in violations
+    {
+        eprintln!("Policy violation: {}: {}", rewrite_path(path, options), violation);
+    }
+}
+
+/// Warn about problems found in files (e.g. by --check-keys) on stderr, as
+/// (path, problem) pairs.
+pub fn problems(problems: &[(String, String)], options: &Options)
+{
+    for (path, problem) in problems
+    {
+        eprintln!("Warning: {}: {}", rewrite_path(path, options), problem);
+    }
+}
+
+/// Report files and directories which couldn't be read, e.g. because of
+/// their permissions, so incomplete scans don't go unnoticed.
+pub fn warnings(warnings: &[(String, String)], options: &Options)
+{
+    for (path, reason) in warnings
+    {
+        eprintln!("Warning: couldn't read {}: {}", rewrite_path(path, options), reason);
+    }
+
+    if !warnings.is_empty()
+    {
+        eprintln!("Warning: {} path(s) couldn't be read, so results may be incomplete.", warnings.len());
+    }
+}
+
+/// NUL-delimited display mode handler: each chain is written as its key,
+/// request ("-" if missing), and certificates (each followed by its signing
+/// certificates), each terminated by a NUL, with an extra NUL after each
+/// chain.
+pub fn print0(chains: Vec<Chain>)
+{
+    let mut stdout = io::stdout().lock();
+
+    for chain in chains
+    {
+        if chain.origin().is_none()
+        {
+            continue;
+        }
+
+        // Ignore write errors (e.g. a closed pipe) as the default mode does.
+        let _ = stdout.write_all(&get_print0_record(&chain));
+    }
+}
+
+fn get_print0_record(chain: &Chain) -> Vec<u8>
+{
+    let mut paths = vec![];
+
+    if let Some(origin) = chain.origin()
+    {
+        paths.push(origin.to_string());
+    }
+
+    paths.push(chain.request.as_ref().map(|request| request.path.clone()).unwrap_or_else(|| String::from("-")));
+
+    for certificate in &chain.certificates
+    {
+        paths.push(certificate.path.clone());
+
+        for signing_certificate in certificate.signing_certificate_chain()
+        {
+            paths.push(signing_certificate.path.clone());
+        }
+    }
+
+    let mut record = vec![];
+
+    for path in paths
+    {
+        record.extend_from_slice(path.as_bytes());
+        record.push(0);
+    }
+
+    record.push(0);
+
+    record
+}
+
+/// CSV display mode handler.
+pub fn csv(chains: Vec<Chain>, options: &Options)
+{
+    delimited(chains, ',', escape_csv_field, options);
+}
+
+/// TSV display mode handler.
+pub fn tsv(chains: Vec<Chain>, options: &Options)
+{
+    delimited(chains, '\t', escape_tsv_field, options);
+}
+
+/// Print a header row, then one row per chain, with fields escaped so
+/// paths containing delimiters, quotes, or spaces survive intact. Expiry,
+/// verification, and OCSP columns are added with --expiry-warn, --verify,
+/// and --ocsp, and a column for why endpoints couldn't be reached with
+/// --connect or --connect-file.
+fn delimited(chains: Vec<Chain>, delimiter: char, escape: fn(&str) -> String, options: &Options)
+{
+    let print_row = |fields: Vec<String>| {
+        let escaped: Vec<String> = fields.iter().map(|field| escape(field)).collect();
+
+        println!("{}", escaped.join(&delimiter.to_string()));
+    };
+
+    let mut header = vec![
+        "name".to_string(),
+        "key".to_string(),
+        "request".to_string(),
+        "certificate_chain".to_string(),
+    ];
+
+    if options.expiry_warn.is_some()
+    {
+        header.push("expiry".to_string());
+    }
+
+    if options.verify
+    {
+        header.push("verification".to_string());
+    }
+
+    if options.ocsp
+    {
+        header.push("ocsp".to_string());
+    }
+
+    let endpoints = !options.connect.is_empty() || options.connect_file.is_some();
+
+    if endpoints
+    {
+        header.push("unreachable".to_string());
+    }
+
+    print_row(header);
+
+    for chain in chains
+    {
+        let key = match chain.origin() {
+            Some(origin) => origin.to_string(),
+            _ => continue
+        };
+
+        let request = match &chain.request {
+            Some(request) => request.path.clone(),
+            _ => String::new()
+        };
+
+        let mut row = vec![get_display_name(&chain), key, request, get_certificate_chain_field(&chain)];
+
+        if let Some(days) = options.expiry_warn
+        {
+            row.push(chain.expiry(days).map(|expiry| expiry.as_str()).unwrap_or_default().to_string());
+        }
+
+        if options.verify
+        {
+            row.push(chain.verify().as_str().to_string());
+        }
+
+        if options.ocsp
+        {
+            row.push(revocation(&chain, options).map(|r| r.as_str()).unwrap_or_default().to_string());
+        }
+
+        if endpoints
+        {
+            row.push(chain.unreachable.clone().unwrap_or_default());
+        }
+
+        print_row(row);
+    }
+}
+
+/// Format a chain's certificates as a single field: certificates are
+/// separated by semicolons, and each is followed by its signing
+/// certificates, separated by pipes.
+pub fn get_certificate_chain_field(chain: &Chain) -> String
+{
+    let mut certificates = vec![];
+
+    for certificate in &chain.certificates
+    {
+        let mut paths = vec![certificate.path.clone()];
+
+        if certificate.self_signed
+        {
+            paths.push("(self-signed)".to_string());
+            certificates.push(paths.join("|"));
+
+            continue;
+        }
+
+        for signing_certificate in certificate.signing_certificate_chain()
+        {
+            paths.push(signing_certificate.path.clone());
+        }
+
+        certificates.push(paths.join("|"));
+    }
+
+    certificates.join(";")
+}
+
+/// Quote a CSV field (RFC 4180) if it contains a comma, quote, or line break.
+fn escape_csv_field(field: &str) -> String
+{
+    if field.contains([',', '"', '\r', '\n'])
+    {
+        return format!("\"{}\"", field.replace('"', "\"\""));
+    }
+
+    field.to_string()
+}
+
+/// Escape backslashes, tabs, and line breaks in a TSV field.
+fn escape_tsv_field(field: &str) -> String
+{
+    field.replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\r', "\\r")
+        .replace('\n', "\\n")
+}
+
+/// NDJSON display mode handler, called once per chain as soon as it's
+/// built. Output is flushed after each line so consumers can stream it.
+pub fn ndjson(chain: Chain, options: &Options)
+{
+    if chain.origin().is_none()
+    {
+        return;
+    }
+
+    let mut stdout = io::stdout().lock();
+
+    // Ignore write errors (e.g. a closed pipe) as the default mode does.
+    let _ = writeln!(stdout, "{}", to_json(&chain, options));
+    let _ = stdout.flush();
+}
+
+/// Represent a chain as a JSON object (see schema), with its expiry
+/// ("ok", "expiring", "expired", or null without certificates),
+/// verification result, and OCSP status (null without a responder), if
+/// requested (--expiry-warn, --verify, --ocsp).
+pub fn to_json(chain: &Chain, options: &Options) -> Value
+{
+    let mut value = serde_json::to_value(chain).unwrap_or_default();
+
+    if let Value::Object(fields) = &mut value
+    {
+        if let Some(days) = options.expiry_warn
+        {
+            fields.insert(String::from("expiry"), chain.expiry(days).map(|expiry| expiry.as_str()).into());
+        }
+
+        if options.verify
+        {
+            let verification = chain.verify();
+
+            fields.insert(String::from("verification"), verification.as_str().into());
+            fields.insert(String::from("verification_reason"), verification.reason().into());
+        }
+
+        if options.ocsp
+        {
+            let revocation = revocation(chain, options);
+
+            fields.insert(String::from("ocsp"), revocation.as_ref().map(|r| r.as_str()).into());
+            fields.insert(String::from("ocsp_reason"), revocation.as_ref().and_then(|r| r.reason()).into());
+        }
+    }
+
+    value
+}
+
+/// Rewrite a chain's paths for display, as absolute paths (--absolute) or
+/// relative to a directory (--relative-to).
+pub fn rewrite_paths(chain: &mut Chain, options: &Options)
+{
+    // Served chains' paths name endpoints, not files.
+    if (!options.absolute && options.relative_to.is_none()) || chain.endpoint.is_some()
+    {
+        return;
+    }
+
+    if let Some(key) = &mut chain.key
+    {
+        key.path = rewrite_path(&key.path, options);
+    }
+
+    if let Some(request) = &mut chain.request
+    {
+        request.path = rewrite_path(&request.path, options);
+    }
+
+    for certificate in chain.certificates.iter_mut()
+    {
+        let mut certificate = Some(certificate);
+
+        while let Some(c) = certificate
+        {
+            c.path = rewrite_path(&c.path, options);
+            c.duplicates = c.duplicates.iter().map(|path| rewrite_path(path, options)).collect();
+
+            certificate = c.signing_certificate.as_deref_mut();
+        }
+    }
+}
+
+/// Rewrite a single path for display. Paths which can't be resolved (e.g.
+/// standard input) are left as they are.
+pub fn rewrite_path(path: &str, options: &Options) -> String
+{
+    if !options.absolute && options.relative_to.is_none()
+    {
+        return path.to_string();
+    }
+
+    // Resolve the container portion of member paths ("archive:member").
+    let (file, member) = match chain::container_path(path)
+    {
+        Some(container) => (container, &path[container.len()..]),
+        None => (path, "")
+    };
+
+    let absolute = match fs::canonicalize(file)
+    {
+        Ok(absolute) => absolute,
+        Err(_) => return path.to_string()
+    };
+
+    let resolved = match options.relative_to.as_ref().and_then(|base| fs::canonicalize(base).ok())
+    {
+        Some(base) => relative_path(&absolute, &base),
+        None => absolute
+    };
+
+    format!("{}{}", resolved.display(), member)
+}
+
+/// Get the path of one absolute path relative to another (a directory).
+fn relative_path(path: &Path, base: &Path) -> PathBuf
+{
+    let path: Vec<Component> = path.components().collect();
+    let base: Vec<Component> = base.components().collect();
+
+    let common = path.iter().zip(base.iter()).take_while(|(a, b)| a == b).count();
+
+    let mut relative = PathBuf::new();
+
+    for _ in common..base.len()
+    {
+        relative.push("..");
+    }
+
+    for component in &path[common..]
+    {
+        relative.push(component);
+    }
+
+    relative
+}
+
+/// Print a certificate's subject and issuer (in verbose mode), and serial
+/// number and fingerprints (if requested) on separate lines.
+fn print_details(certificate: &CertificateFile, indentation: i32, options: &Options)
+{
+    if options.verbose
+    {
+        print_indentation(indentation);
+        println!("Subject: {}", format_name(certificate.certificate.subject_name()));
+
+        print_indentation(indentation);
+        println!("Issuer: {}", format_name(certificate.certificate.issuer_name()));
+    }
+
+    if options.fingerprints.is_empty()
+    {
+        return;
+    }
+
+    if let Some(serial) = certificate.serial()
+    {
+        print_indentation(indentation);
+        println!("Serial: {}", serial);
+    }
+
+    for name in &options.fingerprints
+    {
+        if let Ok(fingerprint) = certificate.fingerprint(digest(name))
+        {
+            print_indentation(indentation);
+            println!("{}: {}", digest_label(name), format_fingerprint(&fingerprint));
+        }
+    }
+}
+
+fn digest(name: &str) -> MessageDigest
+{
+    match name
+    {
+        "sha1" => MessageDigest::sha1(),
+        _ => MessageDigest::sha256()
+    }
+}
+
+fn digest_label(name: &str) -> &'static str
+{
+    match name
+    {
+        "sha1" => "SHA-1",
+        _ => "SHA-256"
+    }
+}
+
+/// Format a fingerprint as colon-separated hexadecimal bytes, as openssl
+/// does.
+pub fn format_fingerprint(fingerprint: &[u8]) -> String
+{
+    fingerprint.iter().map(|byte| format!("{:02X}", byte)).collect::<Vec<String>>().join(":")
+}
+
+/// Format a certificate's validity period for the default display mode,
+/// noting certificates which have expired or aren't yet valid.
+fn format_validity(certificate: &CertificateFile) -> String
+{
+    let x509 = &certificate.certificate;
+    let now = Asn1Time::days_from_now(0).unwrap();
+
+    let status = if x509.not_after() < now
+    {
+        ", expired"
+    }
+    else if x509.not_before() > now
+    {
+        ", not yet valid"
+    }
+    else
+    {
+        ""
+    };
+
+    format!("{} to {}{}", format_time(x509.not_before()), format_time(x509.not_after()), status)
+}
+
+/// Format a certificate's validity period as an ISO 8601 interval, e.g.
+/// "2022-04-01T23:53:59Z/2049-08-16T23:53:59Z".
+fn format_validity_interval(certificate: &CertificateFile) -> String
+{
+    let x509 = &certificate.certificate;
+
+    format!("{}/{}", format_time(x509.not_before()), format_time(x509.not_after()))
+}
+
+/// Print a given number spaces for intentation.
+fn print_indentation(spaces: i32)
+{
+    for _ in 1..=spaces { print!(" "); }
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn escapes_csv_fields()
+    {
+        assert_eq!(escape_csv_field("samples/ca_signed.key"), "samples/ca_signed.key");
+        assert_eq!(escape_csv_field("my certs/ca_signed.key"), "my certs/ca_signed.key");
+        assert_eq!(escape_csv_field("a,b.key"), "\"a,b.key\"");
+        assert_eq!(escape_csv_field("a \"b\".key"), "\"a \"\"b\"\".key\"");
+        assert_eq!(escape_csv_field("a\nb.key"), "\"a\nb.key\"");
+    }
+
+    #[test]
+    fn escapes_tsv_fields()
+    {
+        assert_eq!(escape_tsv_field("my certs/ca_signed.key"), "my certs/ca_signed.key");
+        assert_eq!(escape_tsv_field("a\tb.key"), "a\\tb.key");
+        assert_eq!(escape_tsv_field("a\\b\nc.key"), "a\\\\b\\nc.key");
+    }
+
+    #[test]
+    fn represents_chains_as_json()
+    {
+        use sslchains::chain;
+
+        let paths = vec![
+            String::from("samples/ca_signed.key"),
+            String::from("samples/ca_signed.crt"),
+            String::from("samples/intermediate_ca.crt"),
+        ];
+
+        let chains = chain::build(paths).unwrap();
+
+        assert_eq!(
+            to_json(&chains[0], &Options::default()),
+            json!({
+                "schema_version": 1,
+                "name": "example.com",
+                "key": "samples/ca_signed.key",
+                "request": null,
+                "certificates": [
+                    {
+                        "path": "samples/ca_signed.crt",
+                        "self_signed": false,
+                        "duplicates": [],
+                        "signing_certificates": ["samples/intermediate_ca.crt"],
+                    }
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn describes_chains_stopped_in_loops()
+    {
+        let chains = chain::build(vec![
+            String::from("samples/mutually_signed/example.com.key"),
+            String::from("samples/mutually_signed/example.com.crt"),
+            String::from("samples/mutually_signed/ca_x.crt"),
+            String::from("samples/mutually_signed/ca_y.crt"),
+        ]).unwrap();
+
+        let signing_certificates = chains[0].certificates[0].signing_certificate_chain();
+        let top = signing_certificates.last().unwrap();
+
+        assert_eq!(top.path, "samples/mutually_signed/ca_y.crt");
+        assert_eq!(trust_status(top).as_deref(), Some("loop at C=US, O=Sample Org, CN=CA X"));
+        assert_eq!(signing_certificates[0].stopped, None);
+    }
+
+    #[test]
+    fn formats_validity_periods()
+    {
+        use sslchains::chain;
+
+        let path = "samples/ca_signed.crt";
+        let contents = chain::get_file_contents(path).unwrap();
+        let x509 = chain::bytes_to_x509(&contents).unwrap();
+        let certificate = chain::CertificateFile::new(path, x509);
+
+        assert_eq!(format_validity(&certificate), "2022-04-01T23:53:59Z to 2049-08-16T23:53:59Z");
+        assert_eq!(format_validity_interval(&certificate), "2022-04-01T23:53:59Z/2049-08-16T23:53:59Z");
+    }
+
+    #[test]
+    fn gets_print0_records()
+    {
+        use sslchains::chain;
+
+        let chains = chain::build(vec![
+            String::from("samples/ca_signed.key"),
+            String::from("samples/ca_signed.crt"),
+            String::from("samples/intermediate_ca.crt"),
+        ]).unwrap();
+
+        assert_eq!(
+            get_print0_record(&chains[0]),
+            b"samples/ca_signed.key\0-\0samples/ca_signed.crt\0samples/intermediate_ca.crt\0\0".to_vec()
+        );
+    }
+
+    #[test]
+    fn gets_every_certificate_in_chain_fields()
+    {
+        let chains = chain::build(vec![
+            String::from("samples/renewed/example.com.key"),
+            String::from("samples/renewed/example.com-2024.crt"),
+            String::from("samples/renewed/example.com-2025.crt"),
+        ]).unwrap();
+
+        assert_eq!(
+            get_certificate_chain_field(&chains[0]),
+            "samples/renewed/example.com-2025.crt|(self-signed);samples/renewed/example.com-2024.crt|(self-signed)"
+        );
+    }
+
+    #[test]
+    fn gets_relative_paths()
+    {
+        assert_eq!(relative_path(Path::new("/etc/ssl/a.crt"), Path::new("/etc/ssl")), PathBuf::from("a.crt"));
+        assert_eq!(relative_path(Path::new("/etc/ssl/a.crt"), Path::new("/etc/pki/tls")), PathBuf::from("../../ssl/a.crt"));
+    }
+
+    #[test]
+    fn rewrites_paths()
+    {
+        let absolute = Options { absolute: true, ..Options::default() };
+        let relative = Options { relative_to: Some(String::from("samples/archives")), ..Options::default() };
+
+        let expected = fs::canonicalize("samples/ca_signed.key").unwrap();
+
+        assert_eq!(rewrite_path("samples/ca_signed.key", &absolute), expected.display().to_string());
+        assert_eq!(rewrite_path("samples/ca_signed.key", &relative), "../ca_signed.key");
+        assert_eq!(rewrite_path("samples/archives/ca_signed.zip:ca_signed.key", &relative), "ca_signed.zip:ca_signed.key");
+        assert_eq!(rewrite_path(chain::STDIN_PATH, &absolute), chain::STDIN_PATH);
+        assert_eq!(rewrite_path("samples/ca_signed.key", &Options::default()), "samples/ca_signed.key");
+    }
+
+    #[test]
+    fn gets_chain_directories()
+    {
+        let chains = chain::build(vec![
+            String::from("samples/ca_signed.key"),
+            String::from("samples/der/ca_signed.crt"),
+            String::from("samples/intermediate_ca.crt"),
+        ]).unwrap();
+
+        assert_eq!(get_directory(&chains[0]), "samples");
+
+        let chains = chain::build(vec![
+            String::from("samples/haproxy/example.com.pem"),
+            String::from("samples/haproxy/example.com.pem.key"),
+            String::from("samples/intermediate_ca.crt"),
+        ]).unwrap();
+
+        assert_eq!(get_directory(&chains[0]), "samples/haproxy");
+
+        let chains = chain::build(vec![String::from("samples/self_signed.key")]).unwrap();
+
+        assert_eq!(get_directory(&chains[0]), "samples");
+    }
+
+    #[test]
+    fn formats_names()
+    {
+        use sslchains::chain;
+
+        let contents = chain::get_file_contents("samples/ca_signed.crt").unwrap();
+        let x509 = chain::bytes_to_x509(&contents).unwrap();
+
+        assert_eq!(format_name(x509.subject_name()), "C=US, O=Sample Org, CN=example.com");
+        assert_eq!(format_name(x509.issuer_name()), "C=US, O=Sample Org, CN=ca.example.com");
+    }
+
+    #[test]
+    fn formats_fingerprints()
+    {
+        assert_eq!(format_fingerprint(&[0x0a, 0xbc, 0xff]), "0A:BC:FF");
+    }
+}
@@ -0,0 +1,90 @@
+//! Copyright (C) 2022 Gaz J.
+//!
+//! This program is free software: you can redistribute it and/or modify
+//! it under the terms of the GNU General Public License as published by
+//! the Free Software Foundation, either version 3 of the License, or
+//! (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//! GNU General Public License for more details.
+//!
+//! You should have received a copy of the GNU General Public License
+//! along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Errors raised while reading, parsing, and matching files.
+//!
+//! Errors concern a single file or item, so callers report (or skip) them
+//! and carry on, rather than letting one odd file end a scan.
+
+use std::fmt;
+use std::io;
+use openssl::error::ErrorStack;
+
+#[derive(Debug)]
+pub enum Error
+{
+    /// A file couldn't be read.
+    Io(io::Error),
+
+    /// OpenSSL couldn't parse or process an item.
+    OpenSsl(ErrorStack),
+
+    /// A private key doesn't match a public key.
+    KeyMismatch,
+
+    /// A certificate isn't signed by the given signing certificate.
+    SignatureMismatch,
+
+    /// A path isn't valid UTF-8, so it can't be processed.
+    InvalidPath,
+
+    /// Contents don't hold any key, request, or certificate.
+    Unrecognized,
+
+    /// A private key failed its consistency check, e.g. an RSA key whose
+    /// primes don't produce its modulus.
+    InconsistentKey,
+
+    /// A certificate's signature is malformed, for this reason.
+    MalformedSignature(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        match self
+        {
+            Error::Io(e) => write!(f, "{}", e),
+            Error::OpenSsl(e) => write!(f, "{}", e),
+            Error::KeyMismatch => write!(f, "Key file mismatch"),
+            Error::SignatureMismatch => write!(f, "Certificate not signed by given signing certificate"),
+            Error::InvalidPath => write!(f, "path isn't valid UTF-8"),
+            Error::Unrecognized => write!(f, "not a recognized key, request, or certificate"),
+            Error::InconsistentKey => write!(f, "private key failed its consistency check"),
+            Error::MalformedSignature(reason) => write!(f, "malformed signature: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error
+{
+    fn from(e: io::Error) -> Self
+    {
+        Error::Io(e)
+    }
+}
+
+impl From<ErrorStack> for Error
+{
+    fn from(e: ErrorStack) -> Self
+    {
+        Error::OpenSsl(e)
+    }
+}
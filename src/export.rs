@@ -0,0 +1,471 @@
+//! Copyright (C) 2022 Gaz J.
+//!
+//! This program is free software: you can redistribute it and/or modify
+//! it under the terms of the GNU General Public License as published by
+//! the Free Software Foundation, either version 3 of the License, or
+//! (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//! GNU General Public License for more details.
+//!
+//! You should have received a copy of the GNU General Public License
+//! along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Exports (--export-fullchain, --export-p12, --export-k8s-secret, and
+//! --export-haproxy): files assembled from each complete chain, as servers
+//! are configured with them, written to a directory and named after the
+//! chain, e.g. "example.com.fullchain.pem".
+//!
+//! Chains are complete when they have a key and a certificate whose issuers
+//! were all found (up to a root, which isn't exported, or an issuer the
+//! system trusts).
+
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use openssl::base64;
+use openssl::pkcs12::Pkcs12;
+use openssl::stack::Stack;
+
+use crate::chain::Chain;
+use crate::format;
+
+/// What's exported for each chain.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Format
+{
+    /// The certificate and its intermediates, in order.
+    Fullchain,
+
+    /// A PKCS#12 file holding the key, certificate, and intermediates (for
+    /// Java and Windows), encrypted with a password.
+    Pkcs12(String),
+
+    /// A Kubernetes TLS Secret manifest, whose tls.crt holds the
+    /// certificate and intermediates, and tls.key the key.
+    KubernetesSecret,
+
+    /// A combined PEM file, as HAProxy's crt loads: the certificate, its
+    /// intermediates, then the key. With true, the certificate's issuer is
+    /// also written to an ".issuer" file beside it, for OCSP stapling.
+    Haproxy(bool),
+}
+
+impl Format
+{
+    /// The exported file's suffix, after the chain's name.
+    fn suffix(&self) -> &'static str
+    {
+        match self
+        {
+            Format::Fullchain => ".fullchain.pem",
+            Format::Pkcs12(_) => ".p12",
+            Format::KubernetesSecret => ".secret.yaml",
+            Format::Haproxy(_) => ".pem",
+        }
+    }
+
+    /// Whether the exported file holds the chain's key (so only its owner
+    /// may read it).
+    fn has_key(&self) -> bool
+    {
+        matches!(self, Format::Pkcs12(_) | Format::KubernetesSecret | Format::Haproxy(_))
+    }
+
+    /// Assemble the file for a chain (which has a key and a certificate).
+    fn contents(&self, chain: &Chain) -> io::Result<Vec<u8>>
+    {
+        let certificate = &chain.certificates[0];
+        let key = chain.key.as_ref().ok_or_else(|| io::Error::other("no key"))?;
+        let intermediates: Vec<_> = certificate.signing_certificate_chain().into_iter().filter(|c| !c.self_signed).collect();
+
+        let mut fullchain = certificate.certificate.to_pem().map_err(io::Error::other)?;
+
+        for intermediate in &intermediates
+        {
+            fullchain.extend(intermediate.certificate.to_pem().map_err(io::Error::other)?);
+        }
+
+        match self
+        {
+            Format::Fullchain => Ok(fullchain),
+            Format::Pkcs12(password) => {
+                let mut stack = Stack::new().map_err(io::Error::other)?;
+
+                for intermediate in intermediates
+                {
+                    stack.push(intermediate.certificate).map_err(io::Error::other)?;
+                }
+
+                Pkcs12::builder()
+                    .name(&format::get_display_name(chain))
+                    .pkey(&key.key)
+                    .cert(&certificate.certificate)
+                    .ca(stack)
+                    .build2(password)
+                    .and_then(|pkcs12| pkcs12.to_der())
+                    .map_err(io::Error::other)
+            },
+            Format::KubernetesSecret => {
+                let key = key.key.private_key_to_pem_pkcs8().map_err(io::Error::other)?;
+
+                // Names are sanitized, and base64 needs no quoting.
+                let secret = format!(
+                    "apiVersion: v1\nkind: Secret\ntype: kubernetes.io/tls\nmetadata:\n  name: {}\ndata:\n  tls.crt: {}\n  tls.key: {}\n",
+                    secret_name(&format::get_display_name(chain)),
+                    base64::encode_block(&fullchain),
+                    base64::encode_block(&key)
+                );
+
+                Ok(secret.into_bytes())
+            },
+            Format::Haproxy(_) => {
+                fullchain.extend(key.key.private_key_to_pem_pkcs8().map_err(io::Error::other)?);
+
+                Ok(fullchain)
+            },
+        }
+    }
+
+    /// Files written beside a chain's file, as (suffix, contents) pairs,
+    /// the suffix being added to the file's name.
+    fn sidecars(&self, chain: &Chain) -> io::Result<Vec<(&'static str, Vec<u8>)>>
+    {
+        let issuer = chain.certificates[0].signing_certificate.as_ref().filter(|_| !chain.certificates[0].self_signed);
+
+        match (self, issuer)
+        {
+            (Format::Haproxy(true), Some(issuer)) => Ok(vec![(".issuer", issuer.certificate.to_pem().map_err(io::Error::other)?)]),
+            _ => Ok(vec![])
+        }
+    }
+}
+
+/// A file written for a chain.
+#[derive(Debug)]
+pub struct Exported
+{
+    /// The chain's key.
+    pub key: String,
+
+    pub path: String,
+}
+
+/// Writes a format's files to a directory.
+#[derive(Debug)]
+pub struct Exporter
+{
+    pub format: Format,
+    pub directory: PathBuf,
+
+    pub exported: Vec<Exported>,
+
+    /// (key path, problem) pairs for chains which weren't exported.
+    pub problems: Vec<(String, String)>,
+
+    /// Names given to files so far, so chains with the same name (e.g. a
+    /// renewal with a new key) don't overwrite each other.
+    names: HashSet<String>,
+}
+
+impl Exporter
+{
+    /// Export to a directory, creating it if need be.
+    pub fn new(format: Format, directory: &str) -> io::Result<Exporter>
+    {
+        fs::create_dir_all(directory).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", directory, e)))?;
+
+        Ok(Exporter { format, directory: PathBuf::from(directory), exported: vec![], problems: vec![], names: HashSet::new() })
+    }
+
+    /// Export a chain, if it's complete. Served chains, and keys without
+    /// certificates, are passed over.
+    pub fn export(&mut self, chain: &Chain)
+    {
+        let (key, certificate) = match (&chain.key, chain.certificates.first())
+        {
+            (Some(key), Some(certificate)) if chain.endpoint.is_none() => (key.path.clone(), certificate),
+            _ => return
+        };
+
+        if let Some(top) = certificate.missing_issuer()
+        {
+            return self.problems.push((key, format!("wasn't exported, as its chain is incomplete (no issuer found for {})", top)));
+        }
+
+        let name = self.name(chain);
+        let path = self.directory.join(&name);
+
+        let written = self.format.contents(chain)
+            .and_then(|contents| write(&path, &contents, self.format.has_key()))
+            .and_then(|_| self.format.sidecars(chain))
+            .and_then(|sidecars| {
+                let mut paths = vec![path.clone()];
+
+                for (suffix, contents) in sidecars
+                {
+                    let sidecar = self.directory.join(format!("{}{}", name, suffix));
+
+                    write(&sidecar, &contents, false)?;
+                    paths.push(sidecar);
+                }
+
+                Ok(paths)
+            });
+
+        match written
+        {
+            Ok(paths) => self.exported.extend(paths.iter().map(|path| Exported { key: key.clone(), path: path.display().to_string() })),
+            Err(e) => self.problems.push((key, format!("couldn't export to {}: {}", path.display(), e)))
+        }
+    }
+
+    /// A file name for a chain, from its display name, which is unique
+    /// among those this exporter has written.
+    fn name(&mut self, chain: &Chain) -> String
+    {
+        let base = file_name(&format::get_display_name(chain));
+        let mut name = format!("{}{}", base, self.format.suffix());
+        let mut count = 1;
+
+        while !self.names.insert(name.clone())
+        {
+            count += 1;
+            name = format!("{}-{}{}", base, count, self.format.suffix());
+        }
+
+        name
+    }
+}
+
+/// Read a password given as "pass:PASSWORD", "env:VARIABLE", or
+/// "file:PATH" (its first line), as OpenSSL's -passout is.
+pub fn read_password(source: &str) -> io::Result<String>
+{
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidInput, message);
+
+    match source.split_once(':')
+    {
+        Some(("pass", password)) => Ok(password.to_string()),
+        Some(("env", variable)) => env::var(variable).map_err(|_| invalid(format!("{} isn't set", variable))),
+        Some(("file", path)) => fs::read_to_string(path)
+            .map(|contents| contents.lines().next().unwrap_or_default().to_string())
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path, e))),
+        _ => Err(invalid(format!("{} isn't pass:PASSWORD, env:VARIABLE, or file:PATH", source)))
+    }
+}
+
+/// Write a file, replacing it, readable only by its owner if it holds a
+/// key: it's created so, and one being replaced is made so before the key is
+/// written.
+fn write(path: &Path, contents: &[u8], has_key: bool) -> io::Result<()>
+{
+    let mut options = fs::OpenOptions::new();
+
+    options.write(true).create(true).truncate(true);
+
+    #[cfg(unix)]
+    if has_key
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+
+        options.mode(0o600);
+    }
+
+    let mut file = options.open(path)?;
+
+    #[cfg(unix)]
+    if has_key
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    }
+
+    file.write_all(contents)
+}
+
+/// Make a chain's name usable as a Secret's name (a DNS subdomain), e.g.
+/// "*.example.com" becomes "wildcard.example.com-tls".
+fn secret_name(name: &str) -> String
+{
+    let name: String = file_name(name).to_lowercase().chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '.' { c } else { '-' })
+        .collect();
+
+    format!("{}-tls", name.trim_matches(['.', '-']))
+}
+
+/// Make a chain's name usable as a file name, e.g. "*.example.com" becomes
+/// "wildcard.example.com".
+pub fn file_name(name: &str) -> String
+{
+    let name = name.replacen("*.", "wildcard.", 1);
+
+    let name: String = name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || ".-_".contains(c) { c } else { '_' })
+        .collect();
+
+    match name.trim_start_matches('.')
+    {
+        "" => String::from("unnamed"),
+        name => name.to_string()
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+    use crate::{chain, manifest};
+
+    /// A directory to export to, removed (even if the test fails) when
+    /// dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir
+    {
+        fn new(name: &str) -> TempDir
+        {
+            TempDir(std::env::temp_dir().join(format!("sslchains-test-export-{}-{}", name, std::process::id())))
+        }
+
+        fn path(&self) -> &str
+        {
+            self.0.to_str().unwrap()
+        }
+    }
+
+    impl Drop for TempDir
+    {
+        fn drop(&mut self)
+        {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// The complete cross_signed chain (through intermediate A, to root A).
+    fn cross_signed() -> Vec<Chain>
+    {
+        chain::build(vec![
+            String::from("samples/cross_signed/example.com.key"),
+            String::from("samples/cross_signed/example.com.crt"),
+            String::from("samples/cross_signed/intermediate_by_a.crt"),
+            String::from("samples/cross_signed/root_a.crt"),
+        ]).unwrap()
+    }
+
+    #[test]
+    fn exports_fullchains()
+    {
+        let directory = TempDir::new("fullchain");
+        let mut exporter = Exporter::new(Format::Fullchain, directory.path()).unwrap();
+
+        let complete = cross_signed();
+
+        let incomplete = chain::build(vec![String::from("samples/cross_signed/example.com.key"), String::from("samples/cross_signed/example.com.crt")]).unwrap();
+
+        exporter.export(&complete[0]);
+        exporter.export(&complete[0]);
+        exporter.export(&incomplete[0]);
+
+        let names: Vec<&str> = exporter.exported.iter().map(|exported| exported.path.rsplit('/').next().unwrap()).collect();
+
+        assert_eq!(names, vec!["example.com.fullchain.pem", "example.com-2.fullchain.pem"]);
+        assert!(exporter.problems[0].1.starts_with("wasn't exported, as its chain is incomplete"));
+
+        let written = chain::bytes_to_x509_stack(&fs::read(&exporter.exported[0].path).unwrap()).unwrap();
+        let subjects: Vec<String> = written.iter().map(|certificate| format::format_name(certificate.subject_name())).collect();
+
+        assert_eq!(subjects.len(), 2);
+        assert!(subjects[1].contains("Intermediate"));
+        assert_eq!(file_name("*.example.com"), "wildcard.example.com");
+        assert_eq!(file_name("../etc/passwd"), "_etc_passwd");
+    }
+
+    #[test]
+    fn exports_pkcs12()
+    {
+        let directory = TempDir::new("p12");
+        let password = read_password("pass:secret").unwrap();
+        let mut exporter = Exporter::new(Format::Pkcs12(password), directory.path()).unwrap();
+
+        let chains = cross_signed();
+
+        exporter.export(&chains[0]);
+
+        let path = &exporter.exported[0].path;
+        let parsed = Pkcs12::from_der(&fs::read(path).unwrap()).unwrap().parse2("secret").unwrap();
+
+        assert!(path.ends_with("/example.com.p12"));
+        assert!(parsed.pkey.unwrap().public_eq(&chains[0].key.as_ref().unwrap().key));
+        assert_eq!(parsed.cert.unwrap().to_der().unwrap(), chains[0].certificates[0].certificate.to_der().unwrap());
+        assert_eq!(parsed.ca.unwrap().len(), 1);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            assert_eq!(fs::metadata(path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+
+        assert!(read_password("secret").is_err());
+        assert_eq!(read_password("file:samples/kubeconfig/missing").err().unwrap().kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn exports_kubernetes_secrets()
+    {
+        let directory = TempDir::new("secret");
+        let mut exporter = Exporter::new(Format::KubernetesSecret, directory.path()).unwrap();
+
+        let chains = cross_signed();
+
+        exporter.export(&chains[0]);
+
+        let path = &exporter.exported[0].path;
+        let secret: serde_yaml::Value = serde_yaml::from_str(&fs::read_to_string(path).unwrap()).unwrap();
+
+        assert!(path.ends_with("/example.com.secret.yaml"));
+        assert_eq!(secret["metadata"]["name"].as_str(), Some("example.com-tls"));
+        assert_eq!(secret["type"].as_str(), Some("kubernetes.io/tls"));
+
+        // The manifest reads back as the chain it was exported from.
+        let members = manifest::members(path).unwrap();
+        let exported = chain::build(members).unwrap();
+
+        assert_eq!(exported.len(), 1);
+        assert_eq!(exported[0].key.as_ref().unwrap().path, format!("{}:tls.key", path));
+        assert_eq!(exported[0].certificates[0].signing_certificate_chain().len(), 1);
+        assert_eq!(secret_name("*.Example.com"), "wildcard.example.com-tls");
+    }
+
+    #[test]
+    fn exports_haproxy_files()
+    {
+        let directory = TempDir::new("haproxy");
+        let mut exporter = Exporter::new(Format::Haproxy(true), directory.path()).unwrap();
+
+        let chains = cross_signed();
+
+        exporter.export(&chains[0]);
+
+        let paths: Vec<&str> = exporter.exported.iter().map(|exported| exported.path.rsplit('/').next().unwrap()).collect();
+
+        assert_eq!(paths, vec!["example.com.pem", "example.com.pem.issuer"]);
+
+        // The combined file makes the same chain, as HAProxy reads it.
+        let combined = chain::build(vec![exporter.exported[0].path.clone(), String::from("samples/cross_signed/root_a.crt")]).unwrap();
+
+        assert_eq!(combined[0].key.as_ref().unwrap().path, exporter.exported[0].path);
+        assert!(combined[0].certificates[0].bundle_problems(&[&exporter.exported[0].path]).is_empty());
+
+        let issuer = chain::bytes_to_x509(&fs::read(&exporter.exported[1].path).unwrap()).unwrap();
+
+        assert_eq!(issuer.to_der().unwrap(), fs::read("samples/cross_signed/intermediate_by_a.crt").map(|pem| chain::bytes_to_x509(&pem).unwrap().to_der().unwrap()).unwrap());
+    }
+}
@@ -0,0 +1,341 @@
+//! Copyright (C) 2022 Gaz J.
+//!
+//! This program is free software: you can redistribute it and/or modify
+//! it under the terms of the GNU General Public License as published by
+//! the Free Software Foundation, either version 3 of the License, or
+//! (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//! GNU General Public License for more details.
+//!
+//! You should have received a copy of the GNU General Public License
+//! along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Fetches missing signing certificates from the caIssuers URLs in
+//! certificates' Authority Information Access extension (--fetch-missing).
+//!
+//! Downloads are kept for the rest of the run, as many certificates usually
+//! share an intermediate.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+use log::debug;
+use openssl::nid::Nid;
+use openssl::x509::X509;
+
+use crate::chain::{self, CertificateFile};
+use crate::http;
+use crate::trust::{self, Status};
+
+/// Signing certificates fetched for a chain, at most.
+const MAX_FETCHES: usize = 4;
+
+/// Downloads (or why they failed) by URL.
+type Downloads = Mutex<HashMap<String, Result<Vec<u8>, String>>>;
+
+static DOWNLOADS: OnceLock<Downloads> = OnceLock::new();
+
+/// A signing certificate fetched for a chain.
+#[derive(Debug)]
+pub struct Fetched
+{
+    /// Certificate it signs.
+    pub certificate: String,
+
+    /// Where it came from.
+    pub url: String,
+
+    /// Where it was saved, if it was.
+    pub saved: Option<String>,
+}
+
+/// caIssuers URLs (http:// and https:// only) in a certificate's Authority
+/// Information Access extension.
+pub fn ca_issuers(certificate: &X509) -> Vec<String>
+{
+    certificate.authority_info().into_iter()
+        .flatten()
+        .filter(|access| access.method().nid() == Nid::AD_CA_ISSUERS)
+        .filter_map(|access| access.location().uri().map(String::from))
+        .filter(|url| http::Url::parse(url).is_some())
+        .collect()
+}
+
+/// Fetch the certificate which signed a certificate, from its caIssuers
+/// URLs, with the URL it came from.
+pub fn issuer(certificate: &X509, timeout: Duration) -> Result<(String, X509), String>
+{
+    let urls = ca_issuers(certificate);
+
+    if urls.is_empty()
+    {
+        return Err(String::from("no caIssuers URL"));
+    }
+
+    let mut reasons = vec![];
+
+    for url in urls
+    {
+        let contents = match download(&url, timeout)
+        {
+            Ok(contents) => contents,
+            Err(e) => {
+                reasons.push(format!("couldn't fetch {}: {}", url, e));
+
+                continue;
+            }
+        };
+
+        // caIssuers URLs usually serve one DER certificate, but PEM bundles
+        // are seen too.
+        let issuer = chain::bytes_to_x509_stack(&contents).unwrap_or_default().into_iter()
+            .find(|issuer| issuer.public_key().and_then(|key| certificate.verify(&key)).unwrap_or(false));
+
+        match issuer
+        {
+            Some(issuer) => return Ok((url, issuer)),
+            None => reasons.push(format!("{} didn't serve the signing certificate", url))
+        }
+    }
+
+    Err(reasons.join("; "))
+}
+
+fn download(url: &str, timeout: Duration) -> Result<Vec<u8>, String>
+{
+    let downloads = DOWNLOADS.get_or_init(Default::default);
+
+    if let Some(result) = downloads.lock().unwrap().get(url)
+    {
+        return result.clone();
+    }
+
+    let result = http::get(url, timeout).map_err(|e| e.to_string());
+
+    downloads.lock().unwrap().insert(url.to_string(), result.clone());
+
+    result
+}
+
+/// Complete a certificate's signing certificate chain, as long as its top
+/// certificate's issuer is neither known nor a trusted root, by fetching
+/// issuers. Fetched certificates are saved beside the certificate if save
+/// is set (without replacing existing files), and are otherwise named by
+/// their URL. Failures are returned as (certificate path, reason) pairs.
+pub fn complete(certificate: &mut CertificateFile, save: bool, timeout: Duration) -> (Vec<Fetched>, Vec<(String, String)>)
+{
+    let directory = Path::new(chain::container_path(&certificate.path).unwrap_or(&certificate.path))
+        .parent()
+        .map(|directory| directory.to_path_buf());
+
+    let mut fetched = vec![];
+    let mut failures = vec![];
+    let mut top = certificate;
+
+    for _ in 0..MAX_FETCHES
+    {
+        while top.signing_certificate.is_some()
+        {
+            top = top.signing_certificate.as_mut().unwrap();
+        }
+
+        // Chains stopped in a loop have their issuer already.
+        if top.self_signed || top.stopped.is_some() || !matches!(trust::system_status(&top.certificate), Some(Status::MissingIssuer(_)))
+        {
+            break;
+        }
+
+        let (url, issuer) = match issuer(&top.certificate, timeout)
+        {
+            Ok(issuer) => issuer,
+            Err(reason) => {
+                failures.push((top.path.clone(), format!("couldn't fetch its missing issuer: {}", reason)));
+
+                break;
+            }
+        };
+
+        let saved = match &directory
+        {
+            Some(directory) if save => match save_pem(directory, &url, &issuer)
+            {
+                Ok(path) => Some(path),
+                Err(e) => {
+                    failures.push((top.path.clone(), format!("couldn't save the certificate from {}: {}", url, e)));
+
+                    None
+                }
+            },
+            _ => None
+        };
+
+        debug!("Fetched the signing certificate of {} from {}", top.path, url);
+
+        fetched.push(Fetched { certificate: top.path.clone(), url: url.clone(), saved: saved.clone() });
+
+        top.signing_certificate = Some(Box::new(CertificateFile::new(&saved.unwrap_or(url), issuer)));
+    }
+
+    (fetched, failures)
+}
+
+/// Save a certificate as PEM in a directory, named after the last segment
+/// of the URL it came from, returning its path. Existing files aren't
+/// replaced, but one already holding the certificate (e.g. an intermediate
+/// shared by several certificates) is its path.
+fn save_pem(directory: &Path, url: &str, certificate: &X509) -> io::Result<String>
+{
+    let name = url.rsplit('/').next().unwrap_or_default().split(['?', '#']).next().unwrap_or_default();
+
+    // Only plain names are used, e.g. "R11.crt".
+    let stem = Path::new(name).file_stem().and_then(|stem| stem.to_str())
+        .filter(|stem| !stem.is_empty() && stem.chars().all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c)))
+        .unwrap_or("issuer");
+
+    let path = directory.join(format!("{}.crt", stem));
+
+    let saved = fs::OpenOptions::new().write(true).create_new(true).open(&path)
+        .and_then(|mut file| io::Write::write_all(&mut file, &certificate.to_pem()?));
+
+    match saved
+    {
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+            let existing = fs::read(&path).ok().and_then(|contents| chain::bytes_to_x509(&contents).ok());
+
+            if existing.and_then(|existing| existing.to_der().ok()) != certificate.to_der().ok()
+            {
+                return Err(e);
+            }
+        },
+        result => result?
+    }
+
+    path.to_str().map(String::from).ok_or_else(|| io::Error::other("path isn't valid UTF-8"))
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+    use std::io::{BufRead, Write};
+    use std::net::TcpListener;
+    use openssl::asn1::{Asn1Object, Asn1OctetString, Asn1Time};
+    use openssl::hash::MessageDigest;
+    use openssl::pkey::PKey;
+    use openssl::rsa::Rsa;
+    use openssl::x509::{X509Extension, X509NameBuilder};
+
+    /// Issue a certificate naming url as its caIssuers URL, returning it with
+    /// its (self-signed) issuer.
+    fn issue(url: &str) -> (X509, X509)
+    {
+        let issue = |name: &str, key: &PKey<_>, issuer: Option<&X509>, signer: &PKey<_>, extensions: Vec<X509Extension>| {
+            let mut subject = X509NameBuilder::new().unwrap();
+
+            subject.append_entry_by_nid(Nid::COMMONNAME, name).unwrap();
+
+            let subject = subject.build();
+            let mut builder = X509::builder().unwrap();
+
+            builder.set_version(2).unwrap();
+            builder.set_subject_name(&subject).unwrap();
+            builder.set_issuer_name(issuer.map(|issuer| issuer.subject_name()).unwrap_or(&subject)).unwrap();
+            builder.set_pubkey(key).unwrap();
+            builder.set_not_before(&Asn1Time::days_from_now(0).unwrap()).unwrap();
+            builder.set_not_after(&Asn1Time::days_from_now(30).unwrap()).unwrap();
+
+            for extension in extensions
+            {
+                builder.append_extension(extension).unwrap();
+            }
+
+            builder.sign(signer, MessageDigest::sha256()).unwrap();
+            builder.build()
+        };
+
+        let issuer_key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let issuer = issue("Test CA", &issuer_key, None, &issuer_key, vec![]);
+
+        // An Authority Information Access extension with a caIssuers URL.
+        let location = [&[0x86, url.len() as u8], url.as_bytes()].concat();
+        let description = [&[0x06, 0x08, 0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x30, 0x02], location.as_slice()].concat();
+        let description = [&[0x30, description.len() as u8], description.as_slice()].concat();
+        let access = [&[0x30, description.len() as u8], description.as_slice()].concat();
+
+        let extension = X509Extension::new_from_der(
+            &Asn1Object::from_str("1.3.6.1.5.5.7.1.1").unwrap(),
+            false,
+            &Asn1OctetString::new_from_bytes(&access).unwrap()
+        ).unwrap();
+
+        (issue("example.com", &key, Some(&issuer), &issuer_key, vec![extension]), issuer)
+    }
+
+    #[test]
+    fn fetches_missing_issuers()
+    {
+        // Serve the issuer's certificate (in DER form) once.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/intermediate_ca.crt", listener.local_addr().unwrap());
+        let (certificate, issuer) = issue(&url);
+        let issuer = issuer.to_der().unwrap();
+
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = io::BufReader::new(&stream);
+            let mut line = String::new();
+
+            while reader.read_line(&mut line).unwrap() > 2
+            {
+                line.clear();
+            }
+
+            write!(stream, "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n", issuer.len()).unwrap();
+            stream.write_all(&issuer).unwrap();
+        });
+
+        let mut certificate = CertificateFile::new("example.com.crt", certificate);
+
+        assert_eq!(ca_issuers(&certificate.certificate), vec![url.clone()]);
+
+        let (fetched, failures) = complete(&mut certificate, false, Duration::from_secs(5));
+
+        server.join().unwrap();
+
+        assert!(failures.is_empty());
+        assert_eq!(fetched.len(), 1);
+        assert_eq!(fetched[0].url, url);
+
+        let signing_certificate = certificate.signing_certificate.unwrap();
+
+        assert_eq!(signing_certificate.path, url);
+        assert!(signing_certificate.self_signed);
+    }
+
+    #[test]
+    fn saves_fetched_certificates_once()
+    {
+        let directory = std::env::temp_dir().join(format!("sslchains-test-{}-fetched", std::process::id()));
+        let url = "http://example.com/intermediate_ca.crt";
+        let (certificate, issuer) = issue(url);
+
+        fs::create_dir_all(&directory).unwrap();
+
+        let path = save_pem(&directory, url, &issuer).unwrap();
+
+        // Saving the same certificate again (e.g. an intermediate shared by
+        // several certificates) finds it, but others aren't saved over it.
+        assert_eq!(save_pem(&directory, url, &issuer).unwrap(), path);
+        assert_eq!(save_pem(&directory, url, &certificate).unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(chain::bytes_to_x509(&fs::read(&path).unwrap()).unwrap().to_der().unwrap(), issuer.to_der().unwrap());
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
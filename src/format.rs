@@ -0,0 +1,267 @@
+//! Copyright (C) 2022 Gaz J.
+//!
+//! This program is free software: you can redistribute it and/or modify
+//! it under the terms of the GNU General Public License as published by
+//! the Free Software Foundation, either version 3 of the License, or
+//! (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//! GNU General Public License for more details.
+//!
+//! You should have received a copy of the GNU General Public License
+//! along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Formatting of chain names, distinguished names, and times, as shown in
+//! output and caches.
+
+use openssl::asn1::{Asn1Time, Asn1TimeRef};
+use openssl::x509::X509NameRef;
+
+use crate::chain::{Chain, CertificateFile, CertificateRequestFile};
+
+/// Format a distinguished name, e.g. "CN=example.com, O=Example".
+pub fn format_name(name: &X509NameRef) -> String
+{
+    name.entries()
+        .map(|entry| {
+            let key = entry.object().nid().short_name().unwrap_or("?");
+            let value = entry.data().as_utf8().map(|v| v.to_string()).unwrap_or_default();
+
+            format!("{}={}", key, value)
+        })
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+/// Convert an ASN.1 time to seconds since the Unix epoch.
+pub fn unix_time(time: &Asn1TimeRef) -> Option<i64>
+{
+    let diff = Asn1Time::from_unix(0).ok()?.diff(time).ok()?;
+
+    Some(diff.days as i64 * 86_400 + diff.secs as i64)
+}
+
+/// Format an ASN.1 time as an ISO 8601 UTC timestamp, e.g.
+/// "2030-01-01T00:00:00Z".
+pub fn format_time(time: &Asn1TimeRef) -> String
+{
+    let seconds = match unix_time(time)
+    {
+        Some(seconds) => seconds,
+        None => return time.to_string()
+    };
+
+    let (days, remainder) = (seconds.div_euclid(86_400), seconds.rem_euclid(86_400));
+
+    // Convert days since the epoch to a civil date (Howard Hinnant's
+    // days_from_civil algorithm, in reverse).
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year, month, day, remainder / 3_600, remainder % 3_600 / 60, remainder % 60
+    )
+}
+
+/// Get display name for chain by checking multiple sources.
+pub fn get_display_name(chain: &Chain) -> String
+{
+
+    if let Some(certificate) = &chain.certificates.get(0)
+    {
+        if let Some(name) = get_display_name_from_certificate(&certificate)
+        {
+            return name;
+        }
+    }
+
+    if let Some(request) = &chain.request
+    {
+        if let Some(name) = get_display_name_from_request(&request)
+        {
+            return name;
+        }
+    }
+
+    "(unknown)".to_string()
+}
+
+/// Get display name from a certificate signing request.
+pub fn get_display_name_from_request(request: &CertificateRequestFile) -> Option<String>
+{
+    if let Some(common_name) = request.common_name()
+    {
+        // Names which can't be decoded are passed over.
+        if let Some(line) = common_name.data().as_utf8().ok().and_then(|name| name.lines().last().map(String::from))
+        {
+            return Some(line);
+        }
+    }
+
+    None
+}
+
+/// Get display name from a certificate.
+pub fn get_display_name_from_certificate(certificate: &CertificateFile) -> Option<String>
+{
+    // Prefer subject alternative name values.
+    let x509 = &certificate.certificate;
+
+    if let Some(general_name_stack) = x509.subject_alt_names()
+    {
+        if ! general_name_stack.is_empty()
+        {
+            // Prefer non-www values.
+            for general_name in &general_name_stack
+            {
+                if let Some(dnsname) = general_name.dnsname()
+                {
+                    if ! dnsname.starts_with("www.")
+                    {
+                        return Some(dnsname.to_string());
+                    }
+                }
+            }
+
+            // Fall back to first entry.
+            if let Some(general_name) = general_name_stack.get(0)
+            {
+                if let Some(dnsname) = general_name.dnsname()
+                {
+                    return Some(dnsname.to_string());
+                }
+            }
+        }
+    }
+
+    // Fall back to common name value.
+    if let Some(common_name) = certificate.common_name()
+    {
+        // Names which can't be decoded are passed over.
+        if let Some(line) = common_name.data().as_utf8().ok().and_then(|name| name.lines().last().map(String::from))
+        {
+            return Some(line);
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    #[test]
+    fn formats_times()
+    {
+        assert_eq!(format_time(&Asn1Time::from_unix(0).unwrap()), "1970-01-01T00:00:00Z");
+        assert_eq!(format_time(&Asn1Time::from_unix(951_782_400).unwrap()), "2000-02-29T00:00:00Z");
+        assert_eq!(format_time(&Asn1Time::from_unix(1_893_456_000 - 1).unwrap()), "2029-12-31T23:59:59Z");
+    }
+    #[test]
+    fn gets_display_name_prefers_certificate()
+    {
+        use crate::chain;
+
+        let mut chain = chain::Chain::new();
+
+        let path = "samples/self_signed_san.key";
+        let contents = chain::get_file_contents(&path).unwrap();
+        let key = chain::bytes_to_private_key(&contents).unwrap();
+        chain.key = Some(chain::PrivateKeyFile::new(path, key));
+
+        let path = "samples/self_signed_san.csr";
+        let contents = chain::get_file_contents(&path).unwrap();
+        let x509req = chain::bytes_to_x509req(&contents).unwrap();
+        chain.request = Some(chain::CertificateRequestFile::new(path, x509req));
+
+        let path = "samples/self_signed_san.crt";
+        let contents = chain::get_file_contents(&path).unwrap();
+        let x509 = chain::bytes_to_x509(&contents).unwrap();
+        chain.certificates = vec![chain::CertificateFile::new(path, x509)];
+
+        assert_eq!(get_display_name(&chain), "san.example.com".to_string());
+    }
+
+    #[test]
+    fn gets_display_name_falls_back_to_request()
+    {
+        use crate::chain;
+
+        let mut chain = chain::Chain::new();
+
+        let path = "samples/self_signed_san_no_cert.key";
+        let contents = chain::get_file_contents(&path).unwrap();
+        let key = chain::bytes_to_private_key(&contents).unwrap();
+        chain.key = Some(chain::PrivateKeyFile::new(path, key));
+
+        let path = "samples/self_signed_san_no_cert.csr";
+        let contents = chain::get_file_contents(&path).unwrap();
+        let x509req = chain::bytes_to_x509req(&contents).unwrap();
+        chain.request = Some(chain::CertificateRequestFile::new(path, x509req));
+
+        assert_eq!(get_display_name(&chain), "example.com".to_string());
+    }
+
+    #[test]
+    fn reads_common_name_from_certificate_request()
+    {
+        use crate::chain;
+
+        let path = "samples/self_signed.csr";
+        let contents = chain::get_file_contents(&path).unwrap();
+        let x509req = chain::bytes_to_x509req(&contents).unwrap();
+        let request = chain::CertificateRequestFile::new(path, x509req);
+
+        match get_display_name_from_request(&request)
+        {
+            Some(display_name) => assert_eq!(display_name, "example.com"),
+            _ => assert!(false)
+        }
+    }
+
+    #[test]
+    fn reads_subject_alternative_name_from_certificate_request()
+    {
+        use crate::chain;
+
+        let path = "samples/self_signed_san.crt";
+        let contents = chain::get_file_contents(&path).unwrap();
+        let x509 = chain::bytes_to_x509(&contents).unwrap();
+        let certificate = chain::CertificateFile::new(path, x509);
+
+        match get_display_name_from_certificate(&certificate)
+        {
+            Some(display_name) => assert_eq!(display_name, "san.example.com"),
+            _ => assert!(false)
+        }
+    }
+
+    #[test]
+    fn reads_subject_alternative_name_from_certificate_request_with_www_fallback()
+    {
+        use crate::chain;
+
+        let path = "samples/self_signed_san_www_only.crt";
+        let contents = chain::get_file_contents(&path).unwrap();
+        let x509 = chain::bytes_to_x509(&contents).unwrap();
+        let certificate = chain::CertificateFile::new(path, x509);
+
+        match get_display_name_from_certificate(&certificate)
+        {
+            Some(display_name) => assert_eq!(display_name, "www.san.example.com"),
+            _ => assert!(false)
+        }
+    }
+}
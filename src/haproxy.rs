@@ -0,0 +1,247 @@
+//! Copyright (C) 2022 Gaz J.
+//!
+//! This program is free software: you can redistribute it and/or modify
+//! it under the terms of the GNU General Public License as published by
+//! the Free Software Foundation, either version 3 of the License, or
+//! (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//! GNU General Public License for more details.
+//!
+//! You should have received a copy of the GNU General Public License
+//! along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! HAProxy configurations (--haproxy): the combined PEM files each bind line
+//! loads with crt (files, or every file in a directory) and crt-list, so
+//! they can be checked for a matching key and a complete chain.
+//!
+//! HAProxy resolves relative paths against its working directory, which is
+//! taken to be the configuration's directory here (crt-base still applies).
+
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use crate::configured::{Configured, Settings};
+
+/// Where HAProxy's configuration usually is.
+pub const DEFAULT_CONFIG: &str = "/etc/haproxy/haproxy.cfg";
+
+/// Files HAProxy loads alongside certificates, rather than as certificates,
+/// from crt directories.
+const EXTRA_EXTENSIONS: [&str; 4] = ["key", "ocsp", "issuer", "sctl"];
+
+/// Certificates have no settings of their own, but crt-lists give SNI
+/// filters.
+const SETTINGS: Settings = Settings {
+    certificate: "",
+    key: "",
+    name: "SNI filter",
+};
+
+/// The certificates a configuration's bind lines load (with the SNI filters
+/// of crt-list lines as their names), labelled e.g. "https-in :443
+/// (haproxy.cfg:10)".
+#[derive(Debug, Default)]
+pub struct Config
+{
+    pub bindings: Vec<Configured>,
+
+    /// (path, problem) pairs for crt-lists and directories which couldn't
+    /// be read.
+    pub problems: Vec<(String, String)>,
+}
+
+impl Config
+{
+    pub fn read(path: &Path) -> io::Result<Config>
+    {
+        let contents = fs::read_to_string(path).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+        let directory = path.parent().map(Path::to_path_buf).unwrap_or_default();
+
+        let mut config = Config::default();
+        let mut base = directory.clone();
+        let mut frontend = String::new();
+
+        for (index, line) in contents.lines().enumerate()
+        {
+            let line = line.split('#').next().unwrap_or_default();
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let location = format!("{}:{}", path.display(), index + 1);
+
+            match words.as_slice()
+            {
+                ["crt-base", dir, ..] => base = join(&directory, dir),
+                ["frontend" | "listen", name, ..] => frontend = name.to_string(),
+                ["backend" | "defaults" | "global", ..] => frontend = String::new(),
+                ["bind", address, parameters @ ..] => {
+                    let binding = Configured {
+                        label: format!("{} {} ({})", frontend, address, location),
+                        settings: SETTINGS,
+                        ..Configured::default()
+                    };
+
+                    for pair in parameters.windows(2)
+                    {
+                        match pair
+                        {
+                            ["crt", crt] => config.add_crt(&binding, &join(&base, crt), vec![]),
+                            ["crt-list", list] => config.add_crt_list(&binding, &base, &join(&directory, list)),
+                            _ => ()
+                        }
+                    }
+                },
+                _ => ()
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Add a crt: a file, or every certificate file in a directory.
+    fn add_crt(&mut self, binding: &Configured, path: &Path, filters: Vec<String>)
+    {
+        let files = match fs::read_dir(path)
+        {
+            Ok(entries) => {
+                let mut files: Vec<PathBuf> = entries.flatten()
+                    .map(|entry| entry.path())
+                    .filter(|path| path.is_file())
+                    .filter(|path| !path.extension().map(|e| EXTRA_EXTENSIONS.iter().any(|extra| e == *extra)).unwrap_or(false))
+                    .collect();
+
+                files.sort();
+                files
+            },
+            Err(_) => vec![path.to_path_buf()]
+        };
+
+        // HAProxy 2.x reads keys from foo.pem.key when foo.pem has none.
+        for file in files
+        {
+            self.bindings.push(Configured {
+                certificate: file.display().to_string(),
+                fallback_key: Some(format!("{}.key", file.display())),
+                names: filters.clone(),
+                ..binding.clone()
+            });
+        }
+    }
+
+    /// Add the certificates a crt-list names: a path per line, then
+    /// optional [SSL options], then optional SNI filters.
+    fn add_crt_list(&mut self, binding: &Configured, base: &Path, list: &Path)
+    {
+        let contents = match fs::read_to_string(list)
+        {
+            Ok(contents) => contents,
+            Err(e) => return self.problems.push((list.display().to_string(), format!("couldn't read crt-list: {}", e)))
+        };
+
+        for line in contents.lines()
+        {
+            let line = line.split('#').next().unwrap_or_default();
+            let mut words = line.split_whitespace();
+
+            let crt = match words.next()
+            {
+                Some(crt) => crt,
+                None => continue
+            };
+
+            let mut filters = vec![];
+            let mut options = false;
+
+            for word in words
+            {
+                if word.starts_with('[') { options = true; }
+
+                if !options { filters.push(word.to_string()); }
+
+                if word.ends_with(']') { options = false; }
+            }
+
+            self.add_crt(binding, &join(base, crt), filters);
+        }
+    }
+}
+
+/// Join paths, removing ".." components (lexically) where possible, so
+/// paths read as HAProxy's users would write them.
+fn join(base: &Path, path: &str) -> PathBuf
+{
+    let mut joined = PathBuf::new();
+
+    for component in base.join(path).components()
+    {
+        match component
+        {
+            Component::ParentDir if matches!(joined.components().next_back(), Some(Component::Normal(_))) => {
+                joined.pop();
+            },
+            component => joined.push(component)
+        }
+    }
+
+    joined
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+    use crate::configured;
+    use crate::Inventory;
+
+    #[test]
+    fn checks_bindings()
+    {
+        let mut config = Config::read(Path::new("samples/haproxy_cfg/haproxy.cfg")).unwrap();
+
+        let files: Vec<(&str, &str, Vec<String>)> = config.bindings.iter()
+            .map(|binding| (binding.label.as_str(), binding.certificate.as_str(), binding.names.clone()))
+            .collect();
+
+        assert_eq!(files, vec![
+            ("https-in :443,:::443 (samples/haproxy_cfg/haproxy.cfg:10)", "samples/haproxy/example.com.pem", vec![]),
+            ("https-in :443,:::443 (samples/haproxy_cfg/haproxy.cfg:10)", "samples/haproxy/example.com.pem", vec![String::from("example.com"), String::from("!www.example.com")]),
+            ("https-in :443,:::443 (samples/haproxy_cfg/haproxy.cfg:10)", "samples/haproxy/example.com.pem", vec![String::from("www.example.org")]),
+            ("https-in :443,:::443 (samples/haproxy_cfg/haproxy.cfg:10)", "samples/haproxy/missing.pem", vec![]),
+            ("admin 127.0.0.1:8443 (samples/haproxy_cfg/haproxy.cfg:14)", "samples/haproxy_cfg/complete.pem", vec![]),
+            ("admin 127.0.0.1:8444 (samples/haproxy_cfg/haproxy.cfg:15)", "samples/haproxy_cfg/incomplete.pem", vec![]),
+            ("admin 127.0.0.1:8445 (samples/haproxy_cfg/haproxy.cfg:16)", "samples/self_signed_san.crt", vec![]),
+        ]);
+
+        let mut inventory = Inventory::new();
+
+        for path in ["samples/self_signed_san.key", "samples/cross_signed/root_a.crt"]
+        {
+            inventory = inventory.add_path(path);
+        }
+
+        for path in configured::paths(&config.bindings).iter().filter(|path| Path::new(path).exists())
+        {
+            inventory = inventory.add_path(path);
+        }
+
+        let chains = inventory.build_chains().unwrap();
+
+        for binding in config.bindings.iter_mut()
+        {
+            chains.iter().for_each(|chain| binding.consider(chain));
+        }
+
+        let problems: Vec<Vec<String>> = config.bindings.iter().map(Configured::problems).collect();
+
+        assert!(problems[0].is_empty());
+        assert!(problems[1].is_empty());
+        assert_eq!(problems[2], vec!["SNI filter www.example.org isn't covered by samples/haproxy/example.com.pem"]);
+        assert_eq!(problems[3], vec!["samples/haproxy/missing.pem doesn't exist"]);
+        assert!(problems[4].is_empty());
+        assert_eq!(problems[5], vec!["its chain is incomplete (no issuer found for samples/haproxy_cfg/incomplete.pem)"]);
+        assert_eq!(problems[6], vec!["its key is in samples/self_signed_san.key, which isn't read"]);
+        assert_eq!(join(Path::new("a/b"), "../../../c"), PathBuf::from("../c"));
+    }
+}
@@ -0,0 +1,542 @@
+//! Copyright (C) 2022 Gaz J.
+//!
+//! This program is free software: you can redistribute it and/or modify
+//! it under the terms of the GNU General Public License as published by
+//! the Free Software Foundation, either version 3 of the License, or
+//! (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//! GNU General Public License for more details.
+//!
+//! You should have received a copy of the GNU General Public License
+//! along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! A small HTTP client for the few requests made to PKI services (e.g. OCSP
+//! responders), over plain TCP or TLS (trusting the platform's roots), and
+//! to services which need their own CA or credentials (e.g. Kubernetes API
+//! servers).
+//!
+//! Each request uses a new connection, closed by the response, and is bounded
+//! by a timeout as a whole (connecting, writing, and reading), so an
+//! unreachable or slow service fails a request rather than hanging a scan.
+//! Responses are bounded in size too, and must be complete.
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+use log::debug;
+use openssl::ssl::{SslConnector, SslMethod};
+
+use crate::trust;
+
+/// Redirects followed before giving up.
+const MAX_REDIRECTS: usize = 5;
+
+/// Largest response read (e.g. a Kubernetes cluster's Secrets), in bytes.
+const MAX_RESPONSE_SIZE: usize = 64 * 1024 * 1024;
+
+static CONNECTOR: OnceLock<Option<SslConnector>> = OnceLock::new();
+
+/// What a request to a service which needs credentials sends: headers
+/// (e.g. Authorization), and over TLS, a client certificate, or its own
+/// trusted CAs, with a connector set up for them.
+#[derive(Default)]
+pub struct Credentials
+{
+    /// A connector in place of the default (which trusts the platform's
+    /// roots).
+    pub connector: Option<SslConnector>,
+
+    /// (name, value) pairs.
+    pub headers: Vec<(String, String)>,
+}
+
+/// A response's status, other than success or a redirect followed, as the
+/// error a request fails with (see status).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StatusError(pub u16);
+
+impl fmt::Display for StatusError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        write!(f, "HTTP {}", self.0)
+    }
+}
+
+impl std::error::Error for StatusError {}
+
+/// The status a request failed with, if it got a response, e.g. 404.
+pub fn status(error: &io::Error) -> Option<u16>
+{
+    error.get_ref().and_then(|e| e.downcast_ref::<StatusError>()).map(|status| status.0)
+}
+
+/// Status, headers (with lowercase names), and body of a response.
+struct Response
+{
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+/// Parts of an http:// or https:// URL.
+#[derive(Debug, PartialEq)]
+pub struct Url
+{
+    pub tls: bool,
+    pub host: String,
+    pub port: u16,
+    pub path: String,
+}
+
+impl Url
+{
+    /// Parse an http:// or https:// URL, or None for anything else.
+    pub fn parse(url: &str) -> Option<Url>
+    {
+        let (tls, rest) = match url.split_once("://")
+        {
+            Some((scheme, rest)) if scheme.eq_ignore_ascii_case("http") => (false, rest),
+            Some((scheme, rest)) if scheme.eq_ignore_ascii_case("https") => (true, rest),
+            _ => return None
+        };
+
+        let (authority, path) = match rest.find(['/', '?'])
+        {
+            Some(index) if rest[index..].starts_with('/') => (&rest[..index], rest[index..].to_string()),
+            Some(index) => (&rest[..index], format!("/{}", &rest[index..])),
+            None => (rest, String::from("/"))
+        };
+
+        // IPv6 addresses are bracketed, e.g. "[2001:db8::1]:8080".
+        let (host, port) = match authority.strip_prefix('[')
+        {
+            Some(bracketed) => {
+                let (host, port) = bracketed.split_once(']')?;
+
+                (host, port.strip_prefix(':'))
+            },
+            None => match authority.rsplit_once(':')
+            {
+                Some((host, port)) => (host, Some(port)),
+                None => (authority, None)
+            }
+        };
+
+        let port = match port
+        {
+            Some(port) => port.parse().ok()?,
+            None if tls => 443,
+            None => 80
+        };
+
+        if host.is_empty()
+        {
+            return None;
+        }
+
+        Some(Url { tls, host: host.to_string(), port, path })
+    }
+
+    /// The Host header value, which includes non-default ports.
+    pub fn authority(&self) -> String
+    {
+        let host = if self.host.contains(':') { format!("[{}]", self.host) } else { self.host.clone() };
+
+        match (self.tls, self.port)
+        {
+            (false, 80) | (true, 443) => host,
+            (_, port) => format!("{}:{}", host, port)
+        }
+    }
+
+    /// Resolve a redirect's Location, which may be relative to this URL.
+    fn join(&self, location: &str) -> Option<Url>
+    {
+        if location.starts_with('/')
+        {
+            let scheme = if self.tls { "https" } else { "http" };
+
+            return Url::parse(&format!("{}://{}{}", scheme, self.authority(), location));
+        }
+
+        Url::parse(location)
+    }
+}
+
+/// Fetch a URL's body.
+pub fn get(url: &str, timeout: Duration) -> io::Result<Vec<u8>>
+{
+    request("GET", url, None, &[], None, timeout)
+}
+
+/// Fetch a URL's body, with credentials. Redirects are only followed to
+/// the same server, so credentials aren't sent elsewhere.
+pub fn get_with(url: &str, credentials: &Credentials, timeout: Duration) -> io::Result<Vec<u8>>
+{
+    request("GET", url, None, &[], Some(credentials), timeout)
+}
+
+/// Post a body of the given content type to a URL, returning the response
+/// body.
+pub fn post(url: &str, content_type: &str, body: &[u8], timeout: Duration) -> io::Result<Vec<u8>>
+{
+    request("POST", url, Some(content_type), body, None, timeout)
+}
+
+/// Post a body to a URL, with credentials, as with get_with.
+pub fn post_with(url: &str, content_type: &str, body: &[u8], credentials: &Credentials, timeout: Duration) -> io::Result<Vec<u8>>
+{
+    request("POST", url, Some(content_type), body, Some(credentials), timeout)
+}
+
+/// Percent-encode a value for a query string.
+pub fn encode(value: &str) -> String
+{
+    value.bytes()
+        .map(|byte| match byte
+        {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte)
+        })
+        .collect()
+}
+
+fn request(method: &str, url: &str, content_type: Option<&str>, body: &[u8], credentials: Option<&Credentials>, timeout: Duration) -> io::Result<Vec<u8>>
+{
+    let mut url = Url::parse(url)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("unsupported URL {}", url)))?;
+
+    let (mut method, mut content_type, mut body) = (method, content_type, body);
+
+    for _ in 0..=MAX_REDIRECTS
+    {
+        debug!("{} {}://{}{}", method, if url.tls { "https" } else { "http" }, url.authority(), url.path);
+
+        let response = exchange(&url, method, content_type, body, credentials, timeout)?;
+
+        match response.status
+        {
+            200 => return Ok(response.body),
+            status @ (301 | 302 | 303 | 307 | 308) => {
+                let location = header(&response.headers, "location")
+                    .and_then(|location| url.join(location))
+                    .ok_or_else(|| io::Error::other(format!("HTTP {} without a usable Location", status)))?;
+
+                if credentials.is_some() && (location.tls, location.authority()) != (url.tls, url.authority())
+                {
+                    return Err(io::Error::other(format!("HTTP {} to another server", status)));
+                }
+
+                // Only 307 and 308 redirects repeat the method (and body).
+                if status != 307 && status != 308
+                {
+                    (method, content_type, body) = ("GET", None, &[]);
+                }
+
+                url = location;
+            },
+            status => return Err(io::Error::other(StatusError(status)))
+        }
+    }
+
+    Err(io::Error::other("too many redirects"))
+}
+
+/// Connect to a host, trying each of its addresses in turn (e.g. IPv6 then
+/// IPv4) until one accepts, each within the timeout.
+pub fn connect(host: &str, port: u16, timeout: Duration) -> io::Result<(TcpStream, SocketAddr)>
+{
+    let mut error = io::Error::new(io::ErrorKind::NotFound, format!("couldn't resolve {}", host));
+
+    for address in (host, port).to_socket_addrs()?
+    {
+        match TcpStream::connect_timeout(&address, timeout)
+        {
+            Ok(stream) => return Ok((stream, address)),
+            Err(e) => {
+                debug!("Couldn't connect to {}: {}", address, e);
+
+                error = e;
+            }
+        }
+    }
+
+    Err(error)
+}
+
+/// Send a request, and read its response, within the timeout.
+fn exchange(url: &Url, method: &str, content_type: Option<&str>, body: &[u8], credentials: Option<&Credentials>, timeout: Duration) -> io::Result<Response>
+{
+    let deadline = Instant::now() + timeout;
+    let (stream, _) = connect(&url.host, url.port, timeout)?;
+
+    // A clone of the socket, to shorten its timeouts as the deadline nears
+    // (including while it's wrapped with TLS).
+    let socket = stream.try_clone()?;
+
+    set_timeouts(&socket, deadline)?;
+
+    let mut request = format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: sslchains/{}\r\nAccept: */*\r\nConnection: close\r\n",
+        method, url.path, url.authority(), env!("CARGO_PKG_VERSION")
+    ).into_bytes();
+
+    for (name, value) in credentials.iter().flat_map(|credentials| &credentials.headers)
+    {
+        request.extend(format!("{}: {}\r\n", name, value).bytes());
+    }
+
+    if let Some(content_type) = content_type
+    {
+        request.extend(format!("Content-Type: {}\r\nContent-Length: {}\r\n", content_type, body.len()).bytes());
+    }
+
+    request.extend(b"\r\n");
+    request.extend(body);
+
+    let mut response = vec![];
+
+    if url.tls
+    {
+        let connector = match credentials.and_then(|credentials| credentials.connector.as_ref())
+        {
+            Some(connector) => connector,
+            None => CONNECTOR.get_or_init(connector).as_ref().ok_or_else(|| io::Error::other("couldn't set up TLS"))?
+        };
+
+        let mut stream = connector.connect(&url.host, stream).map_err(|e| io::Error::other(e.to_string()))?;
+
+        stream.write_all(&request)?;
+        read_response(&mut stream, &socket, deadline, &mut response)?;
+    }
+    else
+    {
+        let mut stream = stream;
+
+        stream.write_all(&request)?;
+        read_response(&mut stream, &socket, deadline, &mut response)?;
+    }
+
+    parse_response(&response)
+}
+
+/// Bound a socket's reads and writes by the time left until a deadline.
+fn set_timeouts(socket: &TcpStream, deadline: Instant) -> io::Result<()>
+{
+    let remaining = deadline.saturating_duration_since(Instant::now());
+
+    if remaining.is_zero()
+    {
+        return Err(io::Error::new(io::ErrorKind::TimedOut, "request timed out"));
+    }
+
+    socket.set_read_timeout(Some(remaining))?;
+    socket.set_write_timeout(Some(remaining))
+}
+
+/// Read until the server closes the connection, the deadline passes, or the
+/// response grows too large. TLS servers which close without a close_notify
+/// alert are tolerated, provided the response is complete.
+fn read_response<R: Read>(stream: &mut R, socket: &TcpStream, deadline: Instant, response: &mut Vec<u8>) -> io::Result<()>
+{
+    let mut buffer = [0; 16 * 1024];
+
+    loop
+    {
+        set_timeouts(socket, deadline)?;
+
+        match stream.read(&mut buffer)
+        {
+            Ok(0) => return Ok(()),
+            Ok(count) => {
+                response.extend(&buffer[..count]);
+
+                if response.len() > MAX_RESPONSE_SIZE
+                {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "HTTP response too large"));
+                }
+            },
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {},
+            Err(_) if is_complete(response) => return Ok(()),
+            Err(e) => return Err(e)
+        }
+    }
+}
+
+/// Determine whether a response holds its whole body, as framed by its
+/// Content-Length or chunked encoding. Responses framed by the connection
+/// closing can't be known to be complete.
+fn is_complete(response: &[u8]) -> bool
+{
+    match parse_response(response)
+    {
+        Ok(response) => header(&response.headers, "content-length").is_some() || is_chunked(&response.headers),
+        Err(_) => false
+    }
+}
+
+fn parse_response(response: &[u8]) -> io::Result<Response>
+{
+    let malformed = || io::Error::new(io::ErrorKind::InvalidData, "malformed HTTP response");
+
+    let end = response.windows(4).position(|w| w == b"\r\n\r\n").ok_or_else(malformed)?;
+    let head = std::str::from_utf8(&response[..end]).map_err(|_| malformed())?;
+    let mut lines = head.split("\r\n");
+
+    let status = lines.next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(malformed)?;
+
+    let headers: Vec<(String, String)> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_ascii_lowercase(), value.trim().to_string()))
+        .collect();
+
+    let body = &response[end + 4..];
+
+    let body = match (is_chunked(&headers), header(&headers, "content-length"))
+    {
+        (true, _) => dechunk(body).ok_or_else(malformed)?,
+        (false, Some(length)) => {
+            let length: usize = length.parse().map_err(|_| malformed())?;
+
+            body.get(..length).ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "truncated HTTP response"))?.to_vec()
+        },
+        (false, None) => body.to_vec()
+    };
+
+    Ok(Response { status, headers, body })
+}
+
+fn is_chunked(headers: &[(String, String)]) -> bool
+{
+    header(headers, "transfer-encoding").is_some_and(|encoding| encoding.eq_ignore_ascii_case("chunked"))
+}
+
+fn header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str>
+{
+    headers.iter().find(|(n, _)| n == name).map(|(_, value)| value.as_str())
+}
+
+/// Decode a chunked body.
+fn dechunk(mut body: &[u8]) -> Option<Vec<u8>>
+{
+    let mut decoded = vec![];
+
+    loop
+    {
+        let end = body.windows(2).position(|w| w == b"\r\n")?;
+        let size = std::str::from_utf8(&body[..end]).ok()?;
+        let size = usize::from_str_radix(size.split(';').next()?.trim(), 16).ok()?;
+
+        body = &body[end + 2..];
+
+        if size == 0
+        {
+            return Some(decoded);
+        }
+
+        decoded.extend(body.get(..size)?);
+        body = body.get(size + 2..)?;
+    }
+}
+
+fn connector() -> Option<SslConnector>
+{
+    let mut builder = SslConnector::builder(SslMethod::tls_client()).ok()?;
+
+    builder.set_cert_store(trust::verification_store(vec![])?);
+
+    Some(builder.build())
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+    use std::io::BufRead;
+
+    #[test]
+    fn parses_urls()
+    {
+        assert_eq!(
+            Url::parse("http://ocsp.example.com"),
+            Some(Url { tls: false, host: String::from("ocsp.example.com"), port: 80, path: String::from("/") })
+        );
+        assert_eq!(
+            Url::parse("HTTPS://[2001:db8::1]:8443/ca.crt?x=1"),
+            Some(Url { tls: true, host: String::from("2001:db8::1"), port: 8443, path: String::from("/ca.crt?x=1") })
+        );
+        assert_eq!(Url::parse("ldap://example.com/cn=CA"), None);
+        assert_eq!(Url::parse("http://:80/"), None);
+
+        let url = Url::parse("http://example.com:8080/a/b").unwrap();
+
+        assert_eq!(url.join("/c").unwrap().path, "/c");
+        assert_eq!(url.join("/c").unwrap().port, 8080);
+        assert_eq!(url.join("https://example.org/").unwrap().host, "example.org");
+    }
+
+    #[test]
+    fn parses_responses()
+    {
+        let response = parse_response(
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n4\r\nWiki\r\n5\r\npedia\r\n0\r\n\r\n"
+        ).unwrap();
+
+        assert_eq!(response.status, 200);
+        assert_eq!(header(&response.headers, "transfer-encoding"), Some("chunked"));
+        assert_eq!(response.body, b"Wikipedia");
+
+        assert!(parse_response(b"HTTP/1.1 200 OK\r\n").is_err());
+
+        // Bodies are cut to their Content-Length, and must be complete.
+        assert_eq!(parse_response(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nca\r\n").unwrap().body, b"ca");
+        assert_eq!(
+            parse_response(b"HTTP/1.1 200 OK\r\nContent-Length: 4\r\n\r\nca").err().map(|e| e.kind()),
+            Some(io::ErrorKind::UnexpectedEof)
+        );
+        assert!(parse_response(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n4\r\nWi").is_err());
+
+        assert!(is_complete(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nca"));
+        assert!(!is_complete(b"HTTP/1.1 200 OK\r\nContent-Length: 4\r\n\r\nca"));
+        assert!(!is_complete(b"HTTP/1.1 200 OK\r\n\r\nca"));
+    }
+
+    #[test]
+    fn follows_redirects()
+    {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let server = std::thread::spawn(move || {
+            let responses: [&[u8]; 2] = [b"HTTP/1.1 302 Found\r\nLocation: /ca.crt\r\n\r\n", b"HTTP/1.1 200 OK\r\n\r\nca"];
+
+            for response in responses
+            {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = io::BufReader::new(&stream);
+                let mut line = String::new();
+
+                // Read the request up to the blank line ending its headers.
+                while reader.read_line(&mut line).unwrap() > 2
+                {
+                    line.clear();
+                }
+
+                stream.write_all(response).unwrap();
+            }
+        });
+
+        assert_eq!(get(&format!("http://{}/ca", address), Duration::from_secs(5)).unwrap(), b"ca");
+
+        server.join().unwrap();
+    }
+}
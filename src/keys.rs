@@ -11,7 +11,7 @@ use crate::chain;
 /**
  * Generate SSL keypairs for testing purposes.
  */
-pub fn generate() -> (Rsa<Private>, X509Req, X509)
+pub fn generate() -> (PKey<Private>, X509Req, X509)
 {
     generate_with_sans(vec![])
 }
@@ -20,7 +20,7 @@ pub fn generate() -> (Rsa<Private>, X509Req, X509)
  * Generate SSL keypairs for testing purposes, with a given list of values for
  * the Subject Alternative Name X509 extension.
  */
-pub fn generate_with_sans(sans: Vec<&str>) -> (Rsa<Private>, X509Req, X509)
+pub fn generate_with_sans(sans: Vec<&str>) -> (PKey<Private>, X509Req, X509)
 {
     let rsa: Rsa<Private> = Rsa::generate(2048).unwrap();
 
@@ -35,13 +35,11 @@ pub fn generate_with_sans(sans: Vec<&str>) -> (Rsa<Private>, X509Req, X509)
     )
 }
 
-fn private_key(rsa: &Rsa<Private>) -> Rsa<Private>
+fn private_key(rsa: &Rsa<Private>) -> PKey<Private>
 {
     let pem: Vec<u8> = rsa.private_key_to_pem().unwrap();
 
-    let contents = String::from_utf8(pem).unwrap();
-
-    chain::str_to_private_key(&contents).unwrap()
+    chain::bytes_to_private_key(&pem).unwrap()
 }
 
 fn certificate_request(pkey: &PKey<Public>, sans: Option<Vec<&str>>) -> X509Req
@@ -0,0 +1,544 @@
+//! Copyright (C) 2022 Gaz J.
+//!
+//! This program is free software: you can redistribute it and/or modify
+//! it under the terms of the GNU General Public License as published by
+//! the Free Software Foundation, either version 3 of the License, or
+//! (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//! GNU General Public License for more details.
+//!
+//! You should have received a copy of the GNU General Public License
+//! along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Kubernetes clusters (--kubeconfig): the TLS Secrets in every namespace,
+//! listed by the API server of a kubeconfig context. Their keys and
+//! certificates are named as the files of a mounted Secret would be, under
+//! their namespace, e.g. "kubernetes:default/example-tls/tls.crt".
+//!
+//! The Ingresses, Gateways, and cert-manager Certificates referring to the
+//! Secrets are listed too, so each can be checked against its chain, and
+//! Secrets nothing refers to reported.
+//!
+//! Users authenticate with a token or a client certificate; exec and
+//! auth-provider plugins aren't supported.
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+use openssl::base64;
+use openssl::pkey::PKey;
+use openssl::ssl::{SslConnector, SslMethod, SslVerifyMode};
+use openssl::x509::X509;
+use openssl::x509::store::X509StoreBuilder;
+use serde_json::Value as Json;
+use serde_yaml::Value;
+
+use crate::configured::Configured;
+use crate::http::{self, Credentials};
+use crate::manifest;
+use crate::trust;
+
+/// Prefixes the names of Secrets' keys and certificates.
+pub const PREFIX: &str = "kubernetes:";
+
+/// Objects listed per request.
+const PAGE_SIZE: usize = 500;
+
+/// Kinds of objects which refer to TLS Secrets, and where they're listed.
+const REFERRING: [(&str, &str); 3] = [
+    ("Ingress", "/apis/networking.k8s.io/v1/ingresses"),
+    ("Gateway", "/apis/gateway.networking.k8s.io/v1/gateways"),
+    ("Certificate", "/apis/cert-manager.io/v1/certificates"),
+];
+
+/// (path, problem) pairs, e.g. for Secrets nothing refers to.
+pub type Problems = Vec<(String, String)>;
+
+/// The kubeconfig kubectl would use: the first in $KUBECONFIG, or
+/// ~/.kube/config.
+pub fn default_kubeconfig() -> String
+{
+    let from_environment = env::var_os("KUBECONFIG")
+        .and_then(|paths| env::split_paths(&paths).next())
+        .filter(|path| !path.as_os_str().is_empty());
+
+    match (from_environment, env::var_os("HOME"))
+    {
+        (Some(path), _) => path.display().to_string(),
+        (None, Some(home)) => Path::new(&home).join(".kube/config").display().to_string(),
+        (None, None) => String::from(".kube/config")
+    }
+}
+
+/// A TLS Secret, and its keys and certificates.
+#[derive(Debug)]
+pub struct Secret
+{
+    pub namespace: String,
+    pub name: String,
+
+    /// (field, contents) pairs, e.g. ("tls.crt", PEM).
+    pub fields: Vec<(String, Vec<u8>)>,
+}
+
+impl Secret
+{
+    /// The name a field is read as, e.g. "kubernetes:default/example-tls/tls.crt".
+    pub fn path(&self, field: &str) -> String
+    {
+        format!("{}{}/{}/{}", PREFIX, self.namespace, self.name, field)
+    }
+}
+
+/// Note which references' Secrets exist, returning (path, problem) pairs
+/// for Secrets nothing refers to.
+pub fn correlate(references: &mut [Configured], secrets: &[Secret]) -> Problems
+{
+    let refers = |reference: &Configured, secret: &Secret| reference.certificate == secret.path("tls.crt");
+
+    for reference in references.iter_mut()
+    {
+        reference.exists = Some(secrets.iter().any(|secret| refers(reference, secret)));
+    }
+
+    secrets.iter()
+        .filter(|secret| !references.iter().any(|reference| refers(reference, secret)))
+        .map(|secret| (format!("{}{}/{}", PREFIX, secret.namespace, secret.name), String::from("isn't referred to by an Ingress, Gateway, or Certificate")))
+        .collect()
+}
+
+/// A cluster's API server, and how to authenticate to it.
+pub struct Cluster
+{
+    /// The kubeconfig context it was found by.
+    pub context: String,
+
+    /// The API server's URL, e.g. "https://127.0.0.1:6443".
+    pub server: String,
+
+    credentials: Credentials,
+}
+
+impl Cluster
+{
+    /// Read the cluster and user of a kubeconfig's context (by default, its
+    /// current context).
+    pub fn from_kubeconfig(path: &Path, context: Option<&str>) -> io::Result<Cluster>
+    {
+        let error = |message: String| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), message));
+
+        let contents = fs::read_to_string(path).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+        let config: Value = serde_yaml::from_str(&contents).map_err(|e| error(e.to_string()))?;
+        let directory = path.parent().unwrap_or(Path::new(""));
+
+        let context = match context.or(config["current-context"].as_str())
+        {
+            Some(context) => context.to_string(),
+            None => return Err(error(String::from("has no current-context")))
+        };
+
+        // Contexts, clusters, and users are lists of named entries.
+        let named = |list: &str, entry: &str, name: &str| {
+            config[list].as_sequence().into_iter().flatten()
+                .find(|item| item["name"].as_str() == Some(name))
+                .map(|item| item[entry].clone())
+                .ok_or_else(|| error(format!("has no {} named {}", entry, name)))
+        };
+
+        let entry = named("contexts", "context", &context)?;
+        let cluster = named("clusters", "cluster", entry["cluster"].as_str().unwrap_or_default())?;
+        let user = named("users", "user", entry["user"].as_str().unwrap_or_default())?;
+
+        let server = cluster["server"].as_str().ok_or_else(|| error(format!("cluster of context {} has no server", context)))?;
+        let server = server.trim_end_matches('/').to_string();
+
+        let mut builder = SslConnector::builder(SslMethod::tls_client()).map_err(io::Error::other)?;
+
+        if cluster["insecure-skip-tls-verify"].as_bool().unwrap_or(false)
+        {
+            builder.set_verify(SslVerifyMode::NONE);
+        }
+        else
+        {
+            let store = match read_data(&cluster, "certificate-authority", directory)?
+            {
+                Some(pem) => {
+                    let mut store = X509StoreBuilder::new().map_err(io::Error::other)?;
+
+                    for certificate in X509::stack_from_pem(&pem).map_err(|e| error(format!("certificate-authority: {}", e)))?
+                    {
+                        store.add_cert(certificate).map_err(io::Error::other)?;
+                    }
+
+                    store.build()
+                },
+                None => trust::verification_store(vec![]).ok_or_else(|| io::Error::other("couldn't load the system's trusted roots"))?
+            };
+
+            builder.set_cert_store(store);
+        }
+
+        if let (Some(certificate), Some(key)) = (read_data(&user, "client-certificate", directory)?, read_data(&user, "client-key", directory)?)
+        {
+            let certificate = X509::from_pem(&certificate).map_err(|e| error(format!("client-certificate: {}", e)))?;
+            let key = PKey::private_key_from_pem(&key).map_err(|e| error(format!("client-key: {}", e)))?;
+
+            builder.set_certificate(&certificate).map_err(io::Error::other)?;
+            builder.set_private_key(&key).map_err(io::Error::other)?;
+        }
+
+        let token = match (user["token"].as_str(), user["tokenFile"].as_str())
+        {
+            (Some(token), _) => Some(token.to_string()),
+            (None, Some(file)) => Some(fs::read_to_string(directory.join(file))?.trim().to_string()),
+            (None, None) => None
+        };
+
+        if token.is_none() && (!user["exec"].is_null() || !user["auth-provider"].is_null())
+        {
+            return Err(error(format!("the user of context {} authenticates with a plugin, which isn't supported", context)));
+        }
+
+        let credentials = Credentials {
+            connector: Some(builder.build()),
+            headers: token.map(|token| (String::from("Authorization"), format!("Bearer {}", token))).into_iter().collect(),
+        };
+
+        Ok(Cluster { context, server, credentials })
+    }
+
+    /// List the TLS Secrets in every namespace.
+    pub fn tls_secrets(&self, timeout: Duration) -> io::Result<Vec<Secret>>
+    {
+        let path = format!("/api/v1/secrets?fieldSelector={}", http::encode("type=kubernetes.io/tls"));
+        let items = self.list(&path, timeout).map_err(|e| self.error(e))?;
+
+        Ok(items.iter().map(parse_secret).collect())
+    }
+
+    /// List the Ingresses, Gateways, and cert-manager Certificates in every
+    /// namespace which refer to TLS Secrets, as the Secrets' certificates
+    /// they're configured with (see parse_references). Kinds which the
+    /// cluster doesn't have (as their resource definitions aren't installed)
+    /// are skipped, as are those the user may not list, which are returned
+    /// as (server, problem) pairs.
+    pub fn references(&self, timeout: Duration) -> io::Result<(Vec<Configured>, Problems)>
+    {
+        let mut references = vec![];
+        let mut forbidden = vec![];
+
+        for (kind, path) in REFERRING
+        {
+            match self.list(path, timeout)
+            {
+                Ok(items) => items.iter().for_each(|item| references.extend(parse_references(kind, item))),
+                Err(e) if http::status(&e) == Some(404) => (),
+                Err(e) if http::status(&e) == Some(403) => forbidden.push((self.server.clone(), format!("may not list {} objects ({})", kind, e))),
+                Err(e) => return Err(self.error(e))
+            }
+        }
+
+        Ok((references, forbidden))
+    }
+
+    /// An error from the API server, naming it.
+    fn error(&self, e: io::Error) -> io::Error
+    {
+        io::Error::new(e.kind(), format!("{}: {}", self.server, e))
+    }
+
+    /// List a kind of object in every namespace, a page at a time.
+    fn list(&self, path: &str, timeout: Duration) -> io::Result<Vec<Json>>
+    {
+        let mut items = vec![];
+        let mut next: Option<String> = None;
+
+        loop
+        {
+            let separator = if path.contains('?') { '&' } else { '?' };
+            let mut url = format!("{}{}{}limit={}", self.server, path, separator, PAGE_SIZE);
+
+            if let Some(token) = &next
+            {
+                url.push_str(&format!("&continue={}", http::encode(token)));
+            }
+
+            let body = http::get_with(&url, &self.credentials, timeout)?;
+
+            let mut list: Json = serde_json::from_slice(&body).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+            if let Some(page) = list["items"].as_array_mut()
+            {
+                items.append(page);
+            }
+
+            match list["metadata"]["continue"].as_str().filter(|token| !token.is_empty())
+            {
+                Some(token) => next = Some(token.to_string()),
+                None => return Ok(items)
+            }
+        }
+    }
+}
+
+/// Read a kubeconfig field given inline (as "FIELD-data", in base64), or as
+/// a path (relative to the kubeconfig).
+fn read_data(section: &Value, field: &str, directory: &Path) -> io::Result<Option<Vec<u8>>>
+{
+    if let Some(encoded) = section[format!("{}-data", field).as_str()].as_str()
+    {
+        return base64::decode_block(encoded.trim())
+            .map(Some)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}-data: {}", field, e)));
+    }
+
+    match section[field].as_str()
+    {
+        Some(path) => fs::read(directory.join(path)).map(Some).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path, e))),
+        None => Ok(None)
+    }
+}
+
+/// Read a Secret's key and certificate fields.
+fn parse_secret(item: &Json) -> Secret
+{
+    let fields = manifest::FIELDS.iter()
+        .filter_map(|field| item["data"][*field].as_str().map(|encoded| (field.to_string(), encoded)))
+        .filter_map(|(field, encoded)| base64::decode_block(encoded).ok().map(|contents| (field, contents)))
+        .filter(|(_, contents)| !contents.is_empty())
+        .collect();
+
+    Secret {
+        namespace: item["metadata"]["namespace"].as_str().unwrap_or_default().to_string(),
+        name: item["metadata"]["name"].as_str().unwrap_or_default().to_string(),
+        fields,
+    }
+}
+
+/// Read the Secrets an Ingress, Gateway, or cert-manager Certificate
+/// refers to, labelled e.g. "Ingress shop/storefront (shop.example.com)",
+/// with the hostnames they're for (none for any, e.g. an Ingress's
+/// default).
+fn parse_references(kind: &str, item: &Json) -> Vec<Configured>
+{
+    let namespace = item["metadata"]["namespace"].as_str().unwrap_or_default();
+    let name = item["metadata"]["name"].as_str().unwrap_or_default();
+    let strings = |list: &Json| list.as_array().into_iter().flatten().filter_map(Json::as_str).map(String::from).collect::<Vec<String>>();
+
+    let reference = |secret_namespace: &str, secret_name: &str, hosts: Vec<String>| {
+        let secret_path = |field: &str| format!("{}{}/{}/{}", PREFIX, secret_namespace, secret_name, field);
+
+        let label = match hosts.is_empty()
+        {
+            true => format!("{} {}/{}", kind, namespace, name),
+            false => format!("{} {}/{} ({})", kind, namespace, name, hosts.join(", "))
+        };
+
+        Configured {
+            label,
+            certificate: secret_path("tls.crt"),
+            key: Some(secret_path("tls.key")),
+            names: hosts,
+            exists: Some(false),
+            ..Configured::default()
+        }
+    };
+
+    let spec = &item["spec"];
+
+    match kind
+    {
+        "Ingress" => spec["tls"].as_array().into_iter().flatten()
+            .filter_map(|tls| Some(reference(namespace, tls["secretName"].as_str()?, strings(&tls["hosts"]))))
+            .collect(),
+        "Gateway" => spec["listeners"].as_array().into_iter().flatten()
+            .flat_map(|listener| {
+                let hosts: Vec<String> = listener["hostname"].as_str().map(String::from).into_iter().collect();
+
+                // References are to Secrets unless they name another kind.
+                listener["tls"]["certificateRefs"].as_array().into_iter().flatten()
+                    .filter(|secret| secret["kind"].as_str().unwrap_or("Secret") == "Secret")
+                    .filter_map(|secret| Some(reference(secret["namespace"].as_str().unwrap_or(namespace), secret["name"].as_str()?, hosts.clone())))
+                    .collect::<Vec<_>>()
+            })
+            .collect(),
+        _ => spec["secretName"].as_str()
+            .map(|secret| reference(namespace, secret, strings(&spec["dnsNames"])))
+            .into_iter()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+    use std::io::{BufRead, Write};
+    use std::net::TcpListener;
+    use crate::Inventory;
+
+    #[test]
+    fn reads_kubeconfig()
+    {
+        let cluster = Cluster::from_kubeconfig(Path::new("samples/kubeconfig/config"), None).unwrap();
+
+        assert_eq!(cluster.context, "dev");
+        assert_eq!(cluster.server, "https://127.0.0.1:6443");
+        assert_eq!(cluster.credentials.headers, vec![(String::from("Authorization"), String::from("Bearer sample-token"))]);
+
+        let error = Cluster::from_kubeconfig(Path::new("samples/kubeconfig/config"), Some("prod")).err().unwrap();
+
+        assert!(error.to_string().ends_with("the user of context prod authenticates with a plugin, which isn't supported"));
+        assert!(Cluster::from_kubeconfig(Path::new("samples/kubeconfig/config"), Some("test")).is_err());
+    }
+
+    /// Answer requests on a local port with each (status, file) in turn,
+    /// returning the requests.
+    fn serve(responses: Vec<(&'static str, &'static str)>) -> (String, std::thread::JoinHandle<Vec<String>>)
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let server = std::thread::spawn(move || {
+            let mut requests = vec![];
+
+            for (status, page) in responses
+            {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = io::BufReader::new(&stream);
+                let mut request = String::new();
+
+                // Read the request up to the blank line ending its headers.
+                while reader.read_line(&mut request).unwrap() > 2 {}
+
+                requests.push(request);
+
+                let body = fs::read(page).unwrap_or_default();
+
+                stream.write_all(format!("HTTP/1.1 {}\r\nContent-Length: {}\r\n\r\n", status, body.len()).as_bytes()).unwrap();
+                stream.write_all(&body).unwrap();
+            }
+
+            requests
+        });
+
+        (format!("http://{}", address), server)
+    }
+
+    #[test]
+    fn lists_tls_secrets()
+    {
+        let (address, server) = serve(vec![
+            ("200 OK", "samples/kubeconfig/secrets-1.json"),
+            ("200 OK", "samples/kubeconfig/secrets-2.json"),
+        ]);
+
+        let mut cluster = Cluster::from_kubeconfig(Path::new("samples/kubeconfig/config"), None).unwrap();
+
+        cluster.server = address;
+
+        let secrets = cluster.tls_secrets(Duration::from_secs(5)).unwrap();
+        let requests = server.join().unwrap();
+
+        assert!(requests[0].starts_with("GET /api/v1/secrets?fieldSelector=type%3Dkubernetes.io%2Ftls&limit=500 "));
+        assert!(requests[0].contains("Authorization: Bearer sample-token\r\n"));
+        assert!(requests[1].contains("&continue=eyJ2IjoibWV0YS5rOHMuaW8vdjEiLCJzdGFydCI6InNob3AifQ%3D%3D "));
+
+        let paths: Vec<String> = secrets.iter()
+            .flat_map(|secret| secret.fields.iter().map(|(field, _)| secret.path(field)))
+            .collect();
+
+        assert_eq!(paths, vec![
+            "kubernetes:default/example-tls/tls.crt",
+            "kubernetes:default/example-tls/tls.key",
+            "kubernetes:shop/shop-tls/tls.crt",
+            "kubernetes:shop/shop-tls/tls.key",
+        ]);
+    }
+
+    #[test]
+    fn checks_references_to_secrets()
+    {
+        // This cluster has no Gateway API.
+        let (address, server) = serve(vec![
+            ("200 OK", "samples/kubeconfig/ingresses.json"),
+            ("404 Not Found", ""),
+            ("200 OK", "samples/kubeconfig/certificates.json"),
+        ]);
+
+        let mut cluster = Cluster::from_kubeconfig(Path::new("samples/kubeconfig/config"), None).unwrap();
+
+        cluster.server = address;
+
+        let (mut references, forbidden) = cluster.references(Duration::from_secs(5)).unwrap();
+        let requests = server.join().unwrap();
+
+        assert!(forbidden.is_empty());
+
+        assert!(requests[2].starts_with("GET /apis/cert-manager.io/v1/certificates?limit=500 "));
+
+        let secrets: Vec<Secret> = ["samples/kubeconfig/secrets-1.json", "samples/kubeconfig/secrets-2.json"].iter()
+            .flat_map(|page| serde_json::from_slice::<Json>(&fs::read(page).unwrap()).unwrap()["items"].as_array().unwrap().clone())
+            .map(|item| parse_secret(&item))
+            .collect();
+
+        let unreferenced = correlate(&mut references, &secrets);
+        let mut inventory = Inventory::new();
+
+        for secret in &secrets
+        {
+            secret.fields.iter().for_each(|(field, contents)| inventory.add_bytes(&secret.path(field), contents));
+        }
+
+        for chain in inventory.build_chains().unwrap()
+        {
+            references.iter_mut().for_each(|reference| reference.consider(&chain));
+        }
+
+        let labels: Vec<String> = references.iter().map(|reference| reference.label.clone()).collect();
+        let problems: Vec<Vec<String>> = references.iter().map(Configured::problems).collect();
+
+        assert_eq!(labels, vec![
+            "Ingress default/web (example.com)",
+            "Ingress shop/storefront (san.example.com, shop.example.com)",
+            "Certificate shop/shop (san.example.com, www.san.example.com)",
+        ]);
+        assert_eq!(problems[0], vec!["kubernetes:default/web-tls/tls.crt doesn't exist"]);
+        assert_eq!(problems[1], vec!["shop.example.com isn't covered by kubernetes:shop/shop-tls/tls.crt"]);
+        assert!(problems[2].is_empty());
+        assert_eq!(unreferenced, vec![(String::from("kubernetes:default/example-tls"), String::from("isn't referred to by an Ingress, Gateway, or Certificate"))]);
+
+        let gateways: Json = serde_json::from_slice(&fs::read("samples/kubeconfig/gateways.json").unwrap()).unwrap();
+        let listeners = parse_references("Gateway", &gateways["items"][0]);
+
+        assert_eq!(listeners.len(), 1);
+        assert_eq!(listeners[0].certificate, "kubernetes:default/example-tls/tls.crt");
+        assert_eq!(listeners[0].label, "Gateway gateways/main (example.com)");
+    }
+
+    #[test]
+    fn skips_kinds_it_may_not_list()
+    {
+        let (address, server) = serve(vec![
+            ("200 OK", "samples/kubeconfig/ingresses.json"),
+            ("404 Not Found", ""),
+            ("403 Forbidden", ""),
+        ]);
+
+        let mut cluster = Cluster::from_kubeconfig(Path::new("samples/kubeconfig/config"), None).unwrap();
+
+        cluster.server = address.clone();
+
+        let (references, forbidden) = cluster.references(Duration::from_secs(5)).unwrap();
+
+        server.join().unwrap();
+
+        assert_eq!(references.len(), 2);
+        assert_eq!(forbidden, vec![(address, String::from("may not list Certificate objects (HTTP 403)"))]);
+    }
+}
@@ -0,0 +1,276 @@
+//! Copyright (C) 2022 Gaz J.
+//!
+//! This program is free software: you can redistribute it and/or modify
+//! it under the terms of the GNU General Public License as published by
+//! the Free Software Foundation, either version 3 of the License, or
+//! (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//! GNU General Public License for more details.
+//!
+//! You should have received a copy of the GNU General Public License
+//! along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Let's Encrypt (certbot) layouts (--letsencrypt): each lineage keeps every
+//! version of its files under archive/NAME (cert1.pem, privkey1.pem, and so
+//! on), with symbolic links under live/NAME to its current version.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Where certbot keeps its lineages.
+pub const DEFAULT_DIRECTORY: &str = "/etc/letsencrypt";
+
+/// Files certbot links for each lineage.
+const KINDS: [&str; 4] = ["cert", "chain", "fullchain", "privkey"];
+
+/// How a version of a lineage relates to its live links.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Status
+{
+    /// Linked from live/NAME.
+    Live,
+
+    /// Older than the live version, or archived for a lineage with no live
+    /// links.
+    Stale,
+
+    /// Newer than the live version, e.g. after an interrupted renewal.
+    Unlinked,
+}
+
+impl Status
+{
+    pub fn as_str(&self) -> &'static str
+    {
+        match self
+        {
+            Status::Live => "live",
+            Status::Stale => "stale",
+            Status::Unlinked => "not live",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Lineage
+{
+    pub name: String,
+
+    /// archive/NAME.
+    pub archive: PathBuf,
+
+    /// Versions archived, newest first.
+    pub versions: Vec<u32>,
+
+    /// The version live/NAME/cert.pem links to, if any.
+    pub live: Option<u32>,
+}
+
+impl Lineage
+{
+    pub fn status(&self, version: u32) -> Status
+    {
+        match self.live
+        {
+            Some(live) if version == live => Status::Live,
+            Some(live) if version > live => Status::Unlinked,
+            _ => Status::Stale
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Layout
+{
+    pub lineages: Vec<Lineage>,
+
+    /// (path, problem) pairs for live links which are missing, broken, or
+    /// disagree, and lineages which are only archived, or only live.
+    pub problems: Vec<(String, String)>,
+}
+
+impl Layout
+{
+    /// Read the archive and live directories under a certbot configuration
+    /// directory (e.g. /etc/letsencrypt).
+    pub fn scan(directory: &Path) -> io::Result<Layout>
+    {
+        let mut layout = Layout::default();
+        let live_directory = directory.join("live");
+
+        for archive in subdirectories(&directory.join("archive"))?
+        {
+            let name = archive.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+
+            let mut versions: Vec<u32> = fs::read_dir(&archive)?.flatten()
+                .filter_map(|entry| parse_file_name(&entry.file_name().to_string_lossy()).map(|(_, version)| version))
+                .collect();
+
+            versions.sort_by(|a, b| b.cmp(a));
+            versions.dedup();
+
+            let live = layout.read_live(&live_directory.join(&name), &archive);
+
+            if let Some(live) = live
+            {
+                if let Some(newer) = versions.iter().find(|version| **version > live)
+                {
+                    layout.problems.push((archive.display().to_string(), format!("version {} is archived, but version {} is live", newer, live)));
+                }
+            }
+
+            layout.lineages.push(Lineage { name, archive, versions, live });
+        }
+
+        // Live links without archives are broken, but say so once.
+        for live in subdirectories(&live_directory).unwrap_or_default()
+        {
+            if !layout.lineages.iter().any(|lineage| Some(lineage.name.as_str()) == live.file_name().and_then(|name| name.to_str()))
+            {
+                layout.problems.push((live.display().to_string(), String::from("has no archive")));
+            }
+        }
+
+        Ok(layout)
+    }
+
+    /// Read a lineage's live links, returning the version they link to (by
+    /// cert.pem), and noting any problems.
+    fn read_live(&mut self, live: &Path, archive: &Path) -> Option<u32>
+    {
+        if !live.is_dir()
+        {
+            self.problems.push((archive.display().to_string(), String::from("isn't linked from live (stale lineage)")));
+
+            return None;
+        }
+
+        let archive = fs::canonicalize(archive).ok();
+        let mut versions = vec![];
+
+        for kind in KINDS
+        {
+            let link = live.join(format!("{}.pem", kind));
+            let path = link.display().to_string();
+
+            let target = match fs::read_link(&link)
+            {
+                Ok(target) => live.join(target),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    self.problems.push((path, String::from("is missing")));
+                    continue;
+                },
+                Err(_) => {
+                    self.problems.push((path, String::from("isn't a symbolic link")));
+                    continue;
+                }
+            };
+
+            let parsed = target.file_name().and_then(|name| name.to_str()).and_then(parse_file_name);
+
+            match (fs::canonicalize(&target).ok(), parsed)
+            {
+                (None, _) => self.problems.push((path, format!("is broken (links to {})", target.display()))),
+                (Some(resolved), Some((linked, version))) if linked == kind && resolved.parent() == archive.as_deref() => {
+                    versions.push((kind, version))
+                },
+                (Some(_), _) => self.problems.push((path, format!("links outside the archive ({})", target.display())))
+            }
+        }
+
+        if let Some((kind, version)) = versions.iter().find(|(_, version)| *version != versions[0].1)
+        {
+            self.problems.push((live.display().to_string(), format!("links to version {} for {}, but version {} for {}", versions[0].1, versions[0].0, version, kind)));
+        }
+
+        versions.iter().find(|(kind, _)| *kind == "cert").map(|(_, version)| *version)
+    }
+
+    /// The archive directories to read.
+    pub fn directories(&self) -> Vec<String>
+    {
+        self.lineages.iter().map(|lineage| lineage.archive.display().to_string()).collect()
+    }
+
+    /// The lineage and version of an archived file, judged by its path.
+    pub fn version_of(&self, path: &str) -> Option<(&Lineage, u32)>
+    {
+        let path = Path::new(path);
+        let (_, version) = parse_file_name(path.file_name()?.to_str()?)?;
+        let directory = path.parent()?;
+
+        // Paths may have been made relative, or absolute, since, so only
+        // their last components are compared.
+        if directory.parent()?.file_name()? != "archive"
+        {
+            return None;
+        }
+
+        let lineage = self.lineages.iter().find(|lineage| directory.file_name() == Some(lineage.name.as_ref()))?;
+
+        Some((lineage, version))
+    }
+}
+
+/// Split archived file names (e.g. "privkey12.pem") into their kind and
+/// version.
+fn parse_file_name(name: &str) -> Option<(&str, u32)>
+{
+    let stem = name.strip_suffix(".pem")?;
+    let kind = stem.trim_end_matches(|c: char| c.is_ascii_digit());
+
+    match KINDS.contains(&kind)
+    {
+        true => stem[kind.len()..].parse().ok().map(|version| (kind, version)),
+        false => None
+    }
+}
+
+fn subdirectories(directory: &Path) -> io::Result<Vec<PathBuf>>
+{
+    let mut directories: Vec<PathBuf> = fs::read_dir(directory)?.flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .collect();
+
+    directories.sort();
+
+    Ok(directories)
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    #[test]
+    fn reads_lineages()
+    {
+        let layout = Layout::scan(Path::new("samples/letsencrypt")).unwrap();
+
+        assert_eq!(layout.directories(), vec!["samples/letsencrypt/archive/example.com", "samples/letsencrypt/archive/old.example.com"]);
+
+        let lineage = &layout.lineages[0];
+
+        assert_eq!(lineage.versions, vec![2, 1]);
+        assert_eq!(lineage.live, Some(2));
+        assert_eq!(lineage.status(1), Status::Stale);
+        assert_eq!(lineage.status(3), Status::Unlinked);
+
+        assert_eq!(layout.lineages[1].live, None);
+        assert_eq!(layout.problems, vec![
+            (String::from("samples/letsencrypt/archive/old.example.com"), String::from("isn't linked from live (stale lineage)")),
+        ]);
+
+        let (lineage, version) = layout.version_of("letsencrypt/archive/example.com/fullchain2.pem").unwrap();
+
+        assert_eq!((lineage.name.as_str(), version), ("example.com", 2));
+        assert!(layout.version_of("samples/letsencrypt/live/example.com/cert.pem").is_none());
+        assert_eq!(parse_file_name("privkey12.pem"), Some(("privkey", 12)));
+        assert_eq!(parse_file_name("cert.pem"), None);
+    }
+}
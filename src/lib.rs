@@ -0,0 +1,87 @@
+//! sslchains
+//!
+//! A library to identify related SSL keys, CSRs, and certificates.
+//!
+//! Copyright (C) 2022 Gaz J.
+//!
+//! This program is free software: you can redistribute it and/or modify
+//! it under the terms of the GNU General Public License as published by
+//! the Free Software Foundation, either version 3 of the License, or
+//! (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//! GNU General Public License for more details.
+//!
+//! You should have received a copy of the GNU General Public License
+//! along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//!
+//! Files (or in-memory contents) are read into an [`Inventory`], from which
+//! a [`Chain`] is built for each private key, holding its request and
+//! certificates (each with its signing certificates). Chains can be
+//! serialized with serde (see [`schema`]).
+//!
+//! ```
+//! use std::fs;
+//! use sslchains::Inventory;
+//!
+//! let chains = Inventory::new()
+//!     .add_pem_bytes("example.com.crt", &fs::read("samples/ca_signed.crt").unwrap())
+//!     .add_path("samples/ca_signed.key")
+//!     .add_path("samples/intermediate_ca.crt")
+//!     .build_chains()
+//!     .unwrap();
+//!
+//! assert_eq!(chains[0].certificates[0].path, "example.com.crt");
+//! assert_eq!(chains[0].certificates[0].signing_certificate_chain()[0].path, "samples/intermediate_ca.crt");
+//! ```
+
+pub mod alerts;
+pub mod apache;
+pub mod archive;
+#[cfg(feature = "aws")]
+pub mod aws;
+pub mod bundle;
+pub mod cache;
+pub mod chain;
+pub mod cleanup;
+pub mod configured;
+pub mod connect;
+pub mod container;
+pub mod ct;
+#[cfg(feature = "db")]
+pub mod database;
+pub mod error;
+pub mod export;
+pub mod fetch;
+pub mod format;
+pub mod haproxy;
+pub mod http;
+mod keys;
+pub mod kubernetes;
+pub mod letsencrypt;
+pub mod mail;
+pub mod manifest;
+pub mod metrics;
+#[cfg(feature = "nss")]
+pub mod nss;
+pub mod ocsp;
+pub mod openssh;
+pub mod permissions;
+pub mod policy;
+pub mod rename;
+pub mod schema;
+pub mod server;
+pub mod snapshot;
+pub mod ssh;
+pub mod starttls;
+#[cfg(unix)]
+pub mod syslog;
+pub mod trust;
+pub mod usage;
+#[cfg(target_os = "linux")]
+pub mod watch;
+
+pub use chain::{CertificateFile, CertificateRequestFile, Chain, Inventory, PrivateKeyFile};
+pub use error::{Error, Result};
@@ -0,0 +1,458 @@
+//! Copyright (C) 2022 Gaz J.
+//!
+//! This program is free software: you can redistribute it and/or modify
+//! it under the terms of the GNU General Public License as published by
+//! the Free Software Foundation, either version 3 of the License, or
+//! (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//! GNU General Public License for more details.
+//!
+//! You should have received a copy of the GNU General Public License
+//! along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Mail server configurations: the certificates and keys Postfix's smtpd is
+//! configured with in main.cf (--postfix), and Dovecot's IMAP and POP3
+//! servers in dovecot.conf (--dovecot), so they can be checked against the
+//! chains built from them.
+//!
+//! Both send the certificates in their certificate files, so intermediates
+//! belong there. Postfix's $config_directory is taken to be main.cf's
+//! directory, and Dovecot's relative paths are resolved against that of
+//! dovecot.conf.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::apache::expand;
+use crate::configured::{Configured, Settings};
+
+/// Where Postfix's main configuration file usually is.
+pub const POSTFIX_CONFIG: &str = "/etc/postfix/main.cf";
+
+/// Where Dovecot's main configuration file usually is.
+pub const DOVECOT_CONFIG: &str = "/etc/dovecot/dovecot.conf";
+
+/// Postfix's (certificate, key) parameters.
+const POSTFIX_SETTINGS: [(&str, &str); 2] = [
+    ("smtpd_tls_cert_file", "smtpd_tls_key_file"),
+    ("smtpd_tls_eccert_file", "smtpd_tls_eckey_file"),
+];
+
+/// Dovecot's (certificate, key) settings, including those Dovecot 2.4
+/// renamed them to.
+const DOVECOT_SETTINGS: [(&str, &str); 3] = [
+    ("ssl_cert", "ssl_key"),
+    ("ssl_alt_cert", "ssl_alt_key"),
+    ("ssl_server_cert_file", "ssl_server_key_file"),
+];
+
+/// Includes (and Postfix parameters) nested deeper than this are assumed to
+/// be loops.
+const MAX_DEPTH: usize = 16;
+
+/// The certificates mail servers are configured with, labelled e.g.
+/// "postfix mail.example.com (main.cf:12)". Their names are those they
+/// should cover: Postfix's myhostname, or those of a Dovecot local_name
+/// section.
+#[derive(Debug, Default)]
+pub struct Config
+{
+    pub services: Vec<Configured>,
+
+    /// (path, problem) pairs for includes which couldn't be read.
+    pub problems: Vec<(String, String)>,
+}
+
+/// A (value, location) pair for a setting.
+type Setting = (String, String);
+
+impl Config
+{
+    /// Read Postfix's and Dovecot's configurations, where given.
+    pub fn read(postfix: Option<&str>, dovecot: Option<&str>) -> io::Result<Config>
+    {
+        let mut config = Config::default();
+
+        if let Some(path) = postfix
+        {
+            config.extend(Config::read_postfix(Path::new(path))?);
+        }
+
+        if let Some(path) = dovecot
+        {
+            config.extend(Config::read_dovecot(Path::new(path))?);
+        }
+
+        Ok(config)
+    }
+
+    fn extend(&mut self, other: Config)
+    {
+        self.services.extend(other.services);
+        self.problems.extend(other.problems);
+    }
+
+    /// Read Postfix's main.cf, for smtpd's RSA and ECDSA certificates (and
+    /// myhostname, which they should cover).
+    pub fn read_postfix(path: &Path) -> io::Result<Config>
+    {
+        let contents = read_to_string(path)?;
+        let directory = path.parent().map(Path::to_path_buf).unwrap_or_default();
+
+        // Lines beginning with whitespace continue the previous line.
+        let mut lines: Vec<(usize, String)> = vec![];
+
+        for (index, line) in contents.lines().enumerate()
+        {
+            if line.trim().is_empty() || line.trim_start().starts_with('#')
+            {
+                continue;
+            }
+
+            match (line.starts_with([' ', '\t']), lines.last_mut())
+            {
+                (true, Some((_, previous))) => {
+                    previous.push(' ');
+                    previous.push_str(line.trim());
+                },
+                _ => lines.push((index, line.trim().to_string()))
+            }
+        }
+
+        let mut parameters: HashMap<String, Setting> = HashMap::new();
+
+        parameters.insert(String::from("config_directory"), (directory.display().to_string(), String::new()));
+
+        for (index, line) in lines
+        {
+            if let Some((name, value)) = line.split_once('=')
+            {
+                parameters.insert(name.trim().to_string(), (value.trim().to_string(), format!("{}:{}", path.display(), index + 1)));
+            }
+        }
+
+        let value = |name: &str| parameters.get(name)
+            .map(|(value, _)| expand_parameters(value, &parameters, 0))
+            .filter(|value| !value.is_empty() && value != "none");
+
+        let names: Vec<String> = value("myhostname").into_iter().collect();
+        let mut config = Config::default();
+
+        for settings in POSTFIX_SETTINGS
+        {
+            let (certificate, location) = match (value(settings.0), parameters.get(settings.0))
+            {
+                (Some(certificate), Some((_, location))) => (certificate, location),
+                _ => continue
+            };
+
+            config.services.push(service("postfix", location, &names, settings, certificate, value(settings.1)));
+        }
+
+        Ok(config)
+    }
+
+    /// Read dovecot.conf, and the files it includes, for the certificates
+    /// of the main server and its sections (e.g. local_name).
+    pub fn read_dovecot(path: &Path) -> io::Result<Config>
+    {
+        let contents = read_to_string(path)?;
+
+        let mut parser = DovecotParser {
+            directory: path.parent().map(Path::to_path_buf).unwrap_or_default(),
+            sections: vec![Section::default()],
+            open: vec![0],
+            problems: vec![],
+        };
+
+        parser.parse(path, &contents, 0);
+
+        Ok(parser.config())
+    }
+}
+
+/// The settings of dovecot.conf, or one of its sections.
+#[derive(Debug, Default)]
+struct Section
+{
+    parent: Option<usize>,
+
+    /// Names from a local_name section's header.
+    names: Vec<String>,
+
+    settings: HashMap<String, Setting>,
+}
+
+struct DovecotParser
+{
+    directory: PathBuf,
+
+    /// Sections in the order they were opened, the first being the main
+    /// configuration.
+    sections: Vec<Section>,
+
+    /// Indexes of the sections being read, innermost last.
+    open: Vec<usize>,
+
+    problems: Vec<(String, String)>,
+}
+
+impl DovecotParser
+{
+    fn parse(&mut self, path: &Path, contents: &str, depth: usize)
+    {
+        for (index, line) in contents.lines().enumerate()
+        {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            let location = format!("{}:{}", path.display(), index + 1);
+            let current = self.open.last().copied().unwrap_or_default();
+
+            if let Some(pattern) = line.strip_prefix("!include_try")
+            {
+                self.include(path, pattern.trim(), true, &location, depth);
+            }
+            else if let Some(pattern) = line.strip_prefix("!include")
+            {
+                self.include(path, pattern.trim(), false, &location, depth);
+            }
+            else if line == "}"
+            {
+                if self.open.len() > 1
+                {
+                    self.open.pop();
+                }
+            }
+            else if let Some(header) = line.strip_suffix('{')
+            {
+                let mut words = header.split_whitespace();
+
+                let names = match words.next()
+                {
+                    Some("local_name") => words.map(|word| word.trim_matches('"').to_string()).filter(|word| !word.is_empty()).collect(),
+                    _ => vec![]
+                };
+
+                self.sections.push(Section { parent: Some(current), names, settings: HashMap::new() });
+                self.open.push(self.sections.len() - 1);
+            }
+            else if let Some((name, value)) = line.split_once('=')
+            {
+                // "<path" reads a value from a file, as certificates are.
+                let value = value.trim().trim_start_matches('<').trim().to_string();
+
+                self.sections[current].settings.insert(name.trim().to_string(), (value, location));
+            }
+        }
+    }
+
+    /// Read the files an include matches, relative to the including file.
+    fn include(&mut self, path: &Path, pattern: &str, optional: bool, location: &str, depth: usize)
+    {
+        if depth >= MAX_DEPTH
+        {
+            self.problems.push((location.to_string(), format!("includes {} too deeply", pattern)));
+
+            return;
+        }
+
+        let directory = path.parent().unwrap_or(Path::new(""));
+        let files: Vec<PathBuf> = expand(&directory.join(pattern)).into_iter().filter(|file| file.is_file()).collect();
+
+        if files.is_empty() && !optional
+        {
+            self.problems.push((location.to_string(), format!("!include {} matches no files", pattern)));
+        }
+
+        for file in files
+        {
+            match fs::read_to_string(&file)
+            {
+                Ok(contents) => self.parse(&file, &contents, depth + 1),
+                Err(e) => self.problems.push((file.display().to_string(), format!("couldn't read: {}", e)))
+            }
+        }
+    }
+
+    /// A setting, as a section has it, or inherits it.
+    fn setting(&self, index: usize, name: &str) -> Option<&Setting>
+    {
+        let section = &self.sections[index];
+
+        section.settings.get(name).or_else(|| section.parent.and_then(|parent| self.setting(parent, name)))
+    }
+
+    /// A service for each certificate a section sets, unless TLS is
+    /// disabled for it (ssl = no).
+    fn config(self) -> Config
+    {
+        let mut config = Config { services: vec![], problems: self.problems.clone() };
+
+        for (index, section) in self.sections.iter().enumerate()
+        {
+            if self.setting(index, "ssl").map(|(value, _)| value == "no").unwrap_or(false)
+            {
+                continue;
+            }
+
+            for settings in DOVECOT_SETTINGS
+            {
+                let (certificate, location) = match section.settings.get(settings.0)
+                {
+                    Some(setting) => setting,
+                    None => continue
+                };
+
+                let key = self.setting(index, settings.1).map(|(key, _)| resolve(&self.directory, key));
+
+                config.services.push(service("dovecot", location, &section.names, settings, resolve(&self.directory, certificate), key));
+            }
+        }
+
+        config
+    }
+}
+
+/// A certificate a server ("postfix" or "dovecot") is configured with, by
+/// its (certificate, key) settings. Both read the key from the
+/// certificate's file when it isn't configured.
+fn service(server: &str, location: &str, names: &[String], settings: (&'static str, &'static str), certificate: String, key: Option<String>) -> Configured
+{
+    let label = match names.first()
+    {
+        Some(name) => format!("{} {} ({})", server, name, location),
+        None => format!("{} ({})", server, location)
+    };
+
+    Configured {
+        label,
+        settings: Settings { certificate: settings.0, key: settings.1, name: "" },
+        certificate,
+        key,
+        names: names.to_vec(),
+        ..Configured::default()
+    }
+}
+
+fn read_to_string(path: &Path) -> io::Result<String>
+{
+    fs::read_to_string(path).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))
+}
+
+fn resolve(directory: &Path, path: &str) -> String
+{
+    directory.join(path).display().to_string()
+}
+
+/// Expand $name, ${name}, and $(name) in a Postfix parameter's value, with
+/// the parameters set (others are left as they are).
+fn expand_parameters(value: &str, parameters: &HashMap<String, Setting>, depth: usize) -> String
+{
+    let mut expanded = String::new();
+    let mut rest = value;
+
+    while let Some(start) = rest.find('$')
+    {
+        expanded.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+
+        let (name, after) = match rest.chars().next()
+        {
+            Some(open @ ('{' | '(')) => match rest.find(if open == '{' { '}' } else { ')' })
+            {
+                Some(end) => (&rest[1..end], &rest[end + 1..]),
+                None => (&rest[1..], "")
+            },
+            _ => {
+                let end = rest.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(rest.len());
+
+                (&rest[..end], &rest[end..])
+            }
+        };
+
+        match parameters.get(name)
+        {
+            Some((value, _)) if depth < MAX_DEPTH => expanded.push_str(&expand_parameters(value, parameters, depth + 1)),
+            _ => {
+                expanded.push('$');
+                expanded.push_str(&rest[..rest.len() - after.len()]);
+            }
+        }
+
+        rest = after;
+    }
+
+    expanded.push_str(rest);
+
+    expanded
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+    use crate::configured;
+    use crate::Inventory;
+
+    #[test]
+    fn checks_mail_services()
+    {
+        let mut config = Config::read(Some("samples/mail/main.cf"), Some("samples/mail/dovecot.conf")).unwrap();
+
+        let labels: Vec<String> = config.services.iter().map(|service| service.label.clone()).collect();
+
+        assert_eq!(labels, vec![
+            "postfix mail.example.com (samples/mail/main.cf:4)",
+            "postfix mail.example.com (samples/mail/main.cf:8)",
+            "dovecot (samples/mail/conf.d/10-ssl.conf:2)",
+            "dovecot imap.example.com (samples/mail/conf.d/10-ssl.conf:7)",
+            "dovecot pop.example.com (samples/mail/conf.d/10-ssl.conf:11)",
+        ]);
+
+        assert_eq!(config.services[0].certificate, "samples/mail/ssl/fullchain.pem");
+        assert_eq!(config.services[3].key.as_deref(), Some("samples/mail/ssl/mail.example.com.key"));
+        assert_eq!(config.services[4].names, vec!["pop.example.com", "pop3.example.com"]);
+        assert!(config.problems.is_empty());
+
+        let mut inventory = Inventory::new().add_path("samples/mail/ssl/root.crt");
+
+        for path in configured::paths(&config.services)
+        {
+            inventory = inventory.add_path(&path);
+        }
+
+        let chains = inventory.build_chains().unwrap();
+
+        for service in config.services.iter_mut()
+        {
+            chains.iter().for_each(|chain| service.consider(chain));
+        }
+
+        let problems: Vec<Vec<String>> = config.services.iter().map(Configured::problems).collect();
+
+        assert!(problems[0].is_empty());
+        assert_eq!(problems[1], vec!["doesn't hold its intermediate samples/mail/ssl/fullchain.pem"]);
+        assert!(problems[2].is_empty());
+        assert_eq!(problems[3], vec!["ssl_key samples/mail/ssl/mail.example.com.key doesn't match a certificate in samples/mail/ssl/imap.example.com.crt"]);
+        assert_eq!(problems[4], vec![
+            "pop.example.com isn't covered by samples/mail/ssl/fullchain.pem",
+            "pop3.example.com isn't covered by samples/mail/ssl/fullchain.pem",
+        ]);
+    }
+
+    #[test]
+    fn expands_postfix_parameters()
+    {
+        let parameters: HashMap<String, Setting> = [("a", "x"), ("b", "$a/y"), ("loop", "$loop")].into_iter()
+            .map(|(name, value)| (name.to_string(), (value.to_string(), String::new())))
+            .collect();
+
+        assert_eq!(expand_parameters("${b}/$(a).pem", &parameters, 0), "x/y/x.pem");
+        assert_eq!(expand_parameters("$unset/z", &parameters, 0), "$unset/z");
+        assert_eq!(expand_parameters("$loop", &parameters, 0), "$loop");
+    }
+}
@@ -17,69 +17,1350 @@
 //! You should have received a copy of the GNU General Public License
 //! along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
 use std::process;
-use std::env;
+use std::time::Duration;
+
+#[cfg(feature = "aws")]
+use sslchains::aws;
+#[cfg(feature = "db")]
+use sslchains::database;
+use sslchains::configured::Configured;
+use sslchains::{alerts, apache, bundle, cache, chain, cleanup, connect, container, ct, export, fetch, haproxy, kubernetes, letsencrypt, mail, metrics, permissions, policy, rename, snapshot, ssh};
+
+/// How long changes must stop for before files are read again (--watch).
+#[cfg(target_os = "linux")]
+const WATCH_SETTLE: Duration = Duration::from_millis(500);
 
 mod arguments;
-mod chain;
+mod daemon;
 mod display;
-mod keys;
 mod options;
+mod status;
+mod template;
 
 fn main()
 {
     // Get command line options.
     let options = options::Options::new();
 
-    // Print help and exit.
-    if options.print_help
+    // Log to stderr according to --log-level, unless RUST_LOG says otherwise.
+    env_logger::Builder::new()
+        .filter_level(options.log_level)
+        .parse_default_env()
+        .format_timestamp(None)
+        .init();
+
+    // Split a bundle (--split), which needs nothing else.
+    if let Some((path, directory)) = &options.split
+    {
+        split(path, directory);
+        return;
+    }
+
+    // Check custom output templates before doing any work.
+    let template = match &options.display_mode
+    {
+        options::OptionsDisplayMode::Format(format) => match template::Template::parse(format)
+        {
+            Ok(t) => Some(t),
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(status::ERROR);
+            }
+        },
+        _ => None
+    };
+
+    // Read the policy (--policy) before doing any work, too.
+    let policy = match &options.policy
+    {
+        Some(path) => match policy::Policy::load(path)
+        {
+            Ok(p) => Some(p),
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(status::ERROR);
+            }
+        },
+        None => None
+    };
+
+    // TLS servers to connect to (--connect, --connect-file).
+    let endpoints = match arguments::endpoints(&options)
+    {
+        Ok(endpoints) => endpoints,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(status::ERROR);
+        }
+    };
+
+    #[cfg(not(unix))]
+    if options.syslog
+    {
+        eprintln!("--syslog is only supported on Unix");
+        process::exit(status::USAGE);
+    }
+
+    #[cfg(unix)]
+    if options.syslog
+    {
+        sslchains::syslog::open();
+    }
+
+    // Rescan periodically, raising alerts for changes, until interrupted.
+    if options.daemon
+    {
+        daemon::run(&options);
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    if options.watch
+    {
+        eprintln!("--watch is only supported on Linux");
+        process::exit(status::USAGE);
+    }
+
+    #[cfg(not(feature = "db"))]
+    if options.db.is_some() || options.db_expiring.is_some()
+    {
+        eprintln!("--db and sslchains db need sslchains built with the db feature");
+        process::exit(status::USAGE);
+    }
+
+    // Query the database (sslchains db expiring), rather than scanning.
+    #[cfg(feature = "db")]
+    if let Some(days) = options.db_expiring
+    {
+        db_expiring(&options, days);
+        return;
+    }
+
+    #[cfg(not(feature = "aws"))]
+    if options.acm || !options.aws_secrets.is_empty() || options.aws_region.is_some()
     {
-        help();
+        eprintln!("--acm, --aws-secret, and --aws-region need sslchains built with the aws feature");
+        process::exit(status::USAGE);
     }
 
     // Get command line arguments.
-    let args = match arguments::process(&options)
+    let mut warnings = vec![];
+
+    let expanded = arguments::Arguments::read(&options)
+        .and_then(|arguments| arguments.expand(&options, &mut warnings).map(|args| (arguments, args)));
+
+    let (arguments, args) = match expanded
     {
-        Ok(a) => a,
+        Ok(expanded) => expanded,
         Err(e) => {
             eprintln!("{}", e.to_string());
-            process::exit(1);
+            process::exit(status::ERROR);
         }
     };
 
     // Sort expanded arguments.
     //args.sort();
 
-    // Build chains from the arguments.
-    let chains = match chain::build(args)
+    // List the files which would be processed, without processing them.
+    if options.list_files
+    {
+        display::files(&args, &options);
+
+        if options.warnings || options.verbose
+        {
+            display::warnings(&warnings, &options);
+        }
+
+        return;
+    }
+
+    // Read and parse each file once.
+    let mut inventory = match &options.cache
     {
-        Ok(c) => c,
+        Some(directory) => chain::Inventory::ingest_cached(&args, cache::Cache::open(directory)),
+        None => chain::Inventory::ingest(&args)
+    };
+
+    // Add what isn't read from files: Kubernetes Secrets, containers'
+    // filesystems, and AWS, noting what refers to the Secrets.
+    let (references, kubernetes_problems) = match add_sources(&mut inventory, &options)
+    {
+        Ok(added) => added,
         Err(e) => {
-            eprintln!("{}", e.to_string());
-            process::exit(2);
+            eprintln!("{}", e);
+            process::exit(status::ERROR);
         }
     };
 
+    // Repair a bundle (--fix-bundle) with the certificates read, rather than
+    // displaying chains.
+    if let Some(path) = &options.fix_bundle
+    {
+        fix_bundle(path, &inventory, &options);
+        return;
+    }
+
+    // Suggest (or, with --apply, make) consistent names for chains' files
+    // (--suggest-names), rather than displaying chains.
+    if options.suggest_names
+    {
+        suggest_names(&inventory, &options);
+        return;
+    }
+
+    // Write a script removing superseded, duplicate, and orphaned files
+    // (--cleanup-plan), rather than displaying chains.
+    if options.cleanup_plan
+    {
+        // Only the chains selected (--host and --name), if any are.
+        let selects = |chain: &chain::Chain| options.selects(chain);
+        let selects = (options.host.is_some() || options.name.is_some()).then_some(&selects as &dyn Fn(&chain::Chain) -> bool);
+
+        match inventory.build_chains()
+        {
+            Ok(chains) => print!("{}", cleanup::script(&cleanup::plan(&inventory, &chains, selects))),
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(status::ERROR);
+            }
+        }
+
+        return;
+    }
+
+    // What changed since an earlier snapshot (--load-snapshot), before this
+    // scan's is saved (--save-snapshot), perhaps over it.
+    let drift = snapshot(&inventory, &options);
+
+    // Chains to record in the database (--db), as they're built, before
+    // they're filtered.
+    #[cfg(feature = "db")]
+    let mut recorded = vec![];
+
+    // Chains covering the requested hostname (--host), and matching the
+    // name pattern (--name), or all chains.
+    let selected = |chain: &chain::Chain| options.selects(chain);
+
+    // Complete chains with signing certificates fetched from caIssuers URLs
+    // (--fetch-missing), returning what was fetched, and (path, reason)
+    // pairs for what couldn't be. Chains served by TLS servers are left as
+    // served.
+    let fetch_missing = |chain: &mut chain::Chain| match chain.certificates.first_mut()
+    {
+        Some(certificate) if options.fetch_missing && chain.endpoint.is_none() => {
+            fetch::complete(certificate, options.save_fetched, Duration::from_secs(options.timeout))
+        },
+        _ => (vec![], vec![])
+    };
+
+    // Most severe certificate expiry among displayed chains (--expiry-warn,
+    // or --fail-on expiring or expired).
+    let expiry_of = |chain: &chain::Chain| {
+        let failing = options.fail_on.iter().any(|condition| matches!(condition, status::Condition::Expiring | status::Condition::Expired));
+        let days = options.expiry_warn.or(failing.then_some(alerts::DEFAULT_WARN_DAYS));
+
+        days.filter(|_| chain.origin().is_some()).and_then(|days| chain.expiry(days))
+    };
+
+    // Problems with a chain's private key file permissions, as a (path,
+    // issues) pair, if there are any.
+    let key_permissions = |chain: &chain::Chain| {
+        chain.key.as_ref()
+            .map(|key| (key.path.clone(), permissions::check(&key.path, &options.key_owners)))
+            .filter(|(_, issues)| !issues.is_empty())
+    };
+
+    // What a chain's request asked for, but its certificate doesn't cover,
+    // as a (certificate path, request path, mismatches) tuple.
+    let request_mismatches = |chain: &chain::Chain| match (&chain.request, chain.certificates.first())
+    {
+        (Some(request), Some(certificate)) => {
+            let mismatches = chain.request_mismatches();
+
+            (!mismatches.is_empty()).then(|| (certificate.path.clone(), request.path.clone(), mismatches))
+        },
+        _ => None
+    };
+
+    // Corrupted or tampered keys and malformed signatures (--check-keys), and
+    // usage constraint problems (--check-usage), as (path, problem) pairs.
+    let problems_of = |chain: &chain::Chain| {
+        let mut problems = vec![];
+
+        if options.check_keys
+        {
+            problems.extend(chain.check_keys().into_iter().map(|(path, e)| (path, e.to_string())));
+        }
+
+        if options.check_usage
+        {
+            problems.extend(chain.usage_problems());
+        }
+
+        problems
+    };
+
+    // Serial numbers (with their issuers) of every certificate scanned, which
+    // aren't unknown to Certificate Transparency lookups (--ct).
+    let known_certificates: HashSet<(String, String)> = match options.ct
+    {
+        Some(_) => inventory.certificates.iter()
+            .filter_map(|record| record.load())
+            .filter_map(|certificate| ct::identify(&certificate))
+            .collect(),
+        None => HashSet::new()
+    };
+
+    // What Certificate Transparency logs say about a chain's current
+    // certificate (--ct), as a (path, transparency) pair, or a (path,
+    // reason) pair if they couldn't be searched. Self-signed certificates
+    // are never logged, so aren't looked up.
+    let transparency_of = |chain: &chain::Chain| match (&options.ct, chain.certificates.first())
+    {
+        (Some(frontend), Some(certificate)) if !certificate.self_signed => {
+            let timeout = Duration::from_secs(options.timeout);
+
+            Some(match ct::check(certificate, frontend, &known_certificates, timeout)
+            {
+                Ok(transparency) => Ok((certificate.path.clone(), transparency)),
+                Err(reason) => Err((certificate.path.clone(), format!("couldn't search Certificate Transparency logs: {}", reason)))
+            })
+        },
+        _ => None
+    };
+
+    // Policy violations (--policy), as (path, violation) pairs.
+    let violations_of = |chain: &chain::Chain| match &policy
+    {
+        Some(policy) => policy.check(chain),
+        None => vec![]
+    };
+
+    // Exit status for conditions found in displayed chains (see --fail-on),
+    // after noting policy violations, and lax key permissions.
+    let status = |mut findings: status::Findings, violated: bool, insecure: bool| {
+        if violated
+        {
+            findings.add(status::Condition::Policy);
+        }
+
+        if insecure
+        {
+            findings.add(status::Condition::Permissions);
+        }
+
+        findings.status(&options.fail_on)
+    };
+
+    // Certificates presented by TLS servers (--connect), to be matched
+    // against displayed chains, and (endpoint, reason) pairs for servers
+    // which couldn't be reached. Each endpoint is displayed as a chain of
+    // its own, holding what it presented, or why it couldn't be reached.
+    let mut served = vec![];
+    let mut unreachable = vec![];
+    let mut served_chains: Vec<chain::Chain> = vec![];
+
+    for (endpoint, result) in endpoints.iter().zip(connect::fetch_all(&endpoints, options.concurrency, Duration::from_secs(options.timeout)))
+    {
+        match result
+        {
+            Ok(s) => {
+                served_chains.push(s.chain());
+                served.push(s);
+            },
+            Err(e) => {
+                let chain = connect::unreachable(endpoint, &e);
+
+                unreachable.push((endpoint.to_string(), chain.unreachable.clone().unwrap_or_default()));
+                served_chains.push(chain);
+            }
+        }
+    }
+
+    // Communicate results purely via the exit status.
+    if options.quiet
+    {
+        let mut chains = vec![];
+
+        let built = chain::build_each(&inventory, |chain| {
+            #[cfg(feature = "db")]
+            recorded.extend(options.db.as_ref().and_then(|_| database::ChainRow::new(&chain)));
+
+            if selected(&chain)
+            {
+                chains.push(chain);
+            }
+        });
+
+        match built
+        {
+            Ok(()) => {
+                #[cfg(feature = "db")]
+                record_scan(&options, &inventory, &recorded);
+
+                let mut findings = status::Findings::new();
+
+                findings.skipped(&inventory.skipped);
+                chains.iter().for_each(|chain| findings.chain(chain, expiry_of(chain)));
+                served_chains.iter().filter(|chain| chain.unreachable.is_some()).for_each(|chain| findings.chain(chain, None));
+
+                let status = status(
+                    findings,
+                    chains.iter().any(|chain| !violations_of(chain).is_empty()),
+                    chains.iter().any(|chain| key_permissions(chain).is_some())
+                );
+
+                let mut metrics = metrics::Metrics::new();
+
+                for chain in chains.iter_mut()
+                {
+                    display::rewrite_paths(chain, &options);
+                    metrics.add(chain);
+                }
+
+                write_metrics(&metrics, &options);
+                process::exit(status);
+            },
+            Err(_) => process::exit(status::ERROR)
+        }
+    }
+
+    // Problems with the Let's Encrypt layout (--letsencrypt), e.g. broken
+    // live links, to be reported with those found in chains.
+    let layout_problems = arguments.layout.as_ref().map(|layout| layout.problems.clone()).unwrap_or_default();
+
+    // TLS virtual hosts configured in Apache (--apache), to be matched
+    // against chains, and problems reading the configuration.
+    let (sites, apache_problems) = options.apache.as_ref()
+        .and_then(|config| apache::Config::read(Path::new(config)).ok())
+        .map(|config| (config.sites, config.problems))
+        .unwrap_or_default();
+
+    // Certificates HAProxy's bind lines load (--haproxy), likewise.
+    let (bindings, haproxy_problems) = options.haproxy.as_ref()
+        .and_then(|config| haproxy::Config::read(Path::new(config)).ok())
+        .map(|config| (config.bindings, config.problems))
+        .unwrap_or_default();
+
+    // And the certificates mail servers are configured with (--postfix and
+    // --dovecot).
+    let (services, mail_problems) = mail::Config::read(options.postfix.as_deref(), options.dovecot.as_deref()).ok()
+        .map(|config| (config.services, config.problems))
+        .unwrap_or_default();
+
+    // Each to be matched against the chain its certificate belongs to, with
+    // what refers to Kubernetes Secrets (--kubeconfig).
+    let mut configured = [sites, bindings, services, references].concat();
+
+    // Files each complete chain is exported as (e.g. --export-fullchain).
+    let mut exporters = exporters(&options);
+
+    // Stream chains as soon as each is built.
+    if matches!(options.display_mode, options::OptionsDisplayMode::NdJson)
+    {
+        let mut findings = status::Findings::new();
+        let mut insecure_keys = vec![];
+
+        findings.skipped(&inventory.skipped);
+        let mut problems = vec![];
+        let mut mismatches = vec![];
+        let mut fetched = vec![];
+        let mut transparency = vec![];
+        let mut violations = vec![];
+        let mut metrics = metrics::Metrics::new();
+
+        problems.extend(unreachable);
+        problems.extend(layout_problems);
+        problems.extend(apache_problems);
+        problems.extend(haproxy_problems);
+        problems.extend(mail_problems);
+        problems.extend(kubernetes_problems);
+
+        let mut display_chain = |mut chain| {
+            // What's configured is matched against every chain, as a chain
+            // filtered out is still the one it uses.
+            served.iter_mut().for_each(|served| served.consider(&chain));
+            configured.iter_mut().for_each(|configured| configured.consider(&chain));
+
+            if !selected(&chain)
+            {
+                return;
+            }
+
+            let (fetched_issuers, failures) = fetch_missing(&mut chain);
+
+            fetched.extend(fetched_issuers);
+            problems.extend(failures);
+
+            findings.chain(&chain, expiry_of(&chain));
+            insecure_keys.extend(key_permissions(&chain));
+            problems.extend(problems_of(&chain));
+            mismatches.extend(request_mismatches(&chain));
+            violations.extend(violations_of(&chain));
+
+            match transparency_of(&chain)
+            {
+                Some(Ok(logs)) => transparency.push(logs),
+                Some(Err(failure)) => problems.push(failure),
+                None => ()
+            }
+
+            exporters.iter_mut().for_each(|exporter| exporter.export(&chain));
+
+            display::rewrite_paths(&mut chain, &options);
+            metrics.add(&chain);
+            display::ndjson(chain, &options);
+        };
+
+        let built = chain::build_each(&inventory, |chain| {
+            #[cfg(feature = "db")]
+            recorded.extend(options.db.as_ref().and_then(|_| database::ChainRow::new(&chain)));
+
+            display_chain(chain);
+        });
+
+        if let Err(e) = built
+        {
+            eprintln!("{}", e);
+            process::exit(status::ERROR);
+        }
+
+        #[cfg(feature = "db")]
+        record_scan(&options, &inventory, &recorded);
+
+        served_chains.into_iter().for_each(display_chain);
+        problems.extend(exporters.iter().flat_map(|exporter| exporter.problems.clone()));
+
+        if options.show_skipped
+        {
+            display::skipped(&inventory.skipped, &options);
+        }
+
+        display::fetched(&fetched, &options);
+        display::transparency(&transparency, &options);
+        display::served(&served, &options);
+        display::configured(&configured, &options);
+        display::exported(&exporters, &options);
+        display::request_mismatches(&mismatches, &options);
+        display::key_permissions(&insecure_keys, &options);
+        display::problems(&problems, &options);
+        display::drift(&drift, &options);
+        display::policy_violations(&violations, &options);
+
+        if options.warnings || options.verbose
+        {
+            display::warnings(&warnings, &options);
+        }
+
+        write_metrics(&metrics, &options);
+
+        #[cfg(target_os = "linux")]
+        if options.watch
+        {
+            let _ = io::stdout().flush();
+
+            watch(inventory, &arguments, args, &options, template.as_ref(), &selected);
+        }
+
+        exit_with(status(findings, !violations.is_empty(), !insecure_keys.is_empty()));
+
+        return;
+    }
+
+    // Build chains from the arguments.
+    let mut chains = vec![];
+
+    if let Err(e) = chain::build_each(&inventory, |chain| chains.push(chain))
+    {
+        eprintln!("{}", e);
+        process::exit(status::ERROR);
+    }
+
+    #[cfg(feature = "db")]
+    if options.db.is_some()
+    {
+        recorded.extend(chains.iter().filter_map(database::ChainRow::new));
+        record_scan(&options, &inventory, &recorded);
+    }
+
+    // Find orphans (for the human-readable display modes only) before the
+    // chains are consumed for display, or filtered.
+    let orphans = match options.display_mode
+    {
+        options::OptionsDisplayMode::Default | options::OptionsDisplayMode::OneLine if options.orphans => {
+            Some(chain::find_orphans(&inventory, &chains))
+        },
+        _ => None
+    };
+
+    // Inventory OpenSSH keys and certificates (for the human-readable
+    // display modes only, too).
+    let ssh_inventory = match (&options.ssh, &options.display_mode)
+    {
+        (Some(directory), options::OptionsDisplayMode::Default | options::OptionsDisplayMode::OneLine) => {
+            match ssh::Inventory::scan(Path::new(directory))
+            {
+                Ok(ssh_inventory) => Some(ssh_inventory),
+                Err(e) => {
+                    eprintln!("Couldn't read {}: {}", directory, e);
+                    process::exit(status::ERROR);
+                }
+            }
+        },
+        _ => None
+    };
+
+    chains.extend(served_chains);
+
+    // What's configured is matched against every chain, as a chain filtered
+    // out is still the one it uses.
+    for chain in &chains
+    {
+        served.iter_mut().for_each(|served| served.consider(chain));
+        configured.iter_mut().for_each(|configured| configured.consider(chain));
+    }
+
+    chains.retain(selected);
+
+    let mut fetched = vec![];
+    let mut problems = unreachable;
+
+    problems.extend(layout_problems);
+    problems.extend(apache_problems);
+    problems.extend(haproxy_problems);
+    problems.extend(mail_problems);
+    problems.extend(kubernetes_problems);
+
+    for chain in chains.iter_mut()
+    {
+        let (fetched_issuers, failures) = fetch_missing(chain);
+
+        fetched.extend(fetched_issuers);
+        problems.extend(failures);
+    }
+
+    let mut findings = status::Findings::new();
+
+    findings.skipped(&inventory.skipped);
+    chains.iter().for_each(|chain| findings.chain(chain, expiry_of(chain)));
+
+    let insecure_keys: Vec<(String, Vec<String>)> = chains.iter().filter_map(key_permissions).collect();
+    problems.extend(chains.iter().flat_map(problems_of));
+    let mismatches: Vec<_> = chains.iter().filter_map(request_mismatches).collect();
+    let violations: Vec<_> = chains.iter().flat_map(violations_of).collect();
+    let mut transparency = vec![];
+
+    for logs in chains.iter().filter_map(transparency_of)
+    {
+        match logs
+        {
+            Ok(logs) => transparency.push(logs),
+            Err(failure) => problems.push(failure)
+        }
+    }
+
+    for chain in &chains
+    {
+        exporters.iter_mut().for_each(|exporter| exporter.export(chain));
+    }
+
+    problems.extend(exporters.iter().flat_map(|exporter| exporter.problems.clone()));
+
+    let mut metrics = metrics::Metrics::new();
+
+    for chain in chains.iter_mut()
+    {
+        display::rewrite_paths(chain, &options);
+        metrics.add(chain);
+    }
+
     // Display output.
+    display_chains(chains, &options, template.as_ref(), arguments.layout.as_ref());
+
+    if let Some(orphans) = orphans
+    {
+        display::orphans(&orphans, &options);
+    }
+
+    if let Some(ssh_inventory) = ssh_inventory
+    {
+        display::ssh(&ssh_inventory, &options);
+    }
+
+    if options.show_skipped
+    {
+        display::skipped(&inventory.skipped, &options);
+    }
+
+    display::fetched(&fetched, &options);
+    display::transparency(&transparency, &options);
+    display::served(&served, &options);
+    display::configured(&configured, &options);
+    display::exported(&exporters, &options);
+    display::request_mismatches(&mismatches, &options);
+    display::key_permissions(&insecure_keys, &options);
+    display::problems(&problems, &options);
+    display::drift(&drift, &options);
+    display::policy_violations(&violations, &options);
+
+    if options.warnings || options.verbose
+    {
+        display::warnings(&warnings, &options);
+    }
+
+    write_metrics(&metrics, &options);
+
+    #[cfg(target_os = "linux")]
+    if options.watch
+    {
+        watch(inventory, &arguments, args, &options, template.as_ref(), &selected);
+    }
+
+    exit_with(status(findings, !violations.is_empty(), !insecure_keys.is_empty()));
+}
+
+/// Display chains in the selected display mode.
+fn display_chains(chains: Vec<chain::Chain>, options: &options::Options, template: Option<&template::Template>, layout: Option<&letsencrypt::Layout>)
+{
     match options.display_mode
     {
-        options::OptionsDisplayMode::OneLine => display::oneline(chains),
-        _ => display::default(chains)
+        options::OptionsDisplayMode::OneLine => display::oneline(chains, options),
+        options::OptionsDisplayMode::Csv => display::csv(chains, options),
+        options::OptionsDisplayMode::Tsv => display::tsv(chains, options),
+        options::OptionsDisplayMode::NdJson => chains.into_iter().for_each(|chain| display::ndjson(chain, options)),
+        options::OptionsDisplayMode::Print0 => display::print0(chains),
+        options::OptionsDisplayMode::Format(_) => template::display(chains, template.unwrap()),
+        _ if options.group_by_dir || options.letsencrypt.is_some() || options.kubeconfig.is_some() || !options.containers.is_empty() || options.acm => display::grouped(chains, options, layout),
+        _ => display::default(chains, options)
     }
 }
 
-fn help()
+/// Add what isn't read from files, as each scan does: the TLS Secrets of a
+/// Kubernetes cluster (--kubeconfig), named by namespace and Secret; the
+/// keys and certificates in containers' filesystems (--container), named by
+/// container and path; and certificates from AWS Certificate Manager
+/// (--acm), and secrets from Secrets Manager (--aws-secret), named by region
+/// and ID. Returns what refers to the Secrets, and problems with them (e.g.
+/// Secrets nothing refers to), or why a source couldn't be read.
+pub fn add_sources(inventory: &mut chain::Inventory, options: &options::Options) -> Result<(Vec<Configured>, kubernetes::Problems), String>
 {
-    println!("\nUsage");
-    println!("\t{} [-hlL] [path [...]]", env::current_exe().unwrap().to_str().unwrap());
-    println!("\t\t-h\tPrint this help menu.");
-    println!("\t\t-H\tProcess hidden files and directories.");
-    println!("\t\t-l\tOutput each chain as a row of values.");
-    println!("\t\t-L\tOutput each chain as a row of values (header excluded).");
-    println!("\t\t-r\tProcess arguments recursively.");
-    println!("\t\t-S\tFollow symbolic links.");
-    println!("\t\t-U\tProcess an unlimited number of file paths.");
-    println!("\t\t-X\tCross filesystem boundaries.");
-    process::exit(3);
+    let mut references = vec![];
+    let mut problems = vec![];
+
+    if let Some(kubeconfig) = &options.kubeconfig
+    {
+        let timeout = Duration::from_secs(options.timeout);
+
+        let (cluster, secrets) = kubernetes::Cluster::from_kubeconfig(Path::new(kubeconfig), options.kube_context.as_deref())
+            .and_then(|cluster| cluster.tls_secrets(timeout).map(|secrets| (cluster, secrets)))
+            .map_err(|e| format!("Couldn't list Kubernetes Secrets: {}", e))?;
+
+        for secret in &secrets
+        {
+            for (field, contents) in &secret.fields
+            {
+                inventory.add_bytes(&secret.path(field), contents);
+            }
+        }
+
+        match cluster.references(timeout)
+        {
+            Ok((listed, forbidden)) => {
+                references = listed;
+
+                let unreferenced = kubernetes::correlate(&mut references, &secrets);
+
+                // Secrets may be referred to by what couldn't be listed.
+                problems = if forbidden.is_empty() { unreferenced } else { forbidden };
+            },
+            Err(e) => problems.push((cluster.server.clone(), format!("couldn't list Ingresses, Gateways, and Certificates: {}", e)))
+        }
+    }
+
+    for name in &options.containers
+    {
+        let files = container::Container::inspect(name).and_then(|container| container.files(options.max_file_size))
+            .map_err(|e| format!("Couldn't read container {}", e))?;
+
+        files.iter().for_each(|(name, contents)| inventory.add_bytes(name, contents));
+    }
+
+    #[cfg(feature = "aws")]
+    if options.acm || !options.aws_secrets.is_empty()
+    {
+        add_aws(inventory, options)?;
+    }
+
+    Ok((references, problems))
+}
+
+/// Add certificates and secrets from AWS (--acm and --aws-secret). ACM
+/// certificates which can't be read (e.g. those pending validation) are
+/// reported and skipped.
+#[cfg(feature = "aws")]
+fn add_aws(inventory: &mut chain::Inventory, options: &options::Options) -> Result<(), String>
+{
+    let timeout = Duration::from_secs(options.timeout);
+    let client = aws::Client::new(options.aws_region.as_deref()).map_err(|e| format!("Couldn't read from AWS: {}", e))?;
+
+    if options.acm
+    {
+        let (certificates, failures) = client.acm_certificates(timeout).map_err(|e| format!("Couldn't list ACM certificates: {}", e))?;
+
+        for (name, contents) in certificates
+        {
+            inventory.add_bytes(&name, &contents);
+        }
+
+        for (arn, reason) in failures
+        {
+            eprintln!("{}: {}", arn, reason);
+        }
+    }
+
+    for id in &options.aws_secrets
+    {
+        let fields = client.secret(id, timeout).map_err(|e| format!("Couldn't read AWS secret {}", e))?;
+
+        fields.iter().for_each(|(name, contents)| inventory.add_bytes(name, contents));
+    }
+
+    Ok(())
+}
+
+/// Keep watching the files processed, and the directories holding them, for
+/// changes (--watch). Changed files are read again, new files are added, and
+/// removed files are forgotten, before the chains they're part of (or were,
+/// before the change) are displayed again. Runs until interrupted.
+#[cfg(target_os = "linux")]
+fn watch(
+    mut inventory: chain::Inventory,
+    arguments: &arguments::Arguments,
+    mut paths: Vec<String>,
+    options: &options::Options,
+    template: Option<&template::Template>,
+    selected: &dyn Fn(&chain::Chain) -> bool)
+{
+    use std::collections::HashMap;
+    use sslchains::watch::Watcher;
+
+    let mut watcher = match Watcher::new()
+    {
+        Ok(w) => w,
+        Err(e) => {
+            eprintln!("Couldn't watch for changes: {}", e);
+            process::exit(status::ERROR);
+        }
+    };
+
+    // Chains (by their key's path, or their only file's) of each path, so
+    // chains whose files are changed or removed are found again.
+    let owners = |inventory: &chain::Inventory| {
+        let mut owners: HashMap<String, String> = HashMap::new();
+
+        let _ = chain::build_each(inventory, |chain| {
+            let paths = chain.paths();
+
+            if let Some(owner) = paths.first().map(|path| path.to_string())
+            {
+                owners.extend(paths.into_iter().map(|path| (path.to_string(), owner.clone())));
+            }
+        });
+
+        owners
+    };
+
+    // Findings are logged (with --syslog) as alerts for changes between
+    // snapshots of the selected chains.
+    let snapshot = |inventory: &chain::Inventory| {
+        let mut chains = vec![];
+
+        let _ = chain::build_each(inventory, |chain| {
+            if selected(&chain)
+            {
+                chains.push(chain);
+            }
+        });
+
+        alerts::Snapshot::new(inventory, &chains, options.expiry_warn.unwrap_or(alerts::DEFAULT_WARN_DAYS))
+    };
+
+    let mut last_snapshot = None;
+
+    if options.syslog
+    {
+        let current = snapshot(&inventory);
+
+        daemon::log(&current.alerts(None), options);
+        last_snapshot = Some(current);
+    }
+
+    let mut last_owners = owners(&inventory);
+    let mut layout = None;
+    let mut unwatchable = HashSet::new();
+
+    loop
+    {
+        for directory in arguments.directories(&paths, options)
+        {
+            if let Err(e) = watcher.watch(Path::new(&directory))
+            {
+                if unwatchable.insert(directory.clone())
+                {
+                    eprintln!("Warning: couldn't watch {}: {}", display::rewrite_path(&directory, options), e);
+                }
+            }
+        }
+
+        let changes = match watcher.wait(WATCH_SETTLE)
+        {
+            Ok(changes) => changes,
+            Err(e) => {
+                eprintln!("Couldn't watch for changes: {}", e);
+                process::exit(status::ERROR);
+            }
+        };
+
+        let changes: Vec<&Path> = changes.iter().map(|path| path.as_path()).collect();
+
+        // Expand the arguments again where they changed, to find new and
+        // removed files.
+        let mut warnings = vec![];
+
+        let expanded = match arguments.refresh(&paths, &changes, options, &mut warnings)
+        {
+            Ok(expanded) => expanded,
+            Err(e) => {
+                eprintln!("Warning: couldn't expand arguments again: {}", e);
+                continue;
+            }
+        };
+
+        let known: HashSet<&str> = paths.iter().map(String::as_str).collect();
+        let current: HashSet<&str> = expanded.iter().map(String::as_str).collect();
+
+        let changed: Vec<String> = expanded.iter()
+            .filter(|path| !known.contains(path.as_str()) || arguments::is_changed(path, &changes))
+            .cloned()
+            .collect();
+
+        let removed: Vec<String> = paths.iter().filter(|path| !current.contains(path.as_str())).cloned().collect();
+
+        if changed.is_empty() && removed.is_empty()
+        {
+            continue;
+        }
+
+        for path in &changed
+        {
+            eprintln!("Changed: {}", display::rewrite_path(path, options));
+        }
+
+        for path in &removed
+        {
+            eprintln!("Removed: {}", display::rewrite_path(path, options));
+        }
+
+        inventory.rescan(&changed, &removed);
+
+        // Renewals add versions to the Let's Encrypt layout, and move its
+        // live links.
+        if let Some(directory) = options.letsencrypt.as_deref().filter(|directory| changes.iter().any(|change| change.starts_with(directory)))
+        {
+            layout = letsencrypt::Layout::scan(Path::new(directory)).ok().or(layout);
+        }
+
+        let touched: HashSet<&str> = changed.iter().chain(&removed)
+            .flat_map(|path| [Some(path.as_str()), last_owners.get(path).map(String::as_str)])
+            .flatten()
+            .collect();
+
+        let mut chains = vec![];
+
+        let built = chain::build_each(&inventory, |mut chain| {
+            if selected(&chain) && chain.paths().iter().any(|path| touched.contains(path))
+            {
+                display::rewrite_paths(&mut chain, options);
+                chains.push(chain);
+            }
+        });
+
+        if let Err(e) = built
+        {
+            eprintln!("{}", e);
+            continue;
+        }
+
+        display_chains(chains, options, template, layout.as_ref().or(arguments.layout.as_ref()));
+
+        if options.warnings || options.verbose
+        {
+            display::warnings(&warnings, options);
+        }
+
+        let _ = io::stdout().flush();
+
+        if let Some(last) = &last_snapshot
+        {
+            let current = snapshot(&inventory);
+
+            daemon::log(&current.alerts(Some(last)), options);
+            last_snapshot = Some(current);
+        }
+
+        last_owners = owners(&inventory);
+        paths = expanded;
+    }
+}
+
+/// Repair a bundle (--fix-bundle), writing it to standard output, or
+/// replacing the file (--in-place) if it needs changing, and reporting what
+/// was wrong with it.
+fn fix_bundle(path: &str, inventory: &chain::Inventory, options: &options::Options)
+{
+    let repair = match inventory.contents(path).map_err(|e| e.to_string()).and_then(|contents| bundle::repair(&contents, inventory))
+    {
+        Ok(repair) => repair,
+        Err(e) => {
+            eprintln!("Couldn't repair {}: {}", path, e);
+            process::exit(status::ERROR);
+        }
+    };
+
+    let problems: Vec<(String, String)> = repair.problems.iter().map(|problem| (path.to_string(), problem.clone())).collect();
+
+    display::problems(&problems, options);
+
+    if !options.in_place
+    {
+        let _ = io::stdout().write_all(&repair.contents);
+        return;
+    }
+
+    let original = match fs::read(path)
+    {
+        Ok(original) => original,
+        Err(e) => {
+            eprintln!("Couldn't read {}: {}", path, e);
+            process::exit(status::ERROR);
+        }
+    };
+
+    if problems.is_empty() || original == repair.contents
+    {
+        eprintln!("{} needs no changes", path);
+        return;
+    }
+
+    match backup_file(path, &original).and_then(|backup| replace_file(path, &repair.contents).map(|_| backup))
+    {
+        Ok(backup) => eprintln!("Repaired {} (the original is {})", path, backup),
+        Err(e) => {
+            eprintln!("Couldn't replace {}: {}", path, e);
+            process::exit(status::ERROR);
+        }
+    }
+}
+
+/// Keep a file's contents in a new file beside it, with its permissions:
+/// PATH.bak, or PATH.bak.1, PATH.bak.2, and so on, if those exist. Returns
+/// the backup's path.
+fn backup_file(path: &str, contents: &[u8]) -> io::Result<String>
+{
+    let permissions = fs::metadata(path)?.permissions();
+    let mut index = 0;
+
+    loop
+    {
+        let backup = match index
+        {
+            0 => format!("{}.bak", path),
+            index => format!("{}.bak.{}", path, index)
+        };
+
+        match fs::OpenOptions::new().write(true).create_new(true).open(&backup)
+        {
+            Ok(mut file) => {
+                file.set_permissions(permissions)?;
+                file.write_all(contents)?;
+
+                return Ok(backup);
+            },
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => index += 1,
+            Err(e) => return Err(e)
+        }
+    }
+}
+
+/// Replace a file's contents, keeping its permissions, by writing a new
+/// file beside it and renaming it over the file, so the file is never left
+/// partly written.
+fn replace_file(path: &str, contents: &[u8]) -> io::Result<()>
+{
+    let permissions = fs::metadata(path)?.permissions();
+    let temporary = format!("{}.{}.tmp", path, process::id());
+
+    let replaced = fs::OpenOptions::new().write(true).create_new(true).open(&temporary)
+        .and_then(|mut file| {
+            file.set_permissions(permissions)?;
+            file.write_all(contents)?;
+            file.sync_all()
+        })
+        .and_then(|_| fs::rename(&temporary, path));
+
+    if replaced.is_err()
+    {
+        let _ = fs::remove_file(&temporary);
+    }
+
+    replaced
+}
+
+/// Split a bundle (--split) into a file per block in a directory, listing
+/// the files written, and leaving existing files alone.
+fn split(path: &str, directory: &str)
+{
+    let split = match chain::get_file_contents(path).map_err(|e| e.to_string()).and_then(|contents| bundle::split(&contents))
+    {
+        Ok(split) => split,
+        Err(e) => {
+            eprintln!("Couldn't split {}: {}", path, e);
+            process::exit(status::ERROR);
+        }
+    };
+
+    if let Err(e) = fs::create_dir_all(directory)
+    {
+        eprintln!("Couldn't create {}: {}", directory, e);
+        process::exit(status::ERROR);
+    }
+
+    for problem in &split.problems
+    {
+        eprintln!("Warning: {}: {}", path, problem);
+    }
+
+    let mut failed = false;
+
+    for part in split.parts
+    {
+        let target = Path::new(directory).join(&part.name);
+        let mut file = fs::OpenOptions::new();
+
+        file.write(true).create_new(true);
+
+        #[cfg(unix)]
+        if part.is_key
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+
+            file.mode(0o600);
+        }
+
+        match file.open(&target).and_then(|mut file| file.write_all(&part.contents))
+        {
+            Ok(()) => println!("{}", target.display()),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                eprintln!("{} already exists, so wasn't replaced", target.display());
+                failed = true;
+            },
+            Err(e) => {
+                eprintln!("Couldn't write {}: {}", target.display(), e);
+                failed = true;
+            }
+        }
+    }
+
+    if failed
+    {
+        process::exit(status::ERROR);
+    }
+}
+
+/// Suggest names for chains' files (--suggest-names), as a diff of paths,
+/// or rename them (--apply).
+fn suggest_names(inventory: &chain::Inventory, options: &options::Options)
+{
+    let chains = match inventory.build_chains()
+    {
+        Ok(chains) => chains,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(status::ERROR);
+        }
+    };
+
+    // Only the chains selected (--host and --name).
+    let chains: Vec<chain::Chain> = chains.into_iter().filter(|chain| options.selects(chain)).collect();
+    let renames = rename::suggest(inventory, &chains);
+    let mut failed = false;
+
+    if renames.is_empty()
+    {
+        eprintln!("No files need renaming");
+    }
+
+    for rename in &renames
+    {
+        if !options.apply
+        {
+            println!("-{}\n+{}", rename.from, rename.to);
+            continue;
+        }
+
+        match rename.apply()
+        {
+            Ok(()) => println!("Renamed {} to {}", rename.from, rename.to),
+            Err(e) => {
+                eprintln!("Couldn't rename {}: {}", rename.from, e);
+                failed = true;
+            }
+        }
+    }
+
+    if failed
+    {
+        process::exit(status::ERROR);
+    }
+}
+
+/// Compare the inventory with an earlier snapshot (--load-snapshot),
+/// returning what changed as (path, change) pairs, and save its snapshot
+/// (--save-snapshot), exiting if either fails.
+fn snapshot(inventory: &chain::Inventory, options: &options::Options) -> Vec<(String, String)>
+{
+    if options.load_snapshot.is_none() && options.save_snapshot.is_none()
+    {
+        return vec![];
+    }
+
+    let current = snapshot::Snapshot::new(inventory);
+
+    let drift = match options.load_snapshot.as_deref().map(snapshot::Snapshot::load)
+    {
+        Some(Ok(earlier)) => current.changes(&earlier),
+        Some(Err(e)) => {
+            eprintln!("Couldn't load the snapshot {}", e);
+            process::exit(status::ERROR);
+        },
+        None => vec![]
+    };
+
+    if let Some(path) = &options.save_snapshot
+    {
+        if let Err(e) = current.save(path)
+        {
+            eprintln!("Couldn't save the snapshot {}: {}", path, e);
+            process::exit(status::ERROR);
+        }
+    }
+
+    drift
+}
+
+/// Record a scan in the database (--db), with the chains built from it,
+/// exiting if it can't be.
+#[cfg(feature = "db")]
+fn record_scan(options: &options::Options, inventory: &chain::Inventory, chains: &[database::ChainRow])
+{
+    let Some(path) = &options.db else { return };
+
+    if let Err(e) = database::Database::open(path).and_then(|mut database| database.record(inventory, chains))
+    {
+        eprintln!("Couldn't record the scan in {}: {}", path, e);
+        process::exit(status::ERROR);
+    }
 }
 
+/// List certificates expiring within a number of days, as the database's
+/// latest scans found them (sslchains db expiring), as tab-separated expiry
+/// times, hosts, paths, and subjects.
+#[cfg(feature = "db")]
+fn db_expiring(options: &options::Options, days: u32)
+{
+    let Some(path) = &options.db else {
+        eprintln!("sslchains db needs --db FILE");
+        process::exit(status::USAGE);
+    };
+
+    match database::Database::open_existing(path).and_then(|database| database.expiring(days))
+    {
+        Ok(expiring) => {
+            for certificate in expiring
+            {
+                let not_after = openssl::asn1::Asn1Time::from_unix(certificate.not_after)
+                    .map(|time| sslchains::format::format_time(&time))
+                    .unwrap_or_default();
+
+                println!("{}\t{}\t{}\t{}", not_after, certificate.host, certificate.path, certificate.subject);
+            }
+        },
+        Err(e) => {
+            eprintln!("Couldn't query {}: {}", path, e);
+            process::exit(status::ERROR);
+        }
+    }
+}
+
+/// Exporters for the export options given (e.g. --export-fullchain),
+/// exiting if their directories can't be created, or a password read.
+fn exporters(options: &options::Options) -> Vec<export::Exporter>
+{
+    let password = match options.export_pass.as_deref().map(export::read_password).transpose()
+    {
+        Ok(password) => password.unwrap_or_default(),
+        Err(e) => {
+            eprintln!("Couldn't read the --export-pass password: {}", e);
+            process::exit(status::ERROR);
+        }
+    };
+
+    let formats = [
+        (export::Format::Fullchain, &options.export_fullchain),
+        (export::Format::Pkcs12(password), &options.export_p12),
+        (export::Format::KubernetesSecret, &options.export_k8s_secret),
+        (export::Format::Haproxy(options.export_issuer), &options.export_haproxy),
+    ];
+
+    formats.into_iter()
+        .filter_map(|(format, directory)| directory.as_ref().map(|directory| (format, directory)))
+        .map(|(format, directory)| match export::Exporter::new(format, directory)
+        {
+            Ok(exporter) => exporter,
+            Err(e) => {
+                eprintln!("Couldn't export to {}", e);
+                process::exit(status::ERROR);
+            }
+        })
+        .collect()
+}
+
+/// Write metrics for displayed chains to the --prom-textfile file, if set.
+fn write_metrics(metrics: &metrics::Metrics, options: &options::Options)
+{
+    if let Some(path) = &options.prom_textfile
+    {
+        if let Err(e) = metrics.write(path)
+        {
+            eprintln!("Couldn't write metrics to {}: {}", path, e);
+            process::exit(status::ERROR);
+        }
+    }
+}
+
+/// Exit with a status reflecting problems found (e.g. expiring certificates
+/// with --expiry-warn), if there are any.
+fn exit_with(status: i32)
+{
+    if status != 0
+    {
+        // Output may still be buffered, as exiting skips destructors.
+        let _ = io::stdout().flush();
+
+        process::exit(status);
+    }
+}
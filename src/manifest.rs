@@ -0,0 +1,229 @@
+//! Copyright (C) 2022 Gaz J.
+//!
+//! This program is free software: you can redistribute it and/or modify
+//! it under the terms of the GNU General Public License as published by
+//! the Free Software Foundation, either version 3 of the License, or
+//! (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//! GNU General Public License for more details.
+//!
+//! You should have received a copy of the GNU General Public License
+//! along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Read support for Kubernetes Secret manifests (YAML or JSON).
+//!
+//! Keys and certificates in `data` (base64) and `stringData` (plain text)
+//! fields are exposed as "manifest.yaml:tls.crt" members. When a file holds
+//! several Secrets, members are prefixed with the Secret's namespace (if it
+//! has one) and name, as in "manifest.yaml:default/example-tls/tls.crt".
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use openssl::base64;
+use serde::Deserialize;
+use serde_yaml::Value;
+
+/// Separates a manifest path from the name of one of its members.
+pub const SEPARATOR: char = ':';
+
+/// Secret data fields holding keys and certificates.
+pub const FIELDS: &[&str] = &["tls.crt", "tls.key", "ca.crt"];
+
+/// Determine whether a path looks like a manifest, by its extension.
+pub fn is_manifest(path: &str) -> bool
+{
+    let path = path.to_lowercase();
+
+    path.ends_with(".yaml") || path.ends_with(".yml") || path.ends_with(".json")
+}
+
+/// List the manifest's Secret fields as "manifest:field" paths, failing
+/// when the file can't be parsed or holds no such fields (e.g. a ConfigMap).
+pub fn members(path: &str) -> Result<Vec<String>, io::Error>
+{
+    let fields = read(path)?;
+
+    if fields.is_empty()
+    {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Manifest doesn't hold a Secret with tls.crt, tls.key, or ca.crt"));
+    }
+
+    Ok(fields.into_iter().map(|(name, _)| format!("{}{}{}", path, SEPARATOR, name)).collect())
+}
+
+/// Split a "manifest:field" path into its parts, provided the manifest
+/// portion refers to an existing manifest file.
+pub fn split(path: &str) -> Option<(&str, &str)>
+{
+    for (index, _) in path.match_indices(SEPARATOR)
+    {
+        let (manifest, member) = (&path[..index], &path[index + 1..]);
+
+        if is_manifest(manifest) && Path::new(manifest).is_file()
+        {
+            return Some((manifest, member));
+        }
+    }
+
+    None
+}
+
+/// Read the decoded contents of a single Secret field.
+pub fn read_member(manifest: &str, member: &str) -> Result<Vec<u8>, io::Error>
+{
+    read(manifest)?
+        .into_iter()
+        .find(|(name, _)| name == member)
+        .map(|(_, contents)| contents)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Manifest member not found"))
+}
+
+/// Read all key and certificate fields of the manifest's Secrets, as
+/// (member name, decoded contents) pairs.
+fn read(path: &str) -> Result<Vec<(String, Vec<u8>)>, io::Error>
+{
+    let contents = fs::read_to_string(path)?;
+
+    // YAML is a superset of JSON, and files may hold several documents.
+    let documents = serde_yaml::Deserializer::from_str(&contents)
+        .map(Value::deserialize)
+        .collect::<Result<Vec<Value>, _>>()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+    let mut secrets = vec![];
+
+    for document in &documents
+    {
+        collect_secrets(document, &mut secrets);
+    }
+
+    let prefix = secrets.len() > 1;
+    let mut fields: Vec<(String, Vec<u8>)> = vec![];
+
+    for (index, secret) in secrets.into_iter().enumerate()
+    {
+        let mut name = secret["metadata"]["name"].as_str().unwrap_or_default().to_string();
+
+        if let Some(namespace) = secret["metadata"]["namespace"].as_str()
+        {
+            name = format!("{}/{}", namespace, name);
+        }
+
+        // Secrets which still share a name (e.g. repeated in one file) are
+        // told apart by their position.
+        if fields.iter().any(|(member, _)| member.starts_with(&format!("{}/", name)))
+        {
+            name = format!("{}#{}", name, index + 1);
+        }
+
+        for field in FIELDS
+        {
+            let contents = match (secret["data"][field].as_str(), secret["stringData"][field].as_str())
+            {
+                (Some(encoded), _) => decode(encoded),
+                (None, Some(text)) => Some(text.as_bytes().to_vec()),
+                (None, None) => None
+            };
+
+            if let Some(contents) = contents
+            {
+                let member = if prefix { format!("{}/{}", name, field) } else { field.to_string() };
+
+                fields.push((member, contents));
+            }
+        }
+    }
+
+    Ok(fields)
+}
+
+/// Collect Secret objects, including those inside "List" objects.
+fn collect_secrets<'v>(value: &'v Value, secrets: &mut Vec<&'v Value>)
+{
+    match value["kind"].as_str()
+    {
+        Some("Secret") => secrets.push(value),
+        Some("List") => {
+            for item in value["items"].as_sequence().into_iter().flatten()
+            {
+                collect_secrets(item, secrets);
+            }
+        },
+        _ => {}
+    }
+}
+
+fn decode(encoded: &str) -> Option<Vec<u8>>
+{
+    // Base64 decoding doesn't tolerate line breaks inside the value.
+    let encoded: String = encoded.chars().filter(|c| !c.is_whitespace()).collect();
+
+    base64::decode_block(&encoded).ok()
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    #[test]
+    fn lists_manifest_members()
+    {
+        assert_eq!(
+            members("samples/kubernetes/secret.yaml").unwrap(),
+            vec![
+                String::from("samples/kubernetes/secret.yaml:tls.crt"),
+                String::from("samples/kubernetes/secret.yaml:tls.key"),
+            ]
+        );
+
+        assert_eq!(
+            members("samples/kubernetes/secrets.json").unwrap(),
+            vec![
+                String::from("samples/kubernetes/secrets.json:example-tls/tls.crt"),
+                String::from("samples/kubernetes/secrets.json:example-tls/tls.key"),
+                String::from("samples/kubernetes/secrets.json:intermediate-ca/ca.crt"),
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_manifest_members()
+    {
+        let (manifest, name) = split("samples/kubernetes/secret.yaml:tls.crt").unwrap();
+
+        assert_eq!(read_member(manifest, name).unwrap(), fs::read("samples/ca_signed.crt").unwrap());
+
+        let (manifest, name) = split("samples/kubernetes/secrets.json:intermediate-ca/ca.crt").unwrap();
+
+        assert_eq!(read_member(manifest, name).unwrap(), fs::read("samples/intermediate_ca.crt").unwrap());
+    }
+
+    #[test]
+    fn rejects_other_manifests()
+    {
+        assert_eq!(members("samples/kubernetes/configmap.yaml").unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(members("samples/kubernetes/invalid.yaml").unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn names_members_by_namespace()
+    {
+        assert_eq!(
+            members("samples/kubernetes/namespaces.yaml").unwrap(),
+            vec![
+                String::from("samples/kubernetes/namespaces.yaml:default/web-tls/tls.crt"),
+                String::from("samples/kubernetes/namespaces.yaml:staging/web-tls/tls.crt"),
+                String::from("samples/kubernetes/namespaces.yaml:staging/web-tls#3/tls.crt"),
+            ]
+        );
+
+        let (manifest, name) = split("samples/kubernetes/namespaces.yaml:staging/web-tls/tls.crt").unwrap();
+
+        assert_eq!(read_member(manifest, name).unwrap(), fs::read("samples/intermediate_ca.crt").unwrap());
+    }
+}
@@ -0,0 +1,166 @@
+//! Copyright (C) 2022 Gaz J.
+//!
+//! This program is free software: you can redistribute it and/or modify
+//! it under the terms of the GNU General Public License as published by
+//! the Free Software Foundation, either version 3 of the License, or
+//! (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//! GNU General Public License for more details.
+//!
+//! You should have received a copy of the GNU General Public License
+//! along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Prometheus metrics for chains, in the text exposition format read by
+//! node_exporter's textfile collector (--prom-textfile).
+//!
+//! Files are replaced atomically, so the collector never reads a partly
+//! written file.
+
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::chain::{CertificateFile, Chain};
+use crate::format::{get_display_name, unix_time};
+
+/// Metrics gathered from chains.
+#[derive(Debug, Default)]
+pub struct Metrics
+{
+    /// (name, path, expiry time) of current certificates and their signing
+    /// certificates.
+    expiry: Vec<(String, String, i64)>,
+
+    /// (name, path, complete) of each chain, named by its key (or current
+    /// certificate).
+    complete: Vec<(String, String, bool)>,
+
+    /// (name, path) pairs with an expiry, as signing certificates are shared.
+    seen: HashSet<(String, String)>,
+}
+
+impl Metrics
+{
+    pub fn new() -> Self
+    {
+        Metrics::default()
+    }
+
+    /// Gather a chain's metrics.
+    pub fn add(&mut self, chain: &Chain)
+    {
+        let name = get_display_name(chain);
+
+        let path = match (&chain.key, chain.certificates.first())
+        {
+            (Some(key), _) => key.path.clone(),
+            (None, Some(certificate)) => certificate.path.clone(),
+            (None, None) => return
+        };
+
+        self.complete.push((name.clone(), path, chain.key.is_some() && !chain.certificates.is_empty()));
+
+        // Superseded certificates are left out, as their expiry is expected.
+        let mut certificate: Option<&CertificateFile> = chain.certificates.first();
+
+        while let Some(current) = certificate
+        {
+            if self.seen.insert((name.clone(), current.path.clone()))
+            {
+                if let Some(expiry) = unix_time(current.certificate.not_after())
+                {
+                    self.expiry.push((name.clone(), current.path.clone(), expiry));
+                }
+            }
+
+            certificate = current.signing_certificate.as_deref();
+        }
+    }
+
+    /// Render the metrics in the text exposition format.
+    pub fn render(&self) -> String
+    {
+        let mut text = String::from(
+            "# HELP sslchain_cert_expiry_seconds Time at which the certificate expires, in seconds since the epoch.\n\
+             # TYPE sslchain_cert_expiry_seconds gauge\n"
+        );
+
+        for (name, path, expiry) in &self.expiry
+        {
+            text.push_str(&format!("sslchain_cert_expiry_seconds{{name=\"{}\",path=\"{}\"}} {}\n", escape(name), escape(path), expiry));
+        }
+
+        text.push_str(
+            "# HELP sslchain_chain_complete Whether the chain has both a private key and a certificate.\n\
+             # TYPE sslchain_chain_complete gauge\n"
+        );
+
+        for (name, path, complete) in &self.complete
+        {
+            text.push_str(&format!("sslchain_chain_complete{{name=\"{}\",path=\"{}\"}} {}\n", escape(name), escape(path), *complete as u8));
+        }
+
+        text
+    }
+
+    /// Write the metrics to a file, replacing it atomically (by renaming a
+    /// temporary file in the same directory).
+    pub fn write(&self, path: &str) -> io::Result<()>
+    {
+        let temporary = format!("{}.{}.tmp", path, std::process::id());
+
+        let result = fs::write(&temporary, self.render()).and_then(|_| fs::rename(&temporary, path));
+
+        if result.is_err() && Path::new(&temporary).exists()
+        {
+            let _ = fs::remove_file(&temporary);
+        }
+
+        result
+    }
+}
+
+/// Escape a label value.
+fn escape(value: &str) -> String
+{
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+    use crate::Inventory;
+
+    #[test]
+    fn renders_metrics()
+    {
+        let chains = Inventory::new()
+            .add_path("samples/ca_signed.key")
+            .add_path("samples/ca_signed.crt")
+            .add_path("samples/intermediate_ca.crt")
+            .add_path("samples/self_signed_san_no_cert.key")
+            .build_chains()
+            .unwrap();
+
+        let mut metrics = Metrics::new();
+
+        for chain in &chains
+        {
+            metrics.add(chain);
+        }
+
+        let text = metrics.render();
+
+        assert!(text.contains("sslchain_cert_expiry_seconds{name=\"example.com\",path=\"samples/ca_signed.crt\"} 2512770839\n"));
+        assert!(text.contains("sslchain_cert_expiry_seconds{name=\"example.com\",path=\"samples/intermediate_ca.crt\"} 1680393145\n"));
+        assert!(text.contains("sslchain_chain_complete{name=\"example.com\",path=\"samples/ca_signed.key\"} 1\n"));
+        assert!(text.contains("path=\"samples/self_signed_san_no_cert.key\"} 0\n"));
+
+        assert_eq!(escape("a\"b\\c"), "a\\\"b\\\\c");
+    }
+}
@@ -0,0 +1,139 @@
+//! Copyright (C) 2022 Gaz J.
+//!
+//! This program is free software: you can redistribute it and/or modify
+//! it under the terms of the GNU General Public License as published by
+//! the Free Software Foundation, either version 3 of the License, or
+//! (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//! GNU General Public License for more details.
+//!
+//! You should have received a copy of the GNU General Public License
+//! along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Read support for NSS (Firefox, Thunderbird, etc.) certificate databases.
+//!
+//! Certificates are stored in the `nssPublic` table of `cert9.db`, with
+//! PKCS #11 attributes as columns named after their hexadecimal type.
+//! Private keys (in `key4.db`) are encrypted, and aren't read.
+
+use std::io;
+use std::path::Path;
+use openssl::x509::X509;
+use rusqlite::{Connection, OpenFlags};
+
+/// File name of NSS (SQLite format) certificate databases.
+const DATABASE_NAME: &str = "cert9.db";
+
+/// CKA_CLASS value of certificate objects (CKO_CERTIFICATE), stored as a
+/// big-endian 32-bit integer.
+const CKO_CERTIFICATE: [u8; 4] = [0, 0, 0, 1];
+
+/// Determine whether a path is an NSS certificate database.
+pub fn is_database(path: &str) -> bool
+{
+    Path::new(path).file_name().map(|name| name == DATABASE_NAME).unwrap_or(false)
+}
+
+/// List the database's certificates as "cert9.db:nickname#id" paths.
+pub fn members(path: &str) -> Result<Vec<String>, io::Error>
+{
+    let connection = open(path)?;
+
+    // CKA_LABEL (a3) holds the nickname, CKA_VALUE (a11) the DER certificate.
+    let mut statement = connection
+        .prepare("SELECT id, a3 FROM nssPublic WHERE a0 = ?1 AND a11 IS NOT NULL ORDER BY id")
+        .map_err(to_io_error)?;
+
+    let rows = statement
+        .query_map([&CKO_CERTIFICATE[..]], |row| {
+            let id: i64 = row.get(0)?;
+            let label: Option<Vec<u8>> = row.get(1)?;
+
+            Ok((id, label.map(|l| String::from_utf8_lossy(&l).to_string()).unwrap_or_default()))
+        })
+        .map_err(to_io_error)?;
+
+    let mut members = vec![];
+
+    for row in rows
+    {
+        let (id, label) = row.map_err(to_io_error)?;
+
+        members.push(format!("{}:{}#{}", path, label, id));
+    }
+
+    Ok(members)
+}
+
+/// Split a "cert9.db:nickname#id" path into the database path and row id.
+pub fn split(path: &str) -> Option<(&str, i64)>
+{
+    let index = path.find(&format!("{}:", DATABASE_NAME))? + DATABASE_NAME.len();
+    let (database, member) = (&path[..index], &path[index + 1..]);
+    let id = member.rsplit_once('#')?.1.parse().ok()?;
+
+    if !Path::new(database).is_file()
+    {
+        return None;
+    }
+
+    Some((database, id))
+}
+
+/// Read a certificate from the database, converted to PEM.
+pub fn read_member(database: &str, id: i64) -> Result<String, io::Error>
+{
+    let connection = open(database)?;
+
+    let der: Vec<u8> = connection
+        .query_row("SELECT a11 FROM nssPublic WHERE id = ?1", [id], |row| row.get(0))
+        .map_err(to_io_error)?;
+
+    let pem = X509::from_der(&der)
+        .and_then(|certificate| certificate.to_pem())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+    String::from_utf8(pem).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn open(path: &str) -> Result<Connection, io::Error>
+{
+    Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY).map_err(to_io_error)
+}
+
+fn to_io_error(error: rusqlite::Error) -> io::Error
+{
+    io::Error::other(error)
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    #[test]
+    fn lists_database_certificates()
+    {
+        assert_eq!(
+            members("samples/nss/cert9.db").unwrap(),
+            vec![
+                String::from("samples/nss/cert9.db:example.com#1"),
+                String::from("samples/nss/cert9.db:Sample Org CA#2"),
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_database_certificates()
+    {
+        let (database, id) = split("samples/nss/cert9.db:Sample Org CA#2").unwrap();
+
+        let pem = read_member(database, id).unwrap();
+        let expected = X509::from_pem(&std::fs::read("samples/intermediate_ca.crt").unwrap()).unwrap();
+
+        assert_eq!(X509::from_pem(pem.as_bytes()).unwrap(), expected);
+    }
+}
@@ -0,0 +1,163 @@
+//! Copyright (C) 2022 Gaz J.
+//!
+//! This program is free software: you can redistribute it and/or modify
+//! it under the terms of the GNU General Public License as published by
+//! the Free Software Foundation, either version 3 of the License, or
+//! (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//! GNU General Public License for more details.
+//!
+//! You should have received a copy of the GNU General Public License
+//! along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Checks revocation status with the OCSP responder named in a
+//! certificate's Authority Information Access extension (--ocsp).
+//!
+//! Failures to reach or understand a responder (e.g. when offline) are
+//! reported as an UNKNOWN status, with the reason, rather than as errors.
+
+use std::time::Duration;
+use openssl::hash::MessageDigest;
+use openssl::ocsp::{OcspCertId, OcspCertStatus, OcspFlag, OcspRequest, OcspResponse, OcspResponseStatus};
+use openssl::stack::Stack;
+use openssl::x509::X509;
+use openssl::x509::store::X509StoreBuilder;
+use openssl::x509::verify::X509VerifyFlags;
+
+use crate::http;
+
+/// Clock skew allowed when checking a response's validity period.
+const SKEW_SECONDS: u32 = 300;
+
+/// Revocation status of a certificate, per its OCSP responder.
+#[derive(Debug, PartialEq)]
+pub enum Revocation
+{
+    /// Not revoked.
+    Good,
+
+    /// Revoked, as described (e.g. "revoked at Jan  1 00:00:00 2030 GMT").
+    Revoked(String),
+
+    /// The responder doesn't know the certificate, or couldn't be queried,
+    /// for this reason.
+    Unknown(String),
+}
+
+impl Revocation
+{
+    pub fn as_str(&self) -> &'static str
+    {
+        match self
+        {
+            Revocation::Good => "GOOD",
+            Revocation::Revoked(_) => "REVOKED",
+            Revocation::Unknown(_) => "UNKNOWN",
+        }
+    }
+
+    /// Details of a revoked or unknown status.
+    pub fn reason(&self) -> Option<&str>
+    {
+        match self
+        {
+            Revocation::Revoked(reason) | Revocation::Unknown(reason) => Some(reason),
+            Revocation::Good => None,
+        }
+    }
+}
+
+/// URL of the certificate's OCSP responder, if it names one.
+pub fn responder(certificate: &X509) -> Option<String>
+{
+    certificate.ocsp_responders().ok()?.iter().next().map(|url| url.to_string())
+}
+
+/// Query an OCSP responder for a certificate's status, given its issuer.
+pub fn check(certificate: &X509, issuer: &X509, url: &str, timeout: Duration) -> Revocation
+{
+    query(certificate, issuer, url, timeout).unwrap_or_else(Revocation::Unknown)
+}
+
+fn query(certificate: &X509, issuer: &X509, url: &str, timeout: Duration) -> Result<Revocation, String>
+{
+    let openssl_error = |e: openssl::error::ErrorStack| e.to_string();
+    let id = || OcspCertId::from_cert(MessageDigest::sha1(), certificate, issuer).map_err(openssl_error);
+
+    let mut request = OcspRequest::new().map_err(openssl_error)?;
+
+    request.add_id(id()?).map_err(openssl_error)?;
+
+    let body = http::post(url, "application/ocsp-request", &request.to_der().map_err(openssl_error)?, timeout)
+        .map_err(|e| format!("couldn't query {}: {}", url, e))?;
+
+    let response = OcspResponse::from_der(&body).map_err(|_| format!("{} didn't return an OCSP response", url))?;
+
+    if response.status() != OcspResponseStatus::SUCCESSFUL
+    {
+        return Err(format!("{} returned response status {}", url, response.status().as_raw()));
+    }
+
+    let basic = response.basic().map_err(openssl_error)?;
+
+    // Responses must be signed by the issuer, or by a responder it delegated
+    // to (which needn't chain any further).
+    let mut builder = X509StoreBuilder::new().map_err(openssl_error)?;
+
+    builder.add_cert(issuer.clone()).map_err(openssl_error)?;
+    builder.set_flags(X509VerifyFlags::PARTIAL_CHAIN).map_err(openssl_error)?;
+
+    let store = builder.build();
+    let mut certificates = Stack::new().map_err(openssl_error)?;
+
+    certificates.push(issuer.clone()).map_err(openssl_error)?;
+
+    if basic.verify(&certificates, &store, OcspFlag::empty()).is_err()
+    {
+        return Err(String::from("response signature didn't verify"));
+    }
+
+    let id = id()?;
+    let status = basic.find_status(&id).ok_or("response doesn't cover the certificate")?;
+
+    if status.check_validity(SKEW_SECONDS, None).is_err()
+    {
+        return Err(String::from("response is outside its validity period"));
+    }
+
+    Ok(match status.status
+    {
+        OcspCertStatus::GOOD => Revocation::Good,
+        OcspCertStatus::REVOKED => Revocation::Revoked(match status.revocation_time
+        {
+            Some(time) => format!("revoked at {}", time),
+            None => String::from("revoked")
+        }),
+        _ => Revocation::Unknown(String::from("responder doesn't know the certificate"))
+    })
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    #[test]
+    fn reports_unreachable_responders()
+    {
+        let read = |path| X509::from_pem(&std::fs::read(path).unwrap()).unwrap();
+        let certificate = read("samples/ca_signed.crt");
+        let issuer = read("samples/intermediate_ca.crt");
+
+        assert_eq!(responder(&certificate), None);
+
+        // Nothing listens on the discard port, so the query fails quickly.
+        let revocation = check(&certificate, &issuer, "http://127.0.0.1:9/", Duration::from_secs(1));
+
+        assert_eq!(revocation.as_str(), "UNKNOWN");
+        assert!(revocation.reason().unwrap().starts_with("couldn't query http://127.0.0.1:9/"));
+    }
+}
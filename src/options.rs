@@ -13,26 +13,116 @@
 //! You should have received a copy of the GNU General Public License
 //! along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-extern crate getopt;
+use std::any::Any;
+use std::ffi::OsString;
+use std::process;
+use clap::{Arg, ArgAction, ArgGroup, ArgMatches, Command};
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
+use regex::{Regex, RegexBuilder};
+use sslchains::apache;
+use sslchains::chain::Chain;
+use sslchains::connect::{self, Endpoint};
+use sslchains::ct;
+use sslchains::format;
+use sslchains::haproxy;
+use sslchains::kubernetes;
+use sslchains::letsencrypt;
+use sslchains::mail;
+use sslchains::permissions;
+use sslchains::policy;
+use sslchains::ssh;
+use sslchains::starttls::{self, Protocol};
 
-use getopt::{Opt, Parser};
+use crate::status::{self, Condition};
+use crate::template;
+
+/// Options selecting the display mode, of which the last given wins.
+const DISPLAY_MODES: &[&str] = &["one-line", "one-line-no-header", "csv", "tsv", "ndjson", "print0", "format"];
+
+/// Directory names which recursive scans don't descend into by default.
+pub const DEFAULT_PRUNE: &[&str] = &[".git", ".hg", ".svn", "node_modules", "__pycache__"];
+
+/// Directory scans stop after finding this many paths by default.
+pub const DEFAULT_MAX_FILES: usize = 10_000;
+
+/// Files larger than this (in bytes) are skipped by default.
+pub const DEFAULT_MAX_FILE_SIZE: u64 = 4 * 1024 * 1024;
+
+/// Network requests (e.g. --ocsp) give up after this many seconds by default.
+pub const DEFAULT_TIMEOUT: u64 = 10;
+
+/// At most this many TLS servers (--connect-file) are connected to at once
+/// by default.
+pub const DEFAULT_CONCURRENCY: usize = 8;
+
+/// Daemon mode rescans this often (in seconds) by default.
+pub const DEFAULT_INTERVAL: u64 = 6 * 60 * 60;
+
+/// `sslchains db expiring` looks this many days ahead by default.
+pub const DEFAULT_DB_DAYS: u32 = 30;
 
 #[derive(Debug)]
 pub enum OptionsDisplayMode
 {
     Default,
     OneLine,
+    Csv,
+    Tsv,
+    NdJson,
+    Print0,
+    Format(String),
 }
 
 /// Represents the state/usage of all command line options.
 #[derive(Debug)]
 pub struct Options
 {
-    // Print help and exit.
-    pub print_help: bool,
+    // Display absolute paths.
+    pub absolute: bool,
+
+    // Descend into .zip, .tar, and .tar.gz archives.
+    pub archives: bool,
+
+    // Process all files found in directories, rather than only those
+    // with likely extensions.
+    pub all_files: bool,
+
+    // Keep a cache of what each file holds in this directory, so files
+    // which haven't changed needn't be parsed fully again.
+    pub cache: Option<String>,
+
+    // Check keys for consistency, and certificate signatures for structure.
+    pub check_keys: bool,
+
+    // Check certificates' usage constraints (extended key usage,
+    // basicConstraints, key usage, and path length).
+    pub check_usage: bool,
+
+    // Connect to at most this many TLS servers at once.
+    pub concurrency: usize,
+
+    // Match the certificates these TLS servers present against local chains.
+    pub connect: Vec<Endpoint>,
+
+    // Read more TLS servers to connect to from this file.
+    pub connect_file: Option<String>,
+
+    // Look certificates up in Certificate Transparency logs, via this search
+    // frontend.
+    pub ct: Option<String>,
+
+    // Keep running, rescanning periodically and raising alerts for changes.
+    pub daemon: bool,
+
+    // Stop directory scans after finding this many paths (0 for no limit).
+    pub max_files: usize,
+
+    // Fetch missing signing certificates from caIssuers URLs.
+    pub fetch_missing: bool,
 
-    // Disable the file count limit.
-    pub disable_file_limit: bool,
+    // Read additional paths from this file ("-" for standard input).
+    pub files_from: Option<String>,
 
     // Determines the output format.
     pub display_mode: OptionsDisplayMode,
@@ -40,73 +130,1483 @@ pub struct Options
     // Determine whether to follow symbolic links.
     pub follow_symlinks: bool,
 
+    // Show certificate validity dates.
+    pub dates: bool,
+
+    // Flag certificates which have expired or expire within this many days,
+    // and reflect them in the exit status.
+    pub expiry_warn: Option<u32>,
+
+    // Digest algorithms ("sha256", "sha1") for certificate and request
+    // fingerprints, or empty to hide them.
+    pub fingerprints: Vec<String>,
+
+    // List all Subject Alternative Names of each chain's certificate.
+    pub sans: bool,
+
+    // Only show chains whose current certificate covers this hostname (or
+    // IP address).
+    pub host: Option<String>,
+
+    // Only show chains whose display name matches this pattern.
+    pub name: Option<Pattern>,
+
+    // Group chains by the directory containing their files.
+    pub group_by_dir: bool,
+
     // Determine whether to process hidden files.
     pub include_hidden_files: bool,
 
+    // Rescan this often (in seconds) in daemon mode.
+    pub interval: u64,
+
+    // Read the lineages of this Let's Encrypt (certbot) directory.
+    pub letsencrypt: Option<String>,
+
+    // List the files which would be processed, and exit.
+    pub list_files: bool,
+
+    // Serve chains and metrics over HTTP (in daemon mode) on this address.
+    pub listen: Option<String>,
+
+    // Check the TLS virtual hosts of this Apache httpd configuration.
+    pub apache: Option<String>,
+
+    // Check the certificates bind lines load in this HAProxy configuration.
+    pub haproxy: Option<String>,
+
+    // Check the certificates of this Postfix main.cf.
+    pub postfix: Option<String>,
+
+    // Check the certificates of this Dovecot configuration.
+    pub dovecot: Option<String>,
+
+    // Read the TLS Secrets of the cluster this kubeconfig names.
+    pub kubeconfig: Option<String>,
+
+    // Use this kubeconfig context, rather than its current context.
+    pub kube_context: Option<String>,
+
+    // Read the files of these Docker or Podman containers.
+    pub containers: Vec<String>,
+
+    // Read the certificates in AWS Certificate Manager.
+    pub acm: bool,
+
+    // Read these AWS Secrets Manager secrets.
+    pub aws_secrets: Vec<String>,
+
+    // Read ACM and Secrets Manager in this AWS region.
+    pub aws_region: Option<String>,
+
+    // Query each chain's OCSP responder for its revocation status.
+    pub ocsp: bool,
+
+    // List parsed files which aren't part of any chain.
+    pub orphans: bool,
+
+    // Check chains against the policy in this file.
+    pub policy: Option<String>,
+
+    // Skip files larger than this many bytes (0 for no limit).
+    pub max_file_size: u64,
+
+    // Write Prometheus metrics for node_exporter's textfile collector to
+    // this file.
+    pub prom_textfile: Option<String>,
+
+    // Write each complete chain's certificate and intermediates to a file in
+    // this directory.
+    pub export_fullchain: Option<String>,
+
+    // Write each complete chain's key, certificate, and intermediates to a
+    // PKCS#12 file in this directory.
+    pub export_p12: Option<String>,
+
+    // Where the PKCS#12 files' password comes from (e.g. "env:P12_PASS").
+    pub export_pass: Option<String>,
+
+    // Write each complete chain as a Kubernetes TLS Secret manifest in this
+    // directory.
+    pub export_k8s_secret: Option<String>,
+
+    // Write each complete chain as a combined PEM file for HAProxy in this
+    // directory.
+    pub export_haproxy: Option<String>,
+
+    // Also write each HAProxy file's issuer to a ".issuer" file.
+    pub export_issuer: bool,
+
+    // Repair this bundle, with intermediates from the files read, rather
+    // than displaying chains.
+    pub fix_bundle: Option<String>,
+
+    // Replace the --fix-bundle file (keeping a backup), rather than writing
+    // the repaired bundle to standard output.
+    pub in_place: bool,
+
+    // Split this bundle into a file per block in this directory, rather
+    // than displaying chains.
+    pub split: Option<(String, String)>,
+
+    // Suggest consistent names for chains' files, rather than displaying
+    // chains.
+    pub suggest_names: bool,
+
+    // Rename the files as --suggest-names suggests.
+    pub apply: bool,
+
+    // Write a shell script removing files which can probably go, rather
+    // than displaying chains.
+    pub cleanup_plan: bool,
+
+    // Write a snapshot of the keys, requests, and certificates found to this
+    // file.
+    pub save_snapshot: Option<String>,
+
+    // Report what changed since the snapshot in this file.
+    pub load_snapshot: Option<String>,
+
+    // Record each scan in this SQLite database.
+    pub db: Option<String>,
+
+    // List certificates expiring within this many days, as the --db
+    // database's latest scans found them (sslchains db expiring), rather
+    // than scanning.
+    pub db_expiring: Option<u32>,
+
+    // Suppress output, communicating results via the exit status.
+    pub quiet: bool,
+
+    // Directory names which aren't descended into.
+    pub prune: Vec<String>,
+
+    // Display paths relative to this directory.
+    pub relative_to: Option<String>,
+
     // Determine whether to recursively traverse the directory arguments.
     pub recursive: bool,
 
     // Determine whether to cross filesystem boundaries.
     pub same_file_system: bool,
 
+    // Save fetched signing certificates beside the certificates they sign.
+    pub save_fetched: bool,
+
+    // Conditions which make the exit status non-zero (--fail-on, or those
+    // the other options check for, e.g. lax key permissions with
+    // --strict-permissions).
+    pub fail_on: Vec<Condition>,
+
+    // Users (uids) allowed to own private keys, rather than root and the
+    // current user.
+    pub key_owners: Vec<u32>,
+
+    // Report files which couldn't be parsed, with the reason.
+    pub show_skipped: bool,
+
+    // Inventory OpenSSH host keys, certificates, and CA keys in this
+    // directory too.
+    pub ssh: Option<String>,
+
+    // Upgrade connections to TLS servers with STARTTLS for this protocol.
+    pub starttls: Option<Protocol>,
+
     // If the display mode is OneLine, use this option
     // to suppress the header row.
     pub suppress_oneline_header: bool,
 
-    // Argument index where options end.
-    // Use this to split env::args for argument processing.
-    // e.g. args.split_off(options.index)
-    pub index: usize
+    // Also log findings in daemon or watch mode to syslog.
+    pub syslog: bool,
+
+    // Give up on network requests after this many seconds.
+    pub timeout: u64,
+
+    // Verify each chain against the system trust store.
+    pub verify: bool,
+
+    // Show certificate subjects and issuers, and report additional details
+    // (e.g. skipped files) on stderr.
+    pub verbose: bool,
+
+    // How much is logged on stderr (info for files read and what they
+    // parsed as, debug to also log why they didn't match): as --log-level
+    // gives, or info with -v, and debug with -vv.
+    pub log_level: log::LevelFilter,
+
+    // Report files and directories which couldn't be read (on stderr).
+    pub warnings: bool,
+
+    // Keep running, and display chains again as their files change.
+    pub watch: bool,
+
+    // Post alerts (in daemon mode) to this URL, as JSON.
+    pub webhook: Option<String>,
+
+    // Path arguments remaining after all options are processed.
+    pub arguments: Vec<String>
 }
 
-impl Options
+impl Default for Options
 {
-    pub fn new() -> Options
+    fn default() -> Options
     {
-        let args: Vec<String> = std::env::args().collect();
-        let mut opts = Parser::new(&args, "hHlLrSUX");
-
-        let mut instance = Options {
-            print_help: false,
-            disable_file_limit: false,
+        Options {
+            absolute: false,
+            archives: false,
+            all_files: false,
+            cache: None,
+            check_keys: false,
+            check_usage: false,
+            concurrency: DEFAULT_CONCURRENCY,
+            connect: vec![],
+            connect_file: None,
+            ct: None,
+            daemon: false,
+            max_files: DEFAULT_MAX_FILES,
+            fetch_missing: false,
+            files_from: None,
             display_mode: OptionsDisplayMode::Default,
+            dates: false,
+            expiry_warn: None,
+            fingerprints: vec![],
             follow_symlinks: false,
+            group_by_dir: false,
+            host: None,
+            name: None,
             include_hidden_files: false,
+            interval: DEFAULT_INTERVAL,
+            letsencrypt: None,
+            list_files: false,
+            listen: None,
+            apache: None,
+            haproxy: None,
+            postfix: None,
+            dovecot: None,
+            kubeconfig: None,
+            kube_context: None,
+            containers: vec![],
+            acm: false,
+            aws_secrets: vec![],
+            aws_region: None,
+            max_file_size: DEFAULT_MAX_FILE_SIZE,
+            ocsp: false,
+            orphans: false,
+            policy: None,
+            prom_textfile: None,
+            export_fullchain: None,
+            export_p12: None,
+            export_pass: None,
+            export_k8s_secret: None,
+            export_haproxy: None,
+            export_issuer: false,
+            fix_bundle: None,
+            in_place: false,
+            split: None,
+            suggest_names: false,
+            apply: false,
+            cleanup_plan: false,
+            save_snapshot: None,
+            load_snapshot: None,
+            db: None,
+            db_expiring: None,
+            prune: DEFAULT_PRUNE.iter().map(|name| name.to_string()).collect(),
+            quiet: false,
             recursive: false,
+            relative_to: None,
+            sans: false,
             same_file_system: true,
+            save_fetched: false,
+            show_skipped: false,
+            ssh: None,
+            starttls: None,
+            fail_on: vec![],
+            key_owners: vec![],
             suppress_oneline_header: false,
-            index: 0
+            syslog: false,
+            timeout: DEFAULT_TIMEOUT,
+            verify: false,
+            verbose: false,
+            log_level: log::LevelFilter::Warn,
+            warnings: false,
+            watch: false,
+            webhook: None,
+            arguments: vec![]
+        }
+    }
+}
+
+impl Options
+{
+    pub fn new() -> Options
+    {
+        match Options::from_args(std::env::args())
+        {
+            Ok(options) => options,
+            Err(e) if e.kind() == ErrorKind::DisplayHelp || e.kind() == ErrorKind::DisplayVersion => {
+                let _ = e.print();
+                process::exit(status::SUCCESS);
+            },
+            Err(e) => {
+                let _ = e.print();
+                process::exit(status::USAGE);
+            }
+        }
+    }
+
+    /// Whether a chain should be shown: whether its current certificate
+    /// covers the hostname (--host), and its name, or one of its current
+    /// certificate's names (its common name and Subject Alternative Names),
+    /// matches (--name).
+    pub fn selects(&self, chain: &Chain) -> bool
+    {
+        self.host.as_ref().is_none_or(|host| chain.host_match(host).is_some())
+            && self.name.as_ref().is_none_or(|name| names(chain).iter().any(|n| name.is_match(n)))
+    }
+
+    /// Parse command line arguments (starting with the executable name).
+    pub fn from_args<I, T>(args: I) -> Result<Options, clap::Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone
+    {
+        let matches = Options::command().try_get_matches_from(args)?;
+
+        let options = match matches.subcommand()
+        {
+            Some(("db", _)) | None => Options::from_matches(&matches),
+            Some(("export", export)) => {
+                let mut options = Options::from_matches(export);
+                let directory = export.get_one::<String>("to").cloned();
+
+                match export.get_one::<String>("as").map(String::as_str)
+                {
+                    Some("p12") => options.export_p12 = directory,
+                    Some("k8s-secret") => options.export_k8s_secret = directory,
+                    Some("haproxy") => options.export_haproxy = directory,
+                    _ => options.export_fullchain = directory
+                }
+
+                options.export_pass = export.get_one::<String>("pass").cloned().or(options.export_pass);
+                options.export_issuer |= export.get_flag("issuer");
+                options
+            },
+            Some(("diff", diff)) => {
+                let mut options = Options::from_matches(diff);
+
+                // The first path is the snapshot to compare with.
+                options.load_snapshot = Some(options.arguments.remove(0));
+                options
+            },
+            Some(("connect", connect)) => {
+                let starttls = connect.get_one::<Protocol>("starttls").copied();
+                let mut options = Options::from_matches(connect);
+
+                options.connect.extend(connect.get_many::<Endpoint>("paths").into_iter().flatten().map(|endpoint| Endpoint { starttls, ..endpoint.clone() }));
+                options
+            },
+            Some((_, scan)) => Options::from_matches(scan)
         };
 
-        Options::process_input(&mut instance, &mut opts).unwrap();
+        // Standard input can only be read once.
+        let stdin_readers = [
+            options.arguments.iter().any(|path| path == "-"),
+            options.files_from.as_deref() == Some("-"),
+            options.connect_file.as_deref() == Some("-"),
+        ];
 
-        instance.index = opts.index();
+        if stdin_readers.iter().filter(|reads| **reads).count() > 1
+        {
+            return Err(Options::command().error(
+                ErrorKind::ArgumentConflict,
+                "only one of the - path, --files-from -, and --connect-file - can read standard input"
+            ));
+        }
 
-        instance
+        Ok(options)
     }
 
-    fn process_input(instance: &mut Options, opts: &mut Parser) -> Result<(), Box<dyn std::error::Error>>
+    /// Describe all command line options.
+    pub fn command() -> Command
     {
-        loop {
-            match opts.next().transpose()? {
-                None => break,
-                Some(opt) => match opt {
-                    Opt('h', None) => instance.print_help = true,
-                    Opt('H', None) => instance.include_hidden_files = true,
-                    Opt('l', None) => instance.display_mode = OptionsDisplayMode::OneLine,
-                    Opt('L', None) => {
-                        instance.display_mode = OptionsDisplayMode::OneLine;
-                        instance.suppress_oneline_header = true;
-                    },
-                    Opt('r', None) => instance.recursive = true,
-                    Opt('S', None) => instance.follow_symlinks = true,
-                    Opt('U', None) => instance.disable_file_limit = true,
-                    Opt('X', None) => instance.same_file_system = false,
-                    _ => unreachable!(),
+        let mut placeholders = String::from("Placeholders for --format:");
+
+        for (placeholder, description) in template::PLACEHOLDERS
+        {
+            placeholders.push_str(&format!("\n  {{{}}}\t{}", placeholder, description));
+        }
+
+        // Subcommands take the options relevant to them (scan takes them all).
+        let subcommand = |name: &'static str, about: &'static str| Command::new(name).about(about).after_help(placeholders.clone()).args_override_self(true);
+
+        scanning(Command::new("sslchains")
+            .version(version())
+            .about("A tool to identify related SSL keys, CSRs, and certificates.")
+            .after_help(placeholders.clone())
+            .args_override_self(true))
+            .mut_arg("paths", |arg| arg
+                .help("Paths to process (the current directory by default). Use - to read from standard input. Paths named like subcommands need ./ or a preceding --."))
+            .subcommand(scanning(subcommand("scan", "Identify related keys, CSRs, and certificates in PATHs (as sslchains PATH... does).")))
+            .subcommand(checking(displaying(remote(reading(subcommand("verify", "Verify each chain's signatures and trust (as --verify does).")))))
+                .mut_arg("verify", |arg| arg.default_value("true").hide(true)))
+            .subcommand(checking(displaying(remote(reading(subcommand("export", "Write each complete chain to DIR, in FORMAT (as --export-fullchain, --export-p12, --export-k8s-secret, and --export-haproxy do).")))))
+                .arg(Arg::new("as")
+                    .long("as")
+                    .value_name("FORMAT")
+                    .value_parser(["fullchain", "p12", "k8s-secret", "haproxy"])
+                    .required(true)
+                    .help("Write full chains (NAME.fullchain.pem), PKCS#12 files (NAME.p12), Kubernetes TLS Secrets (NAME.secret.yaml), or HAProxy files (NAME.pem)."))
+                .arg(Arg::new("to")
+                    .long("to")
+                    .value_name("DIR")
+                    .required(true)
+                    .help("Write the files to DIR, creating it if needed."))
+                .arg(Arg::new("pass")
+                    .long("pass")
+                    .value_name("SOURCE")
+                    .help("Protect p12 files with the password from SOURCE (pass:PASSWORD, env:VARIABLE, or file:PATH)."))
+                .arg(flag("issuer", "Also write the issuer of each haproxy file to NAME.pem.issuer, for OCSP stapling.")))
+            .subcommand(displaying(remote(reading(subcommand("diff", "Report what changed at each path since SNAPSHOT (as --load-snapshot does)."))))
+                .mut_arg("paths", |arg| arg
+                    .value_name("SNAPSHOT|PATH")
+                    .required(true)
+                    .help("A snapshot written by --save-snapshot, then the paths to process (the current directory by default).")))
+            .subcommand(connecting(checking(displaying(remote(reading(subcommand("connect", "Connect to TLS servers, matching the certificates they present with the keys read (as --connect does)."))))))
+                .mut_arg("paths", |arg| arg
+                    .value_name("HOST[:PORT]")
+                    .value_parser(parse_endpoint)
+                    .help("TLS servers to connect to (port 443 by default), besides those --connect-file lists. The current directory is read, too, unless --files-from gives paths."))
+                .mut_group("endpoints", |group| group.arg("paths").required(true)))
+            // Kubernetes, containers, and AWS aren't watched.
+            .subcommand(checking(displaying(reading(subcommand("watch", "Display chains, then again whenever the files read change (as --watch does)."))))
+                .arg(flag("watch", "Keep running.").default_value("true").hide(true))
+                .arg(syslog()))
+            .subcommand(Command::new("db")
+                .about("Query the --db database.")
+                .subcommand_required(true)
+                .subcommand(Command::new("expiring")
+                    .about("List certificates expiring within --days days (or expired), as each host's latest scan found them.")
+                    .arg(Arg::new("db")
+                        .long("db")
+                        .value_name("FILE")
+                        .help("The database to query."))
+                    .arg(Arg::new("days")
+                        .long("days")
+                        .value_name("DAYS")
+                        .value_parser(clap::value_parser!(u32))
+                        .help("How many days ahead to look (30 by default)."))))
+    }
+
+    fn from_matches(matches: &ArgMatches) -> Options
+    {
+        // Subcommands don't take every option.
+        let flag = |id| get::<bool>(matches, id).unwrap_or(false);
+        let value = |id| get::<String>(matches, id);
+
+        // When several display modes are given, the last one wins.
+        let display_mode = DISPLAY_MODES.iter()
+            .filter(|id| matches.try_contains_id(id).unwrap_or(false) && matches.value_source(id) == Some(ValueSource::CommandLine))
+            .filter_map(|id| Some((matches.indices_of(id)?.max()?, *id)))
+            .max()
+            .map(|(_, id)| id);
+
+        // sslchains db expiring (the other subcommands' options are parsed
+        // by from_args).
+        let expiring = match matches.subcommand()
+        {
+            Some(("db", db)) => db.subcommand_matches("expiring"),
+            _ => None
+        };
+
+        // Conditions which fail: those given, or by default, those the other
+        // options check for.
+        let fail_on = match get::<Vec<Condition>>(matches, "fail-on")
+        {
+            Some(conditions) => conditions,
+            None => {
+                let mut conditions = vec![];
+
+                if flag("quiet")
+                {
+                    conditions.extend([Condition::NoChains, Condition::NoCompleteChains]);
+                }
+
+                if get::<u32>(matches, "expiry-warn").is_some()
+                {
+                    conditions.extend([Condition::Expiring, Condition::Expired]);
+                }
+
+                if flag("strict-permissions")
+                {
+                    conditions.push(Condition::Permissions);
+                }
+
+                if get::<String>(matches, "policy").is_some()
+                {
+                    conditions.push(Condition::Policy);
                 }
+
+                // The connect subcommand takes endpoints as paths.
+                let endpoints = [get_all::<Endpoint>(matches, "connect"), get_all::<Endpoint>(matches, "paths")].concat();
+
+                if !endpoints.is_empty() || get::<String>(matches, "connect-file").is_some()
+                {
+                    conditions.push(Condition::Unreachable);
+                }
+
+                conditions
+            }
+        };
+
+        // -v raises the log level, as does -vv further.
+        let verbosity = get::<u8>(matches, "verbose").unwrap_or(0);
+
+        // Pruned directory names add to the defaults, unless disabled.
+        let defaults = if flag("no-default-prune") { &[][..] } else { DEFAULT_PRUNE };
+
+        let prune = defaults.iter()
+            .map(|name| name.to_string())
+            .chain(get_all::<String>(matches, "prune"))
+            .collect();
+
+        Options {
+            absolute: flag("absolute"),
+            archives: flag("archives"),
+            all_files: flag("all-files"),
+            cache: value("cache"),
+            check_keys: flag("check-keys"),
+            check_usage: flag("check-usage"),
+            concurrency: get::<u64>(matches, "concurrency").map(|n| n as usize).unwrap_or(DEFAULT_CONCURRENCY),
+            connect: get_all::<Endpoint>(matches, "connect").into_iter()
+                .map(|endpoint| Endpoint { starttls: get::<Protocol>(matches, "starttls"), ..endpoint })
+                .collect(),
+            connect_file: value("connect-file"),
+            ct: match value("ct-url")
+            {
+                Some(url) => Some(url),
+                None => flag("ct").then(|| String::from(ct::DEFAULT_FRONTEND))
+            },
+            daemon: flag("daemon"),
+            max_files: if flag("unlimited") { 0 } else { get::<usize>(matches, "max-files").unwrap_or(DEFAULT_MAX_FILES) },
+            fetch_missing: flag("fetch-missing") || flag("save-fetched"),
+            files_from: value("files-from"),
+            display_mode: match display_mode
+            {
+                Some("one-line") | Some("one-line-no-header") => OptionsDisplayMode::OneLine,
+                Some("csv") => OptionsDisplayMode::Csv,
+                Some("tsv") => OptionsDisplayMode::Tsv,
+                Some("ndjson") => OptionsDisplayMode::NdJson,
+                Some("print0") => OptionsDisplayMode::Print0,
+                Some("format") => OptionsDisplayMode::Format(value("format").unwrap_or_default()),
+                _ => OptionsDisplayMode::Default
+            },
+            dates: flag("dates"),
+            expiry_warn: get::<u32>(matches, "expiry-warn"),
+            fingerprints: get::<Vec<String>>(matches, "fingerprints").unwrap_or_default(),
+            follow_symlinks: flag("follow-symlinks"),
+            group_by_dir: flag("group-by-dir"),
+            host: value("host"),
+            name: get::<Pattern>(matches, "name"),
+            include_hidden_files: flag("hidden"),
+            max_file_size: get::<u64>(matches, "max-file-size").unwrap_or(DEFAULT_MAX_FILE_SIZE),
+            interval: get::<u64>(matches, "interval").unwrap_or(DEFAULT_INTERVAL),
+            letsencrypt: value("letsencrypt"),
+            list_files: flag("list-files"),
+            listen: value("listen"),
+            apache: value("apache").map(|config| if config.is_empty() { apache::default_config() } else { config }),
+            haproxy: value("haproxy"),
+            postfix: value("postfix"),
+            dovecot: value("dovecot"),
+            kubeconfig: value("kubeconfig").map(|config| if config.is_empty() { kubernetes::default_kubeconfig() } else { config }),
+            kube_context: value("kube-context"),
+            containers: get_all::<String>(matches, "container"),
+            acm: flag("acm"),
+            aws_secrets: get_all::<String>(matches, "aws-secret"),
+            aws_region: value("aws-region"),
+            ocsp: flag("ocsp"),
+            orphans: flag("orphans"),
+            policy: value("policy"),
+            prom_textfile: value("prom-textfile"),
+            export_fullchain: value("export-fullchain"),
+            export_p12: value("export-p12"),
+            export_pass: value("export-pass"),
+            export_k8s_secret: value("export-k8s-secret"),
+            export_haproxy: value("export-haproxy"),
+            export_issuer: flag("export-issuer"),
+            fix_bundle: value("fix-bundle"),
+            in_place: flag("in-place"),
+            suggest_names: flag("suggest-names"),
+            apply: flag("apply"),
+            cleanup_plan: flag("cleanup-plan"),
+            save_snapshot: value("save-snapshot"),
+            load_snapshot: value("load-snapshot"),
+            db: expiring.and_then(|expiring| expiring.get_one::<String>("db").cloned()).or_else(|| value("db")),
+            db_expiring: expiring.map(|expiring| expiring.get_one::<u32>("days").copied().unwrap_or(DEFAULT_DB_DAYS)),
+            split: matches.try_get_many::<String>("split").ok().flatten().map(|mut values| (values.next().cloned().unwrap_or_default(), values.next().cloned().unwrap_or_default())),
+            prune,
+            quiet: flag("quiet"),
+            recursive: flag("recursive"),
+            relative_to: value("relative-to"),
+            sans: flag("sans"),
+            same_file_system: !flag("cross-file-systems"),
+            save_fetched: flag("save-fetched"),
+            show_skipped: flag("show-skipped"),
+            ssh: value("ssh"),
+            starttls: get::<Protocol>(matches, "starttls"),
+            fail_on,
+            key_owners: get_all::<u32>(matches, "key-owner"),
+            suppress_oneline_header: display_mode == Some("one-line-no-header"),
+            syslog: flag("syslog"),
+            timeout: get::<u64>(matches, "timeout").unwrap_or(DEFAULT_TIMEOUT),
+            verify: flag("verify"),
+            verbose: verbosity > 0,
+            log_level: value("log-level").and_then(|level| level.parse().ok()).unwrap_or(match verbosity
+            {
+                0 => log::LevelFilter::Warn,
+                1 => log::LevelFilter::Info,
+                _ => log::LevelFilter::Debug
+            }),
+            warnings: flag("warnings"),
+            watch: flag("watch"),
+            webhook: value("webhook"),
+            // (The connect subcommand's positional arguments are endpoints.)
+            arguments: get_all::<String>(matches, "paths")
+        }
+    }
+}
+
+/// Describe the build: the crate version, the git commit it was built from,
+/// and the OpenSSL version in use (which can affect parsing and validation).
+fn version() -> String
+{
+    format!(
+        "{} (commit {}, {})",
+        env!("CARGO_PKG_VERSION"),
+        env!("SSLCHAINS_GIT_COMMIT"),
+        openssl::version::version()
+    )
+}
+
+/// Add every option for scanning to a command (sslchains itself, and its
+/// scan subcommand); the other subcommands take those relevant to them.
+fn scanning(command: Command) -> Command
+{
+    continuing(acting(exporting(connecting(checking(remote(displaying(reading(command))))))))
+}
+
+/// Add the options for what's read (files, and the certificates servers are
+/// configured with) to a command.
+fn reading(command: Command) -> Command
+{
+    command
+
+        .arg(Arg::new("paths")
+            .value_name("PATH")
+            .num_args(0..)
+            .help("Paths to process (the current directory by default). Use - to read from standard input."))
+        .arg(flag("hidden", "Process hidden files and directories.").short('H'))
+        .arg(flag("recursive", "Process arguments recursively.").short('r'))
+        .arg(flag("follow-symlinks", "Follow symbolic links.").short('S'))
+        .arg(flag("unlimited", "Process an unlimited number of file paths (same as --max-files 0).").short('U'))
+        .arg(flag("cross-file-systems", "Cross filesystem boundaries.").short('X'))
+        .arg(Arg::new("log-level")
+            .long("log-level")
+            .value_name("LEVEL")
+            .value_parser(["off", "error", "warn", "info", "debug", "trace"])
+            .help("Log on stderr at LEVEL: info for files read and what they parsed as, debug to also log why files didn't match (default warn)."))
+        .arg(flag("show-skipped", "Report files which couldn't be parsed (on stderr)."))
+        .arg(flag("warnings", "Report files and directories which couldn't be read (on stderr; implied by -v)."))
+        .arg(Arg::new("prune")
+            .long("prune")
+            .value_name("NAME")
+            .action(ArgAction::Append)
+            .help(format!("Don't descend into directories named NAME (repeatable; in addition to {}).", DEFAULT_PRUNE.join(", "))))
+        .arg(flag("no-default-prune", "Descend into directories which are skipped by default."))
+        .arg(flag("all-files", "Process all files found in directories, not only likely extensions."))
+        .arg(flag("archives", "Descend into .zip, .tar, and .tar.gz archives."))
+        .arg(Arg::new("max-file-size")
+            .long("max-file-size")
+            .value_name("SIZE")
+            .value_parser(parse_size)
+            .help("Skip files larger than SIZE bytes (K, M, G suffixes; 0 for no limit; default 4M)."))
+        .arg(Arg::new("max-files")
+            .long("max-files")
+            .value_name("N")
+            .value_parser(clap::value_parser!(usize))
+            .help("Stop scanning directories after finding N paths, and analyze those (0 for no limit; default 10000)."))
+        .arg(Arg::new("apache")
+            .long("apache")
+            .value_name("CONFIG")
+            .num_args(0..=1)
+            .require_equals(true)
+            .default_missing_value("")
+            .help(format!("Read the certificates and keys of the TLS virtual hosts in Apache's CONFIG (default {}), checking each host against its chain.", apache::DEFAULT_CONFIGS.join(" or "))))
+        .arg(Arg::new("haproxy")
+            .long("haproxy")
+            .value_name("CONFIG")
+            .num_args(0..=1)
+            .require_equals(true)
+            .default_missing_value(haproxy::DEFAULT_CONFIG)
+            .help(format!("Read the certificates bind lines load (with crt and crt-list) in HAProxy's CONFIG (default {}), checking each file holds its key and chain.", haproxy::DEFAULT_CONFIG)))
+        .arg(Arg::new("postfix")
+            .long("postfix")
+            .value_name("CONFIG")
+            .num_args(0..=1)
+            .require_equals(true)
+            .default_missing_value(mail::POSTFIX_CONFIG)
+            .help(format!("Read the certificates and keys smtpd is configured with in Postfix's CONFIG (default {}), checking each against its chain.", mail::POSTFIX_CONFIG)))
+        .arg(Arg::new("dovecot")
+            .long("dovecot")
+            .value_name("CONFIG")
+            .num_args(0..=1)
+            .require_equals(true)
+            .default_missing_value(mail::DOVECOT_CONFIG)
+            .help(format!("Read the certificates and keys in Dovecot's CONFIG (default {}), and the files it includes, checking each against its chain.", mail::DOVECOT_CONFIG)))
+        .arg(Arg::new("files-from")
+            .long("files-from")
+            .value_name("FILE")
+            .help("Read newline or NUL-delimited paths from FILE (- for standard input)."))
+        .arg(Arg::new("cache")
+            .long("cache")
+            .value_name("DIR")
+            .help("Cache what each file holds in DIR (e.g. ~/.cache/sslchains), to speed up repeated scans."))
+}
+
+/// Add the options for reading Kubernetes, containers, and AWS to a command.
+fn remote(command: Command) -> Command
+{
+    command
+
+        .arg(Arg::new("kubeconfig")
+            .long("kubeconfig")
+            .value_name("FILE")
+            .num_args(0..=1)
+            .require_equals(true)
+            .default_missing_value("")
+            .help("Also read the TLS Secrets in every namespace of the Kubernetes cluster FILE names (default $KUBECONFIG, or ~/.kube/config), grouping them by Secret."))
+        .arg(Arg::new("kube-context")
+            .long("kube-context")
+            .value_name("NAME")
+            .requires("kubeconfig")
+            .help("Use the kubeconfig's NAME context, rather than its current context."))
+        .arg(Arg::new("container")
+            .long("container")
+            .value_name("NAME|ID")
+            .action(ArgAction::Append)
+            .help("Also read the keys and certificates in the filesystem of the Docker or Podman container NAME|ID, where it's mounted, or with docker export."))
+        .arg(Arg::new("acm")
+            .long("acm")
+            .action(ArgAction::SetTrue)
+            .help("Also read every certificate in AWS Certificate Manager, with its chain (needs the aws feature)."))
+        .arg(Arg::new("aws-secret")
+            .long("aws-secret")
+            .value_name("ID")
+            .action(ArgAction::Append)
+            .help("Also read the AWS Secrets Manager secret ID (a name or ARN), or each field of one holding JSON (needs the aws feature)."))
+        .arg(Arg::new("aws-region")
+            .long("aws-region")
+            .value_name("REGION")
+            .help("Read ACM and Secrets Manager in REGION (default $AWS_REGION)."))
+}
+
+/// Add the options for selecting and displaying chains to a command.
+fn displaying(command: Command) -> Command
+{
+    command
+
+        .arg(flag("dates", "Show certificate validity dates.").short('d'))
+        .arg(flag("one-line", "Output each chain as a row of values.").short('l'))
+        .arg(flag("one-line-no-header", "Output each chain as a row of values (header excluded).").short('L'))
+        .arg(Arg::new("verbose")
+            .long("verbose")
+            .short('v')
+            .action(ArgAction::Count)
+            .help("Show certificate subjects and issuers, and report skipped files; log files read (as --log-level info does), or with -vv, why they didn't match (as debug does)."))
+        .arg(flag("csv", "Display one comma-separated row per chain."))
+        .arg(flag("tsv", "Display one tab-separated row per chain."))
+        .arg(flag("ndjson", "Stream one JSON object per chain."))
+        .arg(flag("print0", "Output NUL-terminated paths (key, CSR, certificates), with an extra NUL after each chain."))
+        .arg(Arg::new("format")
+            .long("format")
+            .value_name("TEMPLATE")
+            .help("Display each chain using TEMPLATE, e.g. '{name}\\t{key}\\t{notafter}'."))
+        .arg(Arg::new("fingerprints")
+            .long("fingerprints")
+            .value_name("DIGESTS")
+            .num_args(0..=1)
+            .require_equals(true)
+            .default_missing_value("sha256")
+            .value_parser(parse_digests)
+            .help("Show serial numbers and SHA-256 (or SHA-1, e.g. --fingerprints=sha256,sha1) fingerprints."))
+        .arg(Arg::new("host")
+            .long("host")
+            .value_name("HOSTNAME")
+            .help("Only show chains whose current certificate covers HOSTNAME (or an IP address), including by wildcard."))
+        .arg(Arg::new("name")
+            .long("name")
+            .value_name("PATTERN")
+            .value_parser(Pattern::parse)
+            .help("Only show chains whose name, or current certificate's common name or Subject Alternative Names, match PATTERN (case-insensitively), with * and ? wildcards (e.g. '*.example.com'), or a regular expression between slashes (e.g. '/^(www|api)\\./')."))
+        .arg(flag("group-by-dir", "Group chains under a heading for the directory containing their files."))
+        .arg(flag("orphans", "List parsed files which aren't part of any chain."))
+        .arg(Arg::new("ssh")
+            .long("ssh")
+            .value_name("DIRECTORY")
+            .num_args(0..=1)
+            .require_equals(true)
+            .default_missing_value(ssh::DEFAULT_DIRECTORY)
+            .help(format!("Also list OpenSSH host keys and certificates, with the CA keys which signed them, in DIRECTORY (default {}).", ssh::DEFAULT_DIRECTORY))
+            .conflicts_with_all(["csv", "tsv", "ndjson", "print0", "format"]))
+        .arg(flag("sans", "List all Subject Alternative Names of each chain's certificate."))
+        .arg(flag("absolute", "Display absolute paths."))
+        .arg(Arg::new("relative-to")
+            .long("relative-to")
+            .value_name("DIR")
+            .help("Display paths relative to DIR."))
+        .arg(Arg::new("letsencrypt")
+            .long("letsencrypt")
+            .value_name("DIRECTORY")
+            .num_args(0..=1)
+            .require_equals(true)
+            .default_missing_value(letsencrypt::DEFAULT_DIRECTORY)
+            .help(format!("Read every version of each Let's Encrypt lineage in DIRECTORY (default {}), grouping them by lineage, and marking live and stale versions.", letsencrypt::DEFAULT_DIRECTORY))
+            .conflicts_with_all(DISPLAY_MODES))
+}
+
+/// Add the options for checking chains (and the exit status) to a command.
+fn checking(command: Command) -> Command
+{
+    command
+
+        .arg(Arg::new("expiry-warn")
+            .long("expiry-warn")
+            .value_name("DAYS")
+            .value_parser(clap::value_parser!(u32))
+            .help("Flag certificates which have expired or expire within DAYS days; exit 5 if any expire soon, 7 if any have expired."))
+        .arg(flag("check-keys", "Check each private key's consistency, and each certificate's signature structure."))
+        .arg(flag("check-usage", "Check each chain's usage constraints (serverAuth, CA:TRUE, keyCertSign, and pathlen)."))
+        .arg(Arg::new("policy")
+            .long("policy")
+            .value_name("FILE")
+            .help("Check each chain against the policy in FILE (TOML); exit 9 if any violate it."))
+        .arg(flag("strict-permissions", "Exit 8 if any private key is group- or world-readable, or has an unexpected owner."))
+        .arg(Arg::new("key-owner")
+            .long("key-owner")
+            .value_name("USER")
+            .action(ArgAction::Append)
+            .value_parser(permissions::lookup_owner)
+            .help("Expect private keys to belong to USER (a name or uid; repeatable), rather than root or the current user."))
+        .arg(Arg::new("fail-on")
+            .long("fail-on")
+            .value_name("CONDITIONS")
+            .value_parser(parse_conditions)
+            .help(format!(
+                "Exit non-zero only for these comma-separated conditions ({}, all, or none), with the status of the most severe found.",
+                status::CONDITIONS.iter().map(|(name, _)| *name).collect::<Vec<_>>().join(", ")
+            )))
+        .arg(flag("verify", "Verify each chain against the system trust store (VALID, EXPIRED, UNTRUSTED, or INCOMPLETE)."))
+        .arg(flag("fetch-missing", "Fetch missing signing certificates from their caIssuers URLs."))
+        .arg(flag("save-fetched", "Save fetched signing certificates beside the certificates they sign (implies --fetch-missing)."))
+        .arg(flag("ocsp", "Query each chain's OCSP responder for its revocation status (GOOD, REVOKED, or UNKNOWN)."))
+        .arg(flag("ct", "Report whether each certificate is in Certificate Transparency logs, and any unknown certificates logged for its names."))
+        .arg(Arg::new("ct-url")
+            .long("ct-url")
+            .value_name("URL")
+            .help(format!("Search Certificate Transparency logs with this crt.sh-compatible frontend (implies --ct; default {}).", ct::DEFAULT_FRONTEND)))
+        .arg(Arg::new("timeout")
+            .long("timeout")
+            .value_name("SECONDS")
+            .value_parser(clap::value_parser!(u64).range(1..))
+            .help(format!("Give up on network requests (e.g. --ocsp, --ct, --connect) after SECONDS seconds (default {}).", DEFAULT_TIMEOUT)))
+        .arg(Arg::new("prom-textfile")
+            .long("prom-textfile")
+            .value_name("FILE")
+            .help("Write certificate expiry and chain completeness metrics to FILE, for node_exporter's textfile collector."))
+}
+
+/// Add the options for connecting to TLS servers to a command.
+fn connecting(command: Command) -> Command
+{
+    command
+
+        .arg(Arg::new("connect")
+            .long("connect")
+            .value_name("HOST:PORT")
+            .action(ArgAction::Append)
+            .value_parser(parse_endpoint)
+            .help(format!("Connect to a TLS server (repeatable; port {} by default), and report which local chain the certificate it presents belongs to; exit 12 if it can't be reached.", connect::DEFAULT_PORT)))
+        .arg(Arg::new("connect-file")
+            .long("connect-file")
+            .value_name("FILE")
+            .help("Connect to each TLS server listed in FILE, one HOST:PORT[:SNI] per line (- for standard input), as with --connect."))
+        .arg(Arg::new("concurrency")
+            .long("concurrency")
+            .value_name("N")
+            .value_parser(clap::value_parser!(u64).range(1..))
+            .help(format!("Connect to at most N TLS servers at once (default {}).", DEFAULT_CONCURRENCY)))
+        .arg(Arg::new("starttls")
+            .long("starttls")
+            .value_name("PROTOCOL")
+            .requires("endpoints")
+            .value_parser(parse_protocol)
+            .help(format!("Upgrade --connect (and --connect-file) connections to TLS with STARTTLS for PROTOCOL ({}), connecting to its standard port by default.", starttls::PROTOCOLS.join(", "))))
+        .group(ArgGroup::new("endpoints")
+            .args(["connect", "connect-file"])
+            .multiple(true))
+}
+
+/// Add the options for exporting chains to a command.
+fn exporting(command: Command) -> Command
+{
+    command
+
+        .arg(Arg::new("export-fullchain")
+            .long("export-fullchain")
+            .value_name("DIR")
+            .help("Write each complete chain's certificate and intermediates (in order, without the root) to NAME.fullchain.pem in DIR, for configuring servers."))
+        .arg(Arg::new("export-p12")
+            .long("export-p12")
+            .value_name("DIR")
+            .help("Write each complete chain's key, certificate, and intermediates to NAME.p12 (PKCS#12) in DIR, for Java and Windows."))
+        .arg(Arg::new("export-pass")
+            .long("export-pass")
+            .value_name("SOURCE")
+            .requires("export-p12")
+            .help("Encrypt --export-p12 files with the password from SOURCE: pass:PASSWORD, env:VARIABLE, or file:PATH (default no password)."))
+        .arg(Arg::new("export-k8s-secret")
+            .long("export-k8s-secret")
+            .value_name("DIR")
+            .help("Write each complete chain as a Kubernetes TLS Secret (tls.crt with its intermediates, and tls.key) to NAME.secret.yaml in DIR, ready for kubectl apply."))
+        .arg(Arg::new("export-haproxy")
+            .long("export-haproxy")
+            .value_name("DIR")
+            .help("Write each complete chain's certificate, intermediates, and key to NAME.pem in DIR, as HAProxy's crt loads them."))
+        .arg(flag("export-issuer", "Also write the issuer of each --export-haproxy certificate to NAME.pem.issuer, for OCSP stapling.").requires("export-haproxy"))
+}
+
+/// Add the options which do something other than display chains (once) to
+/// a command.
+fn acting(command: Command) -> Command
+{
+    command
+
+        .arg(flag("quiet", "Suppress output; exit 6 if no keys are found, 4 if none has a certificate (see --fail-on).").short('q'))
+        .arg(Arg::new("fix-bundle")
+            .long("fix-bundle")
+            .value_name("FILE")
+            .help("Repair the PEM bundle FILE (putting its certificates in order, removing duplicates and certificates from other chains, and adding missing intermediates from the paths read), writing it to standard output."))
+        .arg(flag("in-place", "Replace the --fix-bundle file with the repaired bundle, keeping the original as FILE.bak (or FILE.bak.1, and so on, if that exists).").requires("fix-bundle"))
+        .arg(Arg::new("split")
+            .long("split")
+            .num_args(2)
+            .value_names(["FILE", "DIR"])
+            .help("Split the PEM bundle FILE into a file per certificate in DIR, named CN_NOTAFTER_SERIAL.pem (keys as their certificate's .key), without replacing existing files."))
+        .arg(flag("suggest-names", "List renames giving each chain's files consistent names (NAME.key, NAME.csr, NAME.crt, and NAME.chain.crt for a certificate with its intermediates), rather than displaying chains."))
+        .arg(flag("apply", "Rename the files as --suggest-names suggests.").requires("suggest-names"))
+        .arg(flag("cleanup-plan", "Write a shell script to standard output which removes expired certificates superseded by newer ones, exact copies of other files, and requests without keys, for review, rather than displaying chains. Nothing is removed."))
+        .arg(Arg::new("save-snapshot")
+            .long("save-snapshot")
+            .value_name("FILE")
+            .help("Write a snapshot of the keys, requests, and certificates found (their digests, paths, and validity, but no private key material) to FILE."))
+        .arg(Arg::new("load-snapshot")
+            .long("load-snapshot")
+            .value_name("FILE")
+            .help("Report what changed at each path since the snapshot in FILE (which may also be the --save-snapshot file)."))
+        .arg(Arg::new("db")
+            .long("db")
+            .value_name("FILE")
+            .help("Record each scan (its host and time, and the files, keys, requests, certificates, and chains found) in the SQLite database FILE, creating it if needed (needs the db feature)."))
+        .arg(flag("list-files", "List the files which would be processed (after expansion, filtering, and limits), and exit."))
+}
+
+/// Add the options for keeping running to a command.
+fn continuing(command: Command) -> Command
+{
+    command
+
+        .arg(flag("watch", "Keep running, and display chains again as their files change (Linux only).")
+            .conflicts_with_all(["quiet", "list-files", "kubeconfig", "container", "acm", "aws-secret"]))
+        .arg(flag("daemon", "Keep running, rescanning every --interval, and report new alerts (expiring certificates, broken chains, removed files).")
+            .conflicts_with_all(["quiet", "list-files", "watch"]))
+        .arg(Arg::new("interval")
+            .long("interval")
+            .value_name("DURATION")
+            .value_parser(parse_duration)
+            .help("Rescan this often in daemon mode, e.g. 30m or 6h (default 6h)."))
+        .arg(Arg::new("webhook")
+            .long("webhook")
+            .value_name("URL")
+            .help("Post alerts in daemon mode to URL, as JSON."))
+        .arg(Arg::new("listen")
+            .long("listen")
+            .value_name("ADDRESS")
+            .requires("daemon")
+            .help("Serve /chains (JSON) and /metrics (Prometheus) over HTTP in daemon mode on ADDRESS, e.g. 127.0.0.1:9870."))
+        .arg(syslog().requires("continuous"))
+        .group(ArgGroup::new("continuous")
+            .args(["daemon", "watch"])
+            .multiple(true))
+}
+
+/// An option's value, if it was given (or has a default), and the command
+/// takes it.
+fn get<T: Any + Clone + Send + Sync>(matches: &ArgMatches, id: &str) -> Option<T>
+{
+    matches.try_get_one::<T>(id).ok().flatten().cloned()
+}
+
+/// An option's values, if the command takes it.
+fn get_all<T: Any + Clone + Send + Sync>(matches: &ArgMatches, id: &str) -> Vec<T>
+{
+    matches.try_get_many::<T>(id).ok().flatten().into_iter().flatten().cloned().collect()
+}
+
+/// Describe --syslog, which the watch subcommand takes too.
+fn syslog() -> Arg
+{
+    flag("syslog", "Also log findings in daemon or watch mode (new and broken chains, expiring certificates) to syslog, or the systemd journal.")
+}
+
+/// Describe a long option which takes no value.
+fn flag(name: &'static str, help: &'static str) -> Arg
+{
+    Arg::new(name).long(name).action(ArgAction::SetTrue).help(help)
+}
+
+/// A pattern chains' names are matched against (--name): a wildcard pattern
+/// (see policy::is_match), or a regular expression between slashes, e.g.
+/// "/^(www|api)\./". Both match case-insensitively.
+#[derive(Clone, Debug)]
+pub enum Pattern
+{
+    Wildcard(String),
+    Regex(Regex),
+}
+
+impl Pattern
+{
+    pub fn parse(pattern: &str) -> Result<Pattern, String>
+    {
+        match pattern.strip_prefix('/').and_then(|pattern| pattern.strip_suffix('/'))
+        {
+            Some(expression) => RegexBuilder::new(expression).case_insensitive(true).build().map(Pattern::Regex).map_err(|e| e.to_string()),
+            None => Ok(Pattern::Wildcard(pattern.to_string()))
+        }
+    }
+
+    pub fn is_match(&self, text: &str) -> bool
+    {
+        match self
+        {
+            Pattern::Wildcard(pattern) => policy::is_match(pattern, text),
+            Pattern::Regex(regex) => regex.is_match(text)
+        }
+    }
+}
+
+/// The names --name matches: a chain's name, and its current certificate's
+/// common name and Subject Alternative Names (without their types, e.g.
+/// "example.com" for "DNS:example.com").
+fn names(chain: &Chain) -> Vec<String>
+{
+    let mut names = vec![format::get_display_name(chain)];
+
+    if let Some(certificate) = chain.certificates.first()
+    {
+        names.extend(certificate.common_name().and_then(|name| name.data().as_utf8().ok()).map(|name| name.to_string()));
+        names.extend(certificate.subject_alt_names().iter().map(|name| name.split_once(':').map_or(name.as_str(), |(_, value)| value).to_string()));
+    }
+
+    names
+}
+
+/// Parse a size in bytes, with an optional K, M, or G (binary) suffix.
+fn parse_size(value: &str) -> Result<u64, String>
+{
+    let error = || format!("invalid size -- '{}'", value);
+
+    let (digits, multiplier) = match value.chars().last().map(|c| c.to_ascii_uppercase())
+    {
+        Some('K') => (&value[..value.len() - 1], 1024),
+        Some('M') => (&value[..value.len() - 1], 1024 * 1024),
+        Some('G') => (&value[..value.len() - 1], 1024 * 1024 * 1024),
+        _ => (value, 1)
+    };
+
+    digits.parse::<u64>()
+        .map_err(|_| error())?
+        .checked_mul(multiplier)
+        .ok_or_else(error)
+}
+
+/// Parse a duration in seconds, with an optional s, m, h, or d suffix, into
+/// seconds.
+fn parse_duration(value: &str) -> Result<u64, String>
+{
+    let error = || format!("invalid duration -- '{}'", value);
+
+    let (digits, multiplier) = match value.chars().last().map(|c| c.to_ascii_lowercase())
+    {
+        Some('s') => (&value[..value.len() - 1], 1),
+        Some('m') => (&value[..value.len() - 1], 60),
+        Some('h') => (&value[..value.len() - 1], 60 * 60),
+        Some('d') => (&value[..value.len() - 1], 24 * 60 * 60),
+        _ => (value, 1)
+    };
+
+    match digits.parse::<u64>().map_err(|_| error())?.checked_mul(multiplier)
+    {
+        Some(0) | None => Err(error()),
+        Some(seconds) => Ok(seconds)
+    }
+}
+
+/// Parse a TLS server's host and port.
+fn parse_endpoint(value: &str) -> Result<Endpoint, String>
+{
+    Endpoint::parse(value).ok_or_else(|| format!("invalid endpoint -- '{}'", value))
+}
+
+/// Parse a STARTTLS protocol name.
+fn parse_protocol(value: &str) -> Result<Protocol, String>
+{
+    Protocol::parse(value).ok_or_else(|| format!("unsupported protocol -- '{}' (expected one of {})", value, starttls::PROTOCOLS.join(", ")))
+}
+
+/// Parse a comma-separated list of conditions to fail on (--fail-on).
+fn parse_conditions(value: &str) -> Result<Vec<Condition>, String>
+{
+    let mut conditions: Vec<Condition> = vec![];
+
+    for name in value.split(',').map(str::trim)
+    {
+        match name
+        {
+            "all" => conditions.extend(status::CONDITIONS.iter().map(|(_, condition)| *condition)),
+            "none" => {},
+            _ => conditions.push(Condition::parse(name).ok_or_else(|| format!("unknown condition -- '{}'", name))?)
+        }
+    }
+
+    conditions.sort_unstable();
+    conditions.dedup();
+
+    Ok(conditions)
+}
+
+/// Parse a comma-separated list of fingerprint digest algorithms.
+fn parse_digests(value: &str) -> Result<Vec<String>, String>
+{
+    value.split(',')
+        .map(|digest| match digest.to_lowercase().as_str()
+        {
+            "sha256" | "sha-256" => Ok(String::from("sha256")),
+            "sha1" | "sha-1" => Ok(String::from("sha1")),
+            _ => Err(format!("invalid fingerprint algorithm -- '{}'", digest))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    fn parse(args: &[&str]) -> Options
+    {
+        Options::from_args([&["sslchains"], args].concat()).unwrap()
+    }
+
+    #[test]
+    fn parses_short_and_long_options()
+    {
+        let short = parse(&["-rHSX", "-d"]);
+        let long = parse(&["--recursive", "--hidden", "--follow-symlinks", "--cross-file-systems", "--dates"]);
+
+        for options in [short, long]
+        {
+            assert!(options.recursive);
+            assert!(options.include_hidden_files);
+            assert!(options.follow_symlinks);
+            assert!(!options.same_file_system);
+            assert!(options.dates);
+        }
+
+        let options = parse(&["-v", "--log-level", "debug"]);
+
+        assert!(options.verbose);
+        assert_eq!(options.log_level, log::LevelFilter::Debug);
+        assert_eq!(parse(&["-v"]).log_level, log::LevelFilter::Info);
+        assert_eq!(parse(&["-vv"]).log_level, log::LevelFilter::Debug);
+        assert_eq!(parse(&["-vv", "--log-level", "warn"]).log_level, log::LevelFilter::Warn);
+        assert_eq!(parse(&[]).log_level, log::LevelFilter::Warn);
+
+        let options = parse(&["--fingerprints", "--max-file-size", "64K", "--relative-to=samples", "--max-files", "50"]);
+
+        assert_eq!(options.fingerprints, vec![String::from("sha256")]);
+        assert_eq!(options.max_file_size, 64 * 1024);
+        assert_eq!(options.relative_to, Some(String::from("samples")));
+        assert_eq!(options.max_files, 50);
+        assert_eq!(parse(&["--cache", "cache"]).cache, Some(String::from("cache")));
+        assert_eq!(parse(&["--expiry-warn", "30"]).expiry_warn, Some(30));
+        assert!(parse(&["--verify"]).verify);
+        assert!(parse(&["--ocsp"]).ocsp);
+        assert!(parse(&["--watch"]).watch);
+        assert!(parse(&["--daemon"]).daemon);
+        assert!(parse(&["--watch", "--syslog"]).syslog);
+        assert_eq!(parse(&["--daemon", "--listen", "127.0.0.1:9870"]).listen.as_deref(), Some("127.0.0.1:9870"));
+        assert_eq!(parse(&["--interval", "6h"]).interval, 6 * 60 * 60);
+        assert_eq!(parse(&["--webhook", "https://hooks.example.com/tls"]).webhook.as_deref(), Some("https://hooks.example.com/tls"));
+        assert_eq!(parse(&["--policy", "policy.toml"]).policy.as_deref(), Some("policy.toml"));
+        assert_eq!(parse(&["--prom-textfile", "sslchains.prom"]).prom_textfile.as_deref(), Some("sslchains.prom"));
+        assert_eq!(parse(&["--export-fullchain", "exported"]).export_fullchain.as_deref(), Some("exported"));
+        assert_eq!(parse(&["--export-p12", "exported", "--export-pass", "env:P12_PASS"]).export_pass.as_deref(), Some("env:P12_PASS"));
+        assert_eq!(parse(&["--export-k8s-secret", "manifests"]).export_k8s_secret.as_deref(), Some("manifests"));
+        assert!(parse(&["--export-haproxy", "haproxy", "--export-issuer"]).export_issuer);
+        assert!(parse(&["--fix-bundle", "fullchain.pem", "--in-place"]).in_place);
+        assert!(parse(&["--suggest-names", "--apply"]).apply);
+        assert!(parse(&["--cleanup-plan"]).cleanup_plan);
+        assert_eq!(parse(&["--save-snapshot", "today.json"]).save_snapshot.as_deref(), Some("today.json"));
+        assert_eq!(parse(&["--load-snapshot", "yesterday.json"]).load_snapshot.as_deref(), Some("yesterday.json"));
+        assert_eq!(parse(&["--db", "sslchains.db", "-r", "/etc/ssl"]).db.as_deref(), Some("sslchains.db"));
+        assert_eq!(parse(&["db", "expiring", "--db", "sslchains.db", "--days", "7"]).db_expiring, Some(7));
+        assert_eq!(parse(&["--db", "sslchains.db", "db", "expiring"]).db_expiring, Some(30));
+        assert_eq!(parse(&["--db", "sslchains.db", "db", "expiring"]).db.as_deref(), Some("sslchains.db"));
+        assert_eq!(parse(&["--split", "certs.pem", "split"]).split, Some((String::from("certs.pem"), String::from("split"))));
+        assert_eq!(parse(&["--ct"]).ct.as_deref(), Some(ct::DEFAULT_FRONTEND));
+        assert_eq!(parse(&["--ct-url", "https://ct.example.com/"]).ct.as_deref(), Some("https://ct.example.com/"));
+        assert_eq!(parse(&[]).ct, None);
+        assert!(parse(&["--save-fetched"]).fetch_missing);
+        assert_eq!(parse(&["--timeout", "3"]).timeout, 3);
+        assert_eq!(parse(&[]).timeout, DEFAULT_TIMEOUT);
+        assert_eq!(parse(&["--strict-permissions"]).fail_on, vec![Condition::Permissions]);
+        assert_eq!(parse(&["--key-owner", "root", "--key-owner=1000"]).key_owners, vec![0, 1000]);
+        assert_eq!(parse(&["--strict-permissions", "--expiry-warn", "30"]).fail_on, vec![Condition::Expiring, Condition::Expired, Condition::Permissions]);
+        assert_eq!(parse(&["-q"]).fail_on, vec![Condition::NoChains, Condition::NoCompleteChains]);
+        assert_eq!(parse(&["-q", "--fail-on", "expired,parse-errors"]).fail_on, vec![Condition::ParseErrors, Condition::Expired]);
+        assert_eq!(parse(&["--fail-on", "all"]).fail_on.len(), status::CONDITIONS.len());
+        assert!(parse(&["--policy", "policy.toml", "--fail-on", "none"]).fail_on.is_empty());
+        assert!(parse(&[]).fail_on.is_empty());
+        assert!(parse(&["--check-keys"]).check_keys);
+        assert_eq!(parse(&["--apache=samples/apache/httpd.conf"]).apache.as_deref(), Some("samples/apache/httpd.conf"));
+        assert!(parse(&["--apache"]).apache.is_some());
+        assert_eq!(parse(&["--haproxy"]).haproxy.as_deref(), Some(haproxy::DEFAULT_CONFIG));
+        assert_eq!(parse(&["--postfix=samples/mail/main.cf"]).postfix.as_deref(), Some("samples/mail/main.cf"));
+        assert_eq!(parse(&["--dovecot"]).dovecot.as_deref(), Some(mail::DOVECOT_CONFIG));
+        assert_eq!(parse(&["--kubeconfig=samples/kubeconfig/config", "--kube-context", "prod"]).kube_context.as_deref(), Some("prod"));
+        assert!(parse(&["--kubeconfig"]).kubeconfig.is_some());
+        assert_eq!(parse(&["--container", "web", "--container=0123abcd"]).containers, vec!["web", "0123abcd"]);
+        assert!(parse(&["--acm", "--aws-region", "eu-west-1"]).acm);
+        assert_eq!(parse(&["--aws-secret", "prod/tls", "--aws-secret", "staging/tls"]).aws_secrets, vec!["prod/tls", "staging/tls"]);
+        assert_eq!(parse(&["--letsencrypt"]).letsencrypt.as_deref(), Some(letsencrypt::DEFAULT_DIRECTORY));
+        assert_eq!(parse(&["--ssh"]).ssh.as_deref(), Some(ssh::DEFAULT_DIRECTORY));
+        assert_eq!(parse(&["--ssh=samples/ssh"]).ssh.as_deref(), Some("samples/ssh"));
+        assert!(parse(&["--check-usage"]).check_usage);
+        assert_eq!(parse(&["--connect", "example.com", "--connect", "[::1]:8443"]).connect.len(), 2);
+        assert_eq!(parse(&["--connect", "mail.example.com", "--starttls", "smtp"]).connect[0].port(), 25);
+        assert_eq!(parse(&["--connect-file", "hosts.txt"]).connect_file.as_deref(), Some("hosts.txt"));
+        assert_eq!(parse(&["--connect-file", "hosts.txt", "--starttls", "imap"]).starttls, Some(Protocol::Imap));
+        assert_eq!(parse(&["--concurrency", "32"]).concurrency, 32);
+        assert_eq!(parse(&[]).concurrency, DEFAULT_CONCURRENCY);
+        assert_eq!(parse(&["--host", "example.com"]).host, Some(String::from("example.com")));
+        assert!(parse(&["--name", "*.example.com"]).name.is_some_and(|name| name.is_match("www.example.com")));
+        assert_eq!(parse(&["--max-files", "50", "-U"]).max_files, 0);
+    }
+
+    #[test]
+    fn parses_name_patterns()
+    {
+        let wildcard = Pattern::parse("*.example.com").unwrap();
+
+        assert!(wildcard.is_match("www.Example.com") && wildcard.is_match("*.example.com"));
+        assert!(!wildcard.is_match("example.com"));
+
+        let regex = Pattern::parse("/^(www|api)\\.example\\.com$/").unwrap();
+
+        assert!(regex.is_match("api.example.com") && regex.is_match("API.Example.com") && !regex.is_match("mail.example.com"));
+        assert!(Pattern::parse("/(/").is_err());
+        assert!(Pattern::parse("/").unwrap().is_match("/"));
+
+        // Names are matched against every name of the current certificate.
+        let chains = sslchains::chain::build(vec![String::from("samples/self_signed_san.key"), String::from("samples/self_signed_san.crt")]).unwrap();
+
+        assert!(parse(&["--name", "san.example.com"]).selects(&chains[0]));
+        assert!(parse(&["--name", "/^WWW\\./"]).selects(&chains[0]));
+        assert!(parse(&["--name", "example.com"]).selects(&chains[0]));
+        assert!(!parse(&["--name", "mail.*"]).selects(&chains[0]));
+    }
+
+    #[test]
+    fn parses_subcommands()
+    {
+        assert_eq!(parse(&["scan", "-r", "/etc/ssl"]).arguments, parse(&["-r", "/etc/ssl"]).arguments);
+        assert!(parse(&["scan", "-r"]).recursive);
+        assert!(parse(&["verify", "--dates"]).verify);
+        assert!(parse(&["watch", "-r"]).watch);
+
+        let options = parse(&["export", "--as", "p12", "--to", "exported", "--pass", "env:P12_PASS", "/etc/ssl"]);
+
+        assert_eq!(options.export_p12.as_deref(), Some("exported"));
+        assert_eq!(options.export_pass.as_deref(), Some("env:P12_PASS"));
+        assert_eq!(options.arguments, vec!["/etc/ssl"]);
+        assert_eq!(parse(&["export", "--as", "fullchain", "--to", "exported"]).export_fullchain.as_deref(), Some("exported"));
+        assert!(parse(&["export", "--as", "haproxy", "--to", "haproxy", "--issuer"]).export_issuer);
+        assert!(Options::from_args(["sslchains", "export", "--to", "exported"]).is_err());
+
+        let options = parse(&["diff", "yesterday.json", "/etc/ssl"]);
+
+        assert_eq!(options.load_snapshot.as_deref(), Some("yesterday.json"));
+        assert_eq!(options.arguments, vec!["/etc/ssl"]);
+        assert!(Options::from_args(["sslchains", "diff"]).is_err());
+
+        let options = parse(&["connect", "example.com", "mail.example.com", "--starttls", "smtp", "--files-from", "paths.txt"]);
+
+        assert_eq!(options.connect.len(), 2);
+        assert_eq!(options.connect[1].port(), 25);
+        assert!(options.arguments.is_empty());
+        assert!(Options::from_args(["sslchains", "connect", "example.com:http"]).is_err());
+        assert!(Options::from_args(["sslchains", "connect"]).is_err());
+
+        let options = parse(&["connect", "--connect-file", "hosts.txt"]);
+
+        assert!(options.connect.is_empty());
+        assert_eq!(options.connect_file.as_deref(), Some("hosts.txt"));
+
+        // Subcommands only take the options relevant to them.
+        assert!(parse(&["watch", "--syslog"]).syslog);
+        assert!(Options::from_args(["sslchains", "watch", "--kubeconfig"]).is_err());
+        assert!(Options::from_args(["sslchains", "watch", "--quiet"]).is_err());
+        assert!(Options::from_args(["sslchains", "verify", "--export-p12", "exported"]).is_err());
+        assert!(Options::from_args(["sslchains", "diff", "yesterday.json", "--connect", "example.com"]).is_err());
+
+        // Paths named like subcommands need a directory, or --.
+        assert_eq!(parse(&["./scan"]).arguments, vec!["./scan"]);
+        assert_eq!(parse(&["-r", "--", "scan"]).arguments, vec!["scan"]);
+    }
+
+    #[test]
+    fn parses_prune_list()
+    {
+        let defaults: Vec<String> = DEFAULT_PRUNE.iter().map(|name| name.to_string()).collect();
+
+        assert_eq!(parse(&[]).prune, defaults);
+        assert_eq!(parse(&["--prune", "vendor", "--prune=target"]).prune, [defaults, vec![String::from("vendor"), String::from("target")]].concat());
+        assert_eq!(parse(&["--no-default-prune", "--prune", "vendor"]).prune, vec![String::from("vendor")]);
+    }
+
+    #[test]
+    fn interleaves_options_and_paths()
+    {
+        let options = parse(&["samples", "-r", "-", "--dates", "--", "-d"]);
+
+        assert!(options.recursive);
+        assert!(options.dates);
+        assert_eq!(options.arguments, vec![String::from("samples"), String::from("-"), String::from("-d")]);
+    }
+
+    #[test]
+    fn accepts_dash_prefixed_paths()
+    {
+        let options = parse(&["-r", "--", "-weird.pem", "--dates", "-"]);
+
+        assert!(options.recursive);
+        assert!(!options.dates);
+        assert_eq!(
+            options.arguments,
+            vec![String::from("-weird.pem"), String::from("--dates"), String::from("-")]
+        );
+
+        // Without the separator, they're (invalid) options.
+        assert!(Options::from_args(["sslchains", "-weird.pem"]).is_err());
+    }
+
+    #[test]
+    fn uses_last_display_mode()
+    {
+        let options = parse(&["-L", "--csv"]);
+
+        assert!(matches!(options.display_mode, OptionsDisplayMode::Csv));
+        assert!(!options.suppress_oneline_header);
+
+        let options = parse(&["--format", "{name}", "-L"]);
+
+        assert!(matches!(options.display_mode, OptionsDisplayMode::OneLine));
+        assert!(options.suppress_oneline_header);
+
+        assert!(matches!(parse(&[]).display_mode, OptionsDisplayMode::Default));
+    }
+
+    #[test]
+    fn prints_version()
+    {
+        for flag in ["-V", "--version"]
+        {
+            match Options::from_args(["sslchains", flag])
+            {
+                Err(e) => {
+                    assert_eq!(e.kind(), ErrorKind::DisplayVersion);
+                    assert!(e.to_string().contains(env!("CARGO_PKG_VERSION")));
+                    assert!(e.to_string().contains("OpenSSL"));
+                },
+                Ok(_) => panic!("{} wasn't handled", flag)
             }
         }
-        Ok(())
     }
 
+    #[test]
+    fn rejects_invalid_options()
+    {
+        assert!(Options::from_args(["sslchains", "--bogus"]).is_err());
+        assert!(Options::from_args(["sslchains", "--max-file-size", "4 MB"]).is_err());
+        assert!(Options::from_args(["sslchains", "--fingerprints=md5"]).is_err());
+        assert!(Options::from_args(["sslchains", "--format"]).is_err());
+        assert!(Options::from_args(["sslchains", "--syslog"]).is_err());
+        assert!(Options::from_args(["sslchains", "--connect", "example.com:0"]).is_err());
+        assert!(Options::from_args(["sslchains", "--connect", "example.com", "--starttls", "xmpp"]).is_err());
+        assert!(Options::from_args(["sslchains", "--starttls", "smtp"]).is_err());
+        assert!(Options::from_args(["sslchains", "--listen", "127.0.0.1:9870"]).is_err());
+        assert!(Options::from_args(["sslchains", "--fail-on", "expired,revoked"]).is_err());
+        assert!(Options::from_args(["sslchains", "--files-from", "-", "-"]).is_err());
+        assert!(Options::from_args(["sslchains", "--files-from", "-", "--connect-file", "-"]).is_err());
+        assert!(Options::from_args(["sslchains", "--files-from", "paths.txt", "--connect-file", "-"]).is_ok());
+        assert!(Options::from_args(["sslchains", "--name", "/[/"]).is_err());
+        assert!(Options::from_args(["sslchains", "--key-owner", "no-such-user-for-sslchains"]).is_err());
+        assert!(Options::from_args(["sslchains", "--timeout", "0"]).is_err());
+        assert!(Options::from_args(["sslchains", "--ssh", "--csv"]).is_err());
+        assert!(Options::from_args(["sslchains", "--letsencrypt", "-l"]).is_err());
+        assert!(Options::from_args(["sslchains", "--watch", "--container", "web"]).is_err());
+    }
+
+    #[test]
+    fn parses_sizes()
+    {
+        assert_eq!(parse_size("0"), Ok(0));
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(parse_size("64k"), Ok(64 * 1024));
+        assert_eq!(parse_size("4M"), Ok(4 * 1024 * 1024));
+        assert_eq!(parse_size("1G"), Ok(1024 * 1024 * 1024));
+        assert!(parse_size("").is_err());
+        assert!(parse_size("M").is_err());
+        assert!(parse_size("4 MB").is_err());
+    }
+
+    #[test]
+    fn parses_durations()
+    {
+        assert_eq!(parse_duration("90"), Ok(90));
+        assert_eq!(parse_duration("30m"), Ok(30 * 60));
+        assert_eq!(parse_duration("6h"), Ok(6 * 60 * 60));
+        assert_eq!(parse_duration("1D"), Ok(24 * 60 * 60));
+        assert!(parse_duration("0s").is_err());
+        assert!(parse_duration("h").is_err());
+        assert!(parse_duration("6 hours").is_err());
+    }
+
+    #[test]
+    fn parses_digests()
+    {
+        assert_eq!(parse_digests("sha256"), Ok(vec![String::from("sha256")]));
+        assert_eq!(parse_digests("SHA-256,sha1"), Ok(vec![String::from("sha256"), String::from("sha1")]));
+        assert!(parse_digests("md5").is_err());
+    }
 }
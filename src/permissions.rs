@@ -0,0 +1,143 @@
+//! Copyright (C) 2022 Gaz J.
+//!
+//! This program is free software: you can redistribute it and/or modify
+//! it under the terms of the GNU General Public License as published by
+//! the Free Software Foundation, either version 3 of the License, or
+//! (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//! GNU General Public License for more details.
+//!
+//! You should have received a copy of the GNU General Public License
+//! along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Checks private key files for lax permissions and unexpected owners, as
+//! keys readable by other users are as good as leaked.
+
+use crate::chain;
+
+/// Describe any problems with a private key file's permissions or owner,
+/// e.g. "world-readable (mode 0644)". Keys are expected to belong to one of
+/// the owners (uids), or when there are none, to root or whoever runs the
+/// scan. Keys in archives and manifests are checked by their containing
+/// file. Keys which aren't files (e.g. from standard input) have none.
+#[cfg(unix)]
+pub fn check(path: &str, owners: &[u32]) -> Vec<String>
+{
+    use std::fs;
+    use std::os::unix::fs::MetadataExt;
+
+    let path = chain::container_path(path).unwrap_or(path);
+
+    let metadata = match fs::metadata(path)
+    {
+        Ok(metadata) if metadata.is_file() => metadata,
+        _ => return vec![]
+    };
+
+    let mode = metadata.mode() & 0o7777;
+    let mut issues = vec![];
+
+    if mode & 0o004 != 0
+    {
+        issues.push(format!("world-readable (mode {:04o})", mode));
+    }
+    else if mode & 0o040 != 0
+    {
+        issues.push(format!("group-readable (mode {:04o})", mode));
+    }
+
+    let owner = metadata.uid();
+
+    if owners.is_empty()
+    {
+        if owner != 0 && owner != unsafe { libc::geteuid() }
+        {
+            issues.push(format!("owned by uid {} (not root or the current user)", owner));
+        }
+    }
+    else if !owners.contains(&owner)
+    {
+        issues.push(format!("owned by uid {} (not an allowed owner)", owner));
+    }
+
+    issues
+}
+
+#[cfg(not(unix))]
+pub fn check(_path: &str, _owners: &[u32]) -> Vec<String>
+{
+    vec![]
+}
+
+/// Look up a user who may own private keys, by name or numeric uid.
+#[cfg(unix)]
+pub fn lookup_owner(user: &str) -> Result<u32, String>
+{
+    if let Ok(uid) = user.parse()
+    {
+        return Ok(uid);
+    }
+
+    let name = std::ffi::CString::new(user).map_err(|e| e.to_string())?;
+    let entry = unsafe { libc::getpwnam(name.as_ptr()) };
+
+    if entry.is_null()
+    {
+        return Err(format!("no such user: {}", user));
+    }
+
+    Ok(unsafe { (*entry).pw_uid })
+}
+
+#[cfg(not(unix))]
+pub fn lookup_owner(user: &str) -> Result<u32, String>
+{
+    user.parse().map_err(|_| format!("not a uid: {}", user))
+}
+
+#[cfg(all(test, unix))]
+mod test
+{
+    use super::*;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn looks_up_owners()
+    {
+        assert_eq!(lookup_owner("root"), Ok(0));
+        assert_eq!(lookup_owner("1000"), Ok(1000));
+        assert!(lookup_owner("no-such-user-for-sslchains").is_err());
+    }
+
+    #[test]
+    fn checks_key_permissions()
+    {
+        let path = std::env::temp_dir().join(format!("sslchains-test-{}.key", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        fs::copy("samples/ca_signed.key", path).unwrap();
+
+        for (mode, expected) in [(0o600, vec![]), (0o640, vec!["group-readable (mode 0640)"]), (0o644, vec!["world-readable (mode 0644)"])]
+        {
+            fs::set_permissions(path, fs::Permissions::from_mode(mode)).unwrap();
+
+            assert_eq!(check(path, &[]), expected);
+        }
+
+        // Keys owned by someone else than the allowed owners are flagged.
+        let uid = unsafe { libc::geteuid() };
+
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600)).unwrap();
+
+        assert!(check(path, &[uid]).is_empty());
+        assert_eq!(check(path, &[uid + 1]), vec![format!("owned by uid {} (not an allowed owner)", uid)]);
+
+        fs::remove_file(path).unwrap();
+
+        assert!(check(chain::STDIN_PATH, &[]).is_empty());
+    }
+}
@@ -0,0 +1,320 @@
+//! Copyright (C) 2022 Gaz J.
+//!
+//! This program is free software: you can redistribute it and/or modify
+//! it under the terms of the GNU General Public License as published by
+//! the Free Software Foundation, either version 3 of the License, or
+//! (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//! GNU General Public License for more details.
+//!
+//! You should have received a copy of the GNU General Public License
+//! along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Organizational policies (--policy) which chains are checked against, so
+//! sslchains can gate deployments in CI.
+//!
+//! Policies are TOML files of top-level settings, each optional:
+//!
+//! ```toml
+//! allowed_key_types = ["RSA", "EC"]
+//! allowed_rsa_bits = [2048, 3072, 4096]
+//! allowed_ec_bits = [256, 384]
+//! allowed_issuers = ["*CN=R1?", "*CN=E?"]   # patterns for the issuer's name
+//! max_validity_days = 398
+//! san_patterns = ["example.com", "*.example.com"]
+//! allow_wildcards = false
+//! ```
+//!
+//! Patterns match case-insensitively, with `*` matching any run of characters
+//! and `?` any one character.
+
+use std::fs;
+use openssl::pkey::Id;
+use serde::Deserialize;
+
+use crate::chain::{self, Chain};
+use crate::format::format_name;
+
+/// Rules which chains must follow. Unset rules allow anything.
+#[derive(Debug, Default, PartialEq)]
+pub struct Policy
+{
+    /// Key algorithms allowed (e.g. "RSA", "EC", "Ed25519").
+    pub key_types: Option<Vec<String>>,
+
+    /// RSA key sizes allowed, in bits.
+    pub rsa_bits: Option<Vec<u32>>,
+
+    /// EC key sizes allowed, in bits.
+    pub ec_bits: Option<Vec<u32>>,
+
+    /// Patterns for the names of the issuers allowed, e.g. "*CN=R11".
+    pub issuers: Option<Vec<String>>,
+
+    /// Longest validity period allowed, in days.
+    pub max_validity_days: Option<u32>,
+
+    /// Patterns which every DNS name a certificate covers must match.
+    pub san_patterns: Option<Vec<String>>,
+
+    /// Whether wildcard names (e.g. "*.example.com") are allowed.
+    pub forbid_wildcards: bool,
+}
+
+/// A policy file's settings, as written.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Settings
+{
+    allowed_key_types: Option<Vec<String>>,
+    allowed_rsa_bits: Option<Vec<u32>>,
+    allowed_ec_bits: Option<Vec<u32>>,
+    allowed_issuers: Option<Vec<String>>,
+    max_validity_days: Option<u32>,
+    san_patterns: Option<Vec<String>>,
+    allow_wildcards: Option<bool>,
+}
+
+impl Policy
+{
+    /// Read a policy file.
+    pub fn load(path: &str) -> Result<Policy, String>
+    {
+        let contents = fs::read_to_string(path).map_err(|e| format!("couldn't read policy {}: {}", path, e))?;
+
+        Policy::parse(&contents).map_err(|e| format!("invalid policy {}: {}", path, e))
+    }
+
+    /// Parse a policy from TOML. Unknown settings are errors, so that a typo
+    /// doesn't quietly disable a rule.
+    pub fn parse(contents: &str) -> Result<Policy, String>
+    {
+        let settings: Settings = toml::from_str(contents).map_err(|e| e.to_string())?;
+
+        Ok(Policy {
+            key_types: settings.allowed_key_types,
+            rsa_bits: settings.allowed_rsa_bits,
+            ec_bits: settings.allowed_ec_bits,
+            issuers: settings.allowed_issuers,
+            max_validity_days: settings.max_validity_days,
+            san_patterns: settings.san_patterns,
+            forbid_wildcards: settings.allow_wildcards == Some(false),
+        })
+    }
+
+    /// Check a chain's key and current certificate against the policy,
+    /// returning violations as (path, violation) pairs.
+    pub fn check(&self, chain: &Chain) -> Vec<(String, String)>
+    {
+        let mut violations = vec![];
+        let certificate = chain.certificates.first();
+
+        // The key is checked even without a certificate, or through the
+        // certificate when the key wasn't found.
+        let key = match (&chain.key, certificate)
+        {
+            (Some(key), _) => Some((key.path.clone(), key.key.id(), key.key.bits())),
+            (None, Some(certificate)) => certificate.certificate.public_key().ok()
+                .map(|key| (certificate.path.clone(), key.id(), key.bits())),
+            (None, None) => None
+        };
+
+        if let Some((path, id, bits)) = key
+        {
+            let algorithm = chain::key_algorithm(id);
+
+            if let Some(types) = &self.key_types
+            {
+                if !types.iter().any(|allowed| allowed.eq_ignore_ascii_case(algorithm))
+                {
+                    violations.push((path.clone(), format!("{} keys aren't allowed (allowed: {})", algorithm, types.join(", "))));
+                }
+            }
+
+            let sizes = match id
+            {
+                Id::RSA => &self.rsa_bits,
+                Id::EC => &self.ec_bits,
+                _ => &None
+            };
+
+            if let Some(sizes) = sizes.as_ref().filter(|sizes| !sizes.contains(&bits))
+            {
+                let allowed: Vec<String> = sizes.iter().map(|bits| bits.to_string()).collect();
+
+                violations.push((path, format!("{}-bit {} keys aren't allowed (allowed: {})", bits, algorithm, allowed.join(", "))));
+            }
+        }
+
+        let certificate = match certificate
+        {
+            Some(certificate) => certificate,
+            None => return violations
+        };
+
+        let mut violation = |violation: String| violations.push((certificate.path.clone(), violation));
+
+        if let Some(issuers) = &self.issuers
+        {
+            let issuer = format_name(certificate.certificate.issuer_name());
+
+            if !issuers.iter().any(|pattern| is_match(pattern, &issuer))
+            {
+                violation(format!("issuer {} isn't allowed", issuer));
+            }
+        }
+
+        if let Some(max_days) = self.max_validity_days
+        {
+            if let Ok(diff) = certificate.certificate.not_before().diff(certificate.certificate.not_after())
+            {
+                // Part of a day counts as a day.
+                let days = diff.days as i64 + (diff.secs > 0) as i64;
+
+                if days > max_days as i64
+                {
+                    violation(format!("valid for {} days (at most {} allowed)", days, max_days));
+                }
+            }
+        }
+
+        let names = certificate.dns_names();
+
+        if self.forbid_wildcards
+        {
+            for name in names.iter().filter(|name| name.contains('*'))
+            {
+                violation(format!("wildcard name {} isn't allowed", name));
+            }
+        }
+
+        if let Some(patterns) = &self.san_patterns
+        {
+            for name in names.iter().filter(|name| !patterns.iter().any(|pattern| is_match(pattern, name)))
+            {
+                violation(format!("name {} doesn't match any allowed pattern", name));
+            }
+        }
+
+        violations
+    }
+}
+
+/// Match text against a pattern, ignoring case, where "*" matches any run of
+/// characters and "?" any one character.
+pub fn is_match(pattern: &str, text: &str) -> bool
+{
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+
+    // Positions to resume from when a "*" needs to match more characters.
+    let (mut p, mut t) = (0, 0);
+    let mut resume = None;
+
+    while t < text.len()
+    {
+        match pattern.get(p)
+        {
+            Some('*') => {
+                resume = Some((p, t));
+                p += 1;
+            },
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            },
+            _ => match resume
+            {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    t = matched + 1;
+                    resume = Some((star, matched + 1));
+                },
+                None => return false
+            }
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+    use crate::Inventory;
+
+    #[test]
+    fn parses_policies()
+    {
+        let policy = Policy::parse(r#"
+            # Public certificates.
+            allowed_key_types = ["RSA", 'EC']  # no Ed25519 yet
+            allowed_rsa_bits = [
+                2048,
+                4_096,
+            ]
+            max_validity_days = 398
+            allow_wildcards = false
+        "#).unwrap();
+
+        assert_eq!(policy, Policy {
+            key_types: Some(vec![String::from("RSA"), String::from("EC")]),
+            rsa_bits: Some(vec![2048, 4096]),
+            max_validity_days: Some(398),
+            forbid_wildcards: true,
+            ..Default::default()
+        });
+
+        assert!(Policy::parse("max_validity = 398").unwrap_err().starts_with("unknown field `max_validity`"));
+        assert!(Policy::parse("\nsan_patterns = [\"a\"").unwrap_err().ends_with("at line 2 column 20"));
+        assert!(Policy::parse("[policy]").unwrap_err().starts_with("unknown field `policy`"));
+        assert!(Policy::parse("max_validity_days = \"398\"").unwrap_err().starts_with("invalid type: string \"398\", expected u32"));
+    }
+
+    #[test]
+    fn matches_patterns()
+    {
+        assert!(is_match("*.example.com", "www.EXAMPLE.com"));
+        assert!(is_match("*CN=R1?", "C=US, O=Let's Encrypt, CN=R11"));
+        assert!(is_match("*", ""));
+        assert!(!is_match("*.example.com", "example.com"));
+        assert!(!is_match("*CN=R1?", "C=US, CN=R110"));
+    }
+
+    #[test]
+    fn finds_violations()
+    {
+        let chains = Inventory::new()
+            .add_path("samples/wildcard/example.com.key")
+            .add_path("samples/wildcard/example.com.crt")
+            .build_chains()
+            .unwrap();
+
+        let policy = Policy::parse(r#"
+            allowed_key_types = ["rsa"]
+            allowed_rsa_bits = [3072, 4096]
+            allowed_issuers = ["*CN=R1?"]
+            max_validity_days = 398
+            san_patterns = ["www.example.com", "*.example.com"]
+            allow_wildcards = false
+        "#).unwrap();
+
+        let violations: Vec<String> = policy.check(&chains[0]).into_iter()
+            .map(|(path, violation)| format!("{}: {}", path, violation))
+            .collect();
+
+        assert_eq!(violations, vec![
+            "samples/wildcard/example.com.key: 2048-bit RSA keys aren't allowed (allowed: 3072, 4096)",
+            "samples/wildcard/example.com.crt: issuer C=US, O=Sample Org, CN=*.example.com isn't allowed",
+            "samples/wildcard/example.com.crt: valid for 10000 days (at most 398 allowed)",
+            "samples/wildcard/example.com.crt: wildcard name *.example.com isn't allowed",
+            "samples/wildcard/example.com.crt: name example.com doesn't match any allowed pattern",
+        ]);
+
+        assert!(Policy::default().check(&chains[0]).is_empty());
+    }
+}
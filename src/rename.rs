@@ -0,0 +1,167 @@
+//! Copyright (C) 2022 Gaz J.
+//!
+//! This program is free software: you can redistribute it and/or modify
+//! it under the terms of the GNU General Public License as published by
+//! the Free Software Foundation, either version 3 of the License, or
+//! (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//! GNU General Public License for more details.
+//!
+//! You should have received a copy of the GNU General Public License
+//! along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Consistent file names (--suggest-names): each chain's files renamed, in
+//! their directories, for the chain's name, e.g. "example.com.key",
+//! "example.com.csr", "example.com.crt" (the certificate alone), and
+//! "example.com.chain.crt" (the certificate followed by its intermediates).
+//!
+//! Only regular files holding nothing else are renamed; symbolic links
+//! (e.g. certbot's live directory), shared intermediates, superseded
+//! certificates, and files in archives, containers, and clusters are left.
+
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::chain::{is_regular_file, Chain, Inventory};
+use crate::export;
+use crate::format;
+
+/// A file, and the name suggested for it.
+#[derive(Debug, PartialEq)]
+pub struct Rename
+{
+    pub from: String,
+    pub to: String,
+}
+
+impl Rename
+{
+    /// Rename the file, unless something's been put where it's going.
+    pub fn apply(&self) -> io::Result<()>
+    {
+        if Path::new(&self.to).exists()
+        {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} already exists", self.to)));
+        }
+
+        fs::rename(&self.from, &self.to)
+    }
+}
+
+/// Suggest names for chains' files, made unique (with "-2", "-3", ...) among
+/// each other and files which already exist.
+pub fn suggest(inventory: &Inventory, chains: &[Chain]) -> Vec<Rename>
+{
+    let mut renames = vec![];
+    let mut taken = HashSet::new();
+
+    for chain in chains.iter().filter(|chain| chain.key.is_some())
+    {
+        let name = format::get_display_name(chain);
+
+        if name == "(unknown)"
+        {
+            continue;
+        }
+
+        let base = export::file_name(&name);
+
+        // (path, suffix, how many items the file should hold).
+        let mut files = vec![];
+
+        files.extend(chain.key.as_ref().map(|key| (key.path.as_str(), ".key", 1)));
+        files.extend(chain.request.as_ref().map(|request| (request.path.as_str(), ".csr", 1)));
+
+        if let Some(certificate) = chain.certificates.first()
+        {
+            let intermediates = certificate.signing_certificate_chain().iter()
+                .filter(|c| !c.self_signed && c.is_in(&certificate.path))
+                .count();
+
+            files.push((certificate.path.as_str(), if intermediates > 0 { ".chain.crt" } else { ".crt" }, 1 + intermediates));
+        }
+
+        for (path, suffix, items) in files
+        {
+            if inventory.items_in(path) != items || !is_regular_file(path)
+            {
+                continue;
+            }
+
+            let directory = Path::new(path).parent().unwrap_or(Path::new(""));
+            let mut to = directory.join(format!("{}{}", base, suffix));
+            let mut count = 1;
+
+            while to.to_str() != Some(path) && (taken.contains(&to) || to.exists())
+            {
+                count += 1;
+                to = directory.join(format!("{}-{}{}", base, count, suffix));
+            }
+
+            taken.insert(to.clone());
+
+            if to.to_str() != Some(path)
+            {
+                renames.push(Rename { from: path.to_string(), to: to.to_string_lossy().to_string() });
+            }
+        }
+    }
+
+    renames
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    #[test]
+    fn suggests_names()
+    {
+        let directory = std::env::temp_dir().join(format!("sslchains-test-rename-{}", std::process::id()));
+        let path = |name: &str| directory.join(name).to_string_lossy().to_string();
+
+        fs::create_dir_all(&directory).unwrap();
+        fs::copy("samples/cross_signed/example.com.key", path("key.pem")).unwrap();
+        fs::copy("samples/cross_signed/example.com.crt", path("cert(3).pem")).unwrap();
+        fs::copy("samples/cross_signed/intermediate_by_a.crt", path("intermediate.pem")).unwrap();
+        fs::copy("samples/cross_signed/root_a.crt", path("root.pem")).unwrap();
+        fs::write(path("example.com.crt"), "taken").unwrap();
+
+        let inventory = ["key.pem", "cert(3).pem", "intermediate.pem", "root.pem"].iter()
+            .fold(Inventory::new(), |inventory, name| inventory.add_path(&path(name)));
+        let chains = inventory.build_chains().unwrap();
+
+        // Shared intermediates keep their names, and existing files aren't
+        // replaced.
+        assert_eq!(suggest(&inventory, &chains), vec![
+            Rename { from: path("key.pem"), to: path("example.com.key") },
+            Rename { from: path("cert(3).pem"), to: path("example.com-2.crt") },
+        ]);
+
+        // A certificate with its intermediate is a ".chain.crt".
+        let bundle = [fs::read_to_string(path("cert(3).pem")).unwrap(), fs::read_to_string(path("intermediate.pem")).unwrap()].concat();
+
+        fs::write(path("cert(3).pem"), bundle).unwrap();
+        fs::remove_file(path("example.com.crt")).unwrap();
+        fs::rename(path("key.pem"), path("example.com.key")).unwrap();
+
+        let inventory = ["example.com.key", "cert(3).pem", "root.pem"].iter()
+            .fold(Inventory::new(), |inventory, name| inventory.add_path(&path(name)));
+        let renames = suggest(&inventory, &inventory.build_chains().unwrap());
+
+        assert_eq!(renames, vec![Rename { from: path("cert(3).pem"), to: path("example.com.chain.crt") }]);
+
+        renames[0].apply().unwrap();
+
+        assert!(Path::new(&path("example.com.chain.crt")).exists());
+        assert!(renames[0].apply().is_err());
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
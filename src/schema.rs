@@ -0,0 +1,146 @@
+//! Copyright (C) 2022 Gaz J.
+//!
+//! This program is free software: you can redistribute it and/or modify
+//! it under the terms of the GNU General Public License as published by
+//! the Free Software Foundation, either version 3 of the License, or
+//! (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//! GNU General Public License for more details.
+//!
+//! You should have received a copy of the GNU General Public License
+//! along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Serialization of chains, shared by library consumers and the NDJSON
+//! display mode.
+//!
+//! Each serialized chain carries a schema_version field, which is increased
+//! whenever fields are renamed, removed, or change meaning (fields may be
+//! added without a new version). Version 1 chains hold:
+//!
+//! * `schema_version`: 1
+//! * `name`: display name (see format::get_display_name)
+//! * `key`: path of the private key, or null
+//! * `endpoint`: TLS server which served the certificates (see connect),
+//!   only for served chains, which have no key
+//! * `unreachable`: why the endpoint couldn't be reached, only for chains of
+//!   endpoints which served nothing
+//! * `request`: path of the certificate signing request, or null
+//! * `certificates`: certificates for the key, newest first, each holding:
+//!   * `path`: path of the certificate
+//!   * `self_signed`: whether it's signed by its own key
+//!   * `duplicates`: other paths containing the same certificate
+//!   * `signing_certificates`: paths of its signing certificates, nearest
+//!     first
+//!
+//! Keys and requests are represented by their paths alone, so private key
+//! material is never serialized.
+
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+
+use crate::chain::{Chain, CertificateFile, CertificateRequestFile, PrivateKeyFile};
+use crate::format;
+
+/// Version of the serialized chain schema.
+pub const SCHEMA_VERSION: u32 = 1;
+
+impl Serialize for Chain
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    {
+        let mut chain = serializer.serialize_struct("Chain", 7)?;
+
+        chain.serialize_field("schema_version", &SCHEMA_VERSION)?;
+        chain.serialize_field("name", &format::get_display_name(self))?;
+        chain.serialize_field("key", &self.key)?;
+
+        match &self.endpoint
+        {
+            Some(endpoint) => chain.serialize_field("endpoint", endpoint)?,
+            None => chain.skip_field("endpoint")?
+        }
+        match &self.unreachable
+        {
+            Some(reason) => chain.serialize_field("unreachable", reason)?,
+            None => chain.skip_field("unreachable")?
+        }
+        chain.serialize_field("request", &self.request)?;
+        chain.serialize_field("certificates", &self.certificates)?;
+        chain.end()
+    }
+}
+
+impl Serialize for PrivateKeyFile
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    {
+        serializer.serialize_str(&self.path)
+    }
+}
+
+impl Serialize for CertificateRequestFile
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    {
+        serializer.serialize_str(&self.path)
+    }
+}
+
+impl Serialize for CertificateFile
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    {
+        let signing_certificate_chain = self.signing_certificate_chain();
+        let signing_certificates: Vec<&str> = signing_certificate_chain.iter()
+            .map(|signing_certificate| signing_certificate.path.as_str())
+            .collect();
+
+        let mut certificate = serializer.serialize_struct("CertificateFile", 4)?;
+
+        certificate.serialize_field("path", &self.path)?;
+        certificate.serialize_field("self_signed", &self.self_signed)?;
+        certificate.serialize_field("duplicates", &self.duplicates)?;
+        certificate.serialize_field("signing_certificates", &signing_certificates)?;
+        certificate.end()
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+    use crate::chain::Inventory;
+
+    #[test]
+    fn serializes_chains_with_schema_version()
+    {
+        let chains = Inventory::new()
+            .add_path("samples/ca_signed.key")
+            .add_path("samples/ca_signed.csr")
+            .add_path("samples/ca_signed.crt")
+            .add_path("samples/intermediate_ca.crt")
+            .build_chains()
+            .unwrap();
+
+        let chain = serde_json::to_value(&chains[0]).unwrap();
+
+        assert_eq!(chain["schema_version"], SCHEMA_VERSION);
+        assert_eq!(chain["key"], "samples/ca_signed.key");
+        assert_eq!(chain["request"], "samples/ca_signed.csr");
+        assert_eq!(chain["certificates"][0]["signing_certificates"][0], "samples/intermediate_ca.crt");
+        assert!(chain.get("endpoint").is_none());
+
+        let served = Chain { endpoint: Some(String::from("example.com:443")), ..Chain::new() };
+        let served = serde_json::to_value(&served).unwrap();
+
+        assert_eq!(served["endpoint"], "example.com:443");
+        assert!(served["key"].is_null());
+        assert!(served.get("unreachable").is_none());
+
+        let unreachable = Chain { unreachable: Some(String::from("couldn't connect: timed out")), ..Chain::new() };
+
+        assert_eq!(serde_json::to_value(&unreachable).unwrap()["unreachable"], "couldn't connect: timed out");
+    }
+}
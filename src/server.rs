@@ -0,0 +1,273 @@
+//! Copyright (C) 2022 Gaz J.
+//!
+//! This program is free software: you can redistribute it and/or modify
+//! it under the terms of the GNU General Public License as published by
+//! the Free Software Foundation, either version 3 of the License, or
+//! (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//! GNU General Public License for more details.
+//!
+//! You should have received a copy of the GNU General Public License
+//! along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! A small read-only HTTP server (--listen, in daemon mode), serving pages
+//! rendered after each scan (e.g. /chains and /metrics), so the inventory
+//! can be queried without reading the disk again.
+//!
+//! Connections are handled, one request each, by a fixed pool of worker
+//! threads, and each request must be read and responded to within a
+//! deadline, so slow or numerous clients can't exhaust threads or hold up
+//! others for long.
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+use log::debug;
+
+/// How long reading a request and writing its response can take in all.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Connections handled at once.
+const WORKERS: usize = 4;
+
+/// Connections accepted while all workers are busy; beyond these, further
+/// connections wait in the listen backlog.
+const QUEUED: usize = 16;
+
+/// Longest request (line and headers) read.
+const MAX_REQUEST: u64 = 8 * 1024;
+
+/// A page's content type and body.
+#[derive(Clone, Debug)]
+pub struct Page
+{
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+}
+
+/// Pages, by path.
+type Pages = Arc<RwLock<HashMap<String, Page>>>;
+
+pub struct Server
+{
+    listener: TcpListener,
+    pages: Pages,
+}
+
+impl Server
+{
+    /// Listen on an address, e.g. "127.0.0.1:9870".
+    pub fn bind(address: &str) -> io::Result<Server>
+    {
+        Ok(Server { listener: TcpListener::bind(address)?, pages: Pages::default() })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr>
+    {
+        self.listener.local_addr()
+    }
+
+    /// Serve a page at a path, replacing any served there before.
+    pub fn publish(&self, path: &str, page: Page)
+    {
+        if let Ok(mut pages) = self.pages.write()
+        {
+            pages.insert(path.to_string(), page);
+        }
+    }
+
+    /// Start accepting connections, on a thread of their own, and handling
+    /// them on a pool of WORKERS threads, until the process exits.
+    pub fn spawn(&self) -> io::Result<()>
+    {
+        let listener = self.listener.try_clone()?;
+        let (sender, receiver) = mpsc::sync_channel::<TcpStream>(QUEUED);
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        for _ in 0..WORKERS
+        {
+            let receiver = Arc::clone(&receiver);
+            let pages = Arc::clone(&self.pages);
+
+            thread::spawn(move || work(&receiver, &pages));
+        }
+
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten()
+            {
+                if sender.send(stream).is_err()
+                {
+                    break;
+                }
+            }
+        });
+
+        Ok(())
+    }
+}
+
+/// Handle connections as they're accepted, one at a time.
+fn work(receiver: &Mutex<Receiver<TcpStream>>, pages: &Pages)
+{
+    loop
+    {
+        // The lock is only held while waiting for a connection.
+        let stream = match receiver.lock().map(|receiver| receiver.recv())
+        {
+            Ok(Ok(stream)) => stream,
+            _ => return
+        };
+
+        if let Err(e) = handle(stream, pages, Instant::now() + TIMEOUT)
+        {
+            debug!("Couldn't respond to a request: {}", e);
+        }
+    }
+}
+
+/// A connection, whose reads and writes time out at a deadline (rather than
+/// after each is idle for a while), so a client trickling a request can't
+/// hold a worker for longer.
+#[derive(Clone, Copy)]
+struct Deadline<'a>
+{
+    stream: &'a TcpStream,
+    deadline: Instant,
+}
+
+impl Deadline<'_>
+{
+    /// Time left before the deadline, or an error once it has passed.
+    fn remaining(&self) -> io::Result<Duration>
+    {
+        match self.deadline.checked_duration_since(Instant::now()).filter(|remaining| !remaining.is_zero())
+        {
+            Some(remaining) => Ok(remaining),
+            None => Err(io::Error::new(io::ErrorKind::TimedOut, "request deadline passed"))
+        }
+    }
+}
+
+impl io::Read for Deadline<'_>
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize>
+    {
+        self.stream.set_read_timeout(Some(self.remaining()?))?;
+        io::Read::read(&mut self.stream, buf)
+    }
+}
+
+impl Write for Deadline<'_>
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize>
+    {
+        self.stream.set_write_timeout(Some(self.remaining()?))?;
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()>
+    {
+        self.stream.flush()
+    }
+}
+
+/// Read a request, and respond with the page asked for, before a deadline.
+fn handle(stream: TcpStream, pages: &Pages, deadline: Instant) -> io::Result<()>
+{
+    let mut stream = Deadline { stream: &stream, deadline };
+    let mut reader = BufReader::new(io::Read::take(stream, MAX_REQUEST));
+    let mut request = String::new();
+
+    reader.read_line(&mut request)?;
+
+    // Headers are read (up to the blank line ending them), but not used.
+    let mut line = String::new();
+
+    while reader.read_line(&mut line)? > 0 && !line.trim_end().is_empty()
+    {
+        line.clear();
+    }
+
+    let mut parts = request.split_whitespace();
+    let (method, target) = (parts.next().unwrap_or_default(), parts.next().unwrap_or_default());
+    let path = target.split('?').next().unwrap_or_default();
+
+    debug!("{} {} from {}", method, target, stream.stream.peer_addr().map(|a| a.to_string()).unwrap_or_default());
+
+    let page = pages.read().ok().and_then(|pages| match pages.is_empty()
+    {
+        true => None,
+        false => Some(pages.get(path).cloned())
+    });
+
+    let (status, page) = match (method, page)
+    {
+        ("GET" | "HEAD", Some(Some(page))) => ("200 OK", page),
+        ("GET" | "HEAD", Some(None)) => ("404 Not Found", text("Not found\n")),
+        ("GET" | "HEAD", None) => ("503 Service Unavailable", text("Not scanned yet\n")),
+        _ => ("405 Method Not Allowed", text("Only GET and HEAD are allowed\n"))
+    };
+
+    write!(stream, "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status, page.content_type, page.body.len())?;
+
+    if method != "HEAD"
+    {
+        stream.write_all(&page.body)?;
+    }
+
+    stream.flush()
+}
+
+fn text(body: &str) -> Page
+{
+    Page { content_type: "text/plain; charset=utf-8", body: body.as_bytes().to_vec() }
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+    use crate::http;
+
+    #[test]
+    fn serves_pages()
+    {
+        let server = Server::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", server.local_addr().unwrap());
+        let timeout = Duration::from_secs(5);
+
+        server.spawn().unwrap();
+
+        assert_eq!(http::get(&format!("{}/chains", url), timeout).unwrap_err().to_string(), "HTTP 503");
+
+        server.publish("/chains", Page { content_type: "application/json", body: b"[]".to_vec() });
+
+        assert_eq!(http::get(&format!("{}/chains?pretty", url), timeout).unwrap(), b"[]");
+        assert_eq!(http::get(&format!("{}/metrics", url), timeout).unwrap_err().to_string(), "HTTP 404");
+        assert_eq!(http::post(&format!("{}/chains", url), "application/json", b"[]", timeout).unwrap_err().to_string(), "HTTP 405");
+    }
+
+    #[test]
+    fn gives_up_on_slow_requests()
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+
+        // Start a request, but never finish it.
+        client.write_all(b"GET /chains HTTP/1.1\r\n").unwrap();
+
+        let started = Instant::now();
+
+        assert!(handle(stream, &Pages::default(), started + Duration::from_millis(200)).is_err());
+        assert!(started.elapsed() < TIMEOUT);
+    }
+}
@@ -0,0 +1,256 @@
+//! Copyright (C) 2022 Gaz J.
+//!
+//! This program is free software: you can redistribute it and/or modify
+//! it under the terms of the GNU General Public License as published by
+//! the Free Software Foundation, either version 3 of the License, or
+//! (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//! GNU General Public License for more details.
+//!
+//! You should have received a copy of the GNU General Public License
+//! along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Snapshots of an inventory (--save-snapshot, --load-snapshot): each key,
+//! request, and certificate found, addressed by its digest, with the paths
+//! it was found at and its public metadata (as the cache stores it), so
+//! what changed between scans can be reported without keeping the files.
+//!
+//! Certificates are addressed by their SHA-256 fingerprint, requests by
+//! the SHA-256 digest of their DER encoding, and keys by their public key's
+//! digest; no private key material is stored.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io;
+
+use openssl::asn1::Asn1Time;
+use openssl::sha::sha256;
+use serde_json::{json, Value};
+
+use crate::cache;
+use crate::chain::{self, Inventory};
+use crate::format;
+
+/// The snapshot format's version, which is checked when loading.
+const VERSION: u64 = 1;
+
+#[derive(Debug)]
+pub struct Snapshot
+{
+    /// When the snapshot was taken, e.g. "2030-01-01T00:00:00Z".
+    pub created: String,
+
+    /// Items by address, each with its "paths".
+    pub items: BTreeMap<String, Value>,
+}
+
+impl Snapshot
+{
+    /// Take a snapshot of an inventory.
+    pub fn new(inventory: &Inventory) -> Snapshot
+    {
+        let mut items = BTreeMap::new();
+
+        let mut add = |address: Option<Vec<u8>>, mut item: Value, paths: Vec<&String>| {
+            let Some(address) = address else { return };
+            let entry = items.entry(cache::hex(&address)).or_insert_with(|| {
+                item["paths"] = json!([]);
+                item
+            });
+
+            if let Some(known) = entry["paths"].as_array_mut()
+            {
+                for path in paths.into_iter().map(|path| json!(path))
+                {
+                    if !known.contains(&path)
+                    {
+                        known.push(path);
+                    }
+                }
+            }
+        };
+
+        for key in &inventory.keys
+        {
+            add(chain::key_digest(&key.key), cache::key(&key.key), vec![&key.path]);
+        }
+
+        for request in &inventory.requests
+        {
+            add(request.request.to_der().ok().map(|der| sha256(&der).to_vec()), cache::request(&request.request), vec![&request.path]);
+        }
+
+        for record in &inventory.certificates
+        {
+            if let Some(certificate) = record.certificate()
+            {
+                add(Some(record.fingerprint.clone()), cache::certificate(&certificate), std::iter::once(&record.path).chain(&record.duplicates).collect());
+            }
+        }
+
+        let created = Asn1Time::days_from_now(0).map(|now| format::format_time(&now)).unwrap_or_default();
+
+        Snapshot { created, items }
+    }
+
+    /// Read a snapshot written by save.
+    pub fn load(path: &str) -> io::Result<Snapshot>
+    {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path, message));
+        let contents = fs::read(path).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path, e)))?;
+        let snapshot: Value = serde_json::from_slice(&contents).map_err(|e| invalid(&e.to_string()))?;
+
+        if snapshot["version"] != VERSION
+        {
+            return Err(invalid("isn't a snapshot, or was written by another version"));
+        }
+
+        let items = match &snapshot["items"]
+        {
+            Value::Object(items) => items.iter().map(|(address, item)| (address.clone(), item.clone())).collect(),
+            _ => return Err(invalid("has no items"))
+        };
+
+        Ok(Snapshot { created: snapshot["created"].as_str().unwrap_or_default().to_string(), items })
+    }
+
+    /// Write the snapshot, replacing the file atomically.
+    pub fn save(&self, path: &str) -> io::Result<()>
+    {
+        let snapshot = json!({ "version": VERSION, "created": self.created, "items": self.items });
+        let temporary = format!("{}.tmp", path);
+
+        fs::write(&temporary, serde_json::to_string_pretty(&snapshot)?)?;
+        fs::rename(&temporary, path)
+    }
+
+    /// What changed at each path since an earlier snapshot, as (path,
+    /// change) pairs, e.g. ("example.com.crt", "replaced certificate ...
+    /// with certificate ...").
+    pub fn changes(&self, earlier: &Snapshot) -> Vec<(String, String)>
+    {
+        let before = earlier.by_path();
+        let after = self.by_path();
+        let paths: BTreeSet<&String> = before.keys().chain(after.keys()).copied().collect();
+
+        let mut changes = vec![];
+
+        for path in paths
+        {
+            let held = |snapshot: &BTreeMap<&String, Vec<(&String, &Value)>>, address: &String| {
+                snapshot.get(path).is_some_and(|items| items.iter().any(|(a, _)| *a == address))
+            };
+
+            let removed: Vec<&Value> = before.get(path).into_iter().flatten().filter(|(address, _)| !held(&after, address)).map(|(_, item)| *item).collect();
+            let added: Vec<&Value> = after.get(path).into_iter().flatten().filter(|(address, _)| !held(&before, address)).map(|(_, item)| *item).collect();
+
+            // A single item replaced by another of its type (e.g. a renewed
+            // certificate) is one change.
+            if let ([old], [new]) = (removed.as_slice(), added.as_slice())
+            {
+                if old["type"] == new["type"]
+                {
+                    changes.push((path.clone(), format!("replaced {} with {}", describe(old), describe(new))));
+                    continue;
+                }
+            }
+
+            changes.extend(removed.iter().map(|item| (path.clone(), format!("no longer holds {}", describe(item)))));
+            changes.extend(added.iter().map(|item| (path.clone(), format!("now holds {}", describe(item)))));
+        }
+
+        changes
+    }
+
+    /// The paths holding items.
+    pub fn paths(&self) -> BTreeSet<&String>
+    {
+        self.by_path().into_keys().collect()
+    }
+
+    /// Items at each path, as (address, item) pairs.
+    fn by_path(&self) -> BTreeMap<&String, Vec<(&String, &Value)>>
+    {
+        let mut by_path: BTreeMap<&String, Vec<(&String, &Value)>> = BTreeMap::new();
+
+        for (address, item) in &self.items
+        {
+            for path in item["paths"].as_array().into_iter().flatten()
+            {
+                if let Value::String(path) = path
+                {
+                    by_path.entry(path).or_default().push((address, item));
+                }
+            }
+        }
+
+        by_path
+    }
+}
+
+/// Describe an item, e.g. "certificate CN=example.com (expires
+/// 2030-01-01T00:00:00Z)".
+fn describe(item: &Value) -> String
+{
+    let text = |field: &str| item[field].as_str().unwrap_or_default().to_string();
+
+    match item["type"].as_str()
+    {
+        Some("certificate") => format!("certificate {} (expires {})", text("subject"), text("not_after")),
+        Some("request") => format!("request {}", text("subject")),
+        _ => format!("key {}", text("public_key_sha256").chars().take(16).collect::<String>())
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    #[test]
+    fn tracks_changes_between_snapshots()
+    {
+        let path = std::env::temp_dir().join(format!("sslchains-test-snapshot-{}.json", std::process::id())).to_string_lossy().to_string();
+
+        let before = Snapshot::new(&Inventory::new()
+            .add_path("samples/renewed/example.com.key")
+            .add_path("samples/renewed/example.com-2024.crt")
+            .add_path("samples/self_signed.csr"));
+
+        before.save(&path).unwrap();
+
+        let loaded = Snapshot::load(&path).unwrap();
+
+        assert_eq!(loaded.items, before.items);
+        assert_eq!(loaded.items.len(), 3);
+        assert!(!fs::read_to_string(&path).unwrap().contains("PRIVATE"));
+        assert!(loaded.changes(&before).is_empty());
+
+        // The certificate renewed in place, and the request removed.
+        let mut after = Snapshot::new(&Inventory::new()
+            .add_path("samples/renewed/example.com.key")
+            .add_path("samples/renewed/example.com-2025.crt"));
+
+        let renewed = after.items.values_mut().find(|item| item["type"] == "certificate").unwrap();
+
+        renewed["paths"] = json!(["samples/renewed/example.com-2024.crt"]);
+
+        let changes = after.changes(&loaded);
+
+        assert_eq!(changes, vec![
+            (String::from("samples/renewed/example.com-2024.crt"), String::from("replaced certificate CN=example.com (expires 2025-01-01T00:00:00Z) with certificate CN=example.com (expires 2026-01-01T00:00:00Z)")),
+            (String::from("samples/self_signed.csr"), String::from("no longer holds request C=US, O=Sample Org, CN=example.com")),
+        ]);
+
+        fs::write(&path, "{}").unwrap();
+
+        assert_eq!(Snapshot::load(&path).err().unwrap().kind(), io::ErrorKind::InvalidData);
+
+        fs::remove_file(&path).unwrap();
+
+        assert!(Snapshot::load(&path).err().unwrap().to_string().starts_with(&path));
+    }
+}
@@ -0,0 +1,254 @@
+//! Copyright (C) 2022 Gaz J.
+//!
+//! This program is free software: you can redistribute it and/or modify
+//! it under the terms of the GNU General Public License as published by
+//! the Free Software Foundation, either version 3 of the License, or
+//! (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//! GNU General Public License for more details.
+//!
+//! You should have received a copy of the GNU General Public License
+//! along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Inventories OpenSSH host keys, certificates, and CA keys (--ssh), such
+//! as those under /etc/ssh, relating each certificate to the key it
+//! certifies and the CA key which signed it.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use log::debug;
+
+use crate::openssh::{self, Certificate, PublicKey};
+
+/// Where sshd keeps its keys and certificates.
+pub const DEFAULT_DIRECTORY: &str = "/etc/ssh";
+
+/// Files larger than this (e.g. moduli) aren't keys or certificates.
+const MAX_FILE_SIZE: u64 = 64 * 1024;
+
+/// A key, and the files holding it (a private key and its ".pub" file
+/// usually both do).
+#[derive(Debug)]
+pub struct Key
+{
+    pub key: PublicKey,
+
+    /// Paths, sorted, so private keys come before their ".pub" files.
+    pub paths: Vec<String>,
+}
+
+impl Key
+{
+    /// Host keys are those sshd names ssh_host_*.
+    pub fn is_host_key(&self) -> bool
+    {
+        self.paths.iter().any(|path| {
+            Path::new(path).file_name().map(|name| name.to_string_lossy().starts_with("ssh_host_")).unwrap_or(false)
+        })
+    }
+}
+
+/// Whether a certificate is valid now.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Validity
+{
+    NotYetValid,
+    Valid,
+    Expired,
+}
+
+/// What a directory holds.
+#[derive(Debug, Default)]
+pub struct Inventory
+{
+    pub keys: Vec<Key>,
+
+    /// Certificates, by path.
+    pub certificates: Vec<(String, Certificate)>,
+}
+
+impl Inventory
+{
+    /// Read the files in a directory (but not its subdirectories). Files
+    /// which can't be read (e.g. private host keys, without root) are
+    /// passed over, as their ".pub" files hold their public keys.
+    pub fn scan(directory: &Path) -> io::Result<Inventory>
+    {
+        let mut inventory = Inventory::default();
+        let mut paths: Vec<_> = fs::read_dir(directory)?.flatten().map(|entry| entry.path()).collect();
+
+        paths.sort();
+
+        for path in paths
+        {
+            match fs::metadata(&path)
+            {
+                Ok(metadata) if metadata.is_file() && metadata.len() <= MAX_FILE_SIZE => (),
+                _ => continue
+            }
+
+            match fs::read(&path)
+            {
+                Ok(contents) => inventory.add(&path.to_string_lossy(), &String::from_utf8_lossy(&contents)),
+                Err(e) => debug!("Couldn't read {}: {}", path.display(), e)
+            }
+        }
+
+        Ok(inventory)
+    }
+
+    /// Add the keys and certificates a file holds: an OpenSSH private key,
+    /// or lines of public keys and certificates (as in ".pub" files,
+    /// TrustedUserCAKeys files, and known_hosts "@cert-authority" lines).
+    pub fn add(&mut self, path: &str, contents: &str)
+    {
+        if let Ok(key) = openssh::private_str_to_public_key(contents)
+        {
+            self.add_key(path, key);
+
+            return;
+        }
+
+        for line in contents.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#'))
+        {
+            if let Ok(certificate) = openssh::str_to_certificate(line)
+            {
+                self.certificates.push((path.to_string(), certificate));
+            }
+            else if let Ok(key) = openssh::str_to_public_key(line)
+            {
+                self.add_key(path, key);
+            }
+        }
+    }
+
+    fn add_key(&mut self, path: &str, key: PublicKey)
+    {
+        match self.keys.iter_mut().find(|known| known.key.blob == key.blob)
+        {
+            Some(known) => {
+                if known.key.comment.is_empty()
+                {
+                    known.key.comment = key.comment;
+                }
+
+                if !known.paths.iter().any(|known| known == path)
+                {
+                    known.paths.push(path.to_string());
+                    known.paths.sort();
+                }
+            },
+            None => self.keys.push(Key { key, paths: vec![path.to_string()] })
+        }
+    }
+
+    /// Host keys, and any other keys certified here.
+    pub fn host_keys(&self) -> Vec<&Key>
+    {
+        self.keys.iter().filter(|key| key.is_host_key() || !self.certificates_of(key).is_empty()).collect()
+    }
+
+    /// Keys which aren't host keys: CA keys (whether or not they signed
+    /// certificates found here).
+    pub fn ca_keys(&self) -> Vec<&Key>
+    {
+        self.keys.iter().filter(|key| !key.is_host_key() && self.certificates_of(key).is_empty()).collect()
+    }
+
+    /// Certificates for a key.
+    pub fn certificates_of(&self, key: &Key) -> Vec<&(String, Certificate)>
+    {
+        self.certificates.iter().filter(|(_, certificate)| certificate.key.blob == key.key.blob).collect()
+    }
+
+    /// Certificates signed by a key.
+    pub fn signed_by(&self, key: &Key) -> Vec<&(String, Certificate)>
+    {
+        self.certificates.iter().filter(|(_, certificate)| certificate.signature_key.blob == key.key.blob).collect()
+    }
+
+    /// Certificates whose keys weren't found.
+    pub fn unmatched_certificates(&self) -> Vec<&(String, Certificate)>
+    {
+        self.certificates.iter().filter(|(_, certificate)| {
+            !self.keys.iter().any(|key| key.key.blob == certificate.key.blob)
+        }).collect()
+    }
+
+    /// The CA key which signed a certificate, if it was found.
+    pub fn signer_of(&self, certificate: &Certificate) -> Option<&Key>
+    {
+        self.keys.iter().find(|key| key.key.blob == certificate.signature_key.blob)
+    }
+}
+
+/// Whether a certificate is valid now.
+pub fn validity(certificate: &Certificate) -> Validity
+{
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|now| now.as_secs()).unwrap_or(0);
+
+    if now < certificate.valid_after
+    {
+        Validity::NotYetValid
+    }
+    else if now >= certificate.valid_before
+    {
+        Validity::Expired
+    }
+    else
+    {
+        Validity::Valid
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    #[test]
+    fn correlates_certificates_with_keys()
+    {
+        let inventory = Inventory::scan(Path::new("samples/ssh")).unwrap();
+
+        let host_keys: Vec<&str> = inventory.host_keys().iter().map(|key| key.paths[0].as_str()).collect();
+
+        assert_eq!(host_keys, vec!["samples/ssh/ssh_host_ecdsa_key", "samples/ssh/ssh_host_ed25519_key", "samples/ssh/ssh_host_rsa_key.pub"]);
+
+        let ca_keys = inventory.ca_keys();
+
+        assert_eq!(ca_keys.len(), 1);
+        assert_eq!(ca_keys[0].paths, vec!["samples/ssh/ca", "samples/ssh/ca.pub"]);
+        assert_eq!(ca_keys[0].key.comment, "Example host CA");
+        assert_eq!(ca_keys[0].key.fingerprint(), "SHA256:jBbw18aVn/FBg8dVmrCMeoFMbZo1ZDEDdbLJFoST/Ks");
+
+        let ed25519 = inventory.host_keys()[1];
+        let certificates = inventory.certificates_of(ed25519);
+
+        assert_eq!(certificates.len(), 1);
+
+        let (path, certificate) = certificates[0];
+
+        assert_eq!(path, "samples/ssh/ssh_host_ed25519_key-cert.pub");
+        assert!(certificate.host);
+        assert_eq!(certificate.key_id, "web1");
+        assert_eq!(certificate.serial, 7);
+        assert_eq!(certificate.principals, vec!["web1.example.com", "web1"]);
+        assert_eq!(certificate.valid_before, 2019686400);
+        assert_eq!(inventory.signer_of(certificate).unwrap().paths[0], "samples/ssh/ca");
+        assert_eq!(inventory.signed_by(ca_keys[0]).len(), 1);
+
+        // The ECDSA key's certificate was signed by a CA key that isn't here,
+        // and has expired.
+        let (_, certificate) = inventory.certificates_of(inventory.host_keys()[0])[0];
+
+        assert!(inventory.signer_of(certificate).is_none());
+        assert_eq!(validity(certificate), Validity::Expired);
+        assert!(inventory.unmatched_certificates().is_empty());
+    }
+}
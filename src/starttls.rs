@@ -0,0 +1,330 @@
+//! Copyright (C) 2022 Gaz J.
+//!
+//! This program is free software: you can redistribute it and/or modify
+//! it under the terms of the GNU General Public License as published by
+//! the Free Software Foundation, either version 3 of the License, or
+//! (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//! GNU General Public License for more details.
+//!
+//! You should have received a copy of the GNU General Public License
+//! along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Upgrades plaintext connections to TLS (--starttls), so certificates on
+//! mail, directory, and database servers can be fetched too.
+//!
+//! Replies are read a byte at a time, so nothing the server sends after
+//! agreeing (i.e. its side of the handshake) is consumed.
+
+use std::io::{self, Read, Write};
+
+/// Protocols which can be upgraded, with how.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Protocol
+{
+    Smtp,
+    Imap,
+    Pop3,
+    Ftp,
+    Ldap,
+    Postgres,
+}
+
+/// Names of supported protocols, as given to --starttls.
+pub const PROTOCOLS: [&str; 6] = ["smtp", "imap", "pop3", "ftp", "ldap", "postgres"];
+
+/// StartTLS extended operation request (RFC 4511): message 1, with the
+/// operation's OID.
+const LDAP_STARTTLS: &[u8] = b"\x30\x1d\x02\x01\x01\x77\x18\x80\x16\x31\x2e\x33\x2e\x36\x2e\x31\x2e\x34\x2e\x31\x2e\x31\x34\x36\x36\x2e\x32\x30\x30\x33\x37";
+
+/// SSLRequest message (length 8, then the request code 80877103).
+const POSTGRES_SSL_REQUEST: &[u8] = b"\x00\x00\x00\x08\x04\xd2\x16\x2f";
+
+/// Longest reply line read.
+const MAX_LINE: usize = 4096;
+
+impl Protocol
+{
+    pub fn parse(name: &str) -> Option<Protocol>
+    {
+        match name.to_lowercase().as_str()
+        {
+            "smtp" => Some(Protocol::Smtp),
+            "imap" => Some(Protocol::Imap),
+            "pop3" => Some(Protocol::Pop3),
+            "ftp" => Some(Protocol::Ftp),
+            "ldap" => Some(Protocol::Ldap),
+            "postgres" => Some(Protocol::Postgres),
+            _ => None
+        }
+    }
+
+    /// Port connected to when an endpoint doesn't give one.
+    pub fn default_port(&self) -> u16
+    {
+        match self
+        {
+            Protocol::Smtp => 25,
+            Protocol::Imap => 143,
+            Protocol::Pop3 => 110,
+            Protocol::Ftp => 21,
+            Protocol::Ldap => 389,
+            Protocol::Postgres => 5432,
+        }
+    }
+
+    /// Ask the server to start TLS, returning once it has agreed to.
+    pub fn negotiate<S: Read + Write>(&self, stream: &mut S) -> io::Result<()>
+    {
+        match self
+        {
+            Protocol::Smtp => {
+                expect(&read_reply(stream)?, "220")?;
+                stream.write_all(b"EHLO sslchains\r\n")?;
+                expect(&read_reply(stream)?, "250")?;
+                stream.write_all(b"STARTTLS\r\n")?;
+                expect(&read_reply(stream)?, "220")
+            },
+            Protocol::Imap => {
+                expect(&read_line(stream)?, "* OK")?;
+                stream.write_all(b"a1 STARTTLS\r\n")?;
+
+                // Untagged responses may come before the tagged one.
+                loop
+                {
+                    let line = read_line(stream)?;
+
+                    if line.starts_with("a1 ")
+                    {
+                        return expect(&line, "a1 OK");
+                    }
+                }
+            },
+            Protocol::Pop3 => {
+                expect(&read_line(stream)?, "+OK")?;
+                stream.write_all(b"STLS\r\n")?;
+                expect(&read_line(stream)?, "+OK")
+            },
+            Protocol::Ftp => {
+                expect(&read_reply(stream)?, "220")?;
+                stream.write_all(b"AUTH TLS\r\n")?;
+                expect(&read_reply(stream)?, "234")
+            },
+            Protocol::Ldap => {
+                stream.write_all(LDAP_STARTTLS)?;
+
+                match ldap_result(&read_ldap_message(stream)?)
+                {
+                    Some(0) => Ok(()),
+                    Some(code) => Err(io::Error::other(format!("StartTLS refused (result code {})", code))),
+                    None => Err(io::Error::other("unexpected StartTLS response"))
+                }
+            },
+            Protocol::Postgres => {
+                stream.write_all(POSTGRES_SSL_REQUEST)?;
+
+                let mut answer = [0u8];
+
+                stream.read_exact(&mut answer)?;
+
+                match &answer
+                {
+                    b"S" => Ok(()),
+                    b"N" => Err(io::Error::other("server doesn't support SSL")),
+                    _ => Err(io::Error::other("unexpected SSLRequest response"))
+                }
+            }
+        }
+    }
+}
+
+/// Fail unless a reply starts as expected, quoting it.
+fn expect(reply: &str, prefix: &str) -> io::Result<()>
+{
+    match reply.starts_with(prefix)
+    {
+        true => Ok(()),
+        false => Err(io::Error::other(format!("unexpected reply: {}", reply.lines().last().unwrap_or_default())))
+    }
+}
+
+/// Read a line, without its line ending.
+fn read_line<R: Read>(stream: &mut R) -> io::Result<String>
+{
+    let mut line = vec![];
+    let mut byte = [0u8];
+
+    while line.len() < MAX_LINE
+    {
+        if stream.read(&mut byte)? == 0
+        {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed"));
+        }
+
+        if byte[0] == b'\n'
+        {
+            break;
+        }
+
+        line.push(byte[0]);
+    }
+
+    Ok(String::from_utf8_lossy(&line).trim_end_matches('\r').to_string())
+}
+
+/// Read an SMTP or FTP reply, which continues over lines with a "-" after
+/// their code (e.g. "250-PIPELINING"), up to the last ("250 STARTTLS").
+fn read_reply<R: Read>(stream: &mut R) -> io::Result<String>
+{
+    let mut reply = String::new();
+
+    loop
+    {
+        let line = read_line(stream)?;
+        let last = line.as_bytes().get(3) != Some(&b'-');
+
+        reply.push_str(&line);
+        reply.push('\n');
+
+        if last
+        {
+            return Ok(reply);
+        }
+    }
+}
+
+/// Read a BER-encoded LDAP message (a SEQUENCE), returning its contents.
+fn read_ldap_message<R: Read>(stream: &mut R) -> io::Result<Vec<u8>>
+{
+    let mut header = [0u8; 2];
+
+    stream.read_exact(&mut header)?;
+
+    if header[0] != 0x30
+    {
+        return Err(io::Error::other("unexpected StartTLS response"));
+    }
+
+    // Long form lengths give the number of length bytes which follow.
+    let length = match header[1]
+    {
+        length @ 0..=0x7f => length as usize,
+        count @ 0x81..=0x84 => {
+            let mut bytes = vec![0u8; (count & 0x7f) as usize];
+
+            stream.read_exact(&mut bytes)?;
+            bytes.iter().fold(0, |length, &byte| length << 8 | byte as usize)
+        },
+        _ => return Err(io::Error::other("unexpected StartTLS response"))
+    };
+
+    if length > MAX_LINE
+    {
+        return Err(io::Error::other("StartTLS response too long"));
+    }
+
+    let mut contents = vec![0u8; length];
+
+    stream.read_exact(&mut contents)?;
+
+    Ok(contents)
+}
+
+/// Result code of an ExtendedResponse, from a message's contents (its ID,
+/// then the response, starting with the code as an ENUMERATED).
+fn ldap_result(contents: &[u8]) -> Option<u8>
+{
+    // Skip the message ID (an INTEGER).
+    let id_length = *contents.get(1)? as usize;
+    let response = contents.get(2 + id_length..)?;
+
+    if response.first() != Some(&0x78)
+    {
+        return None;
+    }
+
+    // Skip the response's tag and length (short, or long form).
+    let offset = match *response.get(1)?
+    {
+        0..=0x7f => 2,
+        count => 2 + (count & 0x7f) as usize
+    };
+
+    match response.get(offset..offset + 3)?
+    {
+        [0x0a, 0x01, code] => Some(*code),
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    /// A stream replaying a server's replies, recording what's written.
+    struct Script
+    {
+        replies: io::Cursor<Vec<u8>>,
+        written: Vec<u8>,
+    }
+
+    impl Read for Script
+    {
+        fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize>
+        {
+            self.replies.read(buffer)
+        }
+    }
+
+    impl Write for Script
+    {
+        fn write(&mut self, buffer: &[u8]) -> io::Result<usize>
+        {
+            self.written.write(buffer)
+        }
+
+        fn flush(&mut self) -> io::Result<()>
+        {
+            Ok(())
+        }
+    }
+
+    fn negotiate(protocol: Protocol, replies: &[u8]) -> (io::Result<()>, Vec<u8>)
+    {
+        let mut script = Script { replies: io::Cursor::new(replies.to_vec()), written: vec![] };
+        let result = protocol.negotiate(&mut script);
+
+        (result, script.written)
+    }
+
+    #[test]
+    fn negotiates_starttls()
+    {
+        let (result, written) = negotiate(Protocol::Smtp, b"220 mail.example.com ESMTP\r\n250-mail.example.com\r\n250 STARTTLS\r\n220 Go ahead\r\n");
+
+        assert!(result.is_ok());
+        assert_eq!(written, b"EHLO sslchains\r\nSTARTTLS\r\n");
+
+        let (result, _) = negotiate(Protocol::Smtp, b"220 mail.example.com ESMTP\r\n250 mail.example.com\r\n454 TLS not available\r\n");
+
+        assert_eq!(result.unwrap_err().to_string(), "unexpected reply: 454 TLS not available");
+
+        assert!(negotiate(Protocol::Imap, b"* OK ready\r\n* CAPABILITY IMAP4rev1\r\na1 OK Begin TLS\r\n").0.is_ok());
+        assert!(negotiate(Protocol::Imap, b"* OK ready\r\na1 BAD STARTTLS\r\n").0.is_err());
+        assert!(negotiate(Protocol::Pop3, b"+OK ready\r\n+OK\r\n").0.is_ok());
+        assert!(negotiate(Protocol::Ftp, b"220-Welcome\r\n220 ready\r\n234 AUTH TLS OK\r\n").0.is_ok());
+        assert!(negotiate(Protocol::Postgres, b"S").0.is_ok());
+        assert!(negotiate(Protocol::Postgres, b"N").0.is_err());
+
+        let (result, written) = negotiate(Protocol::Ldap, b"\x30\x0c\x02\x01\x01\x78\x07\x0a\x01\x00\x04\x00\x04\x00");
+
+        assert!(result.is_ok());
+        assert_eq!(written, LDAP_STARTTLS);
+
+        assert!(negotiate(Protocol::Ldap, b"\x30\x0c\x02\x01\x01\x78\x07\x0a\x01\x02\x04\x00\x04\x00").0.is_err());
+    }
+}
@@ -0,0 +1,259 @@
+//! Copyright (C) 2022 Gaz J.
+//!
+//! This program is free software: you can redistribute it and/or modify
+//! it under the terms of the GNU General Public License as published by
+//! the Free Software Foundation, either version 3 of the License, or
+//! (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//! GNU General Public License for more details.
+//!
+//! You should have received a copy of the GNU General Public License
+//! along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//!
+//! Exit statuses: 0 on success, 1 if something couldn't be done, 2 for
+//! invalid usage, and 4 to 12 for conditions found in a scan which --fail-on
+//! names (by default, those the options given check for). When several are
+//! found, the most severe wins.
+//!
+//! The statuses quiet mode, --expiry-warn, --strict-permissions, and
+//! --policy exited with before --fail-on keep their meanings, so conditions
+//! added with it have statuses of their own, and severity doesn't follow
+//! the numbers.
+
+use std::collections::BTreeSet;
+
+use sslchains::chain::{Chain, Expiry, Skipped};
+
+/// Nothing failed.
+pub const SUCCESS: i32 = 0;
+
+/// Something couldn't be done, e.g. a file couldn't be read or written, or
+/// a configuration was invalid.
+pub const ERROR: i32 = 1;
+
+/// The command line was invalid.
+pub const USAGE: i32 = 2;
+
+/// A condition found in a scan, in order of severity.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Condition
+{
+    /// A file looked like a key, request, or certificate, but couldn't be
+    /// parsed.
+    ParseErrors,
+
+    /// No chains (keys) were found.
+    NoChains,
+
+    /// Keys were found, but none has a certificate.
+    NoCompleteChains,
+
+    /// A key has no certificate.
+    Incomplete,
+
+    /// A TLS server couldn't be reached (--connect).
+    Unreachable,
+
+    /// A certificate expires soon (or has expired).
+    Expiring,
+
+    /// A certificate has expired.
+    Expired,
+
+    /// A private key has lax permissions.
+    Permissions,
+
+    /// A chain violates the policy (--policy).
+    Policy,
+}
+
+/// Conditions by name, as --fail-on takes them.
+pub const CONDITIONS: [(&str, Condition); 9] = [
+    ("parse-errors", Condition::ParseErrors),
+    ("no-chains", Condition::NoChains),
+    ("no-complete-chains", Condition::NoCompleteChains),
+    ("incomplete", Condition::Incomplete),
+    ("unreachable", Condition::Unreachable),
+    ("expiring", Condition::Expiring),
+    ("expired", Condition::Expired),
+    ("permissions", Condition::Permissions),
+    ("policy", Condition::Policy),
+];
+
+impl Condition
+{
+    pub fn parse(name: &str) -> Option<Condition>
+    {
+        CONDITIONS.iter().find(|(n, _)| *n == name).map(|(_, condition)| *condition)
+    }
+
+    /// The exit status for the condition.
+    pub fn status(self) -> i32
+    {
+        match self
+        {
+            Condition::NoCompleteChains => 4,
+            Condition::Expiring => 5,
+            Condition::NoChains => 6,
+            Condition::Expired => 7,
+            Condition::Permissions => 8,
+            Condition::Policy => 9,
+            Condition::ParseErrors => 10,
+            Condition::Incomplete => 11,
+            Condition::Unreachable => 12,
+        }
+    }
+}
+
+/// Conditions found among displayed chains, and the files read.
+#[derive(Debug, Default)]
+pub struct Findings
+{
+    conditions: BTreeSet<Condition>,
+    keys: bool,
+
+    /// Whether a key has a certificate.
+    complete: bool,
+}
+
+impl Findings
+{
+    pub fn new() -> Findings
+    {
+        Findings::default()
+    }
+
+    pub fn add(&mut self, condition: Condition)
+    {
+        self.conditions.insert(condition);
+    }
+
+    /// Note a displayed chain, and its most severe expiry.
+    pub fn chain(&mut self, chain: &Chain, expiry: Option<Expiry>)
+    {
+        if chain.unreachable.is_some()
+        {
+            self.add(Condition::Unreachable);
+        }
+
+        if chain.key.is_some()
+        {
+            self.keys = true;
+
+            match chain.certificates.is_empty()
+            {
+                true => self.add(Condition::Incomplete),
+                false => self.complete = true
+            }
+        }
+
+        match expiry
+        {
+            Some(Expiry::Expired) => self.conditions.extend([Condition::Expiring, Condition::Expired]),
+            Some(Expiry::Expiring) => self.add(Condition::Expiring),
+            _ => {}
+        }
+    }
+
+    /// Note skipped files, as (path, reason) pairs; those which aren't
+    /// unrecognized or unreadable couldn't be parsed.
+    pub fn skipped(&mut self, skipped: &[(String, Skipped)])
+    {
+        if skipped.iter().any(|(_, reason)| matches!(reason, Skipped::Invalid(_)))
+        {
+            self.add(Condition::ParseErrors);
+        }
+    }
+
+    /// The exit status: that of the most severe condition found which
+    /// should fail, or 0.
+    pub fn status(&self, fail_on: &[Condition]) -> i32
+    {
+        self.conditions.iter()
+            .chain((!self.keys).then_some(&Condition::NoChains))
+            .chain((self.keys && !self.complete).then_some(&Condition::NoCompleteChains))
+            .filter(|condition| fail_on.contains(condition))
+            .max()
+            .map(|condition| condition.status())
+            .unwrap_or(SUCCESS)
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+    use sslchains::chain;
+
+    #[test]
+    fn reflects_conditions_in_exit_status()
+    {
+        let all: Vec<Condition> = CONDITIONS.iter().map(|(_, condition)| *condition).collect();
+        let statuses: Vec<i32> = all.iter().map(|condition| condition.status()).collect();
+
+        assert_eq!(statuses, vec![10, 6, 4, 11, 12, 5, 7, 8, 9]);
+        assert_eq!(Condition::parse("no-chains"), Some(Condition::NoChains));
+        assert_eq!(Condition::parse("expires"), None);
+
+        let complete = chain::build(vec![String::from("samples/self_signed.key"), String::from("samples/self_signed.crt")]).unwrap();
+        let incomplete = chain::build(vec![String::from("samples/self_signed.key")]).unwrap();
+
+        let mut findings = Findings::new();
+
+        complete.iter().for_each(|chain| findings.chain(chain, Some(Expiry::Valid)));
+
+        assert_eq!(findings.status(&all), SUCCESS);
+
+        incomplete.iter().for_each(|chain| findings.chain(chain, None));
+
+        // Quiet mode only fails without a complete chain, unless told to
+        // fail for any incomplete one.
+        assert_eq!(findings.status(&[Condition::NoChains, Condition::NoCompleteChains]), SUCCESS);
+        assert_eq!(findings.status(&all), 11);
+        assert_eq!(Findings::new().status(&all), 6);
+        assert_eq!(Findings::new().status(&[]), SUCCESS);
+
+        let mut keys_only = Findings::new();
+
+        incomplete.iter().for_each(|chain| keys_only.chain(chain, None));
+
+        assert_eq!(keys_only.status(&[Condition::NoChains, Condition::NoCompleteChains]), 4);
+
+        // Expired certificates are expiring, too, and the most severe
+        // condition wins.
+        findings.chain(&complete[0], Some(Expiry::Expired));
+
+        assert_eq!(findings.status(&[Condition::Expiring]), 5);
+        assert_eq!(findings.status(&all), 7);
+
+        findings.add(Condition::Policy);
+
+        assert_eq!(findings.status(&all), 9);
+
+        let mut findings = Findings::new();
+
+        findings.skipped(&[
+            (String::from("notes.txt"), Skipped::Unrecognized),
+            (String::from("secret.key"), Skipped::Unreadable(String::from("Permission denied (os error 13)"))),
+        ]);
+
+        assert_eq!(findings.status(&[Condition::ParseErrors]), SUCCESS);
+
+        findings.skipped(&[(String::from("corrupted.crt"), Skipped::Invalid(String::from("invalid certificate")))]);
+
+        assert_eq!(findings.status(&[Condition::ParseErrors]), 10);
+
+        // Endpoints which couldn't be reached are still displayed, as
+        // chains holding the reason.
+        let mut findings = Findings::new();
+
+        complete.iter().for_each(|chain| findings.chain(chain, None));
+        findings.chain(&Chain { endpoint: Some(String::from("example.com:443")), unreachable: Some(String::from("couldn't connect: timed out")), ..Chain::new() }, None);
+
+        assert_eq!(findings.status(&[Condition::Incomplete]), SUCCESS);
+        assert_eq!(findings.status(&all), 12);
+    }
+}
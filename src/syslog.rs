@@ -0,0 +1,59 @@
+//! Copyright (C) 2022 Gaz J.
+//!
+//! This program is free software: you can redistribute it and/or modify
+//! it under the terms of the GNU General Public License as published by
+//! the Free Software Foundation, either version 3 of the License, or
+//! (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//! GNU General Public License for more details.
+//!
+//! You should have received a copy of the GNU General Public License
+//! along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Logs messages to syslog (--syslog). Where systemd runs, the journal reads
+//! syslog's socket, so messages reach it too.
+
+use std::ffi::CString;
+
+/// How urgent a message is.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Priority
+{
+    Error,
+    Warning,
+    Notice,
+    Info,
+}
+
+impl Priority
+{
+    fn level(self) -> libc::c_int
+    {
+        match self
+        {
+            Priority::Error => libc::LOG_ERR,
+            Priority::Warning => libc::LOG_WARNING,
+            Priority::Notice => libc::LOG_NOTICE,
+            Priority::Info => libc::LOG_INFO,
+        }
+    }
+}
+
+/// Identify messages logged from now on as sslchains's (with its process ID),
+/// from the daemon facility.
+pub fn open()
+{
+    unsafe { libc::openlog(c"sslchains".as_ptr(), libc::LOG_PID, libc::LOG_DAEMON) };
+}
+
+pub fn log(priority: Priority, message: &str)
+{
+    // Messages can't hold NULs, and are passed as an argument so that they
+    // aren't read as formats.
+    let message = CString::new(message.replace('\0', "")).unwrap_or_default();
+
+    unsafe { libc::syslog(priority.level(), c"%s".as_ptr(), message.as_ptr()) };
+}
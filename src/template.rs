@@ -0,0 +1,219 @@
+//! Copyright (C) 2022 Gaz J.
+//!
+//! This program is free software: you can redistribute it and/or modify
+//! it under the terms of the GNU General Public License as published by
+//! the Free Software Foundation, either version 3 of the License, or
+//! (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//! GNU General Public License for more details.
+//!
+//! You should have received a copy of the GNU General Public License
+//! along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Custom output templates (--format), e.g. '{name}\t{key}\t{notafter}'.
+
+use openssl::hash::MessageDigest;
+use sslchains::chain::Chain;
+use crate::display;
+
+/// Supported placeholders, with descriptions for the help output.
+pub const PLACEHOLDERS: &[(&str, &str)] = &[
+    ("name", "display name"),
+    ("key", "private key path"),
+    ("endpoint", "TLS server which served the certificates (--connect)"),
+    ("unreachable", "why the TLS server couldn't be reached (--connect)"),
+    ("algorithm", "private key algorithm"),
+    ("request", "certificate signing request path"),
+    ("certificate", "first certificate path"),
+    ("chain", "certificates and their signing certificates"),
+    ("sans", "first certificate Subject Alternative Names"),
+    ("subject", "first certificate subject"),
+    ("issuer", "first certificate issuer"),
+    ("serial", "first certificate serial number (hexadecimal)"),
+    ("sha256", "first certificate SHA-256 fingerprint"),
+    ("sha1", "first certificate SHA-1 fingerprint"),
+    ("notbefore", "first certificate start date"),
+    ("notafter", "first certificate expiry date"),
+    ("daysleft", "days until the first certificate expires (negative once expired)"),
+    ("verification", "result of verifying the chain against the system trust store"),
+];
+
+enum Segment
+{
+    Literal(String),
+    Placeholder(String),
+}
+
+/// A parsed output template.
+pub struct Template
+{
+    segments: Vec<Segment>,
+}
+
+impl Template
+{
+    /// Parse a template, which may contain {placeholder} values, {{ and }}
+    /// for literal braces, and \t, \n, and \\ escapes.
+    pub fn parse(template: &str) -> Result<Self, String>
+    {
+        let mut segments = vec![];
+        let mut literal = String::new();
+        let mut chars = template.chars().peekable();
+
+        while let Some(c) = chars.next()
+        {
+            match (c, chars.peek())
+            {
+                ('{', Some('{')) | ('}', Some('}')) | ('\\', Some('\\')) => {
+                    literal.push(c);
+                    chars.next();
+                },
+                ('\\', Some('t')) => {
+                    literal.push('\t');
+                    chars.next();
+                },
+                ('\\', Some('n')) => {
+                    literal.push('\n');
+                    chars.next();
+                },
+                ('{', _) => {
+                    let mut name = String::new();
+
+                    loop
+                    {
+                        match chars.next()
+                        {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(format!("unterminated placeholder -- '{{{}'", name))
+                        }
+                    }
+
+                    if !PLACEHOLDERS.iter().any(|(placeholder, _)| *placeholder == name)
+                    {
+                        return Err(format!("unknown placeholder -- '{{{}}}'", name));
+                    }
+
+                    if !literal.is_empty()
+                    {
+                        segments.push(Segment::Literal(literal.clone()));
+                        literal.clear();
+                    }
+
+                    segments.push(Segment::Placeholder(name));
+                },
+                ('}', _) => return Err(String::from("unmatched '}' in format (use '}}' for a literal brace)")),
+                _ => literal.push(c)
+            }
+        }
+
+        if !literal.is_empty()
+        {
+            segments.push(Segment::Literal(literal));
+        }
+
+        Ok(Template { segments })
+    }
+
+    /// Render the template for a chain. Missing values are shown as "-".
+    pub fn render(&self, chain: &Chain) -> String
+    {
+        self.segments.iter()
+            .map(|segment| match segment
+            {
+                Segment::Literal(text) => text.clone(),
+                Segment::Placeholder(name) => value(name, chain).unwrap_or_else(|| String::from("-"))
+            })
+            .collect()
+    }
+}
+
+/// Template display mode handler.
+pub fn display(chains: Vec<Chain>, template: &Template)
+{
+    for chain in chains
+    {
+        if chain.origin().is_none()
+        {
+            continue;
+        }
+
+        println!("{}", template.render(&chain));
+    }
+}
+
+fn value(name: &str, chain: &Chain) -> Option<String>
+{
+    let certificate = chain.certificates.first().map(|c| &c.certificate);
+
+    match name
+    {
+        "name" => Some(display::get_display_name(chain)),
+        "key" => chain.key.as_ref().map(|key| key.path.clone()),
+        "endpoint" => chain.endpoint.clone(),
+        "unreachable" => chain.unreachable.clone(),
+        "algorithm" => chain.key.as_ref().map(|key| key.algorithm().to_string()),
+        "request" => chain.request.as_ref().map(|request| request.path.clone()),
+        "certificate" => chain.certificates.first().map(|c| c.path.clone()),
+        "chain" => Some(display::get_certificate_chain_field(chain)).filter(|field| !field.is_empty()),
+        "sans" => chain.certificates.first()
+            .map(|c| c.subject_alt_names().join(","))
+            .filter(|sans| !sans.is_empty()),
+        "subject" => certificate.map(|c| display::format_name(c.subject_name())),
+        "issuer" => certificate.map(|c| display::format_name(c.issuer_name())),
+        "serial" => chain.certificates.first().and_then(|c| c.serial()),
+        "sha256" => chain.certificates.first()
+            .and_then(|c| c.fingerprint(MessageDigest::sha256()).ok())
+            .map(|fingerprint| display::format_fingerprint(&fingerprint)),
+        "sha1" => chain.certificates.first()
+            .and_then(|c| c.fingerprint(MessageDigest::sha1()).ok())
+            .map(|fingerprint| display::format_fingerprint(&fingerprint)),
+        "notbefore" => certificate.map(|c| display::format_time(c.not_before())),
+        "notafter" => certificate.map(|c| display::format_time(c.not_after())),
+        "verification" => Some(chain.verify().as_str().to_string()),
+        "daysleft" => chain.certificates.first().and_then(|c| c.days_remaining()).map(|days| days.to_string()),
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+    use sslchains::chain;
+
+    #[test]
+    fn renders_templates()
+    {
+        let paths = vec![
+            String::from("samples/ca_signed.key"),
+            String::from("samples/ca_signed.crt"),
+            String::from("samples/intermediate_ca.crt"),
+        ];
+
+        let chains = chain::build(paths).unwrap();
+
+        let template = Template::parse("{name}\\t{key}\\t{request}\\t{chain} {{literal}}").unwrap();
+
+        assert_eq!(
+            template.render(&chains[0]),
+            "example.com\tsamples/ca_signed.key\t-\tsamples/ca_signed.crt|samples/intermediate_ca.crt {literal}"
+        );
+
+        let template = Template::parse("{notafter}").unwrap();
+        let expected = display::format_time(chains[0].certificates[0].certificate.not_after());
+
+        assert_eq!(template.render(&chains[0]), expected);
+    }
+
+    #[test]
+    fn rejects_invalid_templates()
+    {
+        assert!(Template::parse("{unknown}").is_err());
+        assert!(Template::parse("{name").is_err());
+        assert!(Template::parse("name}").is_err());
+    }
+}
@@ -0,0 +1,309 @@
+//! Copyright (C) 2022 Gaz J.
+//!
+//! This program is free software: you can redistribute it and/or modify
+//! it under the terms of the GNU General Public License as published by
+//! the Free Software Foundation, either version 3 of the License, or
+//! (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//! GNU General Public License for more details.
+//!
+//! You should have received a copy of the GNU General Public License
+//! along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//!
+//! Checks whether chains terminate at a root in the system trust store, and
+//! verifies chains in full (--verify).
+
+use std::fmt;
+use std::sync::OnceLock;
+use log::{debug, warn};
+use openssl::stack::Stack;
+use openssl::x509::{X509, X509StoreContext, X509VerifyResult};
+use openssl::x509::store::{X509Store, X509StoreBuilder};
+use openssl::x509::verify::X509VerifyFlags;
+use crate::format;
+
+static SYSTEM_STORE: OnceLock<Option<X509Store>> = OnceLock::new();
+
+static VERIFICATION_STORE: OnceLock<Option<X509Store>> = OnceLock::new();
+
+static NATIVE_ROOTS: OnceLock<Vec<X509>> = OnceLock::new();
+
+/// Trust status of the top (last) certificate of a chain.
+#[derive(Debug, PartialEq)]
+pub enum Status
+{
+    /// A self-signed root in the trust store.
+    TrustedRoot,
+
+    /// A self-signed root which isn't in the trust store.
+    UntrustedRoot,
+
+    /// Not a root, but issued by a root in the trust store.
+    TrustedIssuer(String),
+
+    /// Not a root, and its issuer is unknown.
+    MissingIssuer(String),
+
+    /// Verification failed for another reason.
+    Untrusted(String),
+}
+
+impl fmt::Display for Status
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        match self
+        {
+            Status::TrustedRoot => write!(f, "root, trusted"),
+            Status::UntrustedRoot => write!(f, "root, untrusted"),
+            Status::TrustedIssuer(issuer) => write!(f, "issued by trusted root {}", issuer),
+            Status::MissingIssuer(issuer) => write!(f, "incomplete — missing issuer {}", issuer),
+            Status::Untrusted(reason) => write!(f, "untrusted: {}", reason),
+        }
+    }
+}
+
+/// Result of verifying a chain in full (--verify).
+#[derive(Debug, PartialEq)]
+pub enum Verification
+{
+    /// Verified up to a trusted root.
+    Valid,
+
+    /// A certificate in the chain has expired.
+    Expired,
+
+    /// Verification failed, for this reason.
+    Untrusted(String),
+
+    /// The chain has no certificate, or is missing an issuer, as described.
+    Incomplete(String),
+}
+
+impl Verification
+{
+    pub fn as_str(&self) -> &'static str
+    {
+        match self
+        {
+            Verification::Valid => "VALID",
+            Verification::Expired => "EXPIRED",
+            Verification::Untrusted(_) => "UNTRUSTED",
+            Verification::Incomplete(_) => "INCOMPLETE",
+        }
+    }
+
+    /// Why the chain isn't valid, if it isn't.
+    pub fn reason(&self) -> Option<&str>
+    {
+        match self
+        {
+            Verification::Untrusted(reason) | Verification::Incomplete(reason) => Some(reason),
+            Verification::Expired => Some("certificate has expired"),
+            Verification::Valid => None,
+        }
+    }
+}
+
+/// Build a trust store from the given certificates, or from the platform's
+/// trusted roots if none are given.
+pub fn store(certificates: Vec<X509>) -> Option<X509Store>
+{
+    let mut builder = builder(certificates)?;
+
+    // Only trust is checked here, not validity dates (shown with -d).
+    builder.set_flags(X509VerifyFlags::NO_CHECK_TIME).ok()?;
+
+    Some(builder.build())
+}
+
+/// Build a trust store from the given certificates (or the platform's
+/// trusted roots), which checks validity dates too.
+pub fn verification_store(certificates: Vec<X509>) -> Option<X509Store>
+{
+    Some(builder(certificates)?.build())
+}
+
+fn builder(certificates: Vec<X509>) -> Option<X509StoreBuilder>
+{
+    let mut builder = X509StoreBuilder::new().ok()?;
+
+    let certificates = if certificates.is_empty() { NATIVE_ROOTS.get_or_init(native_roots).clone() } else { certificates };
+
+    for certificate in certificates
+    {
+        builder.add_cert(certificate).ok()?;
+    }
+
+    Some(builder)
+}
+
+/// Load the platform's trusted roots: the keychain on macOS, the system
+/// certificate store on Windows, and the CA bundle found by OpenSSL's usual
+/// probing (honoring SSL_CERT_FILE and SSL_CERT_DIR) elsewhere.
+fn native_roots() -> Vec<X509>
+{
+    match rustls_native_certs::load_native_certs()
+    {
+        Ok(roots) => {
+            let roots: Vec<X509> = roots.iter().filter_map(|root| X509::from_der(&root.0).ok()).collect();
+
+            debug!("Loaded {} trusted roots from the system trust store", roots.len());
+
+            roots
+        },
+        Err(e) => {
+            warn!("Couldn't load the system trust store: {}", e);
+
+            vec![]
+        }
+    }
+}
+
+/// Get the trust status of a chain's top certificate using the system
+/// trust store.
+pub fn system_status(certificate: &X509) -> Option<Status>
+{
+    let store = SYSTEM_STORE.get_or_init(|| store(vec![])).as_ref()?;
+
+    status(certificate, store)
+}
+
+/// Get the trust status of a chain's top certificate.
+pub fn status(certificate: &X509, store: &X509Store) -> Option<Status>
+{
+    let mut context = X509StoreContext::new().ok()?;
+    let chain = Stack::new().ok()?;
+
+    let result = context.init(store, certificate, &chain, |c| {
+        c.verify_cert()?;
+
+        Ok(c.error())
+    }).ok()?;
+
+    let issuer = format::format_name(certificate.issuer_name());
+
+    Some(match result
+    {
+        X509VerifyResult::OK if is_self_signed(certificate) => Status::TrustedRoot,
+        X509VerifyResult::OK => Status::TrustedIssuer(issuer),
+        e if e.as_raw() == openssl_sys::X509_V_ERR_DEPTH_ZERO_SELF_SIGNED_CERT => Status::UntrustedRoot,
+        e if e.as_raw() == openssl_sys::X509_V_ERR_UNABLE_TO_GET_ISSUER_CERT_LOCALLY
+            || e.as_raw() == openssl_sys::X509_V_ERR_UNABLE_TO_GET_ISSUER_CERT => Status::MissingIssuer(issuer),
+        e => Status::Untrusted(e.error_string().to_string())
+    })
+}
+
+/// Verify a leaf certificate, with its intermediates, using the system trust
+/// store.
+pub fn system_verify(certificate: &X509, intermediates: &[X509]) -> Option<Verification>
+{
+    let store = VERIFICATION_STORE.get_or_init(|| verification_store(vec![])).as_ref()?;
+
+    verify(certificate, intermediates, store)
+}
+
+/// Verify a leaf certificate, with its intermediates, as OpenSSL does when
+/// connecting: each certificate's signature, validity dates, and (for
+/// issuers) basicConstraints are checked, up to a root in the store.
+pub fn verify(certificate: &X509, intermediates: &[X509], store: &X509Store) -> Option<Verification>
+{
+    let mut context = X509StoreContext::new().ok()?;
+    let mut chain = Stack::new().ok()?;
+
+    for intermediate in intermediates
+    {
+        chain.push(intermediate.clone()).ok()?;
+    }
+
+    let result = context.init(store, certificate, &chain, |c| {
+        c.verify_cert()?;
+
+        Ok((c.error(), c.current_cert().map(|current| format::format_name(current.issuer_name()))))
+    }).ok()?;
+
+    Some(match result
+    {
+        (X509VerifyResult::OK, _) => Verification::Valid,
+        (e, _) if e.as_raw() == openssl_sys::X509_V_ERR_CERT_HAS_EXPIRED => Verification::Expired,
+        (e, issuer) if e.as_raw() == openssl_sys::X509_V_ERR_UNABLE_TO_GET_ISSUER_CERT_LOCALLY
+            || e.as_raw() == openssl_sys::X509_V_ERR_UNABLE_TO_GET_ISSUER_CERT => {
+            Verification::Incomplete(format!("missing issuer {}", issuer.unwrap_or_default()))
+        },
+        (e, _) => Verification::Untrusted(e.error_string().to_string())
+    })
+}
+
+/// Determine whether a certificate is self-signed (self-issued, and
+/// verified by its own key).
+pub fn is_self_signed(certificate: &X509) -> bool
+{
+    let issued_by_self = certificate.issuer_name().to_der().ok() == certificate.subject_name().to_der().ok();
+
+    issued_by_self && certificate.public_key().and_then(|key| certificate.verify(&key)).unwrap_or(false)
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+    use crate::chain;
+
+    fn read(path: &str) -> X509
+    {
+        chain::bytes_to_x509(&chain::get_file_contents(path).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn checks_roots()
+    {
+        let root = read("samples/intermediate_ca.crt");
+        let other = read("samples/self_signed.crt");
+
+        let trusted = store(vec![root.clone()]).unwrap();
+        let untrusted = store(vec![other]).unwrap();
+
+        assert_eq!(status(&root, &trusted), Some(Status::TrustedRoot));
+        assert_eq!(status(&root, &untrusted), Some(Status::UntrustedRoot));
+    }
+
+    #[test]
+    fn checks_issuers()
+    {
+        let root = read("samples/intermediate_ca.crt");
+        let leaf = read("samples/ca_signed.crt");
+
+        let trusted = store(vec![root]).unwrap();
+        let untrusted = store(vec![read("samples/self_signed.crt")]).unwrap();
+
+        let issuer = String::from("C=US, O=Sample Org, CN=ca.example.com");
+
+        assert_eq!(status(&leaf, &trusted), Some(Status::TrustedIssuer(issuer.clone())));
+        assert_eq!(status(&leaf, &untrusted), Some(Status::MissingIssuer(issuer)));
+    }
+
+    #[test]
+    fn verifies_chains()
+    {
+        let root = read("samples/cross_signed/root_a.crt");
+        let intermediates = vec![read("samples/cross_signed/intermediate_by_a.crt")];
+        let leaf = read("samples/cross_signed/example.com.crt");
+
+        let trusted = verification_store(vec![root]).unwrap();
+        let untrusted = verification_store(vec![read("samples/self_signed.crt")]).unwrap();
+
+        assert_eq!(verify(&leaf, &intermediates, &trusted), Some(Verification::Valid));
+        assert_eq!(verify(&leaf, &[], &trusted).map(|v| v.as_str()), Some("INCOMPLETE"));
+        assert_eq!(verify(&leaf, &intermediates, &untrusted).map(|v| v.as_str()), Some("INCOMPLETE"));
+
+        // samples/intermediate_ca.crt expired in 2023.
+        let root = read("samples/intermediate_ca.crt");
+        let trusted = verification_store(vec![root.clone()]).unwrap();
+
+        assert_eq!(verify(&read("samples/ca_signed.crt"), &[], &trusted), Some(Verification::Expired));
+        assert_eq!(verify(&root, &[], &untrusted).map(|v| v.as_str()), Some("UNTRUSTED"));
+    }
+}
@@ -0,0 +1,120 @@
+//! Copyright (C) 2022 Gaz J.
+//!
+//! This program is free software: you can redistribute it and/or modify
+//! it under the terms of the GNU General Public License as published by
+//! the Free Software Foundation, either version 3 of the License, or
+//! (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//! GNU General Public License for more details.
+//!
+//! You should have received a copy of the GNU General Public License
+//! along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Checks the usage constraints of server certificates and their signing
+//! certificates, which TLS clients enforce even when every signature in a
+//! chain matches: extended key usage, basicConstraints, key usage, and path
+//! length constraints.
+
+use foreign_types::ForeignTypeRef;
+use openssl::x509::X509Ref;
+use openssl_sys as ffi;
+
+/// Describe problems with the usage constraints of a chain of certificates,
+/// ordered from the server certificate up to (but not beyond) the last known
+/// signing certificate, as (index, problem) pairs.
+pub fn check(certificates: &[&X509Ref]) -> Vec<(usize, String)>
+{
+    let mut problems = vec![];
+
+    for (index, certificate) in certificates.iter().enumerate()
+    {
+        // The openssl crate doesn't expose the decoded extensions. These
+        // functions only read them (decoding and caching them first, if
+        // needed), and are safe to call on any valid certificate.
+        let (flags, key_usage, extended_key_usage) = unsafe {
+            let pointer = certificate.as_ptr();
+
+            (
+                ffi::X509_get_extension_flags(pointer),
+                ffi::X509_get_key_usage(pointer),
+                ffi::X509_get_extended_key_usage(pointer),
+            )
+        };
+
+        if index == 0
+        {
+            // Some clients (e.g. Apple's) require the extension outright.
+            if flags & ffi::EXFLAG_XKUSAGE == 0
+            {
+                problems.push((index, String::from("no extendedKeyUsage (some clients require serverAuth)")));
+            }
+            else if extended_key_usage & (ffi::XKU_SSL_SERVER | ffi::XKU_ANYEKU) == 0
+            {
+                problems.push((index, String::from("extendedKeyUsage doesn't include serverAuth")));
+            }
+
+            continue;
+        }
+
+        if flags & ffi::EXFLAG_CA == 0
+        {
+            problems.push((index, String::from("signs certificates, but isn't a CA (basicConstraints CA:TRUE)")));
+        }
+
+        if flags & ffi::EXFLAG_KUSAGE != 0 && key_usage & ffi::X509v3_KU_KEY_CERT_SIGN == 0
+        {
+            problems.push((index, String::from("signs certificates, but keyUsage doesn't include keyCertSign")));
+        }
+
+        // Path length counts the intermediates below a CA, not the server
+        // certificate.
+        if let Some(pathlen) = certificate.pathlen()
+        {
+            let intermediates = index - 1;
+
+            if intermediates > pathlen as usize
+            {
+                problems.push((index, format!("pathlen:{} is exceeded by {} intermediate(s) below it", pathlen, intermediates)));
+            }
+        }
+    }
+
+    problems
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+    use crate::chain;
+
+    fn read(path: &str) -> openssl::x509::X509
+    {
+        chain::bytes_to_x509(&chain::get_file_contents(path).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn checks_usage_constraints()
+    {
+        let client = read("samples/constraints/client.crt");
+        let intermediate = read("samples/constraints/intermediate_ca.crt");
+        let root = read("samples/constraints/root_ca.crt");
+
+        assert_eq!(check(&[&client, &intermediate, &root]), vec![
+            (0, String::from("extendedKeyUsage doesn't include serverAuth")),
+            (1, String::from("signs certificates, but keyUsage doesn't include keyCertSign")),
+            (2, String::from("pathlen:0 is exceeded by 1 intermediate(s) below it")),
+        ]);
+
+        // Without an intermediate below it, the root's path length isn't
+        // exceeded.
+        assert!(check(&[&intermediate, &root]).iter().all(|(index, _)| *index == 0));
+
+        let leaf = read("samples/ca_signed.crt");
+
+        assert_eq!(check(&[&leaf]), vec![(0, String::from("no extendedKeyUsage (some clients require serverAuth)"))]);
+    }
+}
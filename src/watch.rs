@@ -0,0 +1,209 @@
+//! Copyright (C) 2022 Gaz J.
+//!
+//! This program is free software: you can redistribute it and/or modify
+//! it under the terms of the GNU General Public License as published by
+//! the Free Software Foundation, either version 3 of the License, or
+//! (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//! GNU General Public License for more details.
+//!
+//! You should have received a copy of the GNU General Public License
+//! along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Watches directories for changes with inotify (--watch), so files can be
+//! read again as they're written, renamed, or removed.
+//!
+//! Changes usually come in bursts (e.g. a certificate and its key replaced
+//! together), so they're gathered until directories settle.
+
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use log::debug;
+
+/// Events which change what a directory's files hold.
+const MASK: u32 = libc::IN_CLOSE_WRITE | libc::IN_CREATE | libc::IN_DELETE | libc::IN_MOVED_FROM | libc::IN_MOVED_TO
+    | libc::IN_ATTRIB | libc::IN_DELETE_SELF | libc::IN_MOVE_SELF;
+
+/// Size of an event, without its name.
+const EVENT_SIZE: usize = std::mem::size_of::<libc::inotify_event>();
+
+/// Watches directories (not their subdirectories) for changes.
+pub struct Watcher
+{
+    fd: OwnedFd,
+
+    /// Directories watched, by watch descriptor.
+    directories: HashMap<i32, PathBuf>,
+}
+
+impl Watcher
+{
+    pub fn new() -> io::Result<Watcher>
+    {
+        let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+
+        if fd < 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Watcher { fd: unsafe { OwnedFd::from_raw_fd(fd) }, directories: HashMap::new() })
+    }
+
+    /// Watch a directory, unless it's already watched.
+    pub fn watch(&mut self, directory: &Path) -> io::Result<()>
+    {
+        if self.directories.values().any(|watched| watched == directory)
+        {
+            return Ok(());
+        }
+
+        let path = CString::new(directory.as_os_str().as_bytes())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+        let wd = unsafe { libc::inotify_add_watch(self.fd.as_raw_fd(), path.as_ptr(), MASK) };
+
+        if wd < 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+
+        debug!("Watching {}", directory.display());
+
+        self.directories.insert(wd, directory.to_path_buf());
+
+        Ok(())
+    }
+
+    /// Wait for changes, then gather more until none arrive for the settle
+    /// period, returning the paths changed, in the order first seen. When
+    /// events were lost (the queue overflowed), every watched directory is
+    /// returned instead, to be read again in full.
+    pub fn wait(&mut self, settle: Duration) -> io::Result<Vec<PathBuf>>
+    {
+        let mut changed: Vec<PathBuf> = vec![];
+        let mut timeout = None;
+
+        while self.poll(timeout)?
+        {
+            for path in self.read()?
+            {
+                if !changed.contains(&path)
+                {
+                    changed.push(path);
+                }
+            }
+
+            timeout = Some(settle);
+        }
+
+        Ok(changed)
+    }
+
+    /// Wait for events to be ready, for at most timeout (or indefinitely).
+    fn poll(&self, timeout: Option<Duration>) -> io::Result<bool>
+    {
+        let mut fds = libc::pollfd { fd: self.fd.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+        let timeout = timeout.map(|timeout| timeout.as_millis().min(i32::MAX as u128) as i32).unwrap_or(-1);
+
+        loop
+        {
+            match unsafe { libc::poll(&mut fds, 1, timeout) }
+            {
+                -1 if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted => continue,
+                -1 => return Err(io::Error::last_os_error()),
+                ready => return Ok(ready > 0)
+            }
+        }
+    }
+
+    /// Read the events ready, as the paths they concern.
+    fn read(&mut self) -> io::Result<Vec<PathBuf>>
+    {
+        let mut buffer = [0u8; 64 * 1024];
+        let length = unsafe { libc::read(self.fd.as_raw_fd(), buffer.as_mut_ptr() as *mut libc::c_void, buffer.len()) };
+
+        if length < 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut paths = vec![];
+        let mut offset = 0;
+
+        while offset + EVENT_SIZE <= length as usize
+        {
+            // Events aren't necessarily aligned in the buffer.
+            let event: libc::inotify_event = unsafe { std::ptr::read_unaligned(buffer.as_ptr().add(offset) as *const _) };
+            let name = &buffer[offset + EVENT_SIZE..offset + EVENT_SIZE + event.len as usize];
+            let name = &name[..name.iter().position(|&byte| byte == 0).unwrap_or(name.len())];
+
+            offset += EVENT_SIZE + event.len as usize;
+
+            if event.mask & libc::IN_Q_OVERFLOW != 0
+            {
+                paths.extend(self.directories.values().cloned());
+
+                continue;
+            }
+
+            let directory = match self.directories.get(&event.wd)
+            {
+                Some(directory) => directory.clone(),
+                None => continue
+            };
+
+            // Watches end when their directory is removed.
+            if event.mask & libc::IN_IGNORED != 0
+            {
+                self.directories.remove(&event.wd);
+
+                continue;
+            }
+
+            paths.push(match name.is_empty()
+            {
+                true => directory,
+                false => directory.join(std::ffi::OsStr::from_bytes(name))
+            });
+        }
+
+        Ok(paths)
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn reports_changes()
+    {
+        let directory = std::env::temp_dir().join(format!("sslchains-test-{}.watch", std::process::id()));
+
+        fs::create_dir_all(&directory).unwrap();
+
+        let mut watcher = Watcher::new().unwrap();
+
+        watcher.watch(&directory).unwrap();
+
+        fs::write(directory.join("example.com.crt"), "").unwrap();
+        fs::rename(directory.join("example.com.crt"), directory.join("example.com.pem")).unwrap();
+
+        let changed = watcher.wait(Duration::from_millis(100)).unwrap();
+
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(changed, vec![directory.join("example.com.crt"), directory.join("example.com.pem")]);
+    }
+}